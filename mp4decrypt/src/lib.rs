@@ -1,7 +1,9 @@
 //! This crate provides a safe function to decrypt,
 //! encrypted mp4 data stream using [Bento4](https://github.com/axiomatic-systems/Bento4).
 //!
-//! Maximum supported stream size is around `4.29` G.B i.e. [u32::MAX](u32::MAX).
+//! Maximum supported stream size of [mp4decrypt] is around `4.29` G.B i.e. [u32::MAX](u32::MAX).
+//! Larger fragmented mp4 streams can be decrypted using [mp4decrypt_stream],
+//! which decrypts them one fragment at a time.
 //!
 //! ## Environment Variables
 //!
@@ -17,8 +19,9 @@
 #![allow(improper_ctypes)]
 
 use core::ffi::{c_char, c_int, c_uchar, c_uint};
-use std::collections::HashMap;
 use std::ffi::CString;
+use std::fmt;
+use std::io::{self, Read, Write};
 
 extern "C" {
     fn decrypt_in_memory(
@@ -50,90 +53,346 @@ extern "C" fn decrypt_callback(decrypted_stream: *mut Vec<u8>, data: *const c_uc
     }
 }
 
+/// Identifier of a decryption key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum KeyId {
+    /// 128-bit key ID, only applicable to some encryption methods like MPEG-CENC.
+    Kid([u8; 16]),
+    /// Track ID (non-zero). For dcf files, use 1 as the track ID.
+    TrackId(u32),
+}
+
+/// 128-bit decryption key of a key ID or a track.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub id: KeyId,
+    pub key: [u8; 16],
+}
+
+impl Key {
+    /// Key for a 128-bit key ID.
+    pub fn new(kid: [u8; 16], key: [u8; 16]) -> Self {
+        Self {
+            id: KeyId::Kid(kid),
+            key,
+        }
+    }
+
+    /// Key for a track ID.
+    pub fn for_track(track_id: u32, key: [u8; 16]) -> Self {
+        Self {
+            id: KeyId::TrackId(track_id),
+            key,
+        }
+    }
+
+    /// Parse key from hex strings. `id` is either a 128-bit KID in hex or a track ID in decimal.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let key = mp4decrypt::Key::from_hex(
+    ///     "eb676abbcb345e96bbcf616630f1a3da",
+    ///     "100b6c20940f779a4589152b57d2dacb",
+    /// )
+    /// .unwrap();
+    /// ```
+    pub fn from_hex(id: &str, key: &str) -> Result<Self, Error> {
+        let id = if id.len() == 32 {
+            KeyId::Kid(parse_hex(id).ok_or_else(|| Error::InvalidKeyId(id.to_owned()))?)
+        } else {
+            KeyId::TrackId(
+                id.parse::<u32>()
+                    .ok()
+                    .filter(|x| *x != 0)
+                    .ok_or_else(|| Error::InvalidKeyId(id.to_owned()))?,
+            )
+        };
+
+        Ok(Self {
+            id,
+            key: parse_hex(key).ok_or_else(|| Error::InvalidKey(key.to_owned()))?,
+        })
+    }
+}
+
+fn parse_hex(value: &str) -> Option<[u8; 16]> {
+    if value.len() != 32 || !value.is_ascii() {
+        return None;
+    }
+
+    let mut bytes = [0; 16];
+
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&value[(i * 2)..(i * 2 + 2)], 16).ok()?;
+    }
+
+    Some(bytes)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+/// Errors returned while decrypting data stream.
+#[derive(Debug)]
+pub enum Error {
+    /// Data stream is larger than [u32::MAX](u32::MAX) bytes.
+    DataTooLarge,
+    /// Fragments info stream is larger than [u32::MAX](u32::MAX) bytes.
+    FragmentsInfoTooLarge,
+    /// Key ID is neither a 128-bit KID in hex nor a non-zero track ID.
+    InvalidKeyId(String),
+    /// Key is not a 128-bit key in hex.
+    InvalidKey(String),
+    /// No keys were given for decrypting data stream.
+    NoKeys,
+    /// Bento4 failed to decrypt data stream with this error code.
+    Decrypt(i32),
+    /// Data stream contains a box with invalid size.
+    InvalidBox(String),
+    /// Reading or writing data stream failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DataTooLarge => write!(f, "data stream is too large"),
+            Self::FragmentsInfoTooLarge => write!(f, "fragments info is too large"),
+            Self::InvalidKeyId(x) => write!(f, "invalid key id {}", x),
+            Self::InvalidKey(x) => write!(f, "invalid key {}", x),
+            Self::NoKeys => write!(f, "no keys were given for decrypting data"),
+            Self::Decrypt(x) => write!(f, "failed to decrypt data with error code {}", x),
+            Self::InvalidBox(x) => write!(f, "invalid size of {} box", x),
+            Self::Io(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
 /// Decrypt encrypted mp4 data stream using given keys.
 ///
 /// # Arguments
 ///
 /// * `data` - Encrypted data stream.
-/// * `keys` - Hashmap of keys for decrypting data stream.
-///            Hashmap `key` is either a track ID in decimal or a 128-bit KID in hex.
-///            Hashmap `value` is a 128-bit key in hex. <br>
-///            1. For dcf files, use 1 as the track index <br>
-///            2. For Marlin IPMP/ACGK, use 0 as the track ID <br>
-///            3. KIDs are only applicable to some encryption methods like MPEG-CENC <br>
+/// * `keys` - Keys for decrypting data stream, matched with tracks by their key IDs or track IDs.
 /// * `fragments_info` (optional) - Decrypt the fragments read from data stream, with track info read from this stream.
 ///
 /// # Example
 ///
 /// ```no_run
-/// use std::collections::HashMap;
+/// use mp4decrypt::Key;
 ///
-/// let keys = HashMap::from([(
-///     "eb676abbcb345e96bbcf616630f1a3da".to_owned(),
-///     "100b6c20940f779a4589152b57d2dacb".to_owned(),
-/// )]);
+/// let keys = [Key::from_hex(
+///     "eb676abbcb345e96bbcf616630f1a3da",
+///     "100b6c20940f779a4589152b57d2dacb",
+/// )
+/// .unwrap()];
 ///
-/// let decrypted_data = mp4decrypt::mp4decrypt(&[0, 0, 0, 112], keys, None).unwrap();
+/// let decrypted_data = mp4decrypt::mp4decrypt(&[0, 0, 0, 112], &keys, None).unwrap();
 /// ```
 pub fn mp4decrypt(
     data: &[u8],
-    keys: HashMap<String, String>,
-    fragments_info: Option<Vec<u8>>,
-) -> Result<Vec<u8>, String> {
-    let mut data = data.to_vec();
-    let data_size = u32::try_from(data.len()).map_err(|_| "data stream is too large".to_owned())?;
+    keys: &[Key],
+    fragments_info: Option<&[u8]>,
+) -> Result<Vec<u8>, Error> {
+    let data_size = u32::try_from(data.len()).map_err(|_| Error::DataTooLarge)?;
+
+    if keys.is_empty() {
+        return Err(Error::NoKeys);
+    }
 
     let mut c_kids_holder = vec![];
     let mut c_keys_holder = vec![];
-    let mut c_kids = vec![];
-    let mut c_keys = vec![];
 
-    for (i, (kid, key)) in keys.iter().enumerate() {
-        c_kids_holder.push(CString::new(kid.to_owned()).unwrap());
-        c_keys_holder.push(CString::new(key.to_owned()).unwrap());
-        c_kids.push(c_kids_holder[i].as_ptr());
-        c_keys.push(c_keys_holder[i].as_ptr());
+    for key in keys {
+        let kid = match key.id {
+            KeyId::Kid(kid) => to_hex(&kid),
+            // Track ID 0 is rejected by bento4 as well.
+            KeyId::TrackId(0) => return Err(Error::InvalidKeyId("0".to_owned())),
+            KeyId::TrackId(track_id) => track_id.to_string(),
+        };
+
+        c_kids_holder.push(CString::new(kid).unwrap());
+        c_keys_holder.push(CString::new(to_hex(&key.key)).unwrap());
     }
 
+    let mut c_kids = c_kids_holder.iter().map(|x| x.as_ptr()).collect::<Vec<_>>();
+    let mut c_keys = c_keys_holder.iter().map(|x| x.as_ptr()).collect::<Vec<_>>();
     let mut decrypted_data: Box<Vec<u8>> = Box::default();
 
     let result = unsafe {
-        if let Some(mut fragments_info_data) = fragments_info {
+        if let Some(fragments_info_data) = fragments_info {
             let fragments_info_data_size = u32::try_from(fragments_info_data.len())
-                .map_err(|_| "fragments info is too large".to_owned())?;
+                .map_err(|_| Error::FragmentsInfoTooLarge)?;
 
             decrypt_in_memory_with_fragments_info(
-                data.as_mut_ptr(),
+                data.as_ptr(),
                 data_size,
                 c_kids.as_mut_ptr(),
                 c_keys.as_mut_ptr(),
-                1,
+                keys.len() as c_int,
                 &mut *decrypted_data,
                 decrypt_callback,
-                fragments_info_data.as_mut_ptr(),
+                fragments_info_data.as_ptr(),
                 fragments_info_data_size,
             )
         } else {
             decrypt_in_memory(
-                data.as_mut_ptr(),
+                data.as_ptr(),
                 data_size,
                 c_kids.as_mut_ptr(),
                 c_keys.as_mut_ptr(),
-                1,
+                keys.len() as c_int,
                 &mut *decrypted_data,
                 decrypt_callback,
             )
         }
     };
 
+    // Keys are validated before calling bento4, so only processing errors are expected here.
     if result == 0 {
         Ok(*decrypted_data)
     } else {
-        Err(match result {
-            100 => "invalid hex format for key id".to_owned(),
-            101 => "invalid key id".to_owned(),
-            102 => "invalid hex format for key".to_owned(),
-            x => format!("failed to decrypt data with error code {}", x),
-        })
+        Err(Error::Decrypt(result))
     }
 }
+
+/// Decrypt encrypted fragmented mp4 stream from `input` to `output` using given keys.
+///
+/// Boxes before first `moof` box (i.e. initialization segment) are decrypted first and then every fragment
+/// (`moof` and `mdat` boxes along with boxes preceding them) is decrypted on its own,
+/// with track info read from initialization segment.
+/// So, only one fragment is kept in memory at a time and there is no limit on stream size,
+/// although a single fragment can't be larger than [u32::MAX](u32::MAX).
+/// Streams without fragments are decrypted in memory using [mp4decrypt].
+///
+/// # Example
+///
+/// ```no_run
+/// use mp4decrypt::Key;
+/// use std::{fs::File, io::{BufReader, BufWriter}};
+///
+/// let keys = [Key::from_hex(
+///     "eb676abbcb345e96bbcf616630f1a3da",
+///     "100b6c20940f779a4589152b57d2dacb",
+/// )
+/// .unwrap()];
+///
+/// let input = BufReader::new(File::open("encrypted.mp4").unwrap());
+/// let output = BufWriter::new(File::create("decrypted.mp4").unwrap());
+/// mp4decrypt::mp4decrypt_stream(input, output, &keys).unwrap();
+/// ```
+pub fn mp4decrypt_stream<R: Read, W: Write>(
+    mut input: R,
+    mut output: W,
+    keys: &[Key],
+) -> Result<(), Error> {
+    let mut init = vec![];
+    let mut fragment = vec![];
+    let mut has_moof = false;
+    let mut has_moov = false;
+    let mut init_written = false;
+
+    while let Some((box_type, data)) = read_box(&mut input)? {
+        // Initialization segment ends with moov box.
+        if !has_moov {
+            has_moov = &box_type == b"moov";
+            init.extend_from_slice(&data);
+            continue;
+        }
+
+        if &box_type == b"moof" {
+            if !init_written {
+                output.write_all(&mp4decrypt(&init, keys, None)?)?;
+                init_written = true;
+            }
+
+            has_moof = true;
+        }
+
+        fragment.extend_from_slice(&data);
+
+        if has_moof && &box_type == b"mdat" {
+            output.write_all(&mp4decrypt(&fragment, keys, Some(&init))?)?;
+            fragment.clear();
+            has_moof = false;
+        }
+    }
+
+    if init_written {
+        // Trailing boxes (e.g. mfra) aren't encrypted.
+        output.write_all(&fragment)?;
+    } else {
+        init.append(&mut fragment);
+        output.write_all(&mp4decrypt(&init, keys, None)?)?;
+    }
+
+    output.flush()?;
+    Ok(())
+}
+
+/// Type and data (including box header) of a top level box.
+type RawBox = ([u8; 4], Vec<u8>);
+
+/// Read a top level box, `None` is returned at end of stream.
+fn read_box<R: Read>(input: &mut R) -> Result<Option<RawBox>, Error> {
+    let mut header = [0; 8];
+    let mut read = 0;
+
+    while read < header.len() {
+        match input.read(&mut header[read..])? {
+            0 if read == 0 => return Ok(None),
+            0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            x => read += x,
+        }
+    }
+
+    let box_type = [header[4], header[5], header[6], header[7]];
+    let mut data = header.to_vec();
+
+    let size = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+        // Box extends to end of stream.
+        0 => {
+            input.read_to_end(&mut data)?;
+            return Ok(Some((box_type, data)));
+        }
+        1 => {
+            let mut largesize = [0; 8];
+            input.read_exact(&mut largesize)?;
+            data.extend_from_slice(&largesize);
+            u64::from_be_bytes(largesize)
+        }
+        x => x as u64,
+    };
+
+    if size < data.len() as u64 {
+        return Err(Error::InvalidBox(
+            String::from_utf8_lossy(&box_type).into_owned(),
+        ));
+    }
+
+    let remaining = size - data.len() as u64;
+    let read = input.take(remaining).read_to_end(&mut data)?;
+
+    if (read as u64) < remaining {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+
+    Ok(Some((box_type, data)))
+}
@@ -0,0 +1,1111 @@
+/*
+    REFERENCES
+    ----------
+
+    1. ISO/IEC 23001-7:2016 (Common encryption in ISO base media file format files)
+    2. https://github.com/shaka-project/shaka-packager/blob/d5ca6e84/packager/media/formats/mp4/box_definitions.cc
+    3. https://github.com/axiomatic-systems/Bento4/blob/991d890908fb5da73704920c6de43480fb29f76a/Source/C%2B%2B/Core/Ap4CommonEncryption.cpp
+    4. https://learn.microsoft.com/en-us/iis/media/smooth-streaming/protected-interoperable-file-format (PIFF senc)
+
+*/
+
+use crate::{
+    parser,
+    parser::{CallbackType, Mp4Parser, ParsedBox},
+    Error, Reader, Result,
+};
+use aes::{
+    cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit},
+    Aes128,
+};
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+const PIFF_SENC_USER_TYPE: [u8; 16] = [
+    0xa2, 0x39, 0x4f, 0x52, 0x5a, 0x9b, 0x4f, 0x14, 0xa2, 0x44, 0x6c, 0x42, 0x7c, 0x64, 0x8d, 0xf4,
+];
+
+/// Decrypt `cenc` (AES-CTR), `cens` (AES-CTR pattern), `cbc1` (AES-CBC) and `cbcs` (AES-CBC pattern)
+/// encrypted mp4 data stream using given keys.
+///
+/// Samples are decrypted in place, so decrypted data has same size as of encrypted data.
+/// Encrypted sample entries (`encv`, `enca`) are restored to their original format and
+/// protection boxes (`sinf`, `senc`, `saiz`, `saio`) are replaced with `free` boxes.
+/// Key rotation using sample groups (`seig`) is not supported.
+///
+/// # Arguments
+///
+/// - `data` - Encrypted data stream, either a complete file or an initialization segment followed by fragments.
+/// - `keys` - Keys for decrypting data stream, mapped by their key ids.
+/// - `init` (optional) - Initialization segment, required when `data` contains fragments only.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::collections::HashMap;
+///
+/// let mut data = std::fs::read("init.mp4").unwrap();
+/// data.extend(std::fs::read("segment_0.m4s").unwrap());
+///
+/// let keys = HashMap::from([(
+///     [0xeb, 0x67, 0x6a, 0xbb, 0xcb, 0x34, 0x5e, 0x96, 0xbb, 0xcf, 0x61, 0x66, 0x30, 0xf1, 0xa3, 0xda],
+///     [0x10, 0x0b, 0x6c, 0x20, 0x94, 0x0f, 0x77, 0x9a, 0x45, 0x89, 0x15, 0x2b, 0x57, 0xd2, 0xda, 0xcb],
+/// )]);
+///
+/// let decrypted_data = vsd_mp4::decrypt(&data, &keys, None).unwrap();
+/// ```
+pub fn decrypt(
+    data: &[u8],
+    keys: &HashMap<[u8; 16], [u8; 16]>,
+    init: Option<&[u8]>,
+) -> Result<Vec<u8>> {
+    let mut tracks = HashMap::new();
+
+    if let Some(init) = init {
+        tracks = State::parse(init)?.tracks;
+    }
+
+    let state = State::parse(data)?;
+    tracks.extend(state.tracks);
+
+    let mut data = data.to_vec();
+
+    for fragment in &state.fragments {
+        for traf in &fragment.trafs {
+            let track = match tracks.get(&traf.track_id) {
+                Some(x) if x.is_protected => x,
+                _ => continue,
+            };
+
+            decrypt_traf(&mut data, fragment.start, traf, track, keys)?;
+        }
+    }
+
+    for (offset, name) in state.renames {
+        data[(offset as usize)..(offset as usize + 4)].copy_from_slice(&name);
+    }
+
+    Ok(data)
+}
+
+/// Protection info of a track, parsed from `tenc` box.
+#[derive(Clone, Default)]
+struct Track {
+    constant_iv: Option<Vec<u8>>,
+    crypt_byte_block: u8,
+    default_sample_size: Option<u32>,
+    is_protected: bool,
+    iv_size: u8,
+    kid: [u8; 16],
+    scheme: [u8; 4],
+    skip_byte_block: u8,
+}
+
+#[derive(Default)]
+struct Fragment {
+    start: u64,
+    trafs: Vec<Traf>,
+}
+
+#[derive(Default)]
+struct Traf {
+    base_data_offset: Option<u64>,
+    default_sample_size: Option<u32>,
+    saio: Vec<u64>,
+    saiz: Vec<u8>,
+    senc: Option<Senc>,
+    track_id: u32,
+    truns: Vec<Trun>,
+}
+
+struct Trun {
+    data_offset: Option<i32>,
+    sample_sizes: Vec<Option<u32>>,
+}
+
+struct Senc {
+    flags: u32,
+    iv_size: Option<u8>,
+    payload: Vec<u8>,
+}
+
+struct SampleInfo {
+    iv: Vec<u8>,
+    subsamples: Vec<(u32, u32)>,
+}
+
+#[derive(Default)]
+struct State {
+    current_track: u32,
+    fragments: Vec<Fragment>,
+    /// Offsets of box types which are renamed after decryption.
+    renames: Vec<(u64, [u8; 4])>,
+    sample_entry: Option<u64>,
+    tracks: HashMap<u32, Track>,
+}
+
+impl State {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let state = Arc::new(Mutex::new(Self::default()));
+        let state_encv = state.clone();
+        let state_enca = state.clone();
+        let state_sinf = state.clone();
+        let state_moof = state.clone();
+        let state_traf = state.clone();
+
+        Mp4Parser::default()
+            ._box("moov", Arc::new(parser::children))
+            ._box("trak", Arc::new(parser::children))
+            .full_box(
+                "tkhd",
+                Self::handler(&state, |state, _box| {
+                    let track_id = parse_tkhd(_box)?;
+                    state.current_track = track_id;
+                    state.tracks.entry(track_id).or_default();
+                    Ok(())
+                }),
+            )
+            ._box("mdia", Arc::new(parser::children))
+            ._box("minf", Arc::new(parser::children))
+            ._box("stbl", Arc::new(parser::children))
+            .full_box("stsd", Arc::new(parser::sample_description))
+            ._box(
+                "encv",
+                Arc::new(move |_box| {
+                    state_encv.lock().unwrap().sample_entry = Some(_box.start + 4);
+                    parser::visual_sample_entry(_box)
+                }),
+            )
+            ._box(
+                "enca",
+                Arc::new(move |mut _box| {
+                    state_enca.lock().unwrap().sample_entry = Some(_box.start + 4);
+                    // Skip 28 bytes of audio sample entry fields.
+                    _box.reader
+                        .skip(28)
+                        .map_err(|_| Error::new_read_err("audio sample entry (28 bytes)"))?;
+                    parser::children(_box)
+                }),
+            )
+            ._box(
+                "sinf",
+                Arc::new(move |_box| {
+                    state_sinf.lock().unwrap().rename(&_box, b"free");
+                    parser::children(_box)
+                }),
+            )
+            ._box(
+                "frma",
+                Self::handler(&state, |state, _box| {
+                    let format = _box
+                        .reader
+                        .read_u32()
+                        .map_err(|_| Error::new_read_err("FRMA box data format (u32)"))?;
+
+                    if let Some(offset) = state.sample_entry.take() {
+                        state.renames.push((offset, format.to_be_bytes()));
+                    }
+
+                    Ok(())
+                }),
+            )
+            .full_box(
+                "schm",
+                Self::handler(&state, |state, _box| {
+                    let scheme = _box
+                        .reader
+                        .read_u32()
+                        .map_err(|_| Error::new_read_err("SCHM box scheme type (u32)"))?;
+                    state.track().scheme = scheme.to_be_bytes();
+                    Ok(())
+                }),
+            )
+            ._box("schi", Arc::new(parser::children))
+            .full_box(
+                "tenc",
+                Self::handler(&state, |state, _box| parse_tenc(_box, state.track())),
+            )
+            ._box("mvex", Arc::new(parser::children))
+            .full_box(
+                "trex",
+                Self::handler(&state, |state, _box| {
+                    let (track_id, default_sample_size) = parse_trex(_box)?;
+                    state
+                        .tracks
+                        .entry(track_id)
+                        .or_default()
+                        .default_sample_size = Some(default_sample_size);
+                    Ok(())
+                }),
+            )
+            ._box(
+                "moof",
+                Arc::new(move |_box| {
+                    state_moof.lock().unwrap().fragments.push(Fragment {
+                        start: _box.start,
+                        trafs: vec![],
+                    });
+                    parser::children(_box)
+                }),
+            )
+            ._box(
+                "traf",
+                Arc::new(move |_box| {
+                    if let Some(fragment) = state_traf.lock().unwrap().fragments.last_mut() {
+                        fragment.trafs.push(Traf::default());
+                    }
+
+                    parser::children(_box)
+                }),
+            )
+            .full_box(
+                "tfhd",
+                Self::handler(&state, |state, _box| {
+                    state.with_traf(|x| parse_tfhd(_box, x))
+                }),
+            )
+            .full_box(
+                "trun",
+                Self::handler(&state, |state, _box| {
+                    let trun = parse_trun(_box)?;
+                    state.with_traf(|x| {
+                        x.truns.push(trun);
+                        Ok(())
+                    })
+                }),
+            )
+            .full_box(
+                "senc",
+                Self::handler(&state, |state, _box| {
+                    let senc = Senc {
+                        flags: _box.flags.unwrap(),
+                        iv_size: None,
+                        payload: read_all(_box)?,
+                    };
+                    state.rename(_box, b"free");
+                    state.with_traf(|x| {
+                        x.senc = Some(senc);
+                        Ok(())
+                    })
+                }),
+            )
+            ._box(
+                "uuid",
+                Self::handler(&state, |state, _box| {
+                    if let Some(senc) = parse_piff_senc(_box)? {
+                        state.rename(_box, b"free");
+                        state.with_traf(|x| {
+                            x.senc = Some(senc);
+                            Ok(())
+                        })?;
+                    }
+
+                    Ok(())
+                }),
+            )
+            .full_box(
+                "saiz",
+                Self::handler(&state, |state, _box| {
+                    let saiz = parse_saiz(_box)?;
+                    state.rename(_box, b"free");
+                    state.with_traf(|x| {
+                        x.saiz = saiz;
+                        Ok(())
+                    })
+                }),
+            )
+            .full_box(
+                "saio",
+                Self::handler(&state, |state, _box| {
+                    let saio = parse_saio(_box)?;
+                    state.rename(_box, b"free");
+                    state.with_traf(|x| {
+                        x.saio = saio;
+                        Ok(())
+                    })
+                }),
+            )
+            .parse(data, None, None)?;
+
+        let mut state = state.lock().unwrap();
+        Ok(std::mem::take(&mut *state))
+    }
+
+    /// Callback for boxes without children, state is locked while parsing box.
+    fn handler<F: Fn(&mut Self, &mut ParsedBox) -> Result<()> + 'static>(
+        state: &Arc<Mutex<Self>>,
+        f: F,
+    ) -> CallbackType {
+        let state = state.clone();
+        Arc::new(move |mut _box| f(&mut state.lock().unwrap(), &mut _box))
+    }
+
+    /// Replace type of box after decryption.
+    fn rename(&mut self, _box: &ParsedBox, name: &[u8; 4]) {
+        self.renames.push((_box.start + 4, *name));
+    }
+
+    fn track(&mut self) -> &mut Track {
+        self.tracks.entry(self.current_track).or_default()
+    }
+
+    fn with_traf<F: FnOnce(&mut Traf) -> Result<()>>(&mut self, f: F) -> Result<()> {
+        match self.fragments.last_mut().and_then(|x| x.trafs.last_mut()) {
+            Some(traf) => f(traf),
+            None => Ok(()),
+        }
+    }
+}
+
+fn read_all(_box: &mut ParsedBox) -> Result<Vec<u8>> {
+    let all = _box.reader.get_length() - _box.reader.get_position();
+    _box.reader
+        .read_bytes_u8(all as usize)
+        .map_err(|_| Error::new_read_err(format!("{} box data ({} bytes)", _box.name, all)))
+}
+
+fn parse_tkhd(_box: &mut ParsedBox) -> Result<u32> {
+    // Skip creation and modification times.
+    let skip = if _box.version == Some(1) { 16 } else { 8 };
+    _box.reader
+        .skip(skip)
+        .map_err(|_| Error::new_read_err(format!("TKHD box times ({} bytes)", skip)))?;
+    _box.reader
+        .read_u32()
+        .map_err(|_| Error::new_read_err("TKHD box track id (u32)"))
+}
+
+fn parse_tenc(_box: &mut ParsedBox, track: &mut Track) -> Result<()> {
+    // Skip reserved byte.
+    _box.reader
+        .skip(1)
+        .map_err(|_| Error::new_read_err("TENC box reserved data (1 byte)"))?;
+
+    let pattern = _box
+        .reader
+        .read_bytes_u8(1)
+        .map_err(|_| Error::new_read_err("TENC box pattern (1 byte)"))?[0];
+
+    if _box.version.unwrap_or(0) > 0 {
+        track.crypt_byte_block = pattern >> 4;
+        track.skip_byte_block = pattern & 0x0f;
+    }
+
+    let data = _box
+        .reader
+        .read_bytes_u8(18)
+        .map_err(|_| Error::new_read_err("TENC box default values (18 bytes)"))?;
+    track.is_protected = data[0] != 0;
+    track.iv_size = data[1];
+    track.kid.copy_from_slice(&data[2..]);
+
+    if track.is_protected && track.iv_size == 0 {
+        let size = _box
+            .reader
+            .read_bytes_u8(1)
+            .map_err(|_| Error::new_read_err("TENC box constant iv size (1 byte)"))?[0];
+        track.constant_iv =
+            Some(_box.reader.read_bytes_u8(size as usize).map_err(|_| {
+                Error::new_read_err(format!("TENC box constant iv ({} bytes)", size))
+            })?);
+    }
+
+    Ok(())
+}
+
+fn parse_trex(_box: &mut ParsedBox) -> Result<(u32, u32)> {
+    let track_id = _box
+        .reader
+        .read_u32()
+        .map_err(|_| Error::new_read_err("TREX box track id (u32)"))?;
+    // Skip default sample description index and default sample duration.
+    _box.reader
+        .skip(8)
+        .map_err(|_| Error::new_read_err("TREX box default values (8 bytes)"))?;
+    let default_sample_size = _box
+        .reader
+        .read_u32()
+        .map_err(|_| Error::new_read_err("TREX box default sample size (u32)"))?;
+    Ok((track_id, default_sample_size))
+}
+
+fn parse_tfhd(_box: &mut ParsedBox, traf: &mut Traf) -> Result<()> {
+    let flags = _box.flags.unwrap();
+
+    traf.track_id = _box
+        .reader
+        .read_u32()
+        .map_err(|_| Error::new_read_err("TFHD box track id (u32)"))?;
+
+    if flags & 0x000001 != 0 {
+        traf.base_data_offset = Some(
+            _box.reader
+                .read_u64()
+                .map_err(|_| Error::new_read_err("TFHD box base data offset (u64)"))?,
+        );
+    }
+
+    // Skip sample description index and default sample duration.
+    for flag in [0x000002, 0x000008] {
+        if flags & flag != 0 {
+            _box.reader
+                .skip(4)
+                .map_err(|_| Error::new_read_err("TFHD box default values (4 bytes)"))?;
+        }
+    }
+
+    if flags & 0x000010 != 0 {
+        traf.default_sample_size = Some(
+            _box.reader
+                .read_u32()
+                .map_err(|_| Error::new_read_err("TFHD box default sample size (u32)"))?,
+        );
+    }
+
+    Ok(())
+}
+
+fn parse_trun(_box: &mut ParsedBox) -> Result<Trun> {
+    let flags = _box.flags.unwrap();
+    let sample_count = _box
+        .reader
+        .read_u32()
+        .map_err(|_| Error::new_read_err("TRUN box sample count (u32)"))?;
+
+    let data_offset = if flags & 0x000001 != 0 {
+        Some(
+            _box.reader
+                .read_i32()
+                .map_err(|_| Error::new_read_err("TRUN box data offset (i32)"))?,
+        )
+    } else {
+        None
+    };
+
+    if flags & 0x000004 != 0 {
+        _box.reader
+            .skip(4)
+            .map_err(|_| Error::new_read_err("TRUN box first sample flags (4 bytes)"))?;
+    }
+
+    let mut sample_sizes = vec![];
+
+    for _ in 0..sample_count {
+        if flags & 0x000100 != 0 {
+            _box.reader
+                .skip(4)
+                .map_err(|_| Error::new_read_err("TRUN box sample duration (4 bytes)"))?;
+        }
+
+        sample_sizes.push(if flags & 0x000200 != 0 {
+            Some(
+                _box.reader
+                    .read_u32()
+                    .map_err(|_| Error::new_read_err("TRUN box sample size (u32)"))?,
+            )
+        } else {
+            None
+        });
+
+        // Skip sample flags and sample composition time offset.
+        for flag in [0x000400, 0x000800] {
+            if flags & flag != 0 {
+                _box.reader
+                    .skip(4)
+                    .map_err(|_| Error::new_read_err("TRUN box sample values (4 bytes)"))?;
+            }
+        }
+    }
+
+    Ok(Trun {
+        data_offset,
+        sample_sizes,
+    })
+}
+
+fn parse_piff_senc(_box: &mut ParsedBox) -> Result<Option<Senc>> {
+    let user_type = _box
+        .reader
+        .read_bytes_u8(16)
+        .map_err(|_| Error::new_read_err("UUID box user type (16 bytes)"))?;
+
+    if user_type != PIFF_SENC_USER_TYPE {
+        return Ok(None);
+    }
+
+    let flags = _box
+        .reader
+        .read_u32()
+        .map_err(|_| Error::new_read_err("PIFF SENC box version and flags (u32)"))?
+        & 0xFFFFFF;
+    let mut iv_size = None;
+
+    if flags & 0x000001 != 0 {
+        // Skip algorithm id, read iv size and skip key id.
+        let data = _box
+            .reader
+            .read_bytes_u8(20)
+            .map_err(|_| Error::new_read_err("PIFF SENC box override values (20 bytes)"))?;
+        iv_size = Some(data[3]);
+    }
+
+    Ok(Some(Senc {
+        flags,
+        iv_size,
+        payload: read_all(_box)?,
+    }))
+}
+
+fn parse_saiz(_box: &mut ParsedBox) -> Result<Vec<u8>> {
+    if _box.flags.unwrap() & 0x000001 != 0 {
+        _box.reader
+            .skip(8)
+            .map_err(|_| Error::new_read_err("SAIZ box aux info type (8 bytes)"))?;
+    }
+
+    let default_size = _box
+        .reader
+        .read_bytes_u8(1)
+        .map_err(|_| Error::new_read_err("SAIZ box default sample info size (1 byte)"))?[0];
+    let sample_count = _box
+        .reader
+        .read_u32()
+        .map_err(|_| Error::new_read_err("SAIZ box sample count (u32)"))?;
+
+    if default_size != 0 {
+        return Ok(vec![default_size; sample_count as usize]);
+    }
+
+    _box.reader
+        .read_bytes_u8(sample_count as usize)
+        .map_err(|_| {
+            Error::new_read_err(format!(
+                "SAIZ box sample info sizes ({} bytes)",
+                sample_count
+            ))
+        })
+}
+
+fn parse_saio(_box: &mut ParsedBox) -> Result<Vec<u64>> {
+    if _box.flags.unwrap() & 0x000001 != 0 {
+        _box.reader
+            .skip(8)
+            .map_err(|_| Error::new_read_err("SAIO box aux info type (8 bytes)"))?;
+    }
+
+    let entry_count = _box
+        .reader
+        .read_u32()
+        .map_err(|_| Error::new_read_err("SAIO box entry count (u32)"))?;
+    let mut offsets = vec![];
+
+    for _ in 0..entry_count {
+        offsets.push(if _box.version == Some(0) {
+            _box.reader
+                .read_u32()
+                .map_err(|_| Error::new_read_err("SAIO box offset (u32)"))? as u64
+        } else {
+            _box.reader
+                .read_u64()
+                .map_err(|_| Error::new_read_err("SAIO box offset (u64)"))?
+        });
+    }
+
+    Ok(offsets)
+}
+
+/// Read sample auxiliary information i.e. initialization vector and subsamples of a sample.
+fn parse_sample_info(reader: &mut Reader, iv_size: u8, has_subsamples: bool) -> Result<SampleInfo> {
+    let iv = reader
+        .read_bytes_u8(iv_size as usize)
+        .map_err(|_| Error::new_read_err(format!("sample iv ({} bytes)", iv_size)))?;
+    let mut subsamples = vec![];
+
+    if has_subsamples {
+        let count = reader
+            .read_u16()
+            .map_err(|_| Error::new_read_err("subsample count (u16)"))?;
+
+        for _ in 0..count {
+            let clear = reader
+                .read_u16()
+                .map_err(|_| Error::new_read_err("subsample clear bytes (u16)"))?;
+            let protected = reader
+                .read_u32()
+                .map_err(|_| Error::new_read_err("subsample protected bytes (u32)"))?;
+            subsamples.push((clear as u32, protected));
+        }
+    }
+
+    Ok(SampleInfo { iv, subsamples })
+}
+
+fn decrypt_traf(
+    data: &mut [u8],
+    moof_start: u64,
+    traf: &Traf,
+    track: &Track,
+    keys: &HashMap<[u8; 16], [u8; 16]>,
+) -> Result<()> {
+    // Base data offset defaults to start of moof box (default-base-is-moof).
+    let base = traf.base_data_offset.unwrap_or(moof_start);
+    let mut samples = vec![];
+    let mut offset = base;
+
+    for trun in &traf.truns {
+        if let Some(data_offset) = trun.data_offset {
+            offset = (base as i64 + data_offset as i64) as u64;
+        }
+
+        for size in &trun.sample_sizes {
+            let size = size
+                .or(traf.default_sample_size)
+                .or(track.default_sample_size)
+                .ok_or_else(|| Error::new("sample size is not present in fragment"))?
+                as u64;
+            samples.push(offset..(offset + size));
+            offset += size;
+        }
+    }
+
+    if samples.is_empty() {
+        return Ok(());
+    }
+
+    let mut infos = vec![];
+
+    if let Some(senc) = &traf.senc {
+        let mut reader = Reader::new(&senc.payload, false);
+        let sample_count = reader
+            .read_u32()
+            .map_err(|_| Error::new_read_err("SENC box sample count (u32)"))?;
+
+        for _ in 0..sample_count {
+            infos.push(parse_sample_info(
+                &mut reader,
+                senc.iv_size.unwrap_or(track.iv_size),
+                senc.flags & 0x000002 != 0,
+            )?);
+        }
+    } else if let Some(saio) = traf.saio.first() {
+        let mut offset = base + saio;
+
+        for size in &traf.saiz {
+            let info = data
+                .get((offset as usize)..((offset + *size as u64) as usize))
+                .ok_or_else(|| Error::new_read_err("sample auxiliary information"))?;
+            infos.push(parse_sample_info(
+                &mut Reader::new(info, false),
+                track.iv_size,
+                *size > track.iv_size,
+            )?);
+            offset += *size as u64;
+        }
+    } else {
+        return Err(Error::new(
+            "sample encryption information (SENC or SAIZ and SAIO boxes) is not present in fragment",
+        ));
+    }
+
+    if infos.len() < samples.len() {
+        return Err(Error::new(format!(
+            "sample encryption information is present for {} samples but fragment has {} samples",
+            infos.len(),
+            samples.len()
+        )));
+    }
+
+    let key = keys.get(&track.kid).ok_or_else(|| {
+        Error::new(format!(
+            "key for kid {} is not supplied",
+            track
+                .kid
+                .iter()
+                .map(|x| format!("{:02x}", x))
+                .collect::<String>()
+        ))
+    })?;
+    let cipher = Aes128::new(GenericArray::from_slice(key));
+
+    for (sample, info) in samples.into_iter().zip(infos) {
+        let iv = if info.iv.is_empty() {
+            track.constant_iv.clone().unwrap_or_default()
+        } else {
+            info.iv
+        };
+
+        if iv.len() != 8 && iv.len() != 16 {
+            return Err(Error::new(format!("invalid iv size of {} bytes", iv.len())));
+        }
+
+        let mut iv_c = [0; 16];
+        iv_c[..iv.len()].copy_from_slice(&iv);
+
+        let sample = data
+            .get_mut((sample.start as usize)..(sample.end as usize))
+            .ok_or_else(|| Error::new_read_err("sample data"))?;
+        decrypt_sample(sample, &cipher, iv_c, &info.subsamples, track)?;
+    }
+
+    Ok(())
+}
+
+fn decrypt_sample(
+    sample: &mut [u8],
+    cipher: &Aes128,
+    iv: [u8; 16],
+    subsamples: &[(u32, u32)],
+    track: &Track,
+) -> Result<()> {
+    let mut ranges = vec![];
+
+    if subsamples.is_empty() {
+        ranges.push(0..sample.len());
+    } else {
+        let mut position = 0;
+
+        for (clear, protected) in subsamples {
+            position += *clear as usize;
+            ranges.push(position..(position + *protected as usize));
+            position += *protected as usize;
+        }
+
+        if position > sample.len() {
+            return Err(Error::new("subsamples are larger than sample"));
+        }
+    }
+
+    // A pattern of 0:0 means that every block is encrypted.
+    let (crypt, skip) = match (track.crypt_byte_block, track.skip_byte_block) {
+        (0, 0) => (1, 0),
+        (x, y) => (x as usize, y as usize),
+    };
+
+    match &track.scheme {
+        b"cenc" | b"piff" => {
+            // Protected ranges of all subsamples form a single continuous keystream.
+            let mut ctr = Ctr::new(cipher, iv);
+
+            for range in ranges {
+                ctr.apply(&mut sample[range]);
+            }
+        }
+        b"cens" => {
+            let mut ctr = Ctr::new(cipher, iv);
+
+            for range in ranges {
+                for block in pattern_blocks(range.len(), crypt, skip, true) {
+                    ctr.apply(&mut sample[(range.start + block.start)..(range.start + block.end)]);
+                }
+            }
+        }
+        b"cbc1" => {
+            // Cipher block chain is carried across subsamples, partial blocks are left clear.
+            let mut chain = iv;
+
+            for range in ranges {
+                let end = range.start + (range.len() / 16) * 16;
+                cbc_decrypt(cipher, &mut chain, &mut sample[range.start..end]);
+            }
+        }
+        b"cbcs" => {
+            // Cipher block chain restarts with iv for every subsample.
+            for range in ranges {
+                let mut chain = iv;
+
+                for block in pattern_blocks(range.len(), crypt, skip, false) {
+                    cbc_decrypt(
+                        cipher,
+                        &mut chain,
+                        &mut sample[(range.start + block.start)..(range.start + block.end)],
+                    );
+                }
+            }
+        }
+        x => {
+            return Err(Error::new(format!(
+                "{} encryption scheme is not supported",
+                String::from_utf8_lossy(x)
+            )))
+        }
+    }
+
+    Ok(())
+}
+
+/// Ranges of encrypted 16 byte blocks inside a protected range using `crypt:skip` pattern.
+fn pattern_blocks(len: usize, crypt: usize, skip: usize, partial: bool) -> Vec<Range<usize>> {
+    let mut blocks = vec![];
+    let mut start = 0;
+
+    while start < len {
+        let end = (start + crypt * 16).min(len);
+        let end = if partial {
+            end
+        } else {
+            start + ((end - start) / 16) * 16
+        };
+
+        if end > start {
+            blocks.push(start..end);
+        }
+
+        start += (crypt + skip) * 16;
+    }
+
+    blocks
+}
+
+fn cbc_decrypt(cipher: &Aes128, chain: &mut [u8; 16], data: &mut [u8]) {
+    for block in data.chunks_exact_mut(16) {
+        let mut next_chain = [0; 16];
+        next_chain.copy_from_slice(block);
+
+        let block_c = GenericArray::from_mut_slice(block);
+        cipher.decrypt_block(block_c);
+
+        for (x, y) in block.iter_mut().zip(chain.iter()) {
+            *x ^= y;
+        }
+
+        *chain = next_chain;
+    }
+}
+
+/// AES-CTR keystream, continued across calls of [`Ctr::apply`].
+struct Ctr<'a> {
+    cipher: &'a Aes128,
+    counter: u128,
+    keystream: [u8; 16],
+    position: usize,
+}
+
+impl<'a> Ctr<'a> {
+    fn new(cipher: &'a Aes128, iv: [u8; 16]) -> Self {
+        Self {
+            cipher,
+            counter: u128::from_be_bytes(iv),
+            keystream: [0; 16],
+            position: 16,
+        }
+    }
+
+    fn apply(&mut self, data: &mut [u8]) {
+        for x in data {
+            if self.position == 16 {
+                let mut block = GenericArray::from(self.counter.to_be_bytes());
+                self.cipher.encrypt_block(&mut block);
+                self.keystream.copy_from_slice(&block);
+                self.counter = self.counter.wrapping_add(1);
+                self.position = 0;
+            }
+
+            *x ^= self.keystream[self.position];
+            self.position += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Vectors are encrypted using openssl with key 000102030405060708090a0b0c0d0e0f.
+    const KEY: [u8; 16] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f,
+    ];
+    const KID: [u8; 16] = [0x11; 16];
+    const IV_8: [u8; 8] = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+    const IV_16: [u8; 16] = [
+        0xf0, 0xe0, 0xd0, 0xc0, 0xb0, 0xa0, 0x90, 0x80, 0x70, 0x60, 0x50, 0x40, 0x30, 0x20, 0x10,
+        0x00,
+    ];
+    const CONSTANT_IV: [u8; 16] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee,
+        0xff,
+    ];
+
+    /// Subsamples (5, 40) and (3, 52) encrypted as a single keystream.
+    const CENC: &str = "00010203041dbc6eb34f6bf5e9aac291d1b623f16cda87ba86c4e3d578464f4103a3f3eb\
+        e845244fca5f8f362b2d2e2f19359c79327224835c0a53d6c90926a7ec314568e54911d86e0f2d209f01\
+        4213c69cec88ddbd96c8d67e1906a00f79ccbbb714b2";
+    /// Subsamples (4, 96) and (4, 32) with 1:2 pattern.
+    const CENS: &str = "000102037307fa9c79cf372daefd93fad45402391415161718191a1b1c1d1e1f20212223\
+        2425262728292a2b2c2d2e2f303132337ca1769f97f5321cc7877bc4da3d68824445464748494a4b4c4d\
+        4e4f505152535455565758595a5b5c5d5e5f606162636465666793e2ca425c147ec10bebf5b58054f503\
+        78797a7b7c7d7e7f8081828384858687";
+    /// Subsamples (4, 32) and (6, 40) with a single cipher block chain, last 8 bytes are clear.
+    const CBC1: &str = "000102030b8605f61362648b919d4f916e8955cbee5f014f12f53e4c5b1bfaab74254cd0\
+        242526272829e2e6786cbafdd4df036d9b17e34d95e1b82bf7ee5b60d1a9fe31768256a7f5234a4b4c4d\
+        4e4f5051";
+    /// Subsamples (8, 180) and (4, 40) with 1:9 pattern and constant iv.
+    const CBCS: &str = "00010203040506076c4825a59f44c8830c935062b57e9d0e18191a1b1c1d1e1f20212223\
+        2425262728292a2b2c2d2e2f303132333435363738393a3b3c3d3e3f404142434445464748494a4b4c4d\
+        4e4f505152535455565758595a5b5c5d5e5f606162636465666768696a6b6c6d6e6f7071727374757677\
+        78797a7b7c7d7e7f808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9fa0a1\
+        a2a3a4a5a6a7df45c512f15df7cf9ba20f4078bc80c4b8b9babbbcbdbebfb3d7754731a099e05eedebbe\
+        e3e0b98ad0d1d2d3d4d5d6d7d8d9dadbdcdddedfe0e1e2e3e4e5e6e7";
+
+    fn unhex(data: &str) -> Vec<u8> {
+        (0..data.len())
+            .step_by(2)
+            .map(|x| u8::from_str_radix(&data[x..(x + 2)], 16).unwrap())
+            .collect()
+    }
+
+    fn plaintext(len: usize) -> Vec<u8> {
+        (0..len).map(|x| x as u8).collect()
+    }
+
+    fn track(scheme: &[u8; 4], crypt_byte_block: u8, skip_byte_block: u8) -> Track {
+        Track {
+            crypt_byte_block,
+            is_protected: true,
+            kid: KID,
+            scheme: *scheme,
+            skip_byte_block,
+            ..Default::default()
+        }
+    }
+
+    fn decrypt_vector(
+        vector: &str,
+        iv: &[u8],
+        subsamples: &[(u32, u32)],
+        track: &Track,
+    ) -> Vec<u8> {
+        let mut iv_c = [0; 16];
+        iv_c[..iv.len()].copy_from_slice(iv);
+        let mut sample = unhex(vector);
+        let cipher = Aes128::new(GenericArray::from_slice(&KEY));
+        decrypt_sample(&mut sample, &cipher, iv_c, subsamples, track).unwrap();
+        sample
+    }
+
+    fn mp4_box(name: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(name);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn full_box(name: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+        let mut data = (((version as u32) << 24) | flags).to_be_bytes().to_vec();
+        data.extend_from_slice(payload);
+        mp4_box(name, &data)
+    }
+
+    #[test]
+    fn cenc_subsamples() {
+        let sample = decrypt_vector(CENC, &IV_8, &[(5, 40), (3, 52)], &track(b"cenc", 0, 0));
+        assert_eq!(sample, plaintext(100));
+    }
+
+    #[test]
+    fn cens_pattern() {
+        let sample = decrypt_vector(CENS, &IV_16, &[(4, 96), (4, 32)], &track(b"cens", 1, 2));
+        assert_eq!(sample, plaintext(136));
+    }
+
+    #[test]
+    fn cbc1_subsamples() {
+        let sample = decrypt_vector(CBC1, &IV_16, &[(4, 32), (6, 40)], &track(b"cbc1", 0, 0));
+        assert_eq!(sample, plaintext(82));
+    }
+
+    #[test]
+    fn cbcs_pattern() {
+        let track = Track {
+            constant_iv: Some(CONSTANT_IV.to_vec()),
+            ..track(b"cbcs", 1, 9)
+        };
+        let sample = decrypt_vector(CBCS, &CONSTANT_IV, &[(8, 180), (4, 40)], &track);
+        assert_eq!(sample, plaintext(232));
+    }
+
+    #[test]
+    fn pattern_blocks_ranges() {
+        assert_eq!(
+            pattern_blocks(100, 1, 2, true),
+            vec![0..16, 48..64, 96..100]
+        );
+        assert_eq!(pattern_blocks(100, 1, 2, false), vec![0..16, 48..64]);
+        assert_eq!(pattern_blocks(180, 1, 9, false), vec![0..16, 160..176]);
+    }
+
+    #[test]
+    fn decrypt_fragment() {
+        let mut tenc = vec![0, 0, 1, 8];
+        tenc.extend_from_slice(&KID);
+        let mut schm = b"cenc".to_vec();
+        schm.extend_from_slice(&0x00010000_u32.to_be_bytes());
+        let sinf = mp4_box(
+            b"sinf",
+            &[
+                mp4_box(b"frma", b"avc1"),
+                full_box(b"schm", 0, 0, &schm),
+                mp4_box(b"schi", &full_box(b"tenc", 0, 0, &tenc)),
+            ]
+            .concat(),
+        );
+        let encv = mp4_box(b"encv", &[vec![0; 78], sinf].concat());
+        let stsd = full_box(
+            b"stsd",
+            0,
+            0,
+            &[1_u32.to_be_bytes().to_vec(), encv].concat(),
+        );
+        let mdia = mp4_box(b"mdia", &mp4_box(b"minf", &mp4_box(b"stbl", &stsd)));
+        let tkhd = full_box(
+            b"tkhd",
+            0,
+            0,
+            &[[0; 8].to_vec(), 1_u32.to_be_bytes().to_vec()].concat(),
+        );
+        let moov = mp4_box(b"moov", &mp4_box(b"trak", &[tkhd, mdia].concat()));
+
+        let mut senc = 1_u32.to_be_bytes().to_vec();
+        senc.extend_from_slice(&IV_8);
+        senc.extend_from_slice(&2_u16.to_be_bytes());
+
+        for (clear, protected) in [(5_u16, 40_u32), (3, 52)] {
+            senc.extend_from_slice(&clear.to_be_bytes());
+            senc.extend_from_slice(&protected.to_be_bytes());
+        }
+
+        let moof = |data_offset: i32| {
+            let mut trun = 1_u32.to_be_bytes().to_vec();
+            trun.extend_from_slice(&data_offset.to_be_bytes());
+            trun.extend_from_slice(&100_u32.to_be_bytes());
+            let traf = [
+                full_box(b"tfhd", 0, 0x020000, &1_u32.to_be_bytes()),
+                full_box(b"trun", 0, 0x000201, &trun),
+                full_box(b"senc", 0, 0x000002, &senc),
+            ]
+            .concat();
+            mp4_box(b"moof", &mp4_box(b"traf", &traf))
+        };
+        let moof = moof(moof(0).len() as i32 + 8);
+        let mdat = mp4_box(b"mdat", &unhex(CENC));
+
+        let data = [moov.clone(), moof.clone(), mdat].concat();
+        let keys = HashMap::from([(KID, KEY)]);
+        let decrypted = decrypt(&data, &keys, None).unwrap();
+        let contains = |name: &[u8]| decrypted.windows(4).any(|x| x == name);
+
+        assert_eq!(decrypted[(moov.len() + moof.len() + 8)..], plaintext(100));
+        assert!(contains(b"avc1") && !contains(b"encv"));
+        assert!(!contains(b"sinf") && !contains(b"senc"));
+
+        // Fragments can be decrypted separately from initialization segment.
+        let fragment = [moof, mp4_box(b"mdat", &unhex(CENC))].concat();
+        let decrypted = decrypt(&fragment, &keys, Some(&moov)).unwrap();
+        assert_eq!(decrypted[(fragment.len() - 100)..], plaintext(100));
+
+        assert!(decrypt(&data, &HashMap::new(), None).is_err());
+    }
+}
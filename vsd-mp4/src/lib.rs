@@ -2,19 +2,24 @@
 
 //! This crate contains a mp4 parser ported from [shaka-player](https://github.com/shaka-project/shaka-player) project.
 //! Also, some optional features are added for parsing subtitles and `PSSH` boxes.
+//! Segment index (`SIDX`) boxes can be parsed using [`Sidx`].
 //!
 //! # Optional Features
 //!
 //! The following are a list of [Cargo features](https://doc.rust-lang.org/stable/cargo/reference/features.html#the-features-section) that can be
 //! enabled or disabled:
 //!
+//! - **decrypt**: Enables support for decrypting `cenc`, `cens`, `cbc1` and `cbcs` encrypted mp4 streams.
 //! - **pssh**: Enables support for parsing `PSSH` boxes.
 //! - **text-ttml**: Enables support for extracting ttml subtitles.
 //! - **text-vtt**: Enables support for extracting vtt subtitles.
 
+#[cfg(feature = "decrypt")]
+mod decrypt;
 mod error;
 mod parser;
 mod reader;
+mod sidx;
 
 #[cfg(feature = "pssh")]
 #[cfg_attr(docsrs, doc(cfg(feature = "pssh")))]
@@ -24,9 +29,13 @@ pub mod pssh;
 #[cfg_attr(docsrs, doc(cfg(any(feature = "text-ttml", feature = "text-vtt"))))]
 pub mod text;
 
+#[cfg(feature = "decrypt")]
+#[cfg_attr(docsrs, doc(cfg(feature = "decrypt")))]
+pub use decrypt::decrypt;
 pub use error::Error;
 pub use parser::*;
 pub use reader::Reader;
+pub use sidx::{Sidx, SidxReference};
 
 /// A `Result` alias where the `Err` case is `vsd_mp4::Error`.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -67,6 +67,8 @@ impl std::fmt::Display for KeyIdSystemType {
 
 /// Parse `PSSH` box from mp4 files.
 pub struct Pssh {
+    /// Raw `pssh` boxes (including box header).
+    pub boxes: Vec<Vec<u8>>,
     pub key_ids: Vec<KeyId>,
     /// In hex.
     pub system_ids: Vec<String>,
@@ -75,6 +77,7 @@ pub struct Pssh {
 impl Pssh {
     pub fn new(data: &[u8]) -> Result<Self> {
         let pssh = Arc::new(Mutex::new(Self {
+            boxes: vec![],
             system_ids: vec![],
             key_ids: vec![],
         }));
@@ -99,6 +102,7 @@ impl Pssh {
         }
 
         Ok(Self {
+            boxes: pssh.boxes.clone(),
             key_ids,
             system_ids: pssh.system_ids.clone(),
         })
@@ -133,11 +137,18 @@ impl Pssh {
         // );
         // self.data = view(_box.reader.clone(), - 12, _box.size as i64);
 
-        let system_id = hex::encode(
-            _box.reader
-                .read_bytes_u8(16)
-                .map_err(|_| Error::new_read_err("PSSH box system id (16 bytes)"))?,
-        );
+        let system_id = _box
+            .reader
+            .read_bytes_u8(16)
+            .map_err(|_| Error::new_read_err("PSSH box system id (16 bytes)"))?;
+
+        // Box is rebuilt from its fields, as reader only has a view of payload.
+        let mut data = vec![0, 0, 0, 0];
+        data.extend_from_slice(b"pssh");
+        data.extend_from_slice(&((_box_version << 24) | _box.flags.unwrap()).to_be_bytes());
+        data.extend_from_slice(&system_id);
+
+        let system_id = hex::encode(system_id);
 
         if _box_version > 0 {
             let num_key_ids = _box
@@ -145,12 +156,15 @@ impl Pssh {
                 .read_u32()
                 .map_err(|_| Error::new_read_err("PSSH box number of key ids (u32)"))?;
 
+            data.extend_from_slice(&num_key_ids.to_be_bytes());
+
             for _ in 0..num_key_ids {
-                let key_id = hex::encode(
-                    _box.reader
-                        .read_bytes_u8(16)
-                        .map_err(|_| Error::new_read_err("PSSH box key id (16 bytes)"))?,
-                );
+                let key_id = _box
+                    .reader
+                    .read_bytes_u8(16)
+                    .map_err(|_| Error::new_read_err("PSSH box key id (16 bytes)"))?;
+                data.extend_from_slice(&key_id);
+                let key_id = hex::encode(key_id);
                 self.key_ids.push(KeyId {
                     value: key_id,
                     system_type: if system_id == COMMAN_SYSTEM_ID {
@@ -173,6 +187,12 @@ impl Pssh {
                 Error::new_read_err(format!("PSSH box data ({} bytes)", pssh_data_size))
             })?;
 
+        data.extend_from_slice(&pssh_data_size.to_be_bytes());
+        data.extend_from_slice(&pssh_data);
+        let size = data.len() as u32;
+        data[..4].copy_from_slice(&size.to_be_bytes());
+        self.boxes.push(data);
+
         match system_id.as_str() {
             PLAYREADY_SYSTEM_ID => self.key_ids.extend(playready::parse(&pssh_data)?),
             WIDEVINE_SYSTEM_ID => self.key_ids.extend(widevine::parse(&pssh_data)?),
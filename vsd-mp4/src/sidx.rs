@@ -0,0 +1,168 @@
+/*
+    REFERENCES
+    ----------
+
+    1. https://github.com/shaka-project/shaka-player/blob/d465942c4393e6c891d6a230bea90a44d90cc70b/lib/dash/mp4_segment_index_parser.js
+
+*/
+
+use crate::{
+    parser::{Mp4Parser, ParsedBox},
+    Error, Result,
+};
+use std::sync::{Arc, Mutex};
+
+/// Reference to a media subsegment parsed from `sidx` box.
+#[derive(Clone, Debug)]
+pub struct SidxReference {
+    /// Presentation start time of subsegment (in seconds).
+    pub start_time: f64,
+    /// Presentation end time of subsegment (in seconds).
+    pub end_time: f64,
+    /// Offset of first byte of subsegment inside media file.
+    pub start_byte: u64,
+    /// Offset of last byte of subsegment inside media file (inclusive).
+    pub end_byte: u64,
+}
+
+impl SidxReference {
+    /// Duration of subsegment (in seconds).
+    pub fn duration(&self) -> f64 {
+        self.end_time - self.start_time
+    }
+}
+
+/// Parse `SIDX` box from mp4 files.
+pub struct Sidx {
+    pub references: Vec<SidxReference>,
+    pub timescale: u32,
+}
+
+impl Sidx {
+    /// Parse segment index from `data` which should contain the `sidx` box.
+    ///
+    /// # Arguments
+    ///
+    /// - `sidx_offset` - The byte offset of `data` inside media file.
+    pub fn new(data: &[u8], sidx_offset: u64) -> Result<Self> {
+        let sidx = Arc::new(Mutex::new(None));
+        let sidx_c = sidx.clone();
+
+        Mp4Parser::default()
+            .full_box(
+                "sidx",
+                Arc::new(move |mut _box| {
+                    *sidx_c.lock().unwrap() = Some(Self::parse_sidx_box(&mut _box, sidx_offset)?);
+                    Ok(())
+                }),
+            )
+            .parse(data, None, None)?;
+
+        let sidx = sidx.lock().unwrap().take();
+        sidx.ok_or_else(|| Error::new("SIDX box is not present in segment index data"))
+    }
+
+    fn parse_sidx_box(_box: &mut ParsedBox, sidx_offset: u64) -> Result<Self> {
+        if _box.version.is_none() {
+            return Err(Error::new(
+                "SIDX boxes are full boxes and must have a valid version",
+            ));
+        }
+
+        // Skip "reference_id".
+        _box.reader
+            .skip(4)
+            .map_err(|_| Error::new_read_err("SIDX box reference id (4 bytes)"))?;
+
+        let timescale = _box
+            .reader
+            .read_u32()
+            .map_err(|_| Error::new_read_err("SIDX box timescale (u32)"))?;
+
+        if timescale == 0 {
+            return Err(Error::new("SIDX box has an invalid timescale of 0"));
+        }
+
+        let (earliest_presentation_time, first_offset) = if _box.version.unwrap() == 0 {
+            (
+                _box.reader
+                    .read_u32()
+                    .map_err(|_| Error::new_read_err("SIDX box earliest presentation time (u32)"))?
+                    as u64,
+                _box.reader
+                    .read_u32()
+                    .map_err(|_| Error::new_read_err("SIDX box first offset (u32)"))?
+                    as u64,
+            )
+        } else {
+            (
+                _box.reader.read_u64().map_err(|_| {
+                    Error::new_read_err("SIDX box earliest presentation time (u64)")
+                })?,
+                _box.reader
+                    .read_u64()
+                    .map_err(|_| Error::new_read_err("SIDX box first offset (u64)"))?,
+            )
+        };
+
+        // Skip "reserved".
+        _box.reader
+            .skip(2)
+            .map_err(|_| Error::new_read_err("SIDX box reserved data (2 bytes)"))?;
+
+        let reference_count = _box
+            .reader
+            .read_u16()
+            .map_err(|_| Error::new_read_err("SIDX box reference count (u16)"))?;
+
+        // Subsegments start right after this box, shifted by "first_offset".
+        let mut unscaled_start_time = earliest_presentation_time;
+        let mut start_byte = sidx_offset + _box.start + _box.size as u64 + first_offset;
+        let mut references = vec![];
+
+        for _ in 0..reference_count {
+            // |chunk| is 1 bit for |reference_type|, and 31 bits for |referenced_size|.
+            let chunk = _box
+                .reader
+                .read_u32()
+                .map_err(|_| Error::new_read_err("SIDX box reference (u32)"))?;
+            let reference_type = (chunk & 0x80000000) >> 31;
+            let referenced_size = (chunk & 0x7FFFFFFF) as u64;
+
+            let subsegment_duration = _box
+                .reader
+                .read_u32()
+                .map_err(|_| Error::new_read_err("SIDX box subsegment duration (u32)"))?
+                as u64;
+
+            // Skipping 1 bit for |starts_with_sap|, 3 bits for |sap_type|, and 28
+            // bits for |sap_delta_time|.
+            _box.reader
+                .skip(4)
+                .map_err(|_| Error::new_read_err("SIDX box sap data (4 bytes)"))?;
+
+            // If |reference_type| is 1 then the reference is to another SIDX.
+            // We do not support this.
+            if reference_type == 1 {
+                return Err(Error::new(
+                    "SIDX box with hierarchical references is not supported",
+                ));
+            }
+
+            references.push(SidxReference {
+                start_time: unscaled_start_time as f64 / timescale as f64,
+                end_time: (unscaled_start_time + subsegment_duration) as f64 / timescale as f64,
+                start_byte,
+                end_byte: start_byte + referenced_size - 1,
+            });
+
+            unscaled_start_time += subsegment_duration;
+            start_byte += referenced_size;
+        }
+
+        Ok(Self {
+            references,
+            timescale,
+        })
+    }
+}
@@ -11,9 +11,7 @@
 mod boxes;
 mod subtitles;
 
-use subtitles::Cue;
-
-pub use subtitles::Subtitles;
+pub use subtitles::{Cue, Subtitles};
 
 #[cfg(feature = "text-ttml")]
 #[cfg_attr(docsrs, doc(cfg(feature = "text-ttml")))]
@@ -10,13 +10,18 @@
 
 use std::fmt::Write;
 
+/// Subtitle cue.
 #[derive(Clone)]
-pub(super) struct Cue {
-    pub(super) end_time: f32,
+pub struct Cue {
+    /// End time of cue (in seconds).
+    pub end_time: f32,
     pub(super) _id: String,
-    pub(super) payload: String,
-    pub(super) settings: String,
-    pub(super) start_time: f32,
+    /// Text of cue.
+    pub payload: String,
+    /// Webvtt cue settings (eg. `align:start line:0`).
+    pub settings: String,
+    /// Start time of cue (in seconds).
+    pub start_time: f32,
 }
 
 /// Subtitles builder.
@@ -50,11 +55,63 @@ impl Subtitles {
         Self { cues: trimmed_cues }
     }
 
+    /// Parse subtitles in webvtt or subrip format.
+    /// Cues with invalid timings are left out.
+    pub fn from_text(text: &str) -> Self {
+        let text = text.replace("\r\n", "\n");
+        let mut cues = vec![];
+
+        for block in text.split("\n\n") {
+            let mut lines = block.lines().skip_while(|x| !x.contains("-->"));
+
+            if let Some((start_time, end_time)) = lines.next().and_then(|x| x.split_once("-->")) {
+                let (end_time, settings) = end_time
+                    .trim()
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((end_time.trim(), ""));
+
+                if let (Some(start_time), Some(end_time)) = (
+                    timestamp_to_seconds(start_time.trim()),
+                    timestamp_to_seconds(end_time),
+                ) {
+                    cues.push(Cue {
+                        end_time,
+                        _id: String::new(),
+                        payload: lines.collect::<Vec<_>>().join("\n"),
+                        settings: settings.trim().to_owned(),
+                        start_time,
+                    });
+                }
+            }
+        }
+
+        Self::new(cues)
+    }
+
+    /// Cues of these subtitles.
+    pub fn cues(&self) -> &[Cue] {
+        &self.cues
+    }
+
     /// Extend these subtitles with another subtitles.
     pub fn extend(&mut self, other: Self) {
         self.cues.extend(other.cues.into_iter());
     }
 
+    /// Leave out cues outside of this time range (in seconds) and shift remaining cues,
+    /// so that they start relative to start of the range.
+    /// Cues which partially overlap with the range are cut at its boundaries.
+    pub fn trim(&mut self, start: f32, end: Option<f32>) {
+        let end = end.unwrap_or(f32::INFINITY);
+        self.cues
+            .retain(|x| x.end_time > start && x.start_time < end);
+
+        for cue in &mut self.cues {
+            cue.start_time = cue.start_time.max(start) - start;
+            cue.end_time = cue.end_time.min(end) - start;
+        }
+    }
+
     /// Build subtitles in webvtt format.
     pub fn as_vtt(&self) -> String {
         let mut subtitles = "WEBVTT\n\n".to_owned();
@@ -105,3 +162,17 @@ fn seconds_to_timestamp(seconds: f32, millisecond_sep: &str) -> String {
         hours, minutes, seconds, millisecond_sep, milliseconds
     )
 }
+
+fn timestamp_to_seconds(timestamp: &str) -> Option<f32> {
+    let mut seconds = 0.0;
+
+    for part in timestamp.replace(',', ".").split(':') {
+        seconds = (seconds * 60.0) + part.parse::<f32>().ok()?;
+    }
+
+    if timestamp.split(':').count() > 3 {
+        return None;
+    }
+
+    Some(seconds)
+}
@@ -0,0 +1,113 @@
+use super::save::key_parser;
+use crate::keys::KeyStore;
+use anyhow::{anyhow, bail, Result};
+use clap::{Args, Subcommand};
+use kdam::term::Colorizer;
+use std::path::PathBuf;
+
+/// Manage local keystore of decryption keys.
+#[derive(Debug, Clone, Args)]
+pub struct Keys {
+    #[command(subcommand)]
+    command: KeysCommand,
+
+    /// Path of keystore file.
+    /// By default keys are stored in vsd/keys.json inside configuration directory of current user.
+    #[arg(long, global = true, value_name = "PATH")]
+    keystore: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum KeysCommand {
+    /// Add keys to keystore.
+    Add {
+        /// Keys in format KID:KEY. KEY value can be specified in hex, base64 or file format.
+        #[arg(required = true, value_name = "KID:KEY", value_parser = key_parser)]
+        keys: Vec<(Option<String>, String)>,
+    },
+    /// Export keystore in json format, which can also be used with --keys-file flag of save subcommand.
+    Export {
+        /// Path for exported keystore. By default keystore is written to stdout.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// List stored keys in KID:KEY format.
+    List,
+    /// Remove keys of key ids from keystore.
+    Remove {
+        /// Key ids (in hex) of keys to remove.
+        #[arg(required = true, value_name = "KID")]
+        kids: Vec<String>,
+    },
+}
+
+impl Keys {
+    pub fn execute(self) -> Result<()> {
+        let path = self
+            .keystore
+            .or_else(KeyStore::default_path)
+            .ok_or_else(|| anyhow!("could not determine keystore path, use --keystore flag."))?;
+        let mut keystore = KeyStore::open(path)?;
+
+        match self.command {
+            KeysCommand::Add { keys } => {
+                let mut added = 0;
+
+                for (kid, key) in keys {
+                    let Some(kid) = kid else {
+                        bail!("key id is required for {}, use KID:KEY format.", key);
+                    };
+
+                    if keystore.insert(&kid, &key)? {
+                        added += 1;
+                    }
+                }
+
+                keystore.save()?;
+                eprintln!(
+                    "      {} {} keys to {}",
+                    "Added".colorize("bold green"),
+                    added,
+                    keystore.path().to_string_lossy()
+                );
+            }
+            KeysCommand::Export { output } => {
+                if let Some(output) = output {
+                    std::fs::write(output, keystore.to_json()?)?;
+                } else {
+                    println!("{}", keystore.to_json()?);
+                }
+            }
+            KeysCommand::List => {
+                for (kid, key) in keystore.keys() {
+                    println!("{}:{}", kid, key);
+                }
+            }
+            KeysCommand::Remove { kids } => {
+                let mut removed = 0;
+
+                for kid in kids {
+                    if keystore.remove(&kid).is_some() {
+                        removed += 1;
+                    } else {
+                        eprintln!(
+                            "    {} {} is not in keystore.",
+                            "Warning".colorize("bold yellow"),
+                            kid
+                        );
+                    }
+                }
+
+                keystore.save()?;
+                eprintln!(
+                    "    {} {} keys from {}",
+                    "Removed".colorize("bold green"),
+                    removed,
+                    keystore.path().to_string_lossy()
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
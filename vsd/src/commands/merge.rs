@@ -10,7 +10,7 @@ use std::{
 /// Merge multiple segments to a single file.
 #[derive(Debug, Clone, Args)]
 pub struct Merge {
-    /// List of files (at least 2) to merge together e.g. *.ts, *.m4s etc. .
+    /// List of files (at least 2, except for remux) to merge together e.g. *.ts, *.m4s etc. .
     #[arg(required = true)]
     files: Vec<String>,
 
@@ -25,8 +25,13 @@ pub struct Merge {
 
 #[derive(Debug, Clone, ValueEnum)]
 pub enum MergeType {
+    /// Concatenate files as they are.
     Binary,
+    /// Concatenate files using ffmpeg's concat demuxer.
     Ffmpeg,
+    /// Concatenate mpeg-ts (h264, h265 and aac) files and remux them to a fragmented mp4 file
+    /// without ffmpeg.
+    Remux,
 }
 
 impl Merge {
@@ -39,7 +44,11 @@ impl Merge {
             }
         }
 
-        if 1 >= files.len() {
+        if let MergeType::Remux = self._type {
+            if files.is_empty() {
+                bail!("At least 1 file is required to remux.")
+            }
+        } else if 1 >= files.len() {
             bail!("At least 2 files are required to merge together.")
         }
 
@@ -104,6 +113,14 @@ impl Merge {
 
                 fs::remove_file(concat_file)?;
             }
+            MergeType::Remux => {
+                let files = files
+                    .iter()
+                    .map(|x| x.to_string_lossy().to_string())
+                    .filter(|x| *x != self.output)
+                    .collect::<Vec<_>>();
+                crate::mux::remux(&files, &self.output)?;
+            }
         }
 
         Ok(())
@@ -1,4 +1,5 @@
 mod extract;
+mod keys;
 mod merge;
 mod save;
 
@@ -6,6 +7,7 @@ mod save;
 mod capture;
 
 pub use extract::Extract;
+pub use keys::{Keys, KeysCommand};
 pub use merge::Merge;
 pub use save::{Quality, Save};
 
@@ -21,6 +23,7 @@ use clap::{ColorChoice, Parser, Subcommand};
     long_version = concat!(
         env!("CARGO_PKG_VERSION"),
         "\n\nEnabled features:",
+        "\n  bento4                  : ", cfg!(feature = "bento4"),
         "\n  browser                 : ", cfg!(feature = "browser"),
         "\n  native-tls              : ", cfg!(feature = "native-tls"),
         "\n  rustls-tls-native-roots : ", cfg!(feature = "rustls-tls-native-roots"),
@@ -42,6 +45,7 @@ pub enum Commands {
     #[cfg(feature = "browser")]
     Capture(Capture),
     Extract(Extract),
+    Keys(Keys),
     Merge(Merge),
     Save(Save),
 }
@@ -1,6 +1,11 @@
 use crate::{
     cookie::{CookieJar, CookieParam},
-    downloader::{self, Prompts},
+    downloader::{Clip, Discontinuity, Downloader, FailurePolicy, PlaylistFetcher},
+    keys::{self, CommandKeyProvider, FileKeyProvider, HttpKeyProvider, KeyProvider, KeyStore},
+    mux::{FfmpegPreset, Muxer},
+    playlist::MediaType,
+    retry::RetryPolicy,
+    selector::{StreamSelector, StreamSelectors},
     utils,
 };
 use anyhow::Result;
@@ -8,16 +13,18 @@ use clap::Args;
 use cookie::Cookie;
 use kdam::term::Colorizer;
 use reqwest::{
-    blocking::Client,
     header::{HeaderMap, HeaderName, HeaderValue},
-    Proxy, Url,
+    Client, Proxy, StatusCode, Url,
 };
 use std::{
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
+use tokio_util::sync::CancellationToken;
 
 type CookieParams = Vec<CookieParam>;
+type CommandArgs = Vec<String>;
 
 /// Download DASH and HLS playlists.
 #[derive(Debug, Clone, Args)]
@@ -37,11 +44,18 @@ pub struct Save {
     #[arg(short, long)]
     pub directory: Option<PathBuf>,
 
-    /// Mux all downloaded streams to a video container (.mp4, .mkv, etc.) using ffmpeg.
+    /// Mux all downloaded streams to a video container (.mp4, .mkv, etc.) using ffmpeg or native muxer.
     /// Note that existing files will be overwritten and downloaded streams will be deleted.
     #[arg(short, long)]
     pub output: Option<String>,
 
+    /// Program used for muxing streams with --output flag.
+    /// auto uses ffmpeg if it is installed, otherwise native muxer is used when it supports the streams.
+    /// native muxer writes fragmented mp4 (.mp4, .m4a, .m4v) or matroska (.mkv, .mka) from fragmented mp4 or
+    /// mpeg-ts (h264, h265, aac) video and audio streams along with webvtt and subrip subtitles, without requiring ffmpeg.
+    #[arg(long, value_name = "MUXER", default_value = "auto", value_parser = muxer_parser)]
+    pub muxer: Muxer,
+
     /// Parse playlist and returns it in json format.
     /// Note that `--output` flag is ignored when this flag is used.
     #[arg(long)]
@@ -71,6 +85,28 @@ pub struct Save {
     #[arg(short, long, help_heading = "Automation Options", default_value = "highest", value_name = "WIDTHxHEIGHT|HEIGHTp", value_parser = quality_parser)]
     pub quality: Quality,
 
+    /// Select audio streams without prompts using an expression, e.g. "lang=en|fr,all".
+    /// See --select-video for syntax. This option can be used multiple times.
+    #[arg(long, help_heading = "Automation Options", value_name = "EXPR", value_parser = selector_parser)]
+    pub select_audio: Vec<StreamSelector>,
+
+    /// Select subtitles streams without prompts using an expression, e.g. "lang=*" or "forced".
+    /// All matching subtitles streams are selected by default.
+    /// See --select-video for syntax. This option can be used multiple times.
+    #[arg(long, help_heading = "Automation Options", value_name = "EXPR", value_parser = selector_parser)]
+    pub select_subs: Vec<StreamSelector>,
+
+    /// Select video streams without prompts using an expression, e.g. "res>=720,codec=avc1,best".
+    /// Expression is a comma separated list of filters (KEY=VALUE, KEY!=VALUE or KEY>=VALUE etc.) which all should match,
+    /// along with an optional count (best, worst, all or a number) of matching streams to select.
+    /// Keys are res, bw, codec, lang, channels, forced and default.
+    /// Multiple values can be separated by | and * matches any value.
+    /// Streams are selected from multiple expressions when this option is used multiple times.
+    /// Streams without an expression are selected by defaults (--quality, --prefer-audio-lang and --prefer-subs-lang).
+    /// Download is aborted when no stream matches the expressions (subtitles streams only when expressions have filters).
+    #[arg(long, help_heading = "Automation Options", value_name = "EXPR", value_parser = selector_parser)]
+    pub select_video: Vec<StreamSelector>,
+
     /// Skip user input prompts and proceed with defaults.
     #[arg(long, help_heading = "Automation Options")]
     pub skip_prompts: bool,
@@ -123,30 +159,143 @@ pub struct Save {
     /// Keys for decrypting encrypted streams.
     /// If streams are encrypted with a single key then there is no need to specify key id
     /// else specify decryption key in format KID:KEY.
+    /// AES-128 (HLS) keys are used instead of fetching keys from key uris, either for all key uris
+    /// or only for key uris matching URI (substring or glob pattern) in format URI:KEY.
     /// KEY value can be specified in hex, base64 or file format.
     /// This option can be used multiple times.
-    #[arg(short, long, help_heading = "Decrypt Options", value_name = "KEY|KID:KEY", value_parser = key_parser)]
+    #[arg(short, long, help_heading = "Decrypt Options", value_name = "KEY|KID:KEY|URI:KEY", value_parser = key_parser)]
     pub key: Vec<(Option<String>, String)>,
 
+    /// Command which is run to fetch keys of * (star) prefixed key ids which weren't specified using --key flag.
+    /// Key ids and base64 encoded pssh boxes are written to its stdin as json ({"default_kids": [], "kids": [], "pssh": []})
+    /// and keys are read from its stdout, either as KID:KEY lines or as json ({"keys": [{"kid": "", "key": ""}]}).
+    #[arg(long, help_heading = "Decrypt Options", value_name = "COMMAND", allow_hyphen_values = true, value_parser = args_parser)]
+    pub key_command: Option<CommandArgs>,
+
+    /// Url of an http endpoint which is requested (POST) to fetch keys of * (star) prefixed key ids
+    /// which weren't specified using --key flag.
+    /// Request and response bodies are same as stdin and stdout of --key-command.
+    #[arg(long, help_heading = "Decrypt Options", value_name = "URL")]
+    pub key_server: Option<Url>,
+
+    /// Read keys of * (star) prefixed key ids which weren't specified using --key flag from a file,
+    /// either as KID:KEY lines or in same json format as stdout of --key-command.
+    /// Keys are looked up in keystore first, then in this file, then using --key-command and then using --key-server.
+    #[arg(long, help_heading = "Decrypt Options", value_name = "PATH")]
+    pub keys_file: Option<PathBuf>,
+
+    /// Path of keystore file in which keys of key ids are looked up automatically.
+    /// Keys specified using --key flag (with key ids) and fetched keys are added to it once streams are decrypted using them.
+    /// By default keys are stored in vsd/keys.json inside configuration directory of current user.
+    #[arg(long, help_heading = "Decrypt Options", value_name = "PATH")]
+    pub keystore: Option<PathBuf>,
+
     /// Download encrypted streams without decrypting them.
     /// Note that --output flag is ignored if this flag is used.
     #[arg(long, help_heading = "Decrypt Options")]
     pub no_decrypt: bool,
 
+    /// Don't look up or store keys in keystore.
+    #[arg(long, help_heading = "Decrypt Options")]
+    pub no_keystore: bool,
+
+    /// Action to take for discontinuities (#EXT-X-DISCONTINUITY) of hls streams, e.g. mid-roll ads.
+    /// POLICY can be concat (concatenate segments with continuous timestamps),
+    /// skip (leave out segments of ad breaks and concatenate remaining segments)
+    /// or split (download every discontinuity range to a separate file, vod streams only).
+    /// Gaps (#EXT-X-GAP) are always left out.
+    #[arg(long, help_heading = "Download Options", value_name = "POLICY", default_value = "concat", value_parser = discontinuity_parser)]
+    pub discontinuity: Discontinuity,
+
+    /// Download streams starting from this time. Streams are clipped at segment boundaries
+    /// and subtitles are trimmed to the same time range.
+    /// Duration can be specified in seconds or in HH:MM:SS format.
+    #[arg(long, help_heading = "Download Options", value_name = "DURATION", value_parser = duration_parser, conflicts_with = "segments")]
+    pub start: Option<f32>,
+
+    /// Download streams up to this time, see --start.
+    #[arg(long, help_heading = "Download Options", value_name = "DURATION", value_parser = duration_parser, conflicts_with = "segments")]
+    pub end: Option<f32>,
+
+    /// Download only this range of segments (1-based and inclusive) of first video (or audio) stream,
+    /// e.g. 1-20, 100- or 7. Other streams are clipped to the same time range.
+    #[arg(long, help_heading = "Download Options", value_name = "RANGE", value_parser = segments_parser)]
+    pub segments: Option<(usize, Option<usize>)>,
+
+    /// Stop recording live streams after this much duration is recorded.
+    /// Duration can be specified in seconds or in HH:MM:SS format.
+    /// By default live streams are recorded until they end or ctrl+c is pressed.
+    #[arg(long, help_heading = "Download Options", value_name = "DURATION", value_parser = duration_parser)]
+    pub live_duration: Option<f32>,
+
+    /// Limit download rate of segments and subtitles, e.g. 500K, 5M or 1.5G bytes per second.
+    /// Limit is shared by all streams, or only by video, audio or subtitles streams
+    /// when prefixed with TYPE, in which case it applies along with the shared limit.
+    /// This option can be used multiple times.
+    #[arg(long, help_heading = "Download Options", value_name = "[TYPE:]RATE", value_parser = rate_limit_parser)]
+    pub limit_rate: Vec<(Option<MediaType>, u64)>,
+
+    /// Action to take when a segment couldn't be downloaded, decrypted or written.
+    /// POLICY can be abort (keep downloaded segments for resuming and exit),
+    /// skip (leave out the segment) or filler (write a placeholder which players ignore).
+    /// Policy can be set for only video or audio streams by prefixing it with TYPE.
+    /// This option can be used multiple times. By default download is aborted.
+    #[arg(long, help_heading = "Download Options", value_name = "[TYPE:]POLICY", value_parser = failure_policy_parser)]
+    pub on_failure: Vec<(Option<MediaType>, FailurePolicy)>,
+
     /// Maximum number of retries to download an individual segment.
     #[arg(long, help_heading = "Download Options", default_value_t = 15)]
     pub retry_count: u8,
 
+    /// Maximum number of retries to fetch playlists, decryption keys and initialization segments.
+    #[arg(long, help_heading = "Download Options", default_value_t = 5)]
+    pub fetch_retry_count: u8,
+
+    /// Delay in seconds before first retry of a failed request.
+    /// Delay is doubled (with random jitter) on every next retry.
+    /// Delay asked by server through Retry-After header (429 and 503 responses) is respected up to --max-retry-delay.
+    #[arg(long, help_heading = "Download Options", value_name = "SECONDS", default_value_t = 0.5)]
+    pub retry_delay: f32,
+
+    /// Maximum delay in seconds between retries of a failed request.
+    #[arg(long, help_heading = "Download Options", value_name = "SECONDS", default_value_t = 30.0)]
+    pub max_retry_delay: f32,
+
+    /// Response statuses for which failed requests are never retried.
+    #[arg(long, help_heading = "Download Options", value_name = "STATUS", value_delimiter = ',', default_value = "403,404", value_parser = status_code_parser)]
+    pub no_retry_status: Vec<StatusCode>,
+
     /// Download streams without merging them.
     /// Note that --output flag is ignored if this flag is used.
     #[arg(long, help_heading = "Download Options")]
     pub no_merge: bool,
 
-    /// Maximum number of threads for parllel downloading of segments.
-    /// Number of threads should be in range 1-16 (inclusive).
-    #[arg(short, long, help_heading = "Download Options", default_value_t = 5, value_parser = clap::value_parser!(u8).range(1..=16))]
+    /// Maximum number of segments to download concurrently.
+    /// Segments are downloaded asynchronously, so this can be much higher than number of cpu cores
+    /// for high latency servers. Value should be in range 1-255 (inclusive).
+    #[arg(short, long, help_heading = "Download Options", default_value_t = 5, value_parser = clap::value_parser!(u8).range(1..))]
     pub threads: u8,
 
+    /// Path of ffmpeg binary used for muxing streams.
+    /// By default ffmpeg is searched in PATH.
+    #[arg(long, help_heading = "Mux Options", value_name = "PATH")]
+    pub ffmpeg: Option<PathBuf>,
+
+    /// Extra arguments passed to ffmpeg before output path, e.g. "-metadata title=\"Some Title\"".
+    /// These arguments can override codec arguments set by --ffmpeg-preset.
+    #[arg(long, help_heading = "Mux Options", value_name = "ARGS", allow_hyphen_values = true, value_parser = args_parser)]
+    pub ffmpeg_args: Option<CommandArgs>,
+
+    /// Codec arguments passed to ffmpeg for muxing streams.
+    /// copy copies streams without re-encoding them, h264 and h265 re-encodes video streams
+    /// using libx264 or libx265 and audio streams to aac.
+    #[arg(long, help_heading = "Mux Options", value_name = "PRESET", default_value = "copy", value_parser = ffmpeg_preset_parser)]
+    pub ffmpeg_preset: FfmpegPreset,
+
+    /// Keep downloaded streams after muxing them to output.
+    #[arg(long, help_heading = "Mux Options")]
+    pub keep_temp: bool,
+
     /// Timeout in seconds for each HTTP request during saving.
     #[arg(long, help_heading = "Client Options", default_value_t = 30)]
     pub timeout: u64,
@@ -214,34 +363,231 @@ fn quality_parser(s: &str) -> Result<Quality, String> {
     })
 }
 
-fn key_parser(s: &str) -> Result<(Option<String>, String), String> {
-    let (key_id, mut key) = if let Some((key_id, key)) = s.split_once(':') {
-        (Some(key_id.to_lowercase().replace('-', "")), key.to_owned())
+fn duration_parser(s: &str) -> Result<f32, String> {
+    let mut duration = 0.0;
+
+    for part in s.split(':') {
+        duration = (duration * 60.0)
+            + part
+                .parse::<f32>()
+                .map_err(|_| format!("could not parse {} as duration.", s))?;
+    }
+
+    if duration < 0.0 || s.split(':').count() > 3 {
+        return Err(format!("could not parse {} as duration.", s));
+    }
+
+    Ok(duration)
+}
+
+fn selector_parser(s: &str) -> Result<StreamSelector, String> {
+    s.parse::<StreamSelector>().map_err(|x| x.to_string())
+}
+
+fn segments_parser(s: &str) -> Result<(usize, Option<usize>), String> {
+    let (start, end) = s.split_once('-').unwrap_or((s, s));
+    let start = if start.trim().is_empty() {
+        Ok(1)
+    } else {
+        start.trim().parse::<usize>()
+    };
+    let end = if end.trim().is_empty() {
+        Ok(None)
     } else {
-        (None, s.to_owned())
+        end.trim().parse::<usize>().map(Some)
     };
 
-    if let Ok(decoded_key) = utils::decode_base64(&key) {
-        key = hex::encode(decoded_key);
+    match (start, end) {
+        (Ok(start), Ok(end)) if start != 0 && end.is_none_or(|x| x >= start) => Ok((start, end)),
+        _ => Err(format!("could not parse {} as range of segments.", s)),
+    }
+}
+
+pub(super) fn key_parser(s: &str) -> Result<(Option<String>, String), String> {
+    // Key files can also have colons in their path (C:\key.bin) on windows.
+    let (key_id, key) = if Path::new(s).is_file() {
+        (None, s)
+    } else if let Some((key_id, key)) = s.split_once(':').filter(|x| keys::is_key_id(x.0)) {
+        (Some(key_id.to_lowercase().replace('-', "")), key)
+    } else if let Some((uri, key)) = s.rsplit_once(':') {
+        (Some(uri.to_owned()), key)
     } else {
-        let key_file = Path::new(&key);
+        (None, s)
+    };
+
+    // Hex keys are also valid base64 strings.
+    let key = if key.len() == 32 && hex::decode(key).is_ok() {
+        key.to_lowercase()
+    } else if let Ok(decoded_key) = utils::decode_base64(key) {
+        hex::encode(decoded_key)
+    } else {
+        let key_file = Path::new(key);
 
         if key_file.exists() {
             if key_file.is_file() {
-                key = hex::encode(
+                hex::encode(
                     std::fs::read(key_file).map_err(|_| format!("could not read {}.", key))?,
                 )
             } else {
                 return Err("cannot read key from a non file path.".to_owned());
             }
         } else {
-            key = key.to_lowercase();
+            key.to_lowercase()
         }
-    }
+    };
 
     Ok((key_id, key))
 }
 
+fn failure_policy_parser(s: &str) -> Result<(Option<MediaType>, FailurePolicy), String> {
+    let (media_type, policy) = if let Some((media_type, policy)) = s.split_once(':') {
+        (
+            Some(match media_type.to_lowercase().as_str() {
+                "audio" => MediaType::Audio,
+                "video" => MediaType::Video,
+                _ => {
+                    return Err(format!(
+                        "{} is not a valid stream type (audio, video).",
+                        media_type
+                    ))
+                }
+            }),
+            policy,
+        )
+    } else {
+        (None, s)
+    };
+
+    let policy = match policy.to_lowercase().as_str() {
+        "abort" => FailurePolicy::Abort,
+        "skip" => FailurePolicy::Skip,
+        "filler" => FailurePolicy::Filler,
+        _ => {
+            return Err(format!(
+                "{} is not a valid failure policy (abort, skip, filler).",
+                policy
+            ))
+        }
+    };
+
+    Ok((media_type, policy))
+}
+
+fn rate_limit_parser(s: &str) -> Result<(Option<MediaType>, u64), String> {
+    let (media_type, rate) = if let Some((media_type, rate)) = s.split_once(':') {
+        (
+            Some(match media_type.to_lowercase().as_str() {
+                "audio" => MediaType::Audio,
+                "subtitles" => MediaType::Subtitles,
+                "video" => MediaType::Video,
+                _ => {
+                    return Err(format!(
+                        "{} is not a valid stream type (audio, subtitles, video).",
+                        media_type
+                    ))
+                }
+            }),
+            rate,
+        )
+    } else {
+        (None, s)
+    };
+
+    let rate = rate.trim();
+    let (value, multiplier) = match rate.chars().last().map(|x| x.to_ascii_uppercase()) {
+        Some('K') => (&rate[..rate.len() - 1], 1024.0),
+        Some('M') => (&rate[..rate.len() - 1], 1024.0 * 1024.0),
+        Some('G') => (&rate[..rate.len() - 1], 1024.0 * 1024.0 * 1024.0),
+        _ => (rate, 1.0),
+    };
+
+    match value.parse::<f64>() {
+        Ok(value) if value > 0.0 => Ok((media_type, (value * multiplier).ceil() as u64)),
+        _ => Err(format!("could not parse {} as download rate.", rate)),
+    }
+}
+
+fn discontinuity_parser(s: &str) -> Result<Discontinuity, String> {
+    match s.to_lowercase().as_str() {
+        "concat" => Ok(Discontinuity::Concat),
+        "skip" => Ok(Discontinuity::Skip),
+        "split" => Ok(Discontinuity::Split),
+        _ => Err(format!(
+            "{} is not a valid discontinuity policy (concat, skip, split).",
+            s
+        )),
+    }
+}
+
+fn muxer_parser(s: &str) -> Result<Muxer, String> {
+    match s.to_lowercase().as_str() {
+        "auto" => Ok(Muxer::Auto),
+        "ffmpeg" => Ok(Muxer::Ffmpeg),
+        "native" => Ok(Muxer::Native),
+        _ => Err(format!(
+            "{} is not a valid muxer (auto, ffmpeg, native).",
+            s
+        )),
+    }
+}
+
+fn ffmpeg_preset_parser(s: &str) -> Result<FfmpegPreset, String> {
+    match s.to_lowercase().as_str() {
+        "copy" => Ok(FfmpegPreset::Copy),
+        "h264" => Ok(FfmpegPreset::H264),
+        "h265" => Ok(FfmpegPreset::H265),
+        _ => Err(format!(
+            "{} is not a valid ffmpeg preset (copy, h264, h265).",
+            s
+        )),
+    }
+}
+
+/// Split arguments at whitespaces, except where they are quoted or escaped.
+/// Backslashes are only treated as escape characters before quotes, whitespaces and backslashes,
+/// so that windows paths can be passed as they are.
+fn args_parser(s: &str) -> Result<CommandArgs, String> {
+    let mut args = vec![];
+    let mut arg = None::<String>;
+    let mut quote = None;
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', _)
+                if chars
+                    .peek()
+                    .is_some_and(|x| matches!(x, '"' | '\'' | '\\') || x.is_whitespace()) =>
+            {
+                arg.get_or_insert_with(String::new)
+                    .push(chars.next().unwrap());
+            }
+            ('"' | '\'', None) => {
+                quote = Some(c);
+                arg.get_or_insert_with(String::new);
+            }
+            (c, Some(x)) if c == x => quote = None,
+            (c, None) if c.is_whitespace() => args.extend(arg.take()),
+            (c, _) => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if quote.is_some() {
+        return Err(format!("unclosed quote in {}.", s));
+    }
+
+    args.extend(arg);
+    Ok(args)
+}
+
+fn status_code_parser(s: &str) -> Result<StatusCode, String> {
+    s.trim()
+        .parse::<u16>()
+        .ok()
+        .and_then(|x| StatusCode::from_u16(x).ok())
+        .ok_or_else(|| format!("{} is not a valid http status code.", s))
+}
+
 fn cookie_parser(s: &str) -> Result<CookieParams, String> {
     if Path::new(s).exists() {
         Ok(serde_json::from_slice::<CookieParams>(
@@ -267,7 +613,7 @@ fn proxy_address_parser(s: &str) -> Result<Proxy, String> {
 }
 
 impl Save {
-    pub fn execute(mut self) -> Result<()> {
+    pub async fn execute(mut self) -> Result<()> {
         let mut client_builder = Client::builder()
             .danger_accept_invalid_certs(self.no_certificate_checks)
             .user_agent(self.user_agent)
@@ -309,26 +655,34 @@ impl Save {
 
         let client = client_builder.cookie_provider(Arc::new(jar)).build()?;
 
-        let prompts = Prompts {
-            skip: self.skip_prompts,
-            raw: self.raw_prompts,
+        let retry = RetryPolicy {
+            retries: self.retry_count,
+            delay: Duration::from_secs_f32(self.retry_delay.max(0.0)),
+            max_delay: Duration::from_secs_f32(self.max_retry_delay.max(0.0)),
+            no_retry: self.no_retry_status,
         };
-        let meta =
-            downloader::fetch_playlist(self.base_url.clone(), &client, &self.input, &prompts)?;
+        let fetch_retry = retry.clone().with_retries(self.fetch_retry_count);
+
+        let fetcher = PlaylistFetcher::new(client.clone())
+            .base_url(self.base_url.clone())
+            .prefer_audio_lang(self.prefer_audio_lang)
+            .prefer_subs_lang(self.prefer_subs_lang)
+            .quality(self.quality)
+            .raw_prompts(self.raw_prompts)
+            .retry_policy(fetch_retry.clone())
+            .selectors(StreamSelectors {
+                audio: self.select_audio,
+                subtitles: self.select_subs,
+                video: self.select_video,
+            })
+            .skip_prompts(self.skip_prompts);
+        let meta = fetcher.fetch(&self.input).await?;
 
         if self.parse {
-            let playlist = downloader::parse_all_streams(self.base_url.clone(), &client, &meta)?;
+            let playlist = fetcher.parse_all(&meta).await?;
             serde_json::to_writer(std::io::stdout(), &playlist)?;
         } else {
-            let mut selected_playlists = downloader::parse_selected_streams(
-                self.base_url.clone(),
-                &client,
-                &meta,
-                self.prefer_audio_lang,
-                self.prefer_subs_lang,
-                &prompts,
-                self.quality,
-            )?;
+            let mut selected_playlists = fetcher.parse_selected(&meta).await?;
 
             if !self.no_query_pass {
                 if let Some(query) = self.query.as_mut() {
@@ -362,19 +716,79 @@ impl Save {
                 });
             }
 
-            downloader::download(
-                self.all_keys,
-                self.base_url,
-                client,
-                self.directory,
-                self.key,
-                self.no_decrypt,
-                self.no_merge,
-                self.output,
-                selected_playlists,
-                self.retry_count,
-                self.threads,
-            )?;
+            // First ctrl+c cancels the download, second one exits immediately.
+            let cancel = CancellationToken::new();
+            let cancel_handler = cancel.clone();
+
+            tokio::spawn(async move {
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    cancel_handler.cancel();
+
+                    if tokio::signal::ctrl_c().await.is_ok() {
+                        std::process::exit(1);
+                    }
+                }
+            });
+
+            let mut key_providers: Vec<Arc<dyn KeyProvider>> = vec![];
+
+            if let Some(path) = self.keys_file {
+                key_providers.push(Arc::new(FileKeyProvider::new(path)));
+            }
+
+            if let Some(mut args) = self.key_command.filter(|x| !x.is_empty()) {
+                let program = args.remove(0);
+                key_providers.push(Arc::new(CommandKeyProvider::new(program, args)));
+            }
+
+            if let Some(url) = self.key_server {
+                // Key servers usually run locally, so proxy and cookies of client aren't used.
+                key_providers.push(Arc::new(HttpKeyProvider::new(Client::new(), url)));
+            }
+
+            let keystore = if self.no_keystore {
+                None
+            } else {
+                self.keystore
+                    .or_else(KeyStore::default_path)
+                    .map(KeyStore::open)
+                    .transpose()?
+            };
+
+            Downloader::new(client)
+                .all_keys(self.all_keys)
+                .base_url(self.base_url)
+                .cancellation_token(cancel)
+                .clip(if let Some((start, end)) = self.segments {
+                    Clip::Segments(start, end)
+                } else if self.start.is_some() || self.end.is_some() {
+                    Clip::Time(self.start.unwrap_or_default(), self.end)
+                } else {
+                    Clip::Full
+                })
+                .directory(self.directory)
+                .discontinuity(self.discontinuity)
+                .failure_policies(self.on_failure)
+                .fetch_retry_policy(fetch_retry)
+                .ffmpeg_args(self.ffmpeg_args.unwrap_or_default())
+                .ffmpeg_path(self.ffmpeg)
+                .ffmpeg_preset(self.ffmpeg_preset)
+                .keep_temp(self.keep_temp)
+                .key_providers(key_providers)
+                .keys(self.key)
+                .keystore(keystore)
+                .live_duration(self.live_duration)
+                .muxer(self.muxer)
+                .no_decrypt(self.no_decrypt)
+                .no_merge(self.no_merge)
+                .no_query_pass(self.no_query_pass)
+                .output(self.output)
+                .query(self.query)
+                .rate_limits(self.limit_rate)
+                .retry_policy(retry)
+                .threads(self.threads)
+                .download(selected_playlists)
+                .await?;
         }
 
         Ok(())
@@ -10,59 +10,48 @@
 */
 
 use super::{DashUrl, Template};
-use crate::playlist::{
-    Key, KeyMethod, Map, MasterPlaylist, MediaPlaylist, MediaType, PlaylistType, Range, Segment,
+use crate::{
+    playlist::{
+        Chapter, Key, KeyMethod, Map, MasterPlaylist, MediaPlaylist, MediaType, PlaylistType,
+        Range, Segment,
+    },
+    retry::RetryPolicy,
 };
 use anyhow::{anyhow, bail, Result};
-use dash_mpd::MPD;
-use reqwest::Url;
-use std::collections::HashMap;
+use chrono::Utc;
+use dash_mpd::{AdaptationSet, Period, Representation, MPD};
+use reqwest::{header, Client, StatusCode, Url};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+use vsd_mp4::{Sidx, SidxReference};
 
 pub(crate) fn parse_as_master(mpd: &MPD, uri: &str) -> MasterPlaylist {
     let mut streams = vec![];
+    // Representations which continue a stream of an earlier period.
+    let mut stitched = HashSet::new();
 
-    if let Some(period) = mpd.periods.get(0) {
-        let period_index = 0;
-        // for (period_index, period) in mpd.periods.iter().enumerate() {
+    for (period_index, period) in mpd.periods.iter().enumerate() {
         for (adaptation_index, adaptation_set) in period.adaptations.iter().enumerate() {
             for (representation_index, representation) in
                 adaptation_set.representations.iter().enumerate()
             {
-                // https://dashif.org/codecs/introduction
-                let codecs = representation
-                    .codecs
-                    .clone()
-                    .or(adaptation_set.codecs.clone());
-
-                let mime_type = representation
-                    .mimeType
-                    .clone()
-                    .or(adaptation_set.mimeType.clone())
-                    .or(representation.contentType.clone())
-                    .or(adaptation_set.contentType.clone());
-
-                let mut media_type = if let Some(mime_type) = &mime_type {
-                    match mime_type.as_str() {
-                        "application/ttml+xml" | "application/x-sami" => MediaType::Subtitles,
-                        x if x.starts_with("audio") => MediaType::Audio,
-                        x if x.starts_with("text") => MediaType::Subtitles,
-                        x if x.starts_with("video") => MediaType::Video,
-                        _ => MediaType::Undefined,
-                    }
-                } else {
-                    MediaType::Undefined
-                };
-
-                if media_type == MediaType::Undefined {
-                    if let Some(codecs) = &codecs {
-                        media_type = match codecs.as_str() {
-                            "wvtt" | "stpp" => MediaType::Subtitles,
-                            x if x.starts_with("stpp.") => MediaType::Subtitles,
-                            _ => media_type,
-                        };
-                    }
+                if stitched.contains(&(period_index, adaptation_index, representation_index)) {
+                    continue;
                 }
 
+                stitched.extend(
+                    period_chain(mpd, period_index, adaptation_index, representation_index)
+                        .into_iter()
+                        .skip(1),
+                );
+
+                // https://dashif.org/codecs/introduction
+                let codecs = representation_codecs(adaptation_set, representation);
+                let mime_type = representation_mime_type(adaptation_set, representation);
+                let media_type = detect_media_type(&codecs, &mime_type);
+
                 // if let Some(role) = &representation.role {
                 //     if let Some(value) = &role.value {
                 //         if value == "subtitle" {
@@ -83,10 +72,13 @@ pub(crate) fn parse_as_master(mpd: &MPD, uri: &str) -> MasterPlaylist {
                             .get(0)
                             .and_then(|x| x.value.as_ref().map(|y| y.parse::<f32>().ok()))
                             .flatten()),
+                    chapters: vec![], // Cannot be comment here
                     codecs,
+                    default: has_role(adaptation_set, &["main"]),
                     extension: mime_type
                         .as_ref()
                         .and_then(|x| x.split_once('/').map(|x| x.1.to_owned())),
+                    forced: has_role(adaptation_set, &["forced-subtitle", "forced_subtitle"]),
                     frame_rate: if representation.frameRate.is_some() {
                         parse_frame_rate(&representation.frameRate)
                     } else if adaptation_set.frameRate.is_some() {
@@ -125,27 +117,66 @@ pub(crate) fn parse_as_master(mpd: &MPD, uri: &str) -> MasterPlaylist {
     }
 }
 
-pub(crate) fn push_segments(mpd: &MPD, playlist: &mut MediaPlaylist, base_url: &str) -> Result<()> {
+pub(crate) async fn push_segments(
+    client: &Client,
+    retry: &RetryPolicy,
+    mpd: &MPD,
+    playlist: &mut MediaPlaylist,
+    base_url: &str,
+) -> Result<()> {
     let location = playlist.uri.parse::<DashUrl>().map_err(|x| anyhow!(x))?;
+    let chain = period_chain(
+        mpd,
+        location.period,
+        location.adaptation_set,
+        location.representation,
+    );
+
+    if chain.is_empty() {
+        bail!("representation {} is not present in mpd.", playlist.uri);
+    }
+
+    let mut previous_encryption = None;
+    let mut previous_init = None;
 
-    for (_period_index, period) in mpd.periods.iter().enumerate() {
+    for (period_index, period) in mpd.periods.iter().enumerate() {
         for (adaptation_index, adaptation_set) in period.adaptations.iter().enumerate() {
             for (representation_index, representation) in
                 adaptation_set.representations.iter().enumerate()
             {
-                if adaptation_index == location.adaptation_set
-                    && representation_index == location.representation
-                {
+                if chain.contains(&(period_index, adaptation_index, representation_index)) {
+                    let period_start = playlist.segments.len();
+
+                    if chain.len() > 1 {
+                        playlist.chapters.push(Chapter {
+                            start: playlist.segments.iter().map(|x| x.duration).sum(),
+                            title: period
+                                .id
+                                .clone()
+                                .unwrap_or_else(|| format!("Period {}", period_index + 1)),
+                        });
+                    }
                     let mut period_duration_secs = 0.0;
 
                     if let Some(duration) = &mpd.mediaPresentationDuration {
-                        period_duration_secs = duration.as_secs_f32();
+                        period_duration_secs = duration
+                            .saturating_sub(period.start.unwrap_or_default())
+                            .as_secs_f32();
+                    }
+
+                    if let (Some(start), Some(next_start)) = (
+                        period.start,
+                        mpd.periods.get(period_index + 1).and_then(|x| x.start),
+                    ) {
+                        period_duration_secs = next_start.saturating_sub(start).as_secs_f32();
                     }
 
                     if let Some(duration) = &period.duration {
                         period_duration_secs = duration.as_secs_f32();
                     }
 
+                    let live_elapsed = live_elapsed(mpd, period);
+
                     let mut base_url = base_url.parse::<Url>().unwrap();
 
                     if let Some(mpd_baseurl) = mpd.base_url.get(0).map(|x| x.base.as_ref()) {
@@ -322,6 +353,12 @@ pub(crate) fn push_segments(mpd: &MPD, playlist: &mut MediaPlaylist, base_url: &
                                         let mut count = 0;
                                         // FIXME - Perhaps we also need to account for startTime?
                                         let end_time = period_duration_secs * timescale;
+                                        // Live edge (in timescale units), segments after it are
+                                        // not available yet.
+                                        let live_edge = live_elapsed.map(|x| {
+                                            segment_template.presentationTimeOffset.unwrap_or(0)
+                                                + scale(x, segment_template.timescale.unwrap_or(1))
+                                        });
 
                                         loop {
                                             count += 1;
@@ -334,6 +371,10 @@ pub(crate) fn push_segments(mpd: &MPD, playlist: &mut MediaPlaylist, base_url: &
                                                 if count > r {
                                                     break;
                                                 }
+                                            } else if let Some(live_edge) = live_edge {
+                                                if segment_time + (2 * s.d) > live_edge {
+                                                    break;
+                                                }
                                             } else if segment_time as f32 > end_time {
                                                 break;
                                             }
@@ -388,6 +429,39 @@ pub(crate) fn push_segments(mpd: &MPD, playlist: &mut MediaPlaylist, base_url: &
                                     total_number -= 1;
                                 }
 
+                                if let Some(elapsed) = live_elapsed {
+                                    // Only completely available segments which are still inside
+                                    // time shift buffer can be fetched from live edge. These are
+                                    // counted in timescale units, as f32 isn't precise enough
+                                    // for time elapsed since epoch.
+                                    let (segment_duration, timescale) = segment_template
+                                        .duration
+                                        .map(|x| {
+                                            let timescale = segment_template.timescale.unwrap_or(1);
+                                            ((x * 1000.0) as u64, timescale * 1000)
+                                        })
+                                        .or(period.duration.map(|x| (x.as_millis() as u64, 1000)))
+                                        .unwrap();
+                                    let segment_duration = segment_duration.max(1);
+                                    let mut available =
+                                        scale(elapsed, timescale) / segment_duration;
+
+                                    if period_duration_secs > 0.0 {
+                                        // Period has already ended.
+                                        available = available
+                                            .min((period_duration_secs / duration).ceil() as u64);
+                                    }
+
+                                    let buffered = mpd
+                                        .timeShiftBufferDepth
+                                        .map(|x| scale(x, timescale) / segment_duration)
+                                        .unwrap_or(available)
+                                        .min(available);
+
+                                    number += (available - buffered) as i64;
+                                    total_number = buffered as i64;
+                                }
+
                                 for _ in 1..=total_number {
                                     template.insert("Number", number.to_string());
 
@@ -405,21 +479,22 @@ pub(crate) fn push_segments(mpd: &MPD, playlist: &mut MediaPlaylist, base_url: &
                         // (5) SegmentBase@indexRange
                         // The SegmentBase@indexRange attribute points to a byte range in the media
                         // file that contains index information (an sidx box for MPEG files, or a
-                        // Cues entry for a DASH-WebM stream). To be fully compliant, we should
-                        // download and parse these (for example using the sidx crate) then download
-                        // the referenced content segments. In practice, it seems that the
-                        // indexRange information is mostly provided by DASH encoders to allow
-                        // clients to rewind and fast-foward a stream, and is not necessary if we
-                        // download the full content specified by BaseURL.
+                        // Cues entry for a DASH-WebM stream). The sidx box is downloaded and parsed
+                        // and then every referenced subsegment is addressed as a byte range of the
+                        // content specified by BaseURL.
                         //
                         // Our strategy: if there is a SegmentBase > Initialization > SourceURL
                         // node, download that first, respecting the byte range if it is specified.
-                        // Otherwise, download the full content specified by the BaseURL for this
-                        // segment (ignoring any indexRange attributes).
+                        // Otherwise, the initialization data is the byte range before the index.
+                        // If the index cannot be used (e.g. WebM Cues), download the full content
+                        // specified by the BaseURL for this segment.
                         //
                         // https://github.com/shaka-project/shaka-player/blob/main/lib/dash/segment_base.js
                         // https://github.com/shaka-project/shaka-player/blob/main/lib/media/mp4_segment_index_parser.js
 
+                        let index_range = parse_range(&segment_base.indexRange);
+                        let mut init_range = None;
+
                         if let Some(initialization) = &segment_base.initialization {
                             let byte_range = parse_range(&initialization.range);
 
@@ -428,14 +503,50 @@ pub(crate) fn push_segments(mpd: &MPD, playlist: &mut MediaPlaylist, base_url: &
                                     range: byte_range,
                                     uri: base_url.join(&template.resolve(source_url))?.to_string(),
                                 });
+                            } else {
+                                init_range = byte_range;
                             }
                         }
 
-                        playlist.segments.push(Segment {
-                            uri: base_url.to_string(),
-                            ..Default::default()
-                        });
-                    } else if playlist.segments.is_empty() && !representation.BaseURL.is_empty() {
+                        let references = if let Some(index_range) = &index_range {
+                            segment_index(client, retry, &base_url, index_range).await?
+                        } else {
+                            vec![]
+                        };
+
+                        if references.is_empty() {
+                            playlist.segments.push(Segment {
+                                uri: base_url.to_string(),
+                                ..Default::default()
+                            });
+                        } else {
+                            if init_map.is_none() {
+                                init_map = init_range
+                                    .or(index_range.filter(|x| x.start > 0).map(|x| Range {
+                                        start: 0,
+                                        end: x.start - 1,
+                                    }))
+                                    .map(|x| Map {
+                                        range: Some(x),
+                                        uri: base_url.to_string(),
+                                    });
+                            }
+
+                            for reference in references {
+                                playlist.segments.push(Segment {
+                                    duration: reference.duration() as f32,
+                                    range: Some(Range {
+                                        start: reference.start_byte,
+                                        end: reference.end_byte,
+                                    }),
+                                    uri: base_url.to_string(),
+                                    ..Default::default()
+                                });
+                            }
+                        }
+                    } else if playlist.segments.len() == period_start
+                        && !representation.BaseURL.is_empty()
+                    {
                         // (6) Plain BaseURL
                         playlist.segments.push(Segment {
                             duration: period_duration_secs,
@@ -444,11 +555,11 @@ pub(crate) fn push_segments(mpd: &MPD, playlist: &mut MediaPlaylist, base_url: &
                         });
                     }
 
-                    if playlist.segments.is_empty() {
+                    if playlist.segments.len() == period_start {
                         bail!("no usable addressing mode identified for representation.");
                     }
 
-                    if let Some(first_segment) = playlist.segments.get_mut(0) {
+                    if let Some(first_segment) = playlist.segments.get_mut(period_start) {
                         let mut encryption_type = KeyMethod::None;
                         let mut default_kid = None;
 
@@ -482,131 +593,256 @@ pub(crate) fn push_segments(mpd: &MPD, playlist: &mut MediaPlaylist, base_url: &
 
                         default_kid = default_kid.map(|x| x.to_lowercase());
 
-                        first_segment.key = match encryption_type {
-                            KeyMethod::None => None,
-                            x => Some(Key {
-                                default_kid,
-                                iv: None,
-                                key_format: None,
-                                method: x,
-                                uri: None,
-                            }),
-                        };
+                        // Segments of following periods only need a key or map when it is
+                        // different from the one used by previous period.
+                        let encryption = Some((encryption_type.clone(), default_kid.clone()));
+
+                        if period_start == 0 || previous_encryption != encryption {
+                            first_segment.key = match encryption_type {
+                                KeyMethod::None if period_start == 0 => None,
+                                x => Some(Key {
+                                    default_kid,
+                                    iv: None,
+                                    key_format: None,
+                                    method: x,
+                                    uri: None,
+                                }),
+                            };
+                        }
+
+                        let init = init_map
+                            .as_ref()
+                            .map(|x| (x.uri.clone(), x.range.as_ref().map(|y| (y.start, y.end))));
+
+                        if period_start == 0 || previous_init != init {
+                            first_segment.map = init_map;
+                        }
 
-                        first_segment.map = init_map;
+                        previous_encryption = encryption;
+                        previous_init = init;
                     }
                 }
             }
         }
     }
 
-    // if let Some(segment_template) = representation.segment_template(adaptation_set) {
-    //     let mut template_resolver = TemplateResolver::new(representation.template_vars());
-
-    //     if let Some(initialization) = &segment_template.initialization {
-    //         init_map = Some(playlist::Map {
-    //             byte_range: None,
-    //             uri: template_resolver.resolve(baseurl.join(initialization)?.as_str()),
-    //         });
-
-    //         let mut start_number = segment_template.start_number();
-    //         let timescale = segment_template.timescale();
-
-    //         if let Some(segment_timeline) = &segment_template.segment_timeline {
-    //             let mut current_time = 0;
-
-    //             for s in &segment_timeline.s {
-    //                 if let Some(t) = &s.t {
-    //                     current_time = *t;
-    //                 }
-
-    //                 template_resolver.insert("Time", current_time.to_string());
-    //                 template_resolver.insert("Number", start_number.to_string());
-
-    //                 playlist.segments.push(playlist::Segment {
-    //                     duration: s.d as f32 / timescale,
-    //                     uri: template_resolver.resolve(
-    //                         baseurl
-    //                             .join(segment_template.media.as_ref().unwrap())?
-    //                             .as_str(),
-    //                     ),
-    //                     ..Default::default()
-    //                 });
-
-    //                 start_number += 1;
-
-    //                 let mut repeat_count = s.r.unwrap_or(0);
-
-    //                 if repeat_count < 0 {
-    //                     repeat_count = ((mpd_duration * timescale / s.d as f32) - 1.0) as i64;
-    //                 }
-
-    //                 for _ in 0..repeat_count {
-    //                     current_time += s.d;
-
-    //                     template_resolver.insert("Time", current_time.to_string());
-    //                     template_resolver.insert("Number", start_number.to_string());
-
-    //                     playlist.segments.push(playlist::Segment {
-    //                         duration: s.d as f32 / timescale,
-    //                         uri: template_resolver.resolve(
-    //                             baseurl
-    //                                 .join(segment_template.media.as_ref().unwrap())?
-    //                                 .as_str(),
-    //                         ),
-    //                         ..Default::default()
-    //                     });
-
-    //                     start_number += 1;
-    //                 }
-
-    //                 current_time += s.d;
-    //             }
-    //         } else {
-    //             let duration = segment_template.duration();
-    //             let segment_duration = duration / timescale;
-    //             let mut total = (mpd_duration * timescale / duration).ceil() as usize;
-
-    //             if total == 0 && mpd.live() {
-    //                 let now = if let Some(publish_time) = &mpd.publish_time {
-    //                     chrono::DateTime::parse_from_rfc3339(publish_time).unwrap()
-    //                 } else {
-    //                     chrono::Local::now().into()
-    //                 };
-
-    //                 let available_time = chrono::DateTime::parse_from_rfc3339(
-    //                     mpd.availability_start_time.as_ref().unwrap(),
-    //                 )
-    //                 .unwrap();
-    //                 let ts = now - available_time;
-    //                 let update_ts =
-    //                     iso8601_duration_to_seconds(mpd.time_shift_buffer_depth.as_ref().unwrap())
-    //                         .unwrap();
-    //                 start_number +=
-    //                     ((ts.num_seconds() as f32 - update_ts) * timescale / duration) as usize;
-    //                 total = (update_ts * timescale / duration) as usize;
-    //             }
-
-    //             for i in start_number..(start_number + total) {
-    //                 template_resolver.insert("Number", i.to_string());
-
-    //                 playlist.segments.push(playlist::Segment {
-    //                     duration: segment_duration,
-    //                     uri: template_resolver.resolve(
-    //                         baseurl
-    //                             .join(segment_template.media.as_ref().unwrap())?
-    //                             .as_str(),
-    //                     ),
-    //                     ..Default::default()
-    //                 });
-    //             }
-    //         }
-    //     }
-    // }
-
     Ok(())
 }
 
+/// Fetch and parse the segment index (sidx box) of a media file.
+async fn segment_index(
+    client: &Client,
+    retry: &RetryPolicy,
+    url: &Url,
+    range: &Range,
+) -> Result<Vec<SidxReference>> {
+    let request = client
+        .get(url.clone())
+        .header(header::RANGE, range.as_header_value());
+    let mut response = retry.send(request, |_| ()).await?;
+
+    // Server can ignore range header and respond with the whole file,
+    // which is then only read till the end of range.
+    let offset = if response.status() == StatusCode::PARTIAL_CONTENT {
+        response
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.strip_prefix("bytes "))
+            .and_then(|x| x.split_once('-'))
+            .and_then(|(x, _)| x.trim().parse::<u64>().ok())
+            .unwrap_or(range.start)
+    } else {
+        0
+    };
+
+    let mut bytes = vec![];
+
+    while let Some(chunk) = response.chunk().await? {
+        bytes.extend_from_slice(&chunk);
+
+        if offset + bytes.len() as u64 > range.end {
+            bytes.truncate((range.end + 1).saturating_sub(offset) as usize);
+            break;
+        }
+    }
+
+    let sidx = Sidx::new(&bytes, offset)
+        .map_err(|x| anyhow!("couldn't parse segment index of {} ({}).", url, x))?;
+    Ok(sidx.references)
+}
+
+/// Locate representations in following periods which continue the given representation, so that
+/// segments of a multi-period presentation can be stitched into one continuous stream.
+/// Stitching stops at the first period without any matching representation.
+fn period_chain(
+    mpd: &MPD,
+    period: usize,
+    adaptation_set: usize,
+    representation: usize,
+) -> Vec<(usize, usize, usize)> {
+    let mut chain = vec![];
+    let mut current = mpd
+        .periods
+        .get(period)
+        .and_then(|x| x.adaptations.get(adaptation_set))
+        .and_then(|x| x.representations.get(representation).map(|y| (x, y)));
+
+    if current.is_some() {
+        chain.push((period, adaptation_set, representation));
+    }
+
+    for (period_index, next_period) in mpd.periods.iter().enumerate().skip(period + 1) {
+        if let Some((adaptation_set, representation)) = current {
+            if let Some((adaptation_index, representation_index)) =
+                matching_representation(next_period, adaptation_set, representation)
+            {
+                let next_adaptation_set = &next_period.adaptations[adaptation_index];
+                chain.push((period_index, adaptation_index, representation_index));
+                current = Some((
+                    next_adaptation_set,
+                    &next_adaptation_set.representations[representation_index],
+                ));
+                continue;
+            }
+        }
+
+        break;
+    }
+
+    chain
+}
+
+/// Find a representation in period which continues the given representation of an earlier period.
+/// A representation with same @id is preferred, otherwise the one with same media type, codec
+/// family and language having the closest bandwidth is used.
+fn matching_representation(
+    period: &Period,
+    adaptation_set: &AdaptationSet,
+    representation: &Representation,
+) -> Option<(usize, usize)> {
+    let codecs = representation_codecs(adaptation_set, representation);
+    let media_type = detect_media_type(
+        &codecs,
+        &representation_mime_type(adaptation_set, representation),
+    );
+    let codec_family = |x: &Option<String>| {
+        x.as_ref()
+            .and_then(|y| y.split('.').next().map(|z| z.to_owned()))
+    };
+    let mut closest = None;
+    let mut closest_difference = u64::MAX;
+
+    for (adaptation_index, next_adaptation_set) in period.adaptations.iter().enumerate() {
+        for (representation_index, next_representation) in
+            next_adaptation_set.representations.iter().enumerate()
+        {
+            let next_codecs = representation_codecs(next_adaptation_set, next_representation);
+            let next_media_type = detect_media_type(
+                &next_codecs,
+                &representation_mime_type(next_adaptation_set, next_representation),
+            );
+
+            if next_media_type != media_type {
+                continue;
+            }
+
+            if representation.id.is_some() && next_representation.id == representation.id {
+                return Some((adaptation_index, representation_index));
+            }
+
+            if codec_family(&next_codecs) != codec_family(&codecs)
+                || next_adaptation_set.lang != adaptation_set.lang
+            {
+                continue;
+            }
+
+            let difference = next_representation
+                .bandwidth
+                .unwrap_or(0)
+                .abs_diff(representation.bandwidth.unwrap_or(0));
+
+            if difference < closest_difference {
+                closest = Some((adaptation_index, representation_index));
+                closest_difference = difference;
+            }
+        }
+    }
+
+    closest
+}
+
+fn representation_codecs(
+    adaptation_set: &AdaptationSet,
+    representation: &Representation,
+) -> Option<String> {
+    representation
+        .codecs
+        .clone()
+        .or(adaptation_set.codecs.clone())
+}
+
+fn representation_mime_type(
+    adaptation_set: &AdaptationSet,
+    representation: &Representation,
+) -> Option<String> {
+    representation
+        .mimeType
+        .clone()
+        .or(adaptation_set.mimeType.clone())
+        .or(representation.contentType.clone())
+        .or(adaptation_set.contentType.clone())
+}
+
+fn detect_media_type(codecs: &Option<String>, mime_type: &Option<String>) -> MediaType {
+    let media_type = if let Some(mime_type) = mime_type {
+        match mime_type.as_str() {
+            "application/ttml+xml" | "application/x-sami" => MediaType::Subtitles,
+            x if x.starts_with("audio") => MediaType::Audio,
+            x if x.starts_with("text") => MediaType::Subtitles,
+            x if x.starts_with("video") => MediaType::Video,
+            _ => MediaType::Undefined,
+        }
+    } else {
+        MediaType::Undefined
+    };
+
+    if media_type == MediaType::Undefined {
+        if let Some(codecs) = codecs {
+            return match codecs.as_str() {
+                "wvtt" | "stpp" => MediaType::Subtitles,
+                x if x.starts_with("stpp.") => MediaType::Subtitles,
+                _ => media_type,
+            };
+        }
+    }
+
+    media_type
+}
+
+/// Check whether adaptation set has one of the roles (urn:mpeg:dash:role:2011 scheme).
+fn has_role(adaptation_set: &AdaptationSet, values: &[&str]) -> bool {
+    adaptation_set
+        .Role
+        .iter()
+        .any(|x| x.value.as_deref().is_some_and(|y| values.contains(&y)))
+}
+
+/// Time elapsed since start of period in a live (dynamic) presentation.
+fn live_elapsed(mpd: &MPD, period: &Period) -> Option<Duration> {
+    if mpd.mpdtype.as_deref() != Some("dynamic") {
+        return None;
+    }
+
+    let elapsed = (Utc::now() - mpd.availabilityStartTime?).to_std().ok()?;
+    elapsed.checked_sub(period.start.unwrap_or_default())
+}
+
+/// Convert duration to timescale units.
+fn scale(duration: Duration, timescale: u64) -> u64 {
+    (duration.as_millis() * timescale as u128 / 1000) as u64
+}
+
 fn parse_frame_rate(frame_rate: &Option<String>) -> Option<f32> {
     frame_rate.as_ref().and_then(|frame_rate| {
         if frame_rate.contains('/') {
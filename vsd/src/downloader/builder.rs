@@ -0,0 +1,400 @@
+use super::{Clip, Discontinuity, FailurePolicy, InputMetadata, SelectedPlaylists};
+use crate::{
+    commands::Quality,
+    keys::{KeyProvider, KeyStore},
+    mux::{FfmpegPreset, Muxer},
+    playlist::{MasterPlaylist, MediaType},
+    progress::{ProgressCallback, ProgressEvent},
+    retry::RetryPolicy,
+    selector::StreamSelectors,
+};
+use anyhow::Result;
+use reqwest::{Client, Url};
+use std::{path::PathBuf, sync::Arc};
+use tokio_util::sync::CancellationToken;
+
+/// Builder for downloading selected streams of a playlist.
+pub struct Downloader {
+    pub(super) all_keys: bool,
+    pub(super) base_url: Option<Url>,
+    pub(super) cancel: CancellationToken,
+    pub(super) client: Client,
+    pub(super) clip: Clip,
+    pub(super) directory: Option<PathBuf>,
+    pub(super) discontinuity: Discontinuity,
+    pub(super) failure_policies: Vec<(Option<MediaType>, FailurePolicy)>,
+    pub(super) fetch_retry: RetryPolicy,
+    pub(super) ffmpeg_args: Vec<String>,
+    pub(super) ffmpeg_path: Option<PathBuf>,
+    pub(super) ffmpeg_preset: FfmpegPreset,
+    pub(super) keep_temp: bool,
+    pub(super) key_providers: Vec<Arc<dyn KeyProvider>>,
+    pub(super) keys: Vec<(Option<String>, String)>,
+    pub(super) keystore: Option<KeyStore>,
+    pub(super) live_duration: Option<f32>,
+    pub(super) muxer: Muxer,
+    pub(super) no_decrypt: bool,
+    pub(super) no_merge: bool,
+    pub(super) no_query_pass: bool,
+    pub(super) output: Option<String>,
+    pub(super) progress: Option<ProgressCallback>,
+    pub(super) query: Option<String>,
+    pub(super) rate_limits: Vec<(Option<MediaType>, u64)>,
+    pub(super) retry: RetryPolicy,
+    pub(super) threads: u8,
+}
+
+impl Downloader {
+    /// Create a new downloader which uses `client` for all requests.
+    pub fn new(client: Client) -> Self {
+        Self {
+            all_keys: false,
+            base_url: None,
+            cancel: CancellationToken::new(),
+            client,
+            clip: Clip::default(),
+            directory: None,
+            discontinuity: Discontinuity::default(),
+            failure_policies: vec![],
+            fetch_retry: RetryPolicy::default().with_retries(5),
+            ffmpeg_args: vec![],
+            ffmpeg_path: None,
+            ffmpeg_preset: FfmpegPreset::default(),
+            keep_temp: false,
+            key_providers: vec![],
+            keys: vec![],
+            keystore: None,
+            live_duration: None,
+            muxer: Muxer::default(),
+            no_decrypt: false,
+            no_merge: false,
+            no_query_pass: false,
+            output: None,
+            progress: None,
+            query: None,
+            rate_limits: vec![],
+            retry: RetryPolicy::default(),
+            threads: 5,
+        }
+    }
+
+    /// Use all supplied keys for decryption instead of using keys which matches with default kid only.
+    pub fn all_keys(mut self, all_keys: bool) -> Self {
+        self.all_keys = all_keys;
+        self
+    }
+
+    /// Base url to be used for building absolute url to segment.
+    pub fn base_url(mut self, base_url: Option<Url>) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    /// Token for cancelling the download. In-flight segment downloads are aborted and
+    /// already downloaded segments are kept for resuming the download later.
+    /// Live streams stop recording and are kept up to their last downloaded segment instead.
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Download only a part of video, audio and subtitles streams, either a time range or
+    /// a range of segments. Streams are clipped at segment boundaries and subtitles are
+    /// trimmed to the same time range. Live streams are not clipped.
+    pub fn clip(mut self, clip: Clip) -> Self {
+        self.clip = clip;
+        self
+    }
+
+    /// Directory path for temporarily downloaded files.
+    pub fn directory(mut self, directory: Option<PathBuf>) -> Self {
+        self.directory = directory;
+        self
+    }
+
+    /// Action to take for discontinuities of HLS streams. By default segments are concatenated
+    /// with continuous timestamps. Gaps (`#EXT-X-GAP`) are always left out.
+    pub fn discontinuity(mut self, discontinuity: Discontinuity) -> Self {
+        self.discontinuity = discontinuity;
+        self
+    }
+
+    /// Action to take when a segment of video or audio streams couldn't be downloaded,
+    /// decrypted or written. Media type can be skipped to set the policy for all streams.
+    /// By default download is aborted.
+    pub fn failure_policy(mut self, media_type: Option<MediaType>, policy: FailurePolicy) -> Self {
+        self.failure_policies.push((media_type, policy));
+        self
+    }
+
+    /// Failure policies for streams, see [`Downloader::failure_policy`].
+    pub fn failure_policies(mut self, policies: Vec<(Option<MediaType>, FailurePolicy)>) -> Self {
+        self.failure_policies = policies;
+        self
+    }
+
+    /// Retry policy for fetching initialization segments and decryption keys,
+    /// which has its own retry budget separate from segments.
+    pub fn fetch_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.fetch_retry = retry;
+        self
+    }
+
+    /// Extra arguments passed to ffmpeg before output path, which can override arguments set by
+    /// [`Downloader::ffmpeg_preset`].
+    pub fn ffmpeg_args(mut self, args: Vec<String>) -> Self {
+        self.ffmpeg_args = args;
+        self
+    }
+
+    /// Path of ffmpeg binary. By default ffmpeg is searched in PATH.
+    pub fn ffmpeg_path(mut self, path: Option<PathBuf>) -> Self {
+        self.ffmpeg_path = path;
+        self
+    }
+
+    /// Codec arguments passed to ffmpeg for muxing streams. By default streams are copied.
+    pub fn ffmpeg_preset(mut self, preset: FfmpegPreset) -> Self {
+        self.ffmpeg_preset = preset;
+        self
+    }
+
+    /// Keep downloaded streams after muxing them to output.
+    pub fn keep_temp(mut self, keep_temp: bool) -> Self {
+        self.keep_temp = keep_temp;
+        self
+    }
+
+    /// Add a key for decrypting encrypted streams.
+    /// Key id (in hex) can be skipped if streams are encrypted with a single key.
+    /// AES-128 (HLS) keys can be supplied for matching key uris (substring or glob pattern)
+    /// by using key uri pattern in place of key id.
+    pub fn key(mut self, kid: Option<String>, key: String) -> Self {
+        self.keys.push((kid, key));
+        self
+    }
+
+    /// Add a provider which is asked for keys of default key ids which weren't supplied
+    /// using [`Downloader::key`]. Providers are asked in the order they are added,
+    /// until keys of all default key ids are found.
+    pub fn key_provider<P: KeyProvider + 'static>(mut self, provider: P) -> Self {
+        self.key_providers.push(Arc::new(provider));
+        self
+    }
+
+    /// Key providers, see [`Downloader::key_provider`].
+    pub fn key_providers(mut self, providers: Vec<Arc<dyn KeyProvider>>) -> Self {
+        self.key_providers = providers;
+        self
+    }
+
+    /// Keys for decrypting encrypted streams.
+    pub fn keys(mut self, keys: Vec<(Option<String>, String)>) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    /// Keystore in which keys of key ids are looked up before asking key providers.
+    /// Supplied and fetched keys (with key ids) are added to it for later downloads, once streams
+    /// are decrypted using them.
+    pub fn keystore(mut self, keystore: Option<KeyStore>) -> Self {
+        self.keystore = keystore;
+        self
+    }
+
+    /// Stop recording live streams after this much duration (in seconds) is recorded.
+    pub fn live_duration(mut self, live_duration: Option<f32>) -> Self {
+        self.live_duration = live_duration;
+        self
+    }
+
+    /// Program used for muxing downloaded streams to output.
+    pub fn muxer(mut self, muxer: Muxer) -> Self {
+        self.muxer = muxer;
+        self
+    }
+
+    /// Download encrypted streams without decrypting them.
+    pub fn no_decrypt(mut self, no_decrypt: bool) -> Self {
+        self.no_decrypt = no_decrypt;
+        self
+    }
+
+    /// Download streams without merging them.
+    pub fn no_merge(mut self, no_merge: bool) -> Self {
+        self.no_merge = no_merge;
+        self
+    }
+
+    /// Skip passing query parameters where not needed.
+    pub fn no_query_pass(mut self, no_query_pass: bool) -> Self {
+        self.no_query_pass = no_query_pass;
+        self
+    }
+
+    /// Mux all downloaded streams to a video container (.mp4, .mkv, etc.), see [`Downloader::muxer`].
+    pub fn output(mut self, output: Option<String>) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Report progress to this callback instead of drawing a progress bar.
+    pub fn progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(ProgressEvent) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Query parameters for requests.
+    pub fn query(mut self, query: Option<String>) -> Self {
+        self.query = query;
+        self
+    }
+
+    /// Limit download rate (in bytes per second) of segments and subtitles.
+    /// Media type can be skipped to set the limit shared by all streams, otherwise the limit
+    /// is shared by streams of that media type only and applies along with the shared limit.
+    pub fn rate_limit(mut self, media_type: Option<MediaType>, rate: u64) -> Self {
+        self.rate_limits.push((media_type, rate));
+        self
+    }
+
+    /// Download rate limits of streams, see [`Downloader::rate_limit`].
+    pub fn rate_limits(mut self, rate_limits: Vec<(Option<MediaType>, u64)>) -> Self {
+        self.rate_limits = rate_limits;
+        self
+    }
+
+    /// Maximum number of retries to download an individual segment.
+    pub fn retry_count(mut self, retry_count: u8) -> Self {
+        self.retry.retries = retry_count;
+        self
+    }
+
+    /// Retry policy for downloading segments.
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Maximum number of segments which are downloaded concurrently.
+    pub fn threads(mut self, threads: u8) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Download video, audio and subtitle streams.
+    pub async fn download(self, selected_playlists: SelectedPlaylists) -> Result<()> {
+        super::download(self, selected_playlists).await
+    }
+}
+
+/// Builder for fetching a playlist and parsing its streams, which can then be downloaded
+/// using [`Downloader`].
+pub struct PlaylistFetcher {
+    pub(super) base_url: Option<Url>,
+    pub(super) client: Client,
+    pub(super) prefer_audio_lang: Option<String>,
+    pub(super) prefer_subs_lang: Option<String>,
+    pub(super) progress: Option<ProgressCallback>,
+    pub(super) quality: Quality,
+    pub(super) raw_prompts: bool,
+    pub(super) retry: RetryPolicy,
+    pub(super) selectors: StreamSelectors,
+    pub(super) skip_prompts: bool,
+}
+
+impl PlaylistFetcher {
+    /// Create a new playlist fetcher which uses `client` for all requests.
+    /// Streams are selected without prompting the user by default.
+    pub fn new(client: Client) -> Self {
+        Self {
+            base_url: None,
+            client,
+            prefer_audio_lang: None,
+            prefer_subs_lang: None,
+            progress: None,
+            quality: Quality::Highest,
+            raw_prompts: false,
+            retry: RetryPolicy::default().with_retries(5),
+            selectors: StreamSelectors::default(),
+            skip_prompts: true,
+        }
+    }
+
+    /// Base url to be used for building absolute url to streams and segments.
+    pub fn base_url(mut self, base_url: Option<Url>) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    /// Preferred language of audio streams, which are sorted first.
+    pub fn prefer_audio_lang(mut self, lang: Option<String>) -> Self {
+        self.prefer_audio_lang = lang;
+        self
+    }
+
+    /// Preferred language of subtitles streams, which are sorted first.
+    pub fn prefer_subs_lang(mut self, lang: Option<String>) -> Self {
+        self.prefer_subs_lang = lang;
+        self
+    }
+
+    /// Report messages to this callback instead of printing them. User is never prompted
+    /// for selecting streams when a callback is set.
+    pub fn progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(ProgressEvent) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Quality of video stream which is selected by default.
+    pub fn quality(mut self, quality: Quality) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    /// Raw style input prompts for old and unsupported terminals.
+    pub fn raw_prompts(mut self, raw_prompts: bool) -> Self {
+        self.raw_prompts = raw_prompts;
+        self
+    }
+
+    /// Retry policy for fetching playlists.
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Expressions for selecting streams without prompts.
+    pub fn selectors(mut self, selectors: StreamSelectors) -> Self {
+        self.selectors = selectors;
+        self
+    }
+
+    /// Skip user input prompts and proceed with default (or selected) streams.
+    pub fn skip_prompts(mut self, skip_prompts: bool) -> Self {
+        self.skip_prompts = skip_prompts;
+        self
+    }
+
+    /// Fetch playlist from a url or read it from a local file. Playlist links are scraped
+    /// when a website is fetched instead of a playlist.
+    pub async fn fetch(&self, input: &str) -> Result<InputMetadata> {
+        super::fetch::fetch_playlist(self, input).await
+    }
+
+    /// Parse all streams of a fetched playlist along with their segments.
+    pub async fn parse_all(&self, meta: &InputMetadata) -> Result<MasterPlaylist> {
+        super::parse::parse_all_streams(self, meta).await
+    }
+
+    /// Select streams of a fetched playlist and parse their segments.
+    pub async fn parse_selected(&self, meta: &InputMetadata) -> Result<SelectedPlaylists> {
+        super::parse::parse_selected_streams(self, meta).await
+    }
+}
@@ -0,0 +1,142 @@
+use crate::playlist::{MediaPlaylist, MediaType};
+use anyhow::{bail, Result};
+
+/// Segments overlapping with a time range by less than this (in seconds) are left out,
+/// as cumulative durations of segments don't add up exactly.
+const TOLERANCE: f32 = 0.01;
+
+/// Part of video, audio and subtitles streams to download.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Clip {
+    /// Download complete streams.
+    #[default]
+    Full,
+    /// Download segments in this range of segment numbers (1-based and inclusive) of the first
+    /// video (or audio) stream. Other streams are clipped to the time range of these segments.
+    Segments(usize, Option<usize>),
+    /// Download segments which overlap with this time range (start and optional end in seconds).
+    Time(f32, Option<f32>),
+}
+
+/// Time range (in seconds) of streams which is downloaded.
+pub(crate) struct Window {
+    pub(super) end: Option<f32>,
+    pub(super) start: f32,
+}
+
+impl Window {
+    /// Time range of a clip, snapped to segment boundaries of the first video (or audio) stream.
+    /// Live streams are never clipped.
+    pub(super) fn new(clip: Clip, streams: &[MediaPlaylist]) -> Result<Option<Self>> {
+        if clip == Clip::Full {
+            return Ok(None);
+        }
+
+        let streams = streams.iter().filter(|x| !x.live).collect::<Vec<_>>();
+        let stream = streams
+            .iter()
+            .find(|x| x.media_type == MediaType::Video)
+            .or(streams.first());
+        let mut time = 0.0;
+        let ranges = stream
+            .map(|x| x.segments.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(|x| {
+                time += x.duration;
+                (time - x.duration, time)
+            })
+            .collect::<Vec<_>>();
+
+        let (first, last) = match clip {
+            Clip::Full => unreachable!(),
+            Clip::Segments(start, end) => {
+                if start == 0 || end.is_some_and(|x| x < start) {
+                    bail!("segment numbers of clip should start from 1 and be in order.");
+                }
+
+                if stream.is_none() {
+                    bail!("segment numbers of clip can only be used with video or audio streams.");
+                }
+
+                if start > ranges.len() {
+                    bail!(
+                        "clip starts from segment {} but stream only has {} segments.",
+                        start,
+                        ranges.len()
+                    );
+                }
+
+                (start - 1, end.map(|x| x.min(ranges.len()) - 1))
+            }
+            Clip::Time(start, end) => {
+                if end.is_some_and(|x| x <= start) {
+                    bail!("end of clip should be greater than its start.");
+                }
+
+                if stream.is_none() {
+                    return Ok(Some(Self { end, start }));
+                }
+
+                let Some(first) = ranges.iter().position(|x| x.1 > start + TOLERANCE) else {
+                    bail!("clip starts after the end of streams.");
+                };
+                let last = end.map(|x| {
+                    ranges
+                        .iter()
+                        .rposition(|y| y.0 < x - TOLERANCE)
+                        .unwrap_or(first)
+                        .max(first)
+                });
+                (first, last)
+            }
+        };
+
+        Ok(Some(Self {
+            end: last.map(|x| ranges[x].1),
+            start: ranges[first].0,
+        }))
+    }
+
+    /// Leave out segments of stream which don't overlap with this time range.
+    /// Keys and initialization segments of left out segments are attached to first segment.
+    pub(super) fn clip(&self, stream: &mut MediaPlaylist) {
+        if stream.live {
+            return;
+        }
+
+        let segments = std::mem::take(&mut stream.segments);
+        let mut time = 0.0;
+        let mut key = None;
+        let mut map = None;
+
+        for mut segment in segments {
+            let start = time;
+            time += segment.duration;
+
+            if segment.key.is_some() {
+                key = segment.key.clone();
+            }
+
+            if segment.map.is_some() {
+                map = segment.map.clone();
+            }
+
+            // Subtitles of single file streams don't have any duration.
+            let overlaps = segment.duration == 0.0
+                || (time > self.start + TOLERANCE
+                    && self.end.is_none_or(|x| start < x - TOLERANCE));
+
+            if !overlaps {
+                continue;
+            }
+
+            if stream.segments.is_empty() {
+                segment.key = key.clone();
+                segment.map = map.clone();
+            }
+
+            stream.segments.push(segment);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use crate::playlist::{MediaPlaylist, Segment};
+
+/// Action to take for discontinuities (`#EXT-X-DISCONTINUITY`) of HLS streams,
+/// e.g. mid-roll ads or encoder restarts.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Discontinuity {
+    /// Concatenate all segments into a single file. Timestamps of segments after a discontinuity
+    /// are rewritten to continue from the end of previous segments.
+    #[default]
+    Concat,
+    /// Leave out segments of ad breaks and concatenate remaining segments like [`Discontinuity::Concat`].
+    Skip,
+    /// Download every discontinuity range of a stream to a separate file.
+    Split,
+}
+
+/// Remove segments which shouldn't be downloaded, i.e. gaps (`#EXT-X-GAP`) and segments of
+/// ad breaks when they are skipped. Keys and initialization segments of removed segments are
+/// carried forward to next segment, as they are only attached where they are first seen.
+pub(super) fn remove_segments(
+    segments: Vec<Segment>,
+    discontinuity: Discontinuity,
+) -> Vec<Segment> {
+    let mut kept = Vec::with_capacity(segments.len());
+    let mut removed: Option<Segment> = None;
+
+    for mut segment in segments {
+        if segment.gap || (discontinuity == Discontinuity::Skip && segment.ad) {
+            let removed = removed.get_or_insert_with(Segment::default);
+            removed.discontinuity |= segment.discontinuity;
+
+            if segment.key.is_some() {
+                removed.key = segment.key;
+            }
+
+            if segment.map.is_some() {
+                removed.map = segment.map;
+            }
+
+            continue;
+        }
+
+        if let Some(removed) = removed.take() {
+            // Timeline is broken wherever segments are left out.
+            segment.discontinuity |= removed.discontinuity || discontinuity != Discontinuity::Split;
+            segment.key = segment.key.or(removed.key);
+            segment.map = segment.map.or(removed.map);
+        }
+
+        kept.push(segment);
+    }
+
+    kept
+}
+
+/// Split stream at discontinuities into separate streams along with their part numbers.
+/// Part number is none when the stream doesn't have any discontinuity.
+pub(super) fn split(mut stream: MediaPlaylist) -> Vec<(Option<usize>, MediaPlaylist)> {
+    if !stream.segments.iter().skip(1).any(|x| x.discontinuity) {
+        return vec![(None, stream)];
+    }
+
+    let segments = std::mem::take(&mut stream.segments);
+    let mut parts: Vec<MediaPlaylist> = vec![];
+    let mut key = None;
+    let mut map = None;
+
+    for mut segment in segments {
+        if segment.key.is_some() {
+            key = segment.key.clone();
+        }
+
+        if segment.map.is_some() {
+            map = segment.map.clone();
+        }
+
+        if parts.is_empty() || segment.discontinuity {
+            // Every part should carry its own key and initialization segment.
+            segment.key = key.clone();
+            segment.map = map.clone();
+            parts.push(MediaPlaylist {
+                segments: vec![],
+                ..stream.clone()
+            });
+        }
+
+        parts.last_mut().unwrap().segments.push(segment);
+    }
+
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, x)| (Some(i + 1), x))
+        .collect()
+}
@@ -0,0 +1,112 @@
+use crate::playlist::MediaType;
+use std::fmt::Display;
+
+/// Action to take when a segment couldn't be downloaded, decrypted or written.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FailurePolicy {
+    /// Stop downloading, already written segments are kept for resuming.
+    #[default]
+    Abort,
+    /// Leave out the segment and continue downloading.
+    Skip,
+    /// Write a placeholder in place of the segment which players ignore
+    /// (null packets for MPEG-TS and `free` box for MP4) and continue downloading.
+    Filler,
+}
+
+impl FailurePolicy {
+    /// Policy for streams of `media_type`. Policies set for a media type takes precedence
+    /// over policies set for all streams.
+    pub(super) fn of(
+        policies: &[(Option<MediaType>, FailurePolicy)],
+        media_type: &MediaType,
+    ) -> Self {
+        policies
+            .iter()
+            .rev()
+            .find(|x| x.0.as_ref() == Some(media_type))
+            .or(policies.iter().rev().find(|x| x.0.is_none()))
+            .map(|x| x.1)
+            .unwrap_or_default()
+    }
+
+    /// Placeholder bytes for a failed segment of a stream with `extension`.
+    pub(super) fn filler(&self, extension: &str) -> Vec<u8> {
+        match self {
+            Self::Filler if extension == "ts" => {
+                let mut packet = vec![0xFF; 188];
+                packet[..4].copy_from_slice(&[0x47, 0x1F, 0xFF, 0x10]);
+                packet
+            }
+            Self::Filler if ["m4a", "m4s", "m4v", "mp4"].contains(&extension) => {
+                vec![0, 0, 0, 8, b'f', b'r', b'e', b'e']
+            }
+            _ => vec![],
+        }
+    }
+}
+
+/// Stage of segment processing where a failure occurred.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SegmentErrorKind {
+    Download,
+    Decrypt,
+    Write,
+}
+
+/// Failure of an individual segment, sent back from download tasks.
+#[derive(Debug)]
+pub struct SegmentError {
+    /// Path of temporary file (or directory) of stream.
+    pub file: String,
+    /// Index of segment in stream.
+    pub index: usize,
+    pub kind: SegmentErrorKind,
+    pub reason: String,
+}
+
+impl SegmentError {
+    pub(super) fn new<T: Display>(
+        file: &str,
+        index: usize,
+        kind: SegmentErrorKind,
+        reason: T,
+    ) -> Self {
+        Self {
+            file: file.to_owned(),
+            index,
+            kind,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl Display for SegmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "segment {} of {} couldn't be {} ({})",
+            self.index,
+            self.file,
+            match self.kind {
+                SegmentErrorKind::Download => "downloaded",
+                SegmentErrorKind::Decrypt => "decrypted",
+                SegmentErrorKind::Write => "written",
+            },
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for SegmentError {}
+
+/// Comma separated indices of failed segments.
+pub(super) fn indices(failures: &[&SegmentError]) -> String {
+    let mut indices = failures.iter().map(|x| x.index).collect::<Vec<_>>();
+    indices.sort();
+    indices
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
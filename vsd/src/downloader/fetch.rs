@@ -1,8 +1,9 @@
-use crate::{downloader::Prompts, playlist::PlaylistType};
+use super::PlaylistFetcher;
+use crate::{playlist::PlaylistType, progress::Progress, retry::RetryPolicy};
 use anyhow::{anyhow, bail, Result};
 use kdam::term::Colorizer;
 use regex::Regex;
-use reqwest::{blocking::Client, header, Url};
+use reqwest::{header, Client, Url};
 use std::{collections::HashSet, io::Write, path::Path};
 
 pub struct InputMetadata {
@@ -12,8 +13,10 @@ pub struct InputMetadata {
 }
 
 impl InputMetadata {
-    fn fetch(&mut self, client: &Client) -> Result<()> {
-        let response = client.get(self.url.as_ref()).send()?;
+    async fn fetch(&mut self, client: &Client, retry: &RetryPolicy, pb: &Progress) -> Result<()> {
+        let response = retry
+            .send(client.get(self.url.as_ref()), |x| pb.print(x))
+            .await?;
         self.url = response.url().to_owned();
 
         if let Some(content_type) = response.headers().get(header::CONTENT_TYPE) {
@@ -28,7 +31,7 @@ impl InputMetadata {
             }
         }
 
-        self.text = response.text()?;
+        self.text = response.text().await?;
         self.update_pl_type_from_text();
         Ok(())
     }
@@ -44,27 +47,27 @@ impl InputMetadata {
     }
 }
 
-pub fn fetch_playlist(
-    base_url: Option<Url>,
-    client: &Client,
+pub(super) async fn fetch_playlist(
+    fetcher: &PlaylistFetcher,
     input: &str,
-    prompts: &Prompts,
 ) -> Result<InputMetadata> {
+    let pb = Progress::new(fetcher.progress.clone());
     let mut meta = InputMetadata {
         pl_type: None,
         text: String::new(),
-        url: base_url
+        url: fetcher
+            .base_url
             .clone()
             .unwrap_or_else(|| "https://example.com".parse::<Url>().unwrap()),
     };
     let path = Path::new(input);
 
     if path.exists() {
-        if base_url.is_none() {
-            println!(
+        if fetcher.base_url.is_none() {
+            pb.print(format!(
                 "    {} base url is not set",
                 "Warning".colorize("bold yellow")
-            );
+            ));
         }
 
         if let Some(ext) = path.extension() {
@@ -81,43 +84,58 @@ pub fn fetch_playlist(
     } else {
         meta.url = input.parse::<Url>().unwrap();
         // TODO - We can add site specific parsers here
-        meta.fetch(&client)?;
+        meta.fetch(&fetcher.client, &fetcher.retry, &pb).await?;
 
         if meta.pl_type.is_none() {
-            fetch_from_website(client, &mut meta, prompts)?;
+            fetch_from_website(fetcher, &mut meta, &pb).await?;
         }
     }
 
     Ok(meta)
 }
 
-fn fetch_from_website(client: &Client, meta: &mut InputMetadata, prompts: &Prompts) -> Result<()> {
-    println!(
+async fn fetch_from_website(
+    fetcher: &PlaylistFetcher,
+    meta: &mut InputMetadata,
+    pb: &Progress,
+) -> Result<()> {
+    // Callbacks are used without any terminal, so user can't be prompted.
+    let skip_prompts = fetcher.skip_prompts || matches!(pb, Progress::Callback(_));
+    pb.print(format!(
         "   {} website for DASH and HLS playlists",
         "Scraping".colorize("bold cyan")
-    );
+    ));
 
     let links = scrape_playlist_links(&meta.text);
 
     match links.len() {
         0 => bail!("No playlists were found in website source."),
         1 => {
-            println!("      {} {}", "Found".colorize("bold green"), &links[0]);
+            pb.print(format!(
+                "      {} {}",
+                "Found".colorize("bold green"),
+                &links[0]
+            ));
             meta.url = links[0].parse::<Url>()?;
         }
         _ => {
-            if prompts.skip || prompts.raw {
-                println!("Select one playlist:");
+            if skip_prompts || fetcher.raw_prompts {
+                pb.print("Select one playlist:");
 
                 for (i, link) in links.iter().enumerate() {
-                    println!("{:2}) [{}] {}", i + 1, if i == 0 { 'x' } else { ' ' }, link);
+                    pb.print(format!(
+                        "{:2}) [{}] {}",
+                        i + 1,
+                        if i == 0 { 'x' } else { ' ' },
+                        link
+                    ));
                 }
 
-                println!("------------------------------");
+                pb.print("------------------------------");
 
                 let mut index = 0;
 
-                if prompts.raw && !prompts.skip {
+                if fetcher.raw_prompts && !skip_prompts {
                     print!(
                         "Press enter to proceed with defaults.\n\
                     Or select playlist to download (1, 2, etc.): "
@@ -142,7 +160,11 @@ fn fetch_from_website(client: &Client, meta: &mut InputMetadata, prompts: &Promp
                     .get(index)
                     .ok_or_else(|| anyhow!("selected playlist is out of index bounds."))?
                     .parse::<Url>()?;
-                println!("   {} {}", "Selected".colorize("bold green"), meta.url);
+                pb.print(format!(
+                    "   {} {}",
+                    "Selected".colorize("bold green"),
+                    meta.url
+                ));
             } else {
                 let question = requestty::Question::select("scraped-link")
                     .message("Select one playlist")
@@ -155,7 +177,7 @@ fn fetch_from_website(client: &Client, meta: &mut InputMetadata, prompts: &Promp
         }
     }
 
-    meta.fetch(client)?;
+    meta.fetch(&fetcher.client, &fetcher.retry, pb).await?;
     Ok(())
 }
 
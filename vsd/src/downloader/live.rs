@@ -0,0 +1,546 @@
+/*
+    REFERENCES
+    ----------
+
+    1. https://datatracker.ietf.org/doc/html/rfc8216#section-6.3.4
+    2. https://dashif.org/docs/DASH-IF-IOP-v4.3.pdf (4.3 Live Services)
+
+*/
+
+use super::{
+    discontinuity, prepare_segment, report_failures, segment_request, stream_file_path,
+    Discontinuity, FailurePolicy, Keys, Scheduler, SegmentTask, Stream,
+};
+use crate::{
+    limiter::RateLimiter,
+    merger::Merger,
+    playlist::{MediaPlaylist, MediaType, Range, Segment},
+    progress::Progress,
+    retry::RetryPolicy,
+};
+use anyhow::{anyhow, bail, Result};
+use kdam::term::Colorizer;
+use reqwest::{Client, Url};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+
+struct LiveStream {
+    base_url: Url,
+    ended: bool,
+    index: usize,
+    map_id: Option<String>,
+    merger: Option<Arc<Mutex<Merger>>>,
+    next_refresh: Instant,
+    previous_key: Option<Keys>,
+    previous_map: Option<Vec<u8>>,
+    recorded: f32,
+    refresh_interval: Duration,
+    seen: HashSet<String>,
+    sequence: Option<u64>,
+    stream: MediaPlaylist,
+    temp_file: String,
+    timer: Arc<Instant>,
+}
+
+impl LiveStream {
+    /// Fetch playlist again and return segments which are not seen before.
+    async fn refresh(
+        &mut self,
+        client: &Client,
+        fetch_retry: &RetryPolicy,
+        pb: &Mutex<Progress>,
+        query: Option<&str>,
+    ) -> Result<Vec<Segment>> {
+        let mut segments = if self.stream.is_hls() {
+            self.refresh_hls(client, fetch_retry, pb, query).await?
+        } else {
+            self.refresh_dash(client, fetch_retry, pb, query).await?
+        };
+
+        // Initialization segment is repeated in every refreshed playlist,
+        // it should only be used again when it changes.
+        for segment in &mut segments {
+            if let Some(map) = &segment.map {
+                let map_id = resource_id(&map.uri, &map.range);
+
+                if self.map_id.as_ref() == Some(&map_id) {
+                    segment.map = None;
+                } else {
+                    self.map_id = Some(map_id);
+                }
+            }
+        }
+
+        Ok(segments)
+    }
+
+    /// Segments of hls playlists are identified by their media sequence number.
+    async fn refresh_hls(
+        &mut self,
+        client: &Client,
+        fetch_retry: &RetryPolicy,
+        pb: &Mutex<Progress>,
+        query: Option<&str>,
+    ) -> Result<Vec<Segment>> {
+        let text = fetch_retry
+            .send(client.get(&self.stream.uri), |x| {
+                let _ = pb.lock().unwrap().write(x);
+            })
+            .await?
+            .text()
+            .await?;
+        let m3u8 = m3u8_rs::parse_media_playlist_res(text.as_bytes()).map_err(|x| {
+            anyhow!(
+                "couldn't parse response as hls playlist (failed with {}).\n\n{}\n\n{}",
+                x,
+                self.stream.uri,
+                text
+            )
+        })?;
+
+        let mut playlist = MediaPlaylist {
+            uri: self.stream.uri.clone(),
+            ..Default::default()
+        };
+        crate::hls::push_segments(&m3u8, &mut playlist);
+
+        if let Some(query) = query {
+            playlist.add_query(query);
+        }
+
+        self.ended = m3u8.end_list;
+        self.refresh_interval = Duration::from_secs(m3u8.target_duration.max(1));
+
+        if let Some(sequence) = self.sequence {
+            if m3u8.media_sequence > sequence + 1 {
+                pb.lock().unwrap().write(format!(
+                    "    {} {} segments were removed from playlist before they could be downloaded",
+                    "Warning".colorize("bold yellow"),
+                    m3u8.media_sequence - sequence - 1,
+                ))?;
+            }
+        }
+
+        let segments = playlist
+            .segments
+            .into_iter()
+            .filter(|x| self.sequence.is_none_or(|y| x.sequence > y))
+            .collect::<Vec<_>>();
+
+        if let Some(segment) = segments.last() {
+            self.sequence = Some(segment.sequence);
+        }
+
+        Ok(segments)
+    }
+
+    /// Segments of dash playlists are identified by their resolved uri and byte range,
+    /// as the representation is re-resolved from live edge on every refresh.
+    async fn refresh_dash(
+        &mut self,
+        client: &Client,
+        fetch_retry: &RetryPolicy,
+        pb: &Mutex<Progress>,
+        query: Option<&str>,
+    ) -> Result<Vec<Segment>> {
+        let response = fetch_retry
+            .send(client.get(&self.stream.uri), |x| {
+                let _ = pb.lock().unwrap().write(x);
+            })
+            .await?;
+        let url = response.url().to_owned();
+        let text = response.text().await?;
+        let mpd = dash_mpd::parse(&text).map_err(|x| {
+            anyhow!(
+                "couldn't parse response as dash playlist (failed with {}).\n\n{}",
+                x,
+                text
+            )
+        })?;
+
+        let mut playlist = crate::dash::parse_as_master(&mpd, url.as_str())
+            .streams
+            .into_iter()
+            .find(|x| {
+                x.media_type == self.stream.media_type
+                    && x.bandwidth == self.stream.bandwidth
+                    && x.codecs == self.stream.codecs
+                    && x.language == self.stream.language
+                    && x.resolution == self.stream.resolution
+            })
+            .ok_or_else(|| {
+                anyhow!(
+                    "{} stream is no longer available in playlist.",
+                    self.stream.display_stream()
+                )
+            })?;
+        crate::dash::push_segments(
+            client,
+            fetch_retry,
+            &mpd,
+            &mut playlist,
+            self.base_url.as_str(),
+        )
+        .await?;
+
+        if let Some(query) = query {
+            playlist.add_query(query);
+        }
+
+        self.ended = mpd.mpdtype.as_deref() != Some("dynamic");
+        self.refresh_interval = mpd
+            .minimumUpdatePeriod
+            .or(playlist
+                .segments
+                .last()
+                .map(|x| Duration::from_secs_f32(x.duration)))
+            .unwrap_or_default()
+            .max(Duration::from_secs(1));
+
+        if let Some(location) = mpd.locations.first() {
+            self.stream.uri = url.join(&location.url)?.to_string();
+        }
+
+        let mut seen = HashSet::new();
+        let mut segments = vec![];
+
+        for segment in playlist.segments {
+            let id = resource_id(&segment.uri, &segment.range);
+
+            if !self.seen.contains(&id) {
+                segments.push(segment);
+            }
+
+            seen.insert(id);
+        }
+
+        // Only remember segments which are still inside time shift buffer.
+        self.seen = seen;
+        Ok(segments)
+    }
+}
+
+fn resource_id(uri: &str, range: &Option<Range>) -> String {
+    if let Some(range) = range {
+        format!("{}#{}-{}", uri, range.start, range.end)
+    } else {
+        uri.to_owned()
+    }
+}
+
+/// Record live streams by refreshing their playlists every target duration (HLS)
+/// or minimum update period (DASH),
+/// until either all of them are ended, `live_duration` is reached or download is cancelled.
+/// Returns total number of bytes downloaded.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn download_live_streams(
+    all_keys: bool,
+    base_url: &Option<Url>,
+    cancel: &CancellationToken,
+    client: &Client,
+    directory: &Option<PathBuf>,
+    discontinuity: Discontinuity,
+    downloaded_bytes: usize,
+    failure_policies: &[(Option<MediaType>, FailurePolicy)],
+    fetch_retry: &RetryPolicy,
+    keys: &[(Option<String>, String)],
+    limiters: &[(Option<MediaType>, RateLimiter)],
+    live_duration: Option<f32>,
+    no_decrypt: bool,
+    no_merge: bool,
+    no_query_pass: bool,
+    one_stream: bool,
+    output: &Option<String>,
+    pb: &Arc<Mutex<Progress>>,
+    query: &Option<String>,
+    retry: &RetryPolicy,
+    streams: Vec<MediaPlaylist>,
+    temp_files: &mut Vec<Stream>,
+    threads: u8,
+) -> Result<usize> {
+    let mut scheduler = Scheduler::new(threads as usize, cancel);
+    let mut live_streams = vec![];
+    let mut key_cache = HashMap::new();
+
+    for stream in streams {
+        let temp_file = stream_file_path(&stream, directory, output, one_stream, None);
+
+        pb.lock().unwrap().write(format!(
+            "{} live {} stream {} to {}",
+            "Recording".colorize("bold green"),
+            stream.media_type,
+            stream.display_stream().colorize("cyan"),
+            temp_file.colorize("cyan"),
+        ))?;
+
+        live_streams.push(LiveStream {
+            base_url: base_url
+                .clone()
+                .unwrap_or(stream.uri.parse::<Url>().unwrap()),
+            ended: false,
+            index: 0,
+            map_id: None,
+            merger: None,
+            next_refresh: Instant::now(),
+            previous_key: None,
+            previous_map: None,
+            recorded: 0.0,
+            refresh_interval: Duration::from_secs(1),
+            seen: HashSet::new(),
+            sequence: None,
+            stream,
+            temp_file,
+            timer: Arc::new(Instant::now()),
+        });
+    }
+
+    pb.lock().unwrap().write(format!(
+        "   {} press ctrl+c to stop recording",
+        "Info".colorize("bold cyan"),
+    ))?;
+
+    let result = async {
+        while !cancel.is_cancelled() && live_streams.iter().any(|x| !x.ended) {
+            let now = Instant::now();
+
+            for live_stream in live_streams
+                .iter_mut()
+                .filter(|x| !x.ended && x.next_refresh <= now)
+            {
+                let stream_query = if no_query_pass {
+                    None
+                } else {
+                    query.clone().or(live_stream
+                        .stream
+                        .uri
+                        .parse::<Url>()
+                        .ok()
+                        .and_then(|x| x.query().map(|y| y.to_owned())))
+                };
+
+                let mut segments = match live_stream
+                    .refresh(client, fetch_retry, pb, stream_query.as_deref())
+                    .await
+                {
+                    Ok(segments) => discontinuity::remove_segments(segments, discontinuity),
+                    Err(e) => {
+                        pb.lock().unwrap().write(format!(
+                            "    {} failed to refresh playlist ({}), retrying",
+                            "Warning".colorize("bold yellow"),
+                            e,
+                        ))?;
+                        live_stream.next_refresh = now + live_stream.refresh_interval / 2;
+                        continue;
+                    }
+                };
+
+                // Playlist should be reloaded after half of refresh interval when it is unchanged.
+                live_stream.next_refresh = Instant::now()
+                    + if segments.is_empty() {
+                        live_stream.refresh_interval / 2
+                    } else {
+                        live_stream.refresh_interval
+                    };
+
+                let mut count = 0;
+
+                for segment in &segments {
+                    if live_duration.is_some_and(|x| live_stream.recorded >= x) {
+                        break;
+                    }
+
+                    live_stream.recorded += segment.duration;
+                    count += 1;
+                }
+
+                if live_duration.is_some_and(|x| live_stream.recorded >= x) {
+                    live_stream.ended = true;
+                }
+
+                segments.truncate(count);
+
+                if segments.is_empty() {
+                    continue;
+                }
+
+                let merger = if let Some(merger) = &live_stream.merger {
+                    merger.lock().unwrap().grow(count);
+                    merger.clone()
+                } else {
+                    let merger = Arc::new(Mutex::new(
+                        if no_merge {
+                            Merger::with_directory(count, &live_stream.temp_file, "live")?
+                        } else {
+                            Merger::new(count, &live_stream.temp_file, "live")?
+                        }
+                        .untrack()?,
+                    ));
+
+                    // Timeline of live streams can be broken at any time by next segments.
+                    if !no_decrypt {
+                        merger.lock().unwrap().normalize(&[])?;
+                    }
+
+                    live_stream.merger = Some(merger.clone());
+                    merger
+                };
+
+                pb.lock().unwrap().extend(count);
+
+                for segment in &segments {
+                    prepare_segment(
+                        all_keys,
+                        client,
+                        fetch_retry,
+                        &mut key_cache,
+                        keys,
+                        no_decrypt,
+                        pb,
+                        segment,
+                        &live_stream.stream,
+                        &live_stream.base_url,
+                        &mut live_stream.previous_key,
+                        &mut live_stream.previous_map,
+                    )
+                    .await?;
+
+                    if segment.discontinuity && !no_decrypt {
+                        merger.lock().unwrap().normalize(&[live_stream.index])?;
+                    }
+
+                    let task = SegmentTask {
+                        downloaded_bytes,
+                        extension: live_stream.stream.extension(),
+                        file: live_stream.temp_file.clone(),
+                        index: live_stream.index,
+                        keys: live_stream
+                            .previous_key
+                            .as_ref()
+                            .map(|x| x.for_segment(segment)),
+                        limiters: RateLimiter::of(limiters, &live_stream.stream.media_type),
+                        map: live_stream.previous_map.clone(),
+                        merger: merger.clone(),
+                        pb: pb.clone(),
+                        policy: FailurePolicy::of(failure_policies, &live_stream.stream.media_type),
+                        relative_size: 0,
+                        request: segment_request(client, &live_stream.base_url, segment)?,
+                        timer: live_stream.timer.clone(),
+                        retry: retry.clone(),
+                    };
+
+                    if live_stream.previous_key.is_none() {
+                        live_stream.previous_map = None;
+                    }
+
+                    scheduler.spawn(task.execute()).await?;
+                    live_stream.index += 1;
+                }
+            }
+
+            tokio::select! {
+                _ = cancel.cancelled() => (),
+                _ = tokio::time::sleep(Duration::from_millis(100)) => (),
+            }
+        }
+
+        Ok(())
+    }
+    .await;
+
+    let mut result = match result {
+        Ok(()) => scheduler.join().await,
+        Err(e) => {
+            scheduler.shutdown().await;
+            Err(e)
+        }
+    };
+
+    // Cancellation stops recording and aborts in-flight segments, streams are kept up to
+    // their last contiguous written segment.
+    if cancel.is_cancelled() {
+        pb.lock().unwrap().write(format!(
+            "    {} recording, in-flight segments are left out",
+            "Stopped".colorize("bold cyan"),
+        ))?;
+
+        for live_stream in &mut live_streams {
+            if let Some(merger) = &live_stream.merger {
+                let mut merger = merger.lock().unwrap();
+                merger.flush()?;
+                merger.truncate();
+
+                if merger.position() == 0 {
+                    drop(merger);
+                    live_stream.merger = None;
+                }
+            }
+        }
+
+        result = Ok(());
+    }
+
+    let failures = scheduler.take_failures();
+    let mut downloaded_bytes = downloaded_bytes;
+
+    for live_stream in live_streams {
+        report_failures(
+            &mut pb.lock().unwrap(),
+            FailurePolicy::of(failure_policies, &live_stream.stream.media_type),
+            &failures
+                .iter()
+                .filter(|x| x.file == live_stream.temp_file)
+                .collect::<Vec<_>>(),
+        )?;
+
+        if let Some(merger) = live_stream.merger {
+            let mut merger = merger.lock().unwrap();
+            merger.flush()?;
+
+            if result.is_err() {
+                pb.lock().unwrap().write(format!(
+                    "    {} {} ({} segments are written)",
+                    "Keeping".colorize("bold cyan"),
+                    live_stream.temp_file,
+                    merger.position(),
+                ))?;
+                continue;
+            }
+
+            if !merger.buffered() {
+                bail!(
+                    "failed to download {} live stream to {}",
+                    live_stream.stream.display_stream().colorize("cyan"),
+                    live_stream.temp_file
+                );
+            }
+
+            downloaded_bytes += merger.stored();
+            temp_files.push(Stream {
+                default: live_stream.stream.default,
+                file_path: live_stream.temp_file,
+                forced: live_stream.stream.forced,
+                language: live_stream.stream.language,
+                media_type: live_stream.stream.media_type,
+            });
+        } else {
+            pb.lock().unwrap().write(format!(
+                "    {} no segments were recorded for {} live stream",
+                "Warning".colorize("bold yellow"),
+                live_stream.stream.display_stream().colorize("cyan"),
+            ))?;
+        }
+    }
+
+    result?;
+    pb.lock().unwrap().write(format!(
+        " {} live streams successfully",
+        "Recorded".colorize("bold green"),
+    ))?;
+
+    Ok(downloaded_bytes)
+}
@@ -1,60 +1,107 @@
+mod builder;
+mod clip;
+mod discontinuity;
+mod failure;
 mod fetch;
+mod live;
 mod parse;
+mod sample_aes;
+mod scheduler;
 mod subtitle;
 
-pub use fetch::{fetch_playlist, InputMetadata};
-pub use parse::{parse_all_streams, parse_selected_streams};
+pub use builder::{Downloader, PlaylistFetcher};
+pub use clip::Clip;
+pub use discontinuity::Discontinuity;
+pub use failure::{FailurePolicy, SegmentError, SegmentErrorKind};
+pub use fetch::InputMetadata;
 pub use subtitle::download_subtitle_streams;
 
 use crate::{
+    keys::{is_key_id, KeyRequest},
+    limiter::RateLimiter,
     merger::Merger,
+    mux::{self, Muxer},
     playlist::{KeyMethod, MediaPlaylist, MediaType, Range, Segment},
+    progress::Progress,
+    retry::RetryPolicy,
     utils,
 };
 use anyhow::{anyhow, bail, Result};
-use kdam::{term::Colorizer, tqdm, BarExt, Column, RichProgress};
-use reqwest::{
-    blocking::{Client, RequestBuilder},
-    header, StatusCode, Url,
-};
+use kdam::term::Colorizer;
+use reqwest::{header, Client, RequestBuilder, Url};
+use scheduler::Scheduler;
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
     path::{Path, PathBuf},
-    process::{Command, Stdio},
     sync::{Arc, Mutex},
     time::Instant,
-    fmt::Write,
 };
 use vsd_mp4::pssh::Pssh;
 
 pub type SelectedPlaylists = (Vec<MediaPlaylist>, Vec<MediaPlaylist>);
 
-pub struct Prompts {
-    pub skip: bool,
-    pub raw: bool,
-}
-
 pub struct Stream {
+    pub default: bool,
     pub file_path: String,
+    pub forced: bool,
     pub language: Option<String>,
     pub media_type: MediaType,
 }
 
-#[allow(clippy::too_many_arguments)]
-pub(crate) fn download(
-    all_keys: bool,
-    base_url: Option<Url>,
-    client: Client,
-    directory: Option<PathBuf>,
-    keys: Vec<(Option<String>, String)>,
-    no_decrypt: bool,
-    no_merge: bool,
-    output: Option<String>,
+pub(crate) async fn download(
+    downloader: Downloader,
     selected_playlists: SelectedPlaylists,
-    retry_count: u8,
-    threads: u8,
 ) -> Result<()> {
-    let (mut video_audio_streams, subtitle_streams) = selected_playlists;
+    let Downloader {
+        all_keys,
+        base_url,
+        cancel,
+        client,
+        clip,
+        directory,
+        discontinuity,
+        failure_policies,
+        fetch_retry,
+        ffmpeg_args,
+        ffmpeg_path,
+        ffmpeg_preset,
+        keep_temp,
+        key_providers,
+        mut keys,
+        mut keystore,
+        live_duration,
+        muxer,
+        no_decrypt,
+        no_merge,
+        no_query_pass,
+        output,
+        progress,
+        query,
+        rate_limits,
+        retry,
+        threads,
+    } = downloader;
+    let limiters = rate_limits
+        .into_iter()
+        .map(|(media_type, rate)| (media_type, RateLimiter::new(rate)))
+        .collect::<Vec<_>>();
+    let (mut video_audio_streams, mut subtitle_streams) = selected_playlists;
+
+    for stream in video_audio_streams.iter_mut() {
+        stream.segments =
+            discontinuity::remove_segments(std::mem::take(&mut stream.segments), discontinuity);
+    }
+
+    let window = clip::Window::new(clip, &video_audio_streams)?;
+
+    if let Some(window) = &window {
+        for stream in video_audio_streams
+            .iter_mut()
+            .chain(subtitle_streams.iter_mut())
+        {
+            window.clip(stream);
+        }
+    }
 
     let one_stream = (video_audio_streams.len() == 1) && subtitle_streams.is_empty();
     let mut should_mux = !no_decrypt && !no_merge;
@@ -79,74 +126,190 @@ pub(crate) fn download(
         should_mux = false;
     }
 
-    if should_mux && utils::find_ffmpeg().is_none() {
-        bail!("ffmpeg couldn't be found, it is required to continue further.");
+    let split = discontinuity == Discontinuity::Split
+        && video_audio_streams
+            .iter()
+            .any(|x| !x.live && x.segments.iter().skip(1).any(|y| y.discontinuity));
+
+    if split {
+        should_mux = false;
+    }
+
+    let ffmpeg = utils::find_ffmpeg(ffmpeg_path.as_deref());
+
+    if should_mux {
+        let native = output.as_deref().is_some_and(mux::supports_output);
+
+        match muxer {
+            Muxer::Auto if !native && ffmpeg.is_none() => {
+                bail!("ffmpeg couldn't be found, it is required to continue further.")
+            }
+            Muxer::Ffmpeg if ffmpeg.is_none() => {
+                bail!("ffmpeg couldn't be found, it is required to continue further.")
+            }
+            Muxer::Native if !native => {
+                bail!("native muxer only supports .mp4, .m4a, .m4v, .mkv and .mka outputs.")
+            }
+            _ => (),
+        }
     }
 
+    // -----------------------------------------------------------------------------------------
+    // Prepare Progress Bar
+    // -----------------------------------------------------------------------------------------
+
+    let mut pb = Progress::new(progress);
+
     // -----------------------------------------------------------------------------------------
     // Parse Key Ids
     // -----------------------------------------------------------------------------------------
 
     let mut default_kids = HashSet::new();
 
+    // Keys and initialization segments are attached again at period boundaries of multi-period
+    // dash streams, key ids of later periods can differ from first one.
     for stream in &video_audio_streams {
-        if let Some(segment) = stream.segments.get(0) {
-            if let Some(key) = &segment.key {
-                if !no_decrypt {
-                    match &key.method {
-                        KeyMethod::Other(x) => bail!("{} decryption is not supported. Use {} flag to download encrypted streams.", x, "--no-decrypt".colorize("bold green")),
-                        KeyMethod::SampleAes => {
-                            if stream.is_hls() {
-                                bail!("sample-aes (HLS) decryption is not supported. Use {} flag to download encrypted streams.", "--no-decrypt".colorize("bold green"));
-                            }
-                        }
-                        _ => (),
+        let fmp4 = stream.segments.iter().any(|x| x.map.is_some());
+
+        for key in stream.segments.iter().filter_map(|x| x.key.as_ref()) {
+            if !no_decrypt {
+                match &key.method {
+                    KeyMethod::Other(x) => bail!("{} decryption is not supported. Use {} flag to download encrypted streams.", x, "--no-decrypt".colorize("bold green")),
+                    KeyMethod::SampleAes if stream.is_hls() && fmp4 => {
+                        bail!("sample-aes (HLS) decryption of fragmented mp4 segments is not supported. Use {} flag to download encrypted streams.", "--no-decrypt".colorize("bold green"));
                     }
+                    _ => (),
                 }
+            }
 
-                if let Some(default_kid) = &key.default_kid {
-                    default_kids.insert(default_kid.replace('-', ""));
-                }
+            if let Some(default_kid) = &key.default_kid {
+                default_kids.insert(default_kid.replace('-', ""));
             }
         }
     }
 
     let mut kids = HashSet::new();
+    let mut pssh_boxes = vec![];
+    let mut maps = HashSet::new();
 
     for stream in &video_audio_streams {
         let stream_base_url = base_url
             .clone()
             .unwrap_or(stream.uri.parse::<Url>().unwrap());
 
-        if let Some(segment) = stream.segments.get(0) {
-            if let Some(map) = &segment.map {
-                let url = stream_base_url.join(&map.uri)?;
-                let mut request = client.get(url);
+        for map in stream.segments.iter().filter_map(|x| x.map.as_ref()) {
+            let url = stream_base_url.join(&map.uri)?;
 
-                if let Some(range) = &map.range {
-                    request = request.header(header::RANGE, range.as_header_value());
+            if !maps.insert((url.clone(), map.range.as_ref().map(|x| (x.start, x.end)))) {
+                continue;
+            }
+
+            let mut request = client.get(url);
+
+            if let Some(range) = &map.range {
+                request = request.header(header::RANGE, range.as_header_value());
+            }
+
+            let bytes = fetch_retry.bytes(request, |x| pb.print(x)).await?;
+            let pssh = Pssh::new(&bytes).map_err(|x| anyhow!(x))?;
+
+            for pssh_box in pssh.boxes {
+                if !pssh_boxes.contains(&pssh_box) {
+                    pssh_boxes.push(pssh_box);
                 }
+            }
 
-                let response = request.send()?;
-                let pssh = Pssh::new(&response.bytes()?).map_err(|x| anyhow!(x))?;
-
-                for key_id in pssh.key_ids {
-                    if !kids.contains(&key_id.value) {
-                        kids.insert(key_id.value.clone());
-                        println!(
-                            "      {} {} {} ({})",
-                            "KeyId".colorize("bold green"),
-                            if default_kids.contains(&key_id.value) {
-                                "*"
-                            } else {
-                                " "
-                            },
-                            key_id.uuid(),
-                            key_id.system_type,
-                        );
+            for key_id in pssh.key_ids {
+                if !kids.contains(&key_id.value) {
+                    kids.insert(key_id.value.clone());
+                    pb.print(format!(
+                        "      {} {} {} ({})",
+                        "KeyId".colorize("bold green"),
+                        if default_kids.contains(&key_id.value) {
+                            "*"
+                        } else {
+                            " "
+                        },
+                        key_id.uuid(),
+                        key_id.system_type,
+                    ));
+                }
+            }
+        }
+    }
+
+    let missing_kids = |keys: &[(Option<String>, String)]| {
+        default_kids
+            .iter()
+            .filter(|x| !keys.iter().flat_map(|y| y.0.as_ref()).any(|y| y == *x))
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+
+    if let Some(keystore) = &keystore {
+        if !no_decrypt {
+            let mut found = 0;
+
+            // Key ids of default_KID attributes and tenc boxes aren't always listed in pssh boxes.
+            for kid in default_kids.union(&kids).collect::<BTreeSet<_>>() {
+                if !keys.iter().flat_map(|x| x.0.as_ref()).any(|x| x == kid) {
+                    if let Some(key) = keystore.get(kid) {
+                        keys.push((Some(kid.to_owned()), key.to_owned()));
+                        found += 1;
                     }
                 }
             }
+
+            if found > 0 {
+                pb.print(format!(
+                    "      {} {} keys in {}",
+                    "Found".colorize("bold green"),
+                    found,
+                    keystore.path().to_string_lossy()
+                ));
+            }
+        }
+    }
+
+    if !no_decrypt && !key_providers.is_empty() && !missing_kids(&keys).is_empty() {
+        let mut default_kids = default_kids.iter().cloned().collect::<Vec<_>>();
+        let mut kids = kids.iter().cloned().collect::<Vec<_>>();
+        default_kids.sort();
+        kids.sort();
+
+        let request = KeyRequest {
+            default_kids,
+            kids,
+            pssh: pssh_boxes.iter().map(utils::encode_base64).collect(),
+        };
+
+        for provider in &key_providers {
+            match provider.keys(&request).await {
+                Ok(provided_keys) => {
+                    pb.print(format!(
+                        "    {} {} keys from {}",
+                        "Fetched".colorize("bold green"),
+                        provided_keys.len(),
+                        provider.name()
+                    ));
+
+                    for key in provided_keys {
+                        if !keys.contains(&key) {
+                            keys.push(key);
+                        }
+                    }
+                }
+                Err(e) => pb.print(format!(
+                    "    {} could not fetch keys from {}. {}",
+                    "Warning".colorize("bold yellow"),
+                    provider.name(),
+                    e
+                )),
+            }
+
+            if missing_kids(&keys).is_empty() {
+                break;
+            }
         }
     }
 
@@ -164,27 +327,6 @@ pub(crate) fn download(
         }
     }
 
-    // -----------------------------------------------------------------------------------------
-    // Prepare Progress Bar
-    // -----------------------------------------------------------------------------------------
-
-    let mut pb = RichProgress::new(
-        tqdm!(unit = " SEG".to_owned(), dynamic_ncols = true),
-        vec![
-            Column::Text("[bold blue]?".to_owned()),
-            Column::Animation,
-            Column::Percentage(0),
-            Column::Text("•".to_owned()),
-            Column::CountTotal,
-            Column::Text("•".to_owned()),
-            Column::ElapsedTime,
-            Column::Text("[cyan]>".to_owned()),
-            Column::RemainingTime,
-            Column::Text("•".to_owned()),
-            Column::Rate,
-        ],
-    );
-
     // -----------------------------------------------------------------------------------------
     // Prepare Directory & Store Streams Metadata
     // -----------------------------------------------------------------------------------------
@@ -206,10 +348,10 @@ pub(crate) fn download(
             .count();
 
         if video_streams_count > 1 {
-            println!(
+            pb.print(format!(
                 "    {} --output is ignored when multiple video streams are selected",
                 "Warning".colorize("bold yellow")
-            );
+            ));
         }
 
         if video_streams_count == 0
@@ -217,32 +359,51 @@ pub(crate) fn download(
                 || subtitle_streams.len() > 1
                 || (audio_streams_count != 0 && !subtitle_streams.is_empty()))
         {
-            println!(
+            pb.print(format!(
                 "    {} --output is ignored when no video streams is selected but multiple audio/subtitle streams are selected",
                 "Warning".colorize("bold yellow")
-            );
+            ));
         }
 
         if no_decrypt {
-            println!(
+            pb.print(format!(
                 "    {} --output is ignored when --no-decrypt is used",
                 "Warning".colorize("bold yellow")
-            );
+            ));
         }
 
         if no_merge {
-            println!(
+            pb.print(format!(
                 "    {} --output is ignored when --no-merge is used",
                 "Warning".colorize("bold yellow")
-            );
+            ));
         }
+
+        if split {
+            pb.print(format!(
+                "    {} --output is ignored when streams are split at discontinuities",
+                "Warning".colorize("bold yellow")
+            ));
+        }
+    }
+
+    if window.is_some()
+        && video_audio_streams
+            .iter()
+            .chain(subtitle_streams.iter())
+            .any(|x| x.live)
+    {
+        pb.print(format!(
+            "    {} live streams are always recorded completely, even if a clip is set",
+            "Warning".colorize("bold yellow")
+        ));
     }
 
     if !subtitle_streams.is_empty() && no_merge {
-        println!(
+        pb.print(format!(
             "    {} subtitle streams are always merged even if --no-merge is used",
             "Warning".colorize("bold yellow")
-        );
+        ));
     }
 
     let mut temp_files = vec![];
@@ -254,11 +415,16 @@ pub(crate) fn download(
     download_subtitle_streams(
         base_url.clone(),
         &client,
+        &fetch_retry,
+        &retry,
+        &RateLimiter::of(&limiters, &MediaType::Subtitles),
         &directory,
         &subtitle_streams,
         &mut pb,
         &mut temp_files,
-    )?;
+        window.as_ref(),
+    )
+    .await?;
 
     // -----------------------------------------------------------------------------------------
     // Estimation
@@ -268,6 +434,10 @@ pub(crate) fn download(
     let mut relative_sizes = VecDeque::new();
 
     for stream in video_audio_streams.iter_mut() {
+        if stream.live {
+            continue;
+        }
+
         let stream_base_url = base_url
             .clone()
             .unwrap_or(stream.uri.parse::<Url>().unwrap());
@@ -281,7 +451,7 @@ pub(crate) fn download(
             let mut request = client.head(url.clone());
 
             if total_segments == 1 {
-                let response = request.send()?;
+                let response = request.send().await?;
                 let content_length = response
                     .headers()
                     .get(header::CONTENT_LENGTH)
@@ -306,7 +476,7 @@ pub(crate) fn download(
                     request = request.header(header::RANGE, range.as_header_value());
                 }
 
-                let response = request.send()?;
+                let response = request.send().await?;
                 let content_length = response
                     .headers()
                     .get(header::CONTENT_LENGTH)
@@ -341,28 +511,55 @@ pub(crate) fn download(
     // Prepare Progress Bar
     // -----------------------------------------------------------------------------------------
 
-    pb.replace(2, Column::Percentage(2));
-    pb.columns.extend_from_slice(&[
-        Column::Text("•".to_owned()),
-        Column::Text("[yellow]?".to_owned()),
-    ]);
-    pb.pb.reset(Some(
-        video_audio_streams.iter().map(|x| x.segments.len()).sum(),
-    ));
+    pb.show_speed();
+
+    // Periods of multi-period dash streams are written as chapters by native muxer.
+    let chapters = video_audio_streams
+        .iter()
+        .find(|x| !x.chapters.is_empty())
+        .map(|x| x.chapters.clone())
+        .unwrap_or_default();
+
+    // Duration of longest stream, used for reporting progress of ffmpeg.
+    let duration = video_audio_streams
+        .iter()
+        .map(|x| {
+            if x.live {
+                live_duration.unwrap_or(0.0)
+            } else {
+                x.segments.iter().map(|y| y.duration).sum()
+            }
+        })
+        .fold(0.0, f32::max);
+
+    let (live_streams, video_audio_streams): (Vec<_>, Vec<_>) =
+        video_audio_streams.into_iter().partition(|x| x.live);
+
+    pb.reset(video_audio_streams.iter().map(|x| x.segments.len()).sum());
     let pb = Arc::new(Mutex::new(pb));
 
     // -----------------------------------------------------------------------------------------
     // Download Video & Audio Streams
     // -----------------------------------------------------------------------------------------
 
-    let pool = threadpool::ThreadPool::new(threads as usize);
+    let mut scheduler = Scheduler::new(threads as usize, &cancel);
+    let mut key_cache = HashMap::new();
 
-    for stream in video_audio_streams {
+    let streams = video_audio_streams.into_iter().flat_map(|x| {
+        if split {
+            discontinuity::split(x)
+        } else {
+            vec![(None, x)]
+        }
+    });
+
+    for (part, stream) in streams {
         pb.lock().unwrap().write(format!(
-            " {} {} stream {}",
+            " {} {} stream {}{}",
             "Processing".colorize("bold green"),
             stream.media_type,
             stream.display_stream().colorize("cyan"),
+            part.map(|x| format!(" (part {})", x)).unwrap_or_default(),
         ))?;
 
         let length = stream.segments.len();
@@ -375,19 +572,12 @@ pub(crate) fn download(
             continue;
         }
 
-        let mut temp_file = stream
-            .file_path(&directory, &stream.extension())
-            .to_string_lossy()
-            .to_string();
-
-        if let Some(output) = &output {
-            if one_stream && output.ends_with(&format!(".{}", stream.extension())) {
-                temp_file = output.to_owned();
-            }
-        }
+        let temp_file = stream_file_path(&stream, &directory, &output, one_stream, part);
 
         temp_files.push(Stream {
+            default: stream.default,
             file_path: temp_file.clone(),
+            forced: stream.forced,
             language: stream.language.clone(),
             media_type: stream.media_type.clone(),
         });
@@ -397,14 +587,48 @@ pub(crate) fn download(
             temp_file.colorize("cyan"),
         ))?;
 
+        let id = stream.journal_id();
         let merger = Arc::new(Mutex::new(if no_merge {
-            Merger::with_directory(stream.segments.len(), &temp_file)?
+            Merger::with_directory(stream.segments.len(), &temp_file, &id)?
         } else {
-            Merger::new(stream.segments.len(), &temp_file)?
+            Merger::new(stream.segments.len(), &temp_file, &id)?
         }));
+
+        // Timestamps of encrypted segments cannot be rewritten.
+        if !no_decrypt {
+            let discontinuities = stream
+                .segments
+                .iter()
+                .enumerate()
+                .skip(1)
+                .filter(|(_, x)| x.discontinuity)
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>();
+
+            if !discontinuities.is_empty() {
+                merger.lock().unwrap().normalize(&discontinuities)?;
+            }
+        }
+
         let timer = Arc::new(Instant::now());
+        let resumed = merger.lock().unwrap().resumed();
+
+        if resumed != 0 {
+            let mut pb = pb.lock().unwrap();
+            pb.write(format!(
+                "   {} {}/{} segments from previous download",
+                "Resuming".colorize("bold cyan"),
+                resumed,
+                length,
+            ))?;
+            pb.update(resumed)?;
+        }
+
+        // Parts of a stream share its estimated size.
+        if part.unwrap_or(1) == 1 {
+            let _ = relative_sizes.pop_front();
+        }
 
-        let _ = relative_sizes.pop_front();
         let relative_size = relative_sizes.iter().sum();
         let mut previous_map = None;
         let mut previous_key = None;
@@ -413,131 +637,100 @@ pub(crate) fn download(
             .clone()
             .unwrap_or(stream.uri.parse::<Url>().unwrap());
 
-        for (i, segment) in stream.segments.iter().enumerate() {
-            if let Some(map) = &segment.map {
-                let url = stream_base_url.join(&map.uri)?;
-                let mut request = client.get(url);
-
-                if let Some(range) = &map.range {
-                    request = request.header(header::RANGE, range.as_header_value());
-                }
-
-                let response = request.send()?;
-                let bytes = response.bytes()?;
-                previous_map = Some(bytes.to_vec())
-            }
-
-            if !no_decrypt {
-                if let Some(key) = &segment.key {
-                    match key.method {
-                        KeyMethod::Aes128 => {
-                            if !keys.is_empty() {
-                                bail!("custom keys with AES-128 encryption is not supported");
-                            }
-
-                            if let Some(uri) = &key.uri {
-                                previous_key = Some(Keys {
-                                    bytes: if key.key_format.is_none() {
-                                        let url = stream_base_url.join(uri)?;
-                                        let request = client.get(url);
-                                        let response = request.send()?;
-                                        response.bytes()?.to_vec()
-                                    } else {
-                                        vec![]
-                                    },
-                                    iv: key.iv.clone(),
-                                    method: key.method.clone(),
-                                });
-                            } else {
-                                bail!("uri cannot be none when key method is AES-128");
-                            }
-                        }
-                        KeyMethod::Cenc => {
-                            let default_kid = stream.default_kid();
-                            let mut decryption_keys = HashMap::new();
+        let extension = stream.extension();
+        let policy = FailurePolicy::of(&failure_policies, &stream.media_type);
+        let stream_limiters = RateLimiter::of(&limiters, &stream.media_type);
+
+        let result = async {
+            for (i, segment) in stream.segments.iter().enumerate() {
+                prepare_segment(
+                    all_keys,
+                    &client,
+                    &fetch_retry,
+                    &mut key_cache,
+                    &keys,
+                    no_decrypt,
+                    &pb,
+                    segment,
+                    &stream,
+                    &stream_base_url,
+                    &mut previous_key,
+                    &mut previous_map,
+                )
+                .await?;
 
-                            if all_keys {
-                                for key in &keys {
-                                    if let Some(kid) = &key.0 {
-                                        decryption_keys.insert(kid.to_owned(), key.1.to_owned());
-                                    } else if let Some(default_kid) = &default_kid {
-                                        decryption_keys
-                                            .insert(default_kid.to_owned(), key.1.to_owned());
-                                    }
-                                }
-                            } else {
-                                for key in &keys {
-                                    if let Some(default_kid) = &default_kid {
-                                        if let Some(kid) = &key.0 {
-                                            if default_kid == kid {
-                                                decryption_keys
-                                                    .insert(kid.to_owned(), key.1.to_owned());
-                                            }
-                                        } else {
-                                            decryption_keys
-                                                .insert(default_kid.to_owned(), key.1.to_owned());
-                                        }
-                                    }
-                                }
-                            }
+                if merger.lock().unwrap().is_resumed(i) {
+                    if previous_key.is_none() {
+                        previous_map = None;
+                    }
 
-                            if decryption_keys.is_empty() {
-                                bail!(
-                                    "cannot determine keys to use, bypass this error using {} flag.",
-                                    "--all-keys".colorize("bold green")
-                                );
-                            }
+                    continue;
+                }
 
-                            for key in &decryption_keys {
-                                pb.lock().unwrap().write(format!(
-                                    "        {} {}:{}",
-                                    "Key".colorize("bold green"),
-                                    key.0,
-                                    key.1
-                                ))?;
-                            }
+                let request = segment_request(&client, &stream_base_url, segment)?;
+                let task = SegmentTask {
+                    downloaded_bytes,
+                    extension: extension.clone(),
+                    file: temp_file.clone(),
+                    index: i,
+                    keys: previous_key.as_ref().map(|x| x.for_segment(segment)),
+                    limiters: stream_limiters.clone(),
+                    map: previous_map.clone(),
+                    merger: merger.clone(),
+                    pb: pb.clone(),
+                    policy,
+                    relative_size,
+                    request,
+                    timer: timer.clone(),
+                    retry: retry.clone(),
+                };
 
-                            previous_key = Some(Keys::from_hex_keys(decryption_keys));
-                        }
-                        _ => previous_key = None,
-                    }
+                if previous_key.is_none() {
+                    previous_map = None;
                 }
+
+                scheduler.spawn(task.execute()).await?;
             }
 
-            let url = stream_base_url.join(&segment.uri)?;
-            let mut request = client.get(url);
+            scheduler.join().await
+        }
+        .await;
 
-            if let Some(range) = &segment.range {
-                request = request.header(header::RANGE, range.as_header_value());
-            }
+        let failures = scheduler.take_failures();
 
-            let thread_data = ThreadData {
-                downloaded_bytes,
-                index: i,
-                keys: previous_key.clone(),
-                map: previous_map.clone(),
-                merger: merger.clone(),
-                pb: pb.clone(),
-                relative_size,
-                request,
-                timer: timer.clone(),
-                total_retries: retry_count,
-            };
+        if let Err(e) = result {
+            // Written segments are tracked in journal, so that download can be resumed later.
+            scheduler.shutdown().await;
+            let mut merger = merger.lock().unwrap();
+            merger.flush()?;
+
+            let mut pb = pb.lock().unwrap();
+            report_failures(&mut pb, policy, &failures.iter().collect::<Vec<_>>())?;
 
-            if previous_key.is_none() {
-                previous_map = None;
+            for temp_file in &temp_files[..(temp_files.len() - 1)] {
+                pb.write(format!(
+                    "    {} {}",
+                    "Keeping".colorize("bold cyan"),
+                    temp_file.file_path
+                ))?;
             }
 
-            pool.execute(move || {
-                if let Err(e) = thread_data.execute() {
-                    let _lock = thread_data.pb.lock().unwrap();
-                    println!("\n{}: {}", "error".colorize("bold red"), e);
-                    std::process::exit(1);
-                }
-            });
+            pb.write(format!(
+                "    {} {} ({}/{} segments are written, download again to resume)",
+                "Keeping".colorize("bold cyan"),
+                temp_file,
+                merger.position(),
+                length,
+            ))?;
+            return Err(e);
         }
 
-        pool.join();
+        report_failures(
+            &mut pb.lock().unwrap(),
+            policy,
+            &failures.iter().collect::<Vec<_>>(),
+        )?;
+
         let mut merger = merger.lock().unwrap();
         merger.flush()?;
 
@@ -549,6 +742,7 @@ pub(crate) fn download(
             );
         }
 
+        merger.finish()?;
         downloaded_bytes += merger.stored();
 
         pb.lock().unwrap().write(format!(
@@ -557,7 +751,67 @@ pub(crate) fn download(
         ))?;
     }
 
-    eprintln!();
+    // -----------------------------------------------------------------------------------------
+    // Record Live Streams
+    // -----------------------------------------------------------------------------------------
+
+    if !live_streams.is_empty() {
+        live::download_live_streams(
+            all_keys,
+            &base_url,
+            &cancel,
+            &client,
+            &directory,
+            discontinuity,
+            downloaded_bytes,
+            &failure_policies,
+            &fetch_retry,
+            &keys,
+            &limiters,
+            live_duration,
+            no_decrypt,
+            no_merge,
+            no_query_pass,
+            one_stream,
+            &output,
+            &pb,
+            &query,
+            &retry,
+            live_streams,
+            &mut temp_files,
+            threads,
+        )
+        .await?;
+    }
+
+    let pb = pb.lock().unwrap();
+    pb.finish();
+
+    // Keys are only saved once streams are decrypted using them, so that wrong keys
+    // aren't reused by later downloads.
+    if let Some(keystore) = &mut keystore {
+        let mut changed = false;
+
+        for (kid, key) in &keys {
+            if let Some(kid) = kid
+                .as_ref()
+                .filter(|x| default_kids.contains(*x) || kids.contains(*x))
+            {
+                // Invalid keys are left for decryption to report.
+                changed |= keystore.insert(kid, key).unwrap_or(false);
+            }
+        }
+
+        if changed && !no_decrypt {
+            if let Err(e) = keystore.save() {
+                pb.print(format!(
+                    "    {} could not update keystore. {}",
+                    "Warning".colorize("bold yellow"),
+                    e
+                ));
+            }
+        }
+    }
 
     // -----------------------------------------------------------------------------------------
     // Mux Downloaded Streams
@@ -598,109 +852,68 @@ pub(crate) fn download(
                 )
                 .collect::<Vec<_>>();
 
-            let mut args = vec![];
-
-            for temp_file in &all_temp_files {
-                args.extend_from_slice(&["-i".to_owned(), temp_file.file_path.clone()]);
+            if Path::new(output).exists() {
+                pb.print(format!("   {} {}", "Deleting".colorize("bold red"), output));
+                std::fs::remove_file(output)?;
             }
 
-            if (video_streams_count == 1)
-                || (audio_streams_count == 1)
-                || (subtitle_streams_count == 1)
-            {
-                // TODO - Re-consider this copy
-                args.extend_from_slice(&["-c".to_owned(), "copy".to_owned()]);
-            } else {
-                args.extend_from_slice(&["-c".to_owned(), "copy".to_owned()]);
-
-                if subtitle_streams_count > 0 && output.ends_with(".mp4") {
-                    args.extend_from_slice(&["-c:s".to_owned(), "mov_text".to_owned()]);
-                }
-
-                for i in 0..all_temp_files.len() {
-                    args.extend_from_slice(&["-map".to_owned(), i.to_string()]);
-                }
-
-                let mut audio_index = 0;
-                let mut subtitle_index = 0;
-
-                for temp_file in &all_temp_files {
-                    match temp_file.media_type {
-                        MediaType::Audio => {
-                            if let Some(language) = &temp_file.language {
-                                args.extend_from_slice(&[
-                                    format!("-metadata:s:a:{}", audio_index),
-                                    format!("language={}", language),
-                                ]);
-                            }
-
-                            audio_index += 1;
-                        }
-                        MediaType::Subtitles => {
-                            if let Some(language) = &temp_file.language {
-                                args.extend_from_slice(&[
-                                    format!("-metadata:s:s:{}", subtitle_index),
-                                    format!("language={}", language),
-                                ]);
-                            }
-
-                            subtitle_index += 1;
-                        }
-                        _ => (),
-                    }
-                }
+            let native = match muxer {
+                Muxer::Auto => ffmpeg.is_none(),
+                Muxer::Ffmpeg => false,
+                Muxer::Native => true,
+            };
 
-                if subtitle_streams_count > 0 {
-                    args.extend_from_slice(&["-disposition:s:0".to_owned(), "default".to_owned()]);
+            if native {
+                if !mux::supports(&all_temp_files, output)? {
+                    bail!(
+                        "native muxer only supports fragmented mp4 and mpeg-ts (h264, h265 \
+                        and aac) video and audio streams along with webvtt and subrip subtitles \
+                        for .mp4 and .mkv outputs (matroska outputs also require unencrypted \
+                        h264, h265, av1, vp9, aac, mp3, ac3, eac3, opus or flac streams), \
+                        ffmpeg is required to mux these streams."
+                    );
                 }
-            }
-
-            args.push(output.to_owned());
-
-            println!(
-                "  {} ffmpeg {}",
-                "Executing".colorize("bold cyan"),
-                args.iter()
-                    .map(|x| if x.contains(' ') {
-                        format!("\"{}\"", x)
-                    } else {
-                        x.to_owned()
-                    })
-                    .collect::<Vec<_>>()
-                    .join(" ")
-            );
 
-            if Path::new(output).exists() {
-                println!("   {} {}", "Deleting".colorize("bold red"), output);
-                std::fs::remove_file(output)?;
+                pb.print(format!(
+                    "     {} streams to {} using native muxer",
+                    "Muxing".colorize("bold cyan"),
+                    output
+                ));
+                mux::mux(&all_temp_files, &chapters, output)?;
+            } else {
+                mux::ffmpeg::mux(
+                    ffmpeg
+                        .as_deref()
+                        .ok_or_else(|| anyhow!("ffmpeg couldn't be found."))?,
+                    (duration > 0.0).then_some(duration),
+                    &ffmpeg_args,
+                    output,
+                    &pb,
+                    ffmpeg_preset,
+                    &all_temp_files,
+                )?;
             }
 
-            let code = Command::new("ffmpeg")
-                .args(args)
-                .stderr(Stdio::null())
-                .spawn()?
-                .wait()?;
-
-            if !code.success() {
-                bail!("ffmpeg exited with code {}", code.code().unwrap_or(1))
+            if keep_temp {
+                return Ok(());
             }
 
             for temp_file in &all_temp_files {
-                println!(
+                pb.print(format!(
                     "   {} {}",
                     "Deleting".colorize("bold red"),
                     temp_file.file_path
-                );
+                ));
                 std::fs::remove_file(&temp_file.file_path)?;
             }
 
             if let Some(directory) = &directory {
                 if std::fs::read_dir(directory)?.next().is_none() {
-                    println!(
+                    pb.print(format!(
                         "   {} {}",
                         "Deleting".colorize("bold red"),
                         directory.to_string_lossy()
-                    );
+                    ));
                     std::fs::remove_dir(directory)?;
                 }
             }
@@ -710,6 +923,217 @@ pub(crate) fn download(
     Ok(())
 }
 
+/// Fetch initialization segment and decryption keys of a segment (if any) and carry them
+/// forward, as they are only attached to the segment where they are first seen.
+#[allow(clippy::too_many_arguments)]
+async fn prepare_segment(
+    all_keys: bool,
+    client: &Client,
+    fetch_retry: &RetryPolicy,
+    key_cache: &mut HashMap<String, Vec<u8>>,
+    keys: &[(Option<String>, String)],
+    no_decrypt: bool,
+    pb: &Mutex<Progress>,
+    segment: &Segment,
+    stream: &MediaPlaylist,
+    stream_base_url: &Url,
+    previous_key: &mut Option<Keys>,
+    previous_map: &mut Option<Vec<u8>>,
+) -> Result<()> {
+    if let Some(map) = &segment.map {
+        let url = stream_base_url.join(&map.uri)?;
+        let mut request = client.get(url);
+
+        if let Some(range) = &map.range {
+            request = request.header(header::RANGE, range.as_header_value());
+        }
+
+        let bytes = fetch_retry
+            .bytes(request, |x| {
+                let _ = pb.lock().unwrap().write(x);
+            })
+            .await?;
+        *previous_map = Some(bytes);
+    }
+
+    if !no_decrypt {
+        if let Some(key) = &segment.key {
+            match key.method {
+                KeyMethod::Aes128 | KeyMethod::SampleAes => {
+                    if let Some(uri) = &key.uri {
+                        let url = stream_base_url.join(uri)?;
+
+                        let bytes = if let Some(custom_key) = custom_key(keys, url.as_str()) {
+                            match hex::decode(custom_key) {
+                                Ok(x) if x.len() == 16 => x,
+                                _ => bail!("{} is not a valid {} key.", custom_key, key.method),
+                            }
+                        } else if key.key_format.is_none()
+                            || key.key_format.as_deref() == Some("identity")
+                        {
+                            // Rotated keys can be referred again by later segments.
+                            if let Some(bytes) = key_cache.get(url.as_str()) {
+                                bytes.to_owned()
+                            } else {
+                                let bytes = fetch_retry
+                                    .bytes(client.get(url.clone()), |x| {
+                                        let _ = pb.lock().unwrap().write(x);
+                                    })
+                                    .await?;
+                                key_cache.insert(url.to_string(), bytes.clone());
+                                bytes
+                            }
+                        } else {
+                            vec![]
+                        };
+
+                        *previous_key = Some(Keys {
+                            bytes,
+                            iv: key.iv.clone(),
+                            method: key.method.clone(),
+                        });
+                    } else {
+                        bail!("uri cannot be none when key method is {}", key.method);
+                    }
+                }
+                KeyMethod::Cenc => {
+                    // Periods of a stitched dash stream can use different keys.
+                    let default_kid = key
+                        .default_kid
+                        .as_ref()
+                        .map(|x| x.replace('-', "").to_lowercase())
+                        .or(stream.default_kid());
+                    let mut decryption_keys = HashMap::new();
+                    // Keys of AES-128 key uris aren't cenc keys.
+                    let keys = keys
+                        .iter()
+                        .filter(|x| x.0.as_ref().is_none_or(|x| is_key_id(x)));
+
+                    if all_keys {
+                        for key in keys {
+                            if let Some(kid) = &key.0 {
+                                decryption_keys.insert(kid.to_owned(), key.1.to_owned());
+                            } else if let Some(default_kid) = &default_kid {
+                                decryption_keys.insert(default_kid.to_owned(), key.1.to_owned());
+                            }
+                        }
+                    } else {
+                        for key in keys {
+                            if let Some(default_kid) = &default_kid {
+                                if let Some(kid) = &key.0 {
+                                    if default_kid == kid {
+                                        decryption_keys.insert(kid.to_owned(), key.1.to_owned());
+                                    }
+                                } else {
+                                    decryption_keys
+                                        .insert(default_kid.to_owned(), key.1.to_owned());
+                                }
+                            }
+                        }
+                    }
+
+                    if decryption_keys.is_empty() {
+                        bail!(
+                            "cannot determine keys to use, bypass this error using {} flag.",
+                            "--all-keys".colorize("bold green")
+                        );
+                    }
+
+                    for key in &decryption_keys {
+                        pb.lock().unwrap().write(format!(
+                            "        {} {}:{}",
+                            "Key".colorize("bold green"),
+                            key.0,
+                            key.1
+                        ))?;
+                    }
+
+                    *previous_key = Some(Keys::from_hex_keys(decryption_keys)?);
+                }
+                _ => *previous_key = None,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Find supplied key for an AES-128 key uri. Keys supplied for matching key uris
+/// (substring or glob pattern) are preferred over keys supplied without key ids.
+fn custom_key<'a>(keys: &'a [(Option<String>, String)], uri: &str) -> Option<&'a String> {
+    keys.iter()
+        .find(|(pattern, _)| match pattern {
+            Some(pattern) if !is_key_id(pattern) => {
+                uri.contains(pattern.as_str())
+                    || glob::Pattern::new(pattern).is_ok_and(|x| x.matches(uri))
+            }
+            _ => false,
+        })
+        .or_else(|| keys.iter().find(|x| x.0.is_none()))
+        .map(|x| &x.1)
+}
+
+/// List indices of segments which were skipped or replaced with filler.
+fn report_failures(
+    pb: &mut Progress,
+    policy: FailurePolicy,
+    failures: &[&SegmentError],
+) -> Result<()> {
+    if !failures.is_empty() {
+        pb.write(format!(
+            "    {} {} segments were {} ({})",
+            "Warning".colorize("bold yellow"),
+            failures.len(),
+            if policy == FailurePolicy::Filler {
+                "replaced with filler"
+            } else {
+                "skipped"
+            },
+            failure::indices(failures),
+        ))?;
+    }
+
+    Ok(())
+}
+
+fn segment_request(client: &Client, base_url: &Url, segment: &Segment) -> Result<RequestBuilder> {
+    let url = base_url.join(&segment.uri)?;
+    let mut request = client.get(url);
+
+    if let Some(range) = &segment.range {
+        request = request.header(header::RANGE, range.as_header_value());
+    }
+
+    Ok(request)
+}
+
+fn stream_file_path(
+    stream: &MediaPlaylist,
+    directory: &Option<PathBuf>,
+    output: &Option<String>,
+    one_stream: bool,
+    part: Option<usize>,
+) -> String {
+    let extension = if let Some(part) = part {
+        format!("part{}.{}", part, stream.extension())
+    } else {
+        stream.extension()
+    };
+
+    let mut temp_file = stream
+        .file_path(directory, &extension)
+        .to_string_lossy()
+        .to_string();
+
+    if let Some(output) = output {
+        if one_stream && part.is_none() && output.ends_with(&format!(".{}", stream.extension())) {
+            temp_file = output.to_owned();
+        }
+    }
+
+    temp_file
+}
+
 #[derive(Clone)]
 struct Keys {
     bytes: Vec<u8>,
@@ -718,32 +1142,59 @@ struct Keys {
 }
 
 impl Keys {
-    fn from_hex_keys(keys: HashMap<String, String>) -> Self {
-        let mut bytes = String::new();
+    /// Cenc keys are stored as consecutive 32 byte chunks of key id and key.
+    fn from_hex_keys(keys: HashMap<String, String>) -> Result<Self> {
+        let mut bytes = vec![];
 
         for (kid, key) in keys {
-            bytes += &(kid + ":" + &key + ";");
+            match hex::decode(&kid) {
+                Ok(x) if x.len() == 16 => bytes.extend_from_slice(&x),
+                _ => bail!("{} is not a valid key id.", kid),
+            }
+
+            match hex::decode(&key) {
+                Ok(x) if x.len() == 16 => bytes.extend_from_slice(&x),
+                _ => bail!("{} is not a valid key.", key),
+            }
         }
 
-        Self {
-            bytes: bytes.get(..(bytes.len() - 1)).unwrap().as_bytes().to_vec(),
+        Ok(Self {
+            bytes,
             iv: None,
             method: KeyMethod::Cenc,
-        }
+        })
     }
 
-    fn as_hex_keys(&self) -> HashMap<String, String> {
-        String::from_utf8(self.bytes.clone())
-            .unwrap()
-            .split(';')
-            .map(|x| {
-                x.split_once(':')
-                    .map(|(x, y)| (x.to_owned(), y.to_owned()))
-                    .unwrap()
-            })
+    #[cfg(feature = "bento4")]
+    fn as_cenc_keys(&self) -> Vec<mp4decrypt::Key> {
+        self.bytes
+            .chunks_exact(32)
+            .map(|x| mp4decrypt::Key::new(x[..16].try_into().unwrap(), x[16..].try_into().unwrap()))
             .collect()
     }
 
+    #[cfg(not(feature = "bento4"))]
+    fn as_cenc_keys(&self) -> HashMap<[u8; 16], [u8; 16]> {
+        self.bytes
+            .chunks_exact(32)
+            .map(|x| (x[..16].try_into().unwrap(), x[16..].try_into().unwrap()))
+            .collect()
+    }
+
+    /// Keys used for decrypting a segment. AES-128 and SAMPLE-AES keys without an iv
+    /// use media sequence number of segment as iv (in big-endian).
+    fn for_segment(&self, segment: &Segment) -> Self {
+        let mut keys = self.clone();
+
+        if keys.iv.is_none()
+            && (keys.method == KeyMethod::Aes128 || keys.method == KeyMethod::SampleAes)
+        {
+            keys.iv = Some(format!("0x{:032x}", segment.sequence));
+        }
+
+        keys
+    }
+
     fn decrypt(&self, mut data: Vec<u8>) -> Result<Vec<u8>> {
         Ok(match self.method {
             KeyMethod::Aes128 => {
@@ -755,8 +1206,29 @@ impl Keys {
 
                 utils::decrypt_aes_128_cbc(&mut data, &self.bytes, iv.as_ref())?
             }
-            KeyMethod::Cenc => {
-                mp4decrypt::mp4decrypt(&data, self.as_hex_keys(), None).map_err(|x| anyhow!(x))?
+            // Single file streams can be larger than what bento4 can decrypt in one go.
+            #[cfg(feature = "bento4")]
+            KeyMethod::Cenc if data.len() > u32::MAX as usize => {
+                let mut decrypted_data = Vec::with_capacity(data.len());
+                mp4decrypt::mp4decrypt_stream(
+                    &data[..],
+                    &mut decrypted_data,
+                    &self.as_cenc_keys(),
+                )?;
+                decrypted_data
+            }
+            #[cfg(feature = "bento4")]
+            KeyMethod::Cenc => mp4decrypt::mp4decrypt(&data, &self.as_cenc_keys(), None)?,
+            #[cfg(not(feature = "bento4"))]
+            KeyMethod::Cenc => vsd_mp4::decrypt(&data, &self.as_cenc_keys(), None)?,
+            KeyMethod::SampleAes => {
+                let iv = if let Some(iv) = &self.iv {
+                    Some(hex::decode(iv.trim_start_matches("0x"))?)
+                } else {
+                    None
+                };
+
+                sample_aes::decrypt(&data, &self.bytes, iv.as_ref())?
             }
             _ => data,
         })
@@ -786,143 +1258,129 @@ impl Iterator for PartialRangeIter {
         }
     }
 }
-struct ThreadData {
+struct SegmentTask {
     downloaded_bytes: usize,
+    extension: String,
+    file: String,
     index: usize,
     keys: Option<Keys>,
+    limiters: Vec<RateLimiter>,
     map: Option<Vec<u8>>,
     merger: Arc<Mutex<Merger>>,
-    pb: Arc<Mutex<RichProgress>>,
+    pb: Arc<Mutex<Progress>>,
+    policy: FailurePolicy,
     relative_size: usize,
     request: RequestBuilder,
     timer: Arc<Instant>,
-    total_retries: u8,
+    retry: RetryPolicy,
 }
 
-impl ThreadData {
-    fn execute(&self) -> Result<()> {
-        let mut segment = self.map.clone().unwrap_or(vec![]);
-        segment.append(&mut self.download_segment()?);
-
-        if let Some(keys) = &self.keys {
-            segment = keys.decrypt(segment)?;
+impl SegmentTask {
+    /// Process the segment and apply failure policy if it fails.
+    /// Failure is returned back when the policy allows to continue.
+    async fn execute(self) -> Result<Option<SegmentError>> {
+        let failure = match self.process().await {
+            Ok(()) => return Ok(None),
+            Err(e) => e,
+        };
+
+        if self.policy == FailurePolicy::Abort {
+            return Err(failure.into());
         }
 
-        let mut merger = self.merger.lock().unwrap();
-        merger.write(self.index, &segment)?;
-        merger.flush()?;
+        self.pb.lock().unwrap().write(format!(
+            "    {} {}, {}",
+            "Warning".colorize("bold yellow"),
+            failure,
+            if self.policy == FailurePolicy::Skip {
+                "skipping it"
+            } else {
+                "writing filler"
+            }
+        ))?;
 
-        self.notify(merger.stored(), merger.estimate())?;
-        Ok(())
+        self.write(self.policy.filler(&self.extension), true)
+            .await?;
+        Ok(Some(failure))
     }
 
-    fn download_segment(&self) -> Result<Vec<u8>> {
-        for i in 0..self.total_retries {
-            let response = match self.request.try_clone().unwrap().send() {
-                Ok(response) => response,
-                Err(error) => {
-                    let message = check_reqwest_error(&error);
-                    if let Err(e) = message {
-                        self.pb.lock().unwrap().write(format!("Warning!!!: bailing out early due to {:?}", e))?;
-                        return Ok(vec![]);
-                    } else {
-                        self.pb.lock().unwrap().write(message.unwrap())?;
-                    }
-                    continue;
-                }
-            };
-
-            let status = response.status();
+    async fn process(&self) -> Result<(), SegmentError> {
+        let mut segment = self.map.clone().unwrap_or(vec![]);
+        segment.append(
+            &mut self
+                .download_segment()
+                .await
+                .map_err(|x| self.error(SegmentErrorKind::Download, x))?,
+        );
 
-            if status.is_client_error() || status.is_server_error() {
-                self.pb
-                    .lock()
-                    .unwrap()
-                    .write(format!("failed to fetch segment attempt {} status {}", i, status))?;
-                // return Ok(vec![]);
-                continue;
+        if let Some(keys) = self.keys.clone() {
+            // Decryption is cpu bound, it should not block other downloads.
+            segment = match tokio::task::spawn_blocking(move || keys.decrypt(segment)).await {
+                Ok(result) => result,
+                Err(e) => Err(e.into()),
             }
+            .map_err(|x| self.error(SegmentErrorKind::Decrypt, x))?;
+        }
 
-            let data = match response.bytes() {
-                Ok(bytes) => bytes.to_vec(),
-                Err(err) => {
-                    // Treat body read/decoding errors as transient and retry
-                    self.pb
-                        .lock()
-                        .unwrap()
-                        .write(format!("    {} body read error: {} (retrying)", "Request".colorize("bold yellow"), err))?;
-                    continue;
-                }
-            };
-            let elapsed_time = self.timer.elapsed().as_secs() as usize;
+        self.write(segment, false)
+            .await
+            .map_err(|x| self.error(SegmentErrorKind::Write, x))
+    }
+
+    /// Write segment (or its filler) to file. Writing to file and journal blocks,
+    /// so it is done on a blocking thread instead of tokio workers.
+    async fn write(&self, data: Vec<u8>, filler: bool) -> Result<()> {
+        let merger = self.merger.clone();
+        let index = self.index;
+        let (stored, estimate) = tokio::task::spawn_blocking(move || -> Result<_> {
+            let mut merger = merger.lock().unwrap();
 
-            if elapsed_time != 0 {
-                let stored = self.merger.lock().unwrap().stored() + data.len();
-                self.pb.lock().unwrap().replace(
-                    12,
-                    Column::Text(format!(
-                        "[yellow]{}/s",
-                        utils::format_bytes(stored / elapsed_time, 2).2
-                    )),
-                );
+            if filler {
+                merger.write_filler(index, &data)?;
+            } else {
+                merger.write(index, &data)?;
             }
 
-            return Ok(data);
+            merger.flush()?;
+            Ok((merger.stored(), merger.estimate()))
+        })
+        .await??;
+
+        self.notify(stored, estimate)
+    }
+
+    fn error<T: std::fmt::Display>(&self, kind: SegmentErrorKind, reason: T) -> SegmentError {
+        SegmentError::new(&self.file, self.index, kind, reason)
+    }
+
+    async fn download_segment(&self) -> Result<Vec<u8>> {
+        let data = self
+            .retry
+            .bytes_limited(
+                self.request.try_clone().unwrap(),
+                &self.limiters,
+                |message| {
+                    let _ = self.pb.lock().unwrap().write(message);
+                },
+            )
+            .await?;
+        let elapsed_time = self.timer.elapsed().as_secs() as usize;
+        let stored = self.merger.lock().unwrap().stored() + data.len();
+
+        if let Some(speed) = stored.checked_div(elapsed_time) {
+            self.pb.lock().unwrap().set_speed(speed);
         }
 
-        self.pb.lock().unwrap().write("reached maximum number of retries to download a segment")?;
-        return Ok(vec![]);
+        Ok(data)
     }
 
     fn notify(&self, stored: usize, estimate: usize) -> Result<()> {
         let mut pb = self.pb.lock().unwrap();
-        pb.replace(
-            0,
-            Column::Text(format!(
-                "[bold blue]{}",
-                utils::format_download_bytes(
-                    self.downloaded_bytes + stored,
-                    self.downloaded_bytes + estimate + self.relative_size,
-                ),
-            )),
+        pb.set_bytes(
+            self.downloaded_bytes + stored,
+            Some(self.downloaded_bytes + estimate + self.relative_size),
         );
         pb.update(1).unwrap();
         Ok(())
     }
 }
-
-fn report(mut err: &(dyn std::error::Error + 'static)) -> String {
-    let mut s = format!("{}", err);
-    while let Some(src) = err.source() {
-        let _ = write!(s, "\n\nCaused by: {}", src);
-        err = src;
-    }
-    s
-}
-
-fn check_reqwest_error(error: &reqwest::Error) -> Result<String> {
-    let request = "Request".colorize("bold yellow");
-    let url = error.url().unwrap();
-
-    if error.is_timeout() {
-        return Ok(format!("    {} {} (timeout)", request, url));
-    } else if error.is_connect() {
-        return Ok(format!("    {} {} (connection error)", request, url));
-    }
-
-    if let Some(status) = error.status() {
-        match status {
-            StatusCode::REQUEST_TIMEOUT => Ok(format!("    {} {} (status timeout)", request, url)),
-            StatusCode::TOO_MANY_REQUESTS => {
-                Ok(format!("    {} {} (too many requests)", request, url))
-            }
-            StatusCode::SERVICE_UNAVAILABLE => {
-                Ok(format!("    {} {} (service unavailable)", request, url))
-            }
-            StatusCode::GATEWAY_TIMEOUT => Ok(format!("    {} {} (gateway timeout)", request, url)),
-            _ => Ok(format!("!!!!!!! unknown download failed {} (HTTP {})", url, status)),
-        }
-    } else {
-        bail!("!!!!!!!!!! no status download failed {}: {:#}", url, report(error))
-    }
-}
@@ -1,17 +1,23 @@
+use super::{InputMetadata, PlaylistFetcher, SelectedPlaylists};
 use crate::{
-    commands::Quality,
-    downloader::{InputMetadata, Prompts, SelectedPlaylists},
     playlist::{MasterPlaylist, MediaPlaylist, PlaylistType},
+    progress::Progress,
     utils
 };
 use anyhow::{anyhow, bail, Result};
-use reqwest::{blocking::Client, Url};
 
-pub fn parse_all_streams(
-    base_url: Option<Url>,
-    client: &Client,
+pub(super) async fn parse_all_streams(
+    fetcher: &PlaylistFetcher,
     meta: &InputMetadata,
 ) -> Result<MasterPlaylist> {
+    let PlaylistFetcher {
+        base_url,
+        client,
+        retry,
+        ..
+    } = fetcher;
+    let pb = Progress::new(fetcher.progress.clone());
+
     match meta.pl_type {
         Some(PlaylistType::Dash) => {
             let mpd = dash_mpd::parse(&meta.text).map_err(|x| {
@@ -25,10 +31,13 @@ pub fn parse_all_streams(
 
             for stream in playlist.streams.iter_mut() {
                 crate::dash::push_segments(
+                    client,
+                    retry,
                     &mpd,
                     stream,
                     base_url.as_ref().unwrap_or(&meta.url).as_str(),
-                )?;
+                )
+                .await?;
                 stream.uri = meta.url.as_ref().to_owned();
             }
 
@@ -50,8 +59,10 @@ pub fn parse_all_streams(
                         let decoded = utils::decode_base64(bs)?;
                         text = String::from_utf8(decoded)?;
                     } else {
-                        let response = client.get(&stream.uri).send()?;
-                        text = response.text()?;
+                        let response = retry
+                            .send(client.get(&stream.uri), |x| pb.print(x))
+                            .await?;
+                        text = response.text().await?;
                     }
 
                     let media_playlist = m3u8_rs::parse_media_playlist_res(text.as_bytes())
@@ -91,15 +102,18 @@ pub fn parse_all_streams(
     }
 }
 
-pub fn parse_selected_streams(
-    base_url: Option<Url>,
-    client: &Client,
+pub(super) async fn parse_selected_streams(
+    fetcher: &PlaylistFetcher,
     meta: &InputMetadata,
-    prefer_audio_lang: Option<String>,
-    prefer_subs_lang: Option<String>,
-    prompts: &Prompts,
-    quality: Quality,
 ) -> Result<SelectedPlaylists> {
+    let PlaylistFetcher {
+        base_url,
+        client,
+        retry,
+        ..
+    } = fetcher;
+    let pb = Progress::new(fetcher.progress.clone());
+
     match meta.pl_type {
         Some(PlaylistType::Dash) => {
             let mpd = dash_mpd::parse(&meta.text).map_err(|x| {
@@ -111,18 +125,30 @@ pub fn parse_selected_streams(
             })?;
             let (mut video_audio_streams, mut subtitle_streams) =
                 crate::dash::parse_as_master(&mpd, meta.url.as_ref())
-                    .sort_streams(prefer_audio_lang, prefer_subs_lang)
-                    .select_streams(quality, prompts.skip, prompts.raw)?;
+                    .sort_streams(
+                        fetcher.prefer_audio_lang.clone(),
+                        fetcher.prefer_subs_lang.clone(),
+                    )
+                    .select_streams(
+                        fetcher.quality.clone(),
+                        &fetcher.selectors,
+                        fetcher.skip_prompts,
+                        fetcher.raw_prompts,
+                        &pb,
+                    )?;
 
             for stream in video_audio_streams
                 .iter_mut()
                 .chain(subtitle_streams.iter_mut())
             {
                 crate::dash::push_segments(
+                    client,
+                    retry,
                     &mpd,
                     stream,
                     base_url.as_ref().unwrap_or(&meta.url).as_str(),
-                )?;
+                )
+                .await?;
                 stream.uri = meta.url.as_ref().to_owned();
             }
 
@@ -132,8 +158,17 @@ pub fn parse_selected_streams(
             Ok(m3u8_rs::Playlist::MasterPlaylist(m3u8)) => {
                 let (mut video_audio_streams, mut subtitle_streams) =
                     crate::hls::parse_as_master(&m3u8, meta.url.as_str())
-                        .sort_streams(prefer_audio_lang, prefer_subs_lang)
-                        .select_streams(quality, prompts.skip, prompts.raw)?;
+                        .sort_streams(
+                            fetcher.prefer_audio_lang.clone(),
+                            fetcher.prefer_subs_lang.clone(),
+                        )
+                        .select_streams(
+                            fetcher.quality.clone(),
+                            &fetcher.selectors,
+                            fetcher.skip_prompts,
+                            fetcher.raw_prompts,
+                            &pb,
+                        )?;
 
                 for stream in video_audio_streams
                     .iter_mut()
@@ -150,8 +185,10 @@ pub fn parse_selected_streams(
                         let decoded = utils::decode_base64(bs)?;
                         text = String::from_utf8(decoded)?;
                     } else {
-                        let response = client.get(&stream.uri).send()?;
-                        text = response.text()?;
+                        let response = retry
+                            .send(client.get(&stream.uri), |x| pb.print(x))
+                            .await?;
+                        text = response.text().await?;
                     }
 
                     let media_playlist = m3u8_rs::parse_media_playlist_res(text.as_bytes())
@@ -0,0 +1,830 @@
+/*
+    REFERENCES
+    ----------
+
+    1. https://developer.apple.com/library/archive/documentation/AudioVideo/Conceptual/HLS_Sample_Encryption/Encryption/Encryption.html
+    2. https://github.com/video-dev/hls.js/blob/master/src/demux/sample-aes.ts
+    3. https://en.wikipedia.org/wiki/MPEG_transport_stream
+    4. https://wiki.multimedia.cx/index.php/ADTS
+    5. https://github.com/FFmpeg/FFmpeg/blob/master/libavcodec/ac3tab.c
+
+*/
+
+use aes::cipher::{block_padding::NoPadding, BlockDecryptMut, KeyIvInit};
+use anyhow::{anyhow, bail, Result};
+use std::collections::{HashMap, HashSet};
+
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+
+const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+
+// AC-3 frame sizes in 16-bit words for 48, 44.1 and 32 kHz sample rates indexed by frmsizecod.
+const AC3_FRAME_SIZES: [[u16; 38]; 3] = [
+    [
+        64, 64, 80, 80, 96, 96, 112, 112, 128, 128, 160, 160, 192, 192, 224, 224, 256, 256, 320,
+        320, 384, 384, 448, 448, 512, 512, 640, 640, 768, 768, 896, 896, 1024, 1024, 1152, 1152,
+        1280, 1280,
+    ],
+    [
+        69, 70, 87, 88, 104, 105, 121, 122, 139, 140, 174, 175, 208, 209, 243, 244, 278, 279, 348,
+        349, 417, 418, 487, 488, 557, 558, 696, 697, 835, 836, 975, 976, 1114, 1115, 1253, 1254,
+        1393, 1394,
+    ],
+    [
+        96, 96, 120, 120, 144, 144, 168, 168, 192, 192, 240, 240, 288, 288, 336, 336, 384, 384,
+        480, 480, 576, 576, 672, 672, 768, 768, 960, 960, 1152, 1152, 1344, 1344, 1536, 1536, 1728,
+        1728, 1920, 1920,
+    ],
+];
+
+#[derive(Clone, Copy)]
+enum Codec {
+    Aac,
+    Ac3,
+    H264,
+}
+
+impl Codec {
+    /// Map stream type of an encrypted elementary stream to its codec and clear stream type.
+    fn from_stream_type(stream_type: u8) -> Option<(Self, u8)> {
+        match stream_type {
+            0xdb => Some((Self::H264, 0x1b)),
+            0xcf => Some((Self::Aac, 0x0f)),
+            0xc1 => Some((Self::Ac3, 0x81)),
+            0xc2 => Some((Self::Ac3, 0x87)),
+            _ => None,
+        }
+    }
+}
+
+struct Pes {
+    adaptation_field: Vec<u8>,
+    continuity_counter: u8,
+    data: Vec<u8>,
+}
+
+struct Decrypter {
+    key: [u8; 16],
+    iv: [u8; 16],
+}
+
+impl Decrypter {
+    /// Decrypt consecutive 16 bytes blocks in place, cipher block chaining is reset for every call.
+    fn decrypt_blocks(&self, data: &mut [u8]) -> Result<()> {
+        Aes128CbcDec::new(&self.key.into(), &self.iv.into())
+            .decrypt_padded_mut::<NoPadding>(data)
+            .map_err(|x| anyhow!("{}", x))?;
+        Ok(())
+    }
+
+    /// Audio samples have 16 bytes of unencrypted leader followed by encrypted blocks.
+    /// Last partial block (less than 16 bytes) is left unencrypted.
+    fn decrypt_audio_sample(&self, sample: &mut [u8]) -> Result<()> {
+        if sample.len() <= 16 {
+            return Ok(());
+        }
+
+        let end = sample.len() - (sample.len() % 16);
+        self.decrypt_blocks(&mut sample[16..end])
+    }
+
+    /// Encrypted slice nal units have 32 bytes of unencrypted leader and then a single 16 bytes encrypted block
+    /// followed by up to 144 bytes of unencrypted data, repeated till the end of nal unit.
+    /// A block is only encrypted if more than 16 bytes remain, so a final 16 bytes block is left clear.
+    /// Emulation prevention is applied after encryption so it should be removed first.
+    fn decrypt_nal_unit(&self, nal_unit: &[u8]) -> Result<Vec<u8>> {
+        let mut nal_unit = remove_emulation_prevention(nal_unit);
+        let mut blocks = vec![];
+        let mut pos = 32;
+
+        while pos + 16 < nal_unit.len() {
+            blocks.extend_from_slice(&nal_unit[pos..(pos + 16)]);
+            pos += 160;
+        }
+
+        self.decrypt_blocks(&mut blocks)?;
+
+        for (i, block) in blocks.chunks(16).enumerate() {
+            let pos = 32 + (i * 160);
+            nal_unit[pos..(pos + 16)].copy_from_slice(block);
+        }
+
+        Ok(nal_unit)
+    }
+
+    fn decrypt_h264(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut starts = vec![];
+        let mut i = 0;
+
+        while i + 3 <= data.len() {
+            if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+                starts.push(i + 3);
+                i += 3;
+            } else {
+                i += 1;
+            }
+        }
+
+        let mut output = Vec::with_capacity(data.len());
+        let mut last = 0;
+
+        for (i, start) in starts.iter().enumerate() {
+            let mut end = starts.get(i + 1).map(|x| x - 3).unwrap_or(data.len());
+
+            // Nal units never end with zero bytes, they belong to next start code.
+            while end > *start && data[end - 1] == 0 {
+                end -= 1;
+            }
+
+            output.extend_from_slice(&data[last..*start]);
+            let nal_unit = &data[*start..end];
+
+            // Only non-idr (1) and idr (5) slices which are longer than 48 bytes are encrypted.
+            if nal_unit.len() > 48 && matches!(nal_unit[0] & 0x1f, 1 | 5) {
+                output.extend_from_slice(&self.decrypt_nal_unit(nal_unit)?);
+            } else {
+                output.extend_from_slice(nal_unit);
+            }
+
+            last = end;
+        }
+
+        output.extend_from_slice(&data[last..]);
+        Ok(output)
+    }
+
+    fn decrypt_adts(&self, data: &mut [u8]) -> Result<()> {
+        let mut pos = 0;
+
+        while pos + 7 <= data.len() {
+            if data[pos] != 0xff || (data[pos + 1] & 0xf0) != 0xf0 {
+                pos += 1;
+                continue;
+            }
+
+            let header_size = if (data[pos + 1] & 0x01) == 1 { 7 } else { 9 };
+            let frame_size = (((data[pos + 3] & 0x03) as usize) << 11)
+                | ((data[pos + 4] as usize) << 3)
+                | ((data[pos + 5] as usize) >> 5);
+
+            if frame_size < header_size || pos + frame_size > data.len() {
+                break;
+            }
+
+            self.decrypt_audio_sample(&mut data[(pos + header_size)..(pos + frame_size)])?;
+            pos += frame_size;
+        }
+
+        Ok(())
+    }
+
+    fn decrypt_ac3(&self, data: &mut [u8]) -> Result<()> {
+        let mut pos = 0;
+
+        while pos + 6 <= data.len() {
+            if data[pos] != 0x0b || data[pos + 1] != 0x77 {
+                pos += 1;
+                continue;
+            }
+
+            let bsid = data[pos + 5] >> 3;
+            let frame_size = if bsid > 10 {
+                // E-AC-3
+                (((((data[pos + 2] & 0x07) as usize) << 8) | data[pos + 3] as usize) + 1) * 2
+            } else {
+                let fscod = (data[pos + 4] >> 6) as usize;
+                let frmsizecod = (data[pos + 4] & 0x3f) as usize;
+
+                if fscod > 2 || frmsizecod > 37 {
+                    pos += 1;
+                    continue;
+                }
+
+                AC3_FRAME_SIZES[fscod][frmsizecod] as usize * 2
+            };
+
+            if pos + frame_size > data.len() {
+                break;
+            }
+
+            self.decrypt_audio_sample(&mut data[pos..(pos + frame_size)])?;
+            pos += frame_size;
+        }
+
+        Ok(())
+    }
+
+    fn decrypt_pes_buffer(&self, mut pes: Pes, codec: Codec) -> Result<Pes> {
+        pes.data = self.decrypt_pes(pes.data, codec)?;
+        Ok(pes)
+    }
+
+    fn decrypt_pes(&self, mut data: Vec<u8>, codec: Codec) -> Result<Vec<u8>> {
+        if data.len() < 9 || data[..3] != [0, 0, 1] {
+            return Ok(data);
+        }
+
+        let header_size = 9 + data[8] as usize;
+
+        if header_size > data.len() {
+            return Ok(data);
+        }
+
+        match codec {
+            Codec::Aac => self.decrypt_adts(&mut data[header_size..])?,
+            Codec::Ac3 => self.decrypt_ac3(&mut data[header_size..])?,
+            Codec::H264 => {
+                let payload = self.decrypt_h264(&data[header_size..])?;
+                data.truncate(header_size);
+                data.extend_from_slice(&payload);
+
+                // Update PES_packet_length when it is bounded.
+                if data[4] != 0 || data[5] != 0 {
+                    let size = u16::try_from(data.len() - 6).unwrap_or(0);
+                    data[4..6].copy_from_slice(&size.to_be_bytes());
+                }
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+/// Decrypt SAMPLE-AES encrypted MPEG-TS segment (or packed audio segment).
+pub(super) fn decrypt(data: &[u8], key: &[u8], iv: Option<&Vec<u8>>) -> Result<Vec<u8>> {
+    if key.len() != 16 {
+        bail!("invalid key size i.e. {} but expected size 16.", key.len());
+    }
+
+    let mut decrypter = Decrypter {
+        key: [0; 16],
+        iv: [0; 16],
+    };
+    decrypter.key.copy_from_slice(key);
+
+    if let Some(iv) = iv {
+        if iv.len() != 16 {
+            bail!("invalid iv size i.e. {} but expected size 16.", iv.len());
+        }
+
+        decrypter.iv.copy_from_slice(iv);
+    }
+
+    if data.first() != Some(&SYNC_BYTE) {
+        return decrypt_packed_audio(&decrypter, data);
+    }
+
+    let mut output = Vec::with_capacity(data.len());
+    let mut pmt_pids = HashSet::new();
+    let mut encrypted_pids = HashMap::new();
+    let mut pes_buffers = HashMap::new();
+
+    for packet in data.chunks(PACKET_SIZE) {
+        if packet.len() != PACKET_SIZE || packet[0] != SYNC_BYTE {
+            bail!("invalid mpeg-ts packet, expected sync byte at every 188 bytes.");
+        }
+
+        let pusi = (packet[1] & 0x40) != 0;
+        let pid = (((packet[1] & 0x1f) as u16) << 8) | packet[2] as u16;
+        let adaptation_field_control = (packet[3] >> 4) & 0x03;
+        let mut offset = 4;
+        let mut adaptation_field = &packet[4..4];
+
+        if (adaptation_field_control & 0x02) != 0 {
+            let end = (5 + packet[4] as usize).min(PACKET_SIZE);
+            adaptation_field = &packet[4..end];
+            offset = end;
+        }
+
+        let payload = if (adaptation_field_control & 0x01) != 0 {
+            &packet[offset..]
+        } else {
+            &packet[PACKET_SIZE..]
+        };
+
+        if pid == 0 && pusi {
+            for pmt_pid in parse_pat(payload) {
+                pmt_pids.insert(pmt_pid);
+            }
+
+            output.extend_from_slice(packet);
+        } else if pmt_pids.contains(&pid) && pusi {
+            let mut packet = packet.to_vec();
+
+            for (es_pid, codec) in patch_pmt(&mut packet[offset..]) {
+                encrypted_pids.insert(es_pid, codec);
+            }
+
+            output.extend_from_slice(&packet);
+        } else if let Some(codec) = encrypted_pids.get(&pid) {
+            if pusi {
+                if let Some(pes) = pes_buffers.remove(&pid) {
+                    write_pes(&mut output, pid, decrypter.decrypt_pes_buffer(pes, *codec)?);
+                }
+
+                pes_buffers.insert(
+                    pid,
+                    Pes {
+                        adaptation_field: adaptation_field.to_vec(),
+                        continuity_counter: packet[3] & 0x0f,
+                        data: payload.to_vec(),
+                    },
+                );
+            } else if let Some(pes) = pes_buffers.get_mut(&pid) {
+                pes.data.extend_from_slice(payload);
+            } else {
+                output.extend_from_slice(packet);
+            }
+        } else {
+            output.extend_from_slice(packet);
+        }
+    }
+
+    let mut pids = pes_buffers.keys().copied().collect::<Vec<_>>();
+    pids.sort();
+
+    for pid in pids {
+        let pes = pes_buffers.remove(&pid).unwrap();
+        write_pes(
+            &mut output,
+            pid,
+            decrypter.decrypt_pes_buffer(pes, encrypted_pids[&pid])?,
+        );
+    }
+
+    Ok(output)
+}
+
+/// Packed audio segments are raw ADTS or AC-3 frames prefixed with an ID3 tag.
+fn decrypt_packed_audio(decrypter: &Decrypter, data: &[u8]) -> Result<Vec<u8>> {
+    let mut output = data.to_vec();
+    let mut pos = 0;
+
+    while output.len() >= pos + 10 && &output[pos..(pos + 3)] == b"ID3" {
+        let size = output[(pos + 6)..(pos + 10)]
+            .iter()
+            .fold(0, |size, x| (size << 7) | (*x & 0x7f) as usize);
+        pos += 10 + size;
+    }
+
+    if pos >= output.len() {
+        return Ok(output);
+    }
+
+    if output[pos] == 0x0b && output.get(pos + 1) == Some(&0x77) {
+        decrypter.decrypt_ac3(&mut output[pos..])?;
+    } else {
+        decrypter.decrypt_adts(&mut output[pos..])?;
+    }
+
+    Ok(output)
+}
+
+/// Returns program map table pids.
+fn parse_pat(payload: &[u8]) -> Vec<u16> {
+    let mut pids = vec![];
+
+    if let Some(pointer) = payload.first() {
+        let section = &payload[(1 + *pointer as usize).min(payload.len())..];
+
+        if section.len() >= 8 {
+            let section_length = (((section[1] & 0x0f) as usize) << 8) | section[2] as usize;
+            let end = (3 + section_length).saturating_sub(4).min(section.len());
+            let mut pos = 8;
+
+            while pos + 4 <= end {
+                let program_number = ((section[pos] as u16) << 8) | section[pos + 1] as u16;
+
+                if program_number != 0 {
+                    pids.push((((section[pos + 2] & 0x1f) as u16) << 8) | section[pos + 3] as u16);
+                }
+
+                pos += 4;
+            }
+        }
+    }
+
+    pids
+}
+
+/// Replace encrypted stream types with their clear stream types and returns encrypted elementary stream pids.
+/// Program map table section is expected to fit in a single packet.
+fn patch_pmt(payload: &mut [u8]) -> Vec<(u16, Codec)> {
+    let mut pids = vec![];
+
+    let pointer = match payload.first() {
+        Some(x) => *x as usize,
+        None => return pids,
+    };
+
+    let start = (1 + pointer).min(payload.len());
+    let section = &mut payload[start..];
+
+    if section.len() < 12 {
+        return pids;
+    }
+
+    let section_length = (((section[1] & 0x0f) as usize) << 8) | section[2] as usize;
+    let crc_pos = (3 + section_length).saturating_sub(4);
+
+    if crc_pos + 4 > section.len() {
+        return pids;
+    }
+
+    let program_info_length = (((section[10] & 0x0f) as usize) << 8) | section[11] as usize;
+    let mut pos = 12 + program_info_length;
+
+    while pos + 5 <= crc_pos {
+        let es_pid = (((section[pos + 1] & 0x1f) as u16) << 8) | section[pos + 2] as u16;
+        let es_info_length =
+            (((section[pos + 3] & 0x0f) as usize) << 8) | section[pos + 4] as usize;
+
+        if let Some((codec, stream_type)) = Codec::from_stream_type(section[pos]) {
+            section[pos] = stream_type;
+            pids.push((es_pid, codec));
+        }
+
+        pos += 5 + es_info_length;
+    }
+
+    if !pids.is_empty() {
+        let crc = crc32_mpeg2(&section[..crc_pos]);
+        section[crc_pos..(crc_pos + 4)].copy_from_slice(&crc.to_be_bytes());
+    }
+
+    pids
+}
+
+fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffff_u32;
+
+    for byte in data {
+        crc ^= (*byte as u32) << 24;
+
+        for _ in 0..8 {
+            crc = if (crc & 0x80000000) != 0 {
+                (crc << 1) ^ 0x04c11db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+fn remove_emulation_prevention(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+
+    for byte in data {
+        if zeros >= 2 && *byte == 3 {
+            zeros = 0;
+            continue;
+        }
+
+        zeros = if *byte == 0 { zeros + 1 } else { 0 };
+        output.push(*byte);
+    }
+
+    output
+}
+
+/// Split PES packet into transport stream packets. Adaptation field of first packet is kept as it is
+/// (it may contain PCR) and last packet is padded using adaptation field stuffing bytes.
+fn write_pes(output: &mut Vec<u8>, pid: u16, pes: Pes) {
+    let mut continuity_counter = pes.continuity_counter;
+    let mut pos = 0;
+    let mut first = true;
+
+    while first || pos < pes.data.len() {
+        let mut adaptation_field = if first && pes.adaptation_field.len() < 184 {
+            pes.adaptation_field.clone()
+        } else {
+            vec![]
+        };
+
+        let remaining = pes.data.len() - pos;
+        let capacity = 184 - adaptation_field.len();
+
+        if remaining < capacity {
+            let padding = capacity - remaining;
+
+            if adaptation_field.is_empty() {
+                adaptation_field.push((padding - 1) as u8);
+
+                if padding > 1 {
+                    adaptation_field.push(0x00);
+                    adaptation_field.resize(padding, 0xff);
+                }
+            } else if adaptation_field.len() == 1 {
+                adaptation_field[0] = padding as u8;
+                adaptation_field.push(0x00);
+                adaptation_field.resize(padding + 1, 0xff);
+            } else {
+                adaptation_field[0] += padding as u8;
+                adaptation_field.resize(adaptation_field.len() + padding, 0xff);
+            }
+        }
+
+        let size = remaining.min(184 - adaptation_field.len());
+
+        output.push(SYNC_BYTE);
+        output.push(if first { 0x40 } else { 0x00 } | ((pid >> 8) as u8 & 0x1f));
+        output.push(pid as u8);
+        output.push(
+            if adaptation_field.is_empty() {
+                0x10
+            } else {
+                0x30
+            } | continuity_counter,
+        );
+        output.extend_from_slice(&adaptation_field);
+        output.extend_from_slice(&pes.data[pos..(pos + size)]);
+
+        continuity_counter = (continuity_counter + 1) & 0x0f;
+        pos += size;
+        first = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Blocks are encrypted using openssl with key 000102030405060708090a0b0c0d0e0f.
+    const KEY: [u8; 16] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f,
+    ];
+    const IV: [u8; 16] = [
+        0xf0, 0xe0, 0xd0, 0xc0, 0xb0, 0xa0, 0x90, 0x80, 0x70, 0x60, 0x50, 0x40, 0x30, 0x20, 0x10,
+        0x00,
+    ];
+    /// Blocks at 32 and 192 of idr slice nal unit, encrypted as a single chain.
+    const VIDEO_BLOCKS: &str = "6ef01da352b45013df440e3c3882bbedbe64b2cc326b54bad02596586355f5e1";
+    /// Blocks at 16 of first and second adts frame payloads.
+    const ADTS_BLOCKS: [&str; 2] = [
+        "35760540f6143d6d133c1b9e3ae7cce4",
+        "4fd2c0d56453af79e0cf3a1c78bade1f",
+    ];
+    /// Blocks from 16 to 128 of ac-3 frame.
+    const AC3_BLOCKS: &str = "05c4332e4df7a8afedb6f27bfa884aad3c07e153ced150ef02d89dce64578006\
+        335d801da85a3e85def9a7e4ddec03f4485215b2f733c7de8d66ae5395031318e2fa9eb24430b32d05cf7d\
+        0ae58c94d6e4d3bf4e408826d11888f773ca9db012e9240662d680f018a5715fad2a7fd49e";
+    /// Adaptation field with PCR.
+    const PCR: [u8; 8] = [0x07, 0x10, 0x00, 0x00, 0x01, 0x00, 0x7e, 0x00];
+
+    fn unhex(data: &str) -> Vec<u8> {
+        (0..data.len())
+            .step_by(2)
+            .map(|x| u8::from_str_radix(&data[x..(x + 2)], 16).unwrap())
+            .collect()
+    }
+
+    fn idr_nal_unit() -> Vec<u8> {
+        let mut nal_unit = (0..218).map(|x| (x % 250) as u8 + 1).collect::<Vec<_>>();
+        nal_unit[0] = 0x65;
+        // Emulation prevention byte of clear data.
+        nal_unit[10..14].copy_from_slice(&[0x00, 0x00, 0x03, 0x01]);
+        nal_unit
+    }
+
+    fn adts_frame(index: usize) -> Vec<u8> {
+        let mut frame = vec![0xff, 0xf1, 0x50, 0x80, 0x05, 0xff, 0xfc];
+        frame.extend((0..40).map(|x| (x + 16 * index) as u8));
+        frame
+    }
+
+    fn ac3_frame() -> Vec<u8> {
+        let mut frame = vec![0x0b, 0x77, 0x00, 0x00, 0x00, 0x40];
+        frame.extend((6..128).map(|x| (x * 5) as u8));
+        frame
+    }
+
+    /// Replace clear data in ranges (start and end) with encrypted blocks.
+    fn encrypt(data: &[u8], ranges: &[(usize, usize)], blocks: &str) -> Vec<u8> {
+        let blocks = unhex(blocks);
+        let mut data = data.to_vec();
+        let mut pos = 0;
+
+        for (start, end) in ranges {
+            data[*start..*end].copy_from_slice(&blocks[pos..(pos + end - start)]);
+            pos += end - start;
+        }
+
+        data
+    }
+
+    fn add_emulation_prevention(data: &[u8]) -> Vec<u8> {
+        let mut output = vec![];
+        let mut zeros = 0;
+
+        for byte in data {
+            if zeros >= 2 && *byte <= 3 {
+                output.push(3);
+                zeros = 0;
+            }
+
+            zeros = if *byte == 0 { zeros + 1 } else { 0 };
+            output.push(*byte);
+        }
+
+        output
+    }
+
+    fn pes(stream_id: u8, payload: &[u8]) -> Vec<u8> {
+        let mut pes = vec![0x00, 0x00, 0x01, stream_id];
+        pes.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        pes.extend_from_slice(&[0x80, 0x80, 0x05, 0x21, 0x00, 0x01, 0x00, 0x01]);
+        pes.extend_from_slice(payload);
+        pes
+    }
+
+    fn video_pes(nal_unit: &[u8]) -> Vec<u8> {
+        // Access unit delimiter followed by slice.
+        let payload = [
+            &[0x00, 0x00, 0x00, 0x01, 0x09, 0xf0, 0x00, 0x00, 0x01],
+            nal_unit,
+        ]
+        .concat();
+        pes(0xe0, &payload)
+    }
+
+    fn psi_packet(pid: u16, section: &[u8]) -> Vec<u8> {
+        let mut packet = vec![SYNC_BYTE, 0x40 | (pid >> 8) as u8, pid as u8, 0x10, 0x00];
+        packet.extend_from_slice(section);
+        packet.extend_from_slice(&crc32_mpeg2(section).to_be_bytes());
+        packet.resize(PACKET_SIZE, 0xff);
+        packet
+    }
+
+    fn pat() -> Vec<u8> {
+        psi_packet(
+            0,
+            &[
+                0x00, 0xb0, 0x0d, 0x00, 0x01, 0xc1, 0x00, 0x00, 0x00, 0x01, 0xf0, 0x00,
+            ],
+        )
+    }
+
+    fn pmt(video_stream_type: u8, audio_stream_type: u8) -> Vec<u8> {
+        psi_packet(
+            0x1000,
+            &[
+                0x02,
+                0xb0,
+                0x17,
+                0x00,
+                0x01,
+                0xc1,
+                0x00,
+                0x00,
+                0xe1,
+                0x00,
+                0xf0,
+                0x00,
+                video_stream_type,
+                0xe1,
+                0x00,
+                0xf0,
+                0x00,
+                audio_stream_type,
+                0xe1,
+                0x01,
+                0xf0,
+                0x00,
+            ],
+        )
+    }
+
+    /// Split PES packet into transport stream packets, last packet is padded using stuffing bytes.
+    fn packets(pid: u16, adaptation_field: &[u8], pes: &[u8]) -> Vec<u8> {
+        let mut output = vec![];
+        let mut pos = 0;
+
+        for counter in 0.. {
+            if pos == pes.len() {
+                break;
+            }
+
+            let mut adaptation_field = if counter == 0 {
+                adaptation_field.to_vec()
+            } else {
+                vec![]
+            };
+            let size = (pes.len() - pos).min(184 - adaptation_field.len());
+            let stuffing = 184 - adaptation_field.len() - size;
+
+            match (stuffing, adaptation_field.is_empty()) {
+                (0, _) => (),
+                (1, true) => adaptation_field.push(0),
+                (_, true) => {
+                    adaptation_field = vec![stuffing as u8 - 1, 0x00];
+                    adaptation_field.resize(stuffing, 0xff);
+                }
+                (_, false) => {
+                    adaptation_field[0] += stuffing as u8;
+                    adaptation_field.resize(adaptation_field.len() + stuffing, 0xff);
+                }
+            }
+
+            output.extend_from_slice(&[
+                SYNC_BYTE,
+                if counter == 0 { 0x40 } else { 0x00 } | (pid >> 8) as u8,
+                pid as u8,
+                if adaptation_field.is_empty() {
+                    0x10
+                } else {
+                    0x30
+                } | counter,
+            ]);
+            output.extend_from_slice(&adaptation_field);
+            output.extend_from_slice(&pes[pos..(pos + size)]);
+            pos += size;
+        }
+
+        output
+    }
+
+    #[test]
+    fn crc32() {
+        assert_eq!(crc32_mpeg2(b"123456789"), 0x0376e6e7);
+    }
+
+    #[test]
+    fn emulation_prevention() {
+        let data = [0x65, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x03, 0x03, 0x01];
+        assert_eq!(
+            remove_emulation_prevention(&data),
+            [0x65, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x01]
+        );
+        assert_eq!(
+            remove_emulation_prevention(&add_emulation_prevention(&idr_nal_unit())),
+            idr_nal_unit()
+        );
+    }
+
+    #[test]
+    fn decrypt_ts() {
+        let nal_unit = idr_nal_unit();
+        let encrypted_nal_unit =
+            add_emulation_prevention(&encrypt(&nal_unit, &[(32, 48), (192, 208)], VIDEO_BLOCKS));
+        assert_eq!(encrypted_nal_unit.len(), nal_unit.len() + 1);
+
+        let frames = [adts_frame(0), adts_frame(1)];
+        let encrypted_frames = frames
+            .iter()
+            .zip(ADTS_BLOCKS)
+            .map(|(x, y)| encrypt(x, &[(23, 39)], y))
+            .collect::<Vec<_>>();
+
+        let input = [
+            pat(),
+            pmt(0xdb, 0xcf),
+            packets(0x100, &PCR, &video_pes(&encrypted_nal_unit)),
+            packets(0x101, &[], &pes(0xc0, &encrypted_frames.concat())),
+        ]
+        .concat();
+        let expected = [
+            pat(),
+            pmt(0x1b, 0x0f),
+            packets(0x100, &PCR, &video_pes(&nal_unit)),
+            packets(0x101, &[], &pes(0xc0, &frames.concat())),
+        ]
+        .concat();
+
+        let output = decrypt(&input, &KEY, Some(&IV.to_vec())).unwrap();
+        assert_eq!(output.len(), expected.len());
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn decrypt_nal_unit_tail() {
+        // Exactly 16 bytes remain after the second 160 bytes run, which are left unencrypted.
+        let nal_unit = idr_nal_unit()[..208].to_vec();
+        let encrypted_nal_unit =
+            add_emulation_prevention(&encrypt(&nal_unit, &[(32, 48)], &VIDEO_BLOCKS[..32]));
+        let decrypter = Decrypter { key: KEY, iv: IV };
+
+        let output = decrypter.decrypt_nal_unit(&encrypted_nal_unit).unwrap();
+        assert_eq!(output[192..], nal_unit[192..]);
+        assert_eq!(output, nal_unit);
+    }
+
+    #[test]
+    fn decrypt_packed_audio() {
+        let id3 = [
+            b"ID3".as_slice(),
+            &[0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04],
+            &[0; 4],
+        ]
+        .concat();
+        let frame = ac3_frame();
+        let input = [id3.clone(), encrypt(&frame, &[(16, 128)], AC3_BLOCKS)].concat();
+
+        let output = decrypt(&input, &KEY, Some(&IV.to_vec())).unwrap();
+        assert_eq!(output, [id3, frame].concat());
+    }
+}
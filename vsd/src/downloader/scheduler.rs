@@ -0,0 +1,95 @@
+use super::SegmentError;
+use anyhow::{anyhow, bail, Result};
+use std::{future::Future, sync::Arc};
+use tokio::{sync::Semaphore, task::JoinSet};
+use tokio_util::sync::CancellationToken;
+
+/// Run segment downloads as tokio tasks with at most `concurrency` of them in flight.
+/// Remaining tasks are aborted when any one of them fails, the cancellation token is
+/// cancelled or the scheduler is dropped.
+///
+/// Tasks resolve to a [`SegmentError`] when a segment failed but its failure policy
+/// allowed to continue, these failures are collected for reporting.
+pub(super) struct Scheduler {
+    cancel: CancellationToken,
+    failures: Vec<SegmentError>,
+    semaphore: Arc<Semaphore>,
+    tasks: JoinSet<Result<Option<SegmentError>>>,
+}
+
+impl Scheduler {
+    pub(super) fn new(concurrency: usize, cancel: &CancellationToken) -> Self {
+        Self {
+            cancel: cancel.child_token(),
+            failures: vec![],
+            semaphore: Arc::new(Semaphore::new(concurrency.max(1))),
+            tasks: JoinSet::new(),
+        }
+    }
+
+    /// Wait for a free slot and then spawn the task.
+    /// Errors of already finished tasks are returned from here, so that no more tasks
+    /// are queued after a failure.
+    pub(super) async fn spawn<F>(&mut self, task: F) -> Result<()>
+    where
+        F: Future<Output = Result<Option<SegmentError>>> + Send + 'static,
+    {
+        let permit = tokio::select! {
+            _ = self.cancel.cancelled() => bail!("download was cancelled."),
+            permit = self.semaphore.clone().acquire_owned() => permit?,
+        };
+
+        self.reap()?;
+
+        let cancel = self.cancel.clone();
+        self.tasks.spawn(async move {
+            let _permit = permit;
+
+            tokio::select! {
+                _ = cancel.cancelled() => Err(anyhow!("download was cancelled.")),
+                result = task => result,
+            }
+        });
+        Ok(())
+    }
+
+    /// Wait for all spawned tasks to finish.
+    pub(super) async fn join(&mut self) -> Result<()> {
+        while let Some(result) = self.tasks.join_next().await {
+            match result? {
+                Ok(failure) => self.failures.extend(failure),
+                Err(e) => {
+                    self.shutdown().await;
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Abort all spawned tasks.
+    pub(super) async fn shutdown(&mut self) {
+        self.cancel.cancel();
+        self.tasks.shutdown().await;
+    }
+
+    /// Failures of segments which were tolerated since last call.
+    pub(super) fn take_failures(&mut self) -> Vec<SegmentError> {
+        std::mem::take(&mut self.failures)
+    }
+
+    fn reap(&mut self) -> Result<()> {
+        while let Some(result) = self.tasks.try_join_next() {
+            match result? {
+                Ok(failure) => self.failures.extend(failure),
+                Err(e) => {
+                    self.cancel.cancel();
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,9 +1,13 @@
-use crate::{downloader::Stream, playlist::MediaPlaylist, utils};
+use super::clip::Window;
+use crate::{
+    downloader::Stream, limiter::RateLimiter, playlist::MediaPlaylist, progress::Progress,
+    retry::RetryPolicy,
+};
 use anyhow::{anyhow, bail, Result};
-use kdam::{term::Colorizer, BarExt, Column, RichProgress};
-use reqwest::{blocking::Client, header, Url};
+use kdam::term::Colorizer;
+use reqwest::{header, Client, Url};
 use std::{fs::File, io::Write, path::PathBuf};
-use vsd_mp4::text::{ttml_text_parser, Mp4TtmlParser, Mp4VttParser};
+use vsd_mp4::text::{ttml_text_parser, Mp4TtmlParser, Mp4VttParser, Subtitles};
 
 enum SubtitleType {
     Mp4Vtt,
@@ -13,13 +17,18 @@ enum SubtitleType {
     VttText,
 }
 
-pub fn download_subtitle_stream(
+#[allow(clippy::too_many_arguments)]
+pub async fn download_subtitle_stream(
     base_url: Option<Url>,
     client: &Client,
+    fetch_retry: &RetryPolicy,
+    retry: &RetryPolicy,
+    limiters: &[RateLimiter],
     directory: &Option<PathBuf>,
     stream: &MediaPlaylist,
-    pb: &mut RichProgress,
+    pb: &mut Progress,
     temp_files: &mut Vec<Stream>,
+    window: Option<&Window>,
 ) -> Result<()> {
     pb.write(format!(
         " {} {} stream {}",
@@ -38,7 +47,7 @@ pub fn download_subtitle_stream(
         return Ok(());
     }
 
-    pb.pb.total = length;
+    pb.reset(length);
 
     let mut ext = stream.extension();
     let mut codec = None;
@@ -78,8 +87,11 @@ pub fn download_subtitle_stream(
                 request = request.header(header::RANGE, range.as_header_value());
             }
 
-            let response = request.send()?;
-            let bytes = response.bytes()?;
+            let bytes = fetch_retry
+                .bytes(request, |x| {
+                    let _ = pb.write(x);
+                })
+                .await?;
             subtitles_data.extend_from_slice(&bytes);
         }
 
@@ -90,8 +102,11 @@ pub fn download_subtitle_stream(
             request = request.header(header::RANGE, range.as_header_value());
         }
 
-        let response = request.send()?;
-        let bytes = response.bytes()?;
+        let bytes = retry
+            .bytes_limited(request, limiters, |x| {
+                let _ = pb.write(x);
+            })
+            .await?;
         subtitles_data.extend_from_slice(&bytes);
 
         if first_run {
@@ -115,7 +130,9 @@ pub fn download_subtitle_stream(
                 .to_string_lossy()
                 .to_string();
             temp_files.push(Stream {
+                default: stream.default,
                 file_path: temp_file.clone(),
+                forced: stream.forced,
                 language: stream.language.clone(),
                 media_type: stream.media_type.clone(),
             });
@@ -126,13 +143,7 @@ pub fn download_subtitle_stream(
             ))?;
         }
 
-        pb.replace(
-            0,
-            Column::Text(format!(
-                "[bold blue]{}",
-                utils::format_bytes(subtitles_data.len(), 2).2
-            )),
-        );
+        pb.set_bytes(subtitles_data.len(), None);
         pb.update(1)?;
     }
 
@@ -144,7 +155,8 @@ pub fn download_subtitle_stream(
             ))?;
 
             let vtt = Mp4VttParser::parse_init(&subtitles_data)?;
-            let subtitles = vtt.parse_media(&subtitles_data, None)?;
+            let mut subtitles = vtt.parse_media(&subtitles_data, None)?;
+            trim(&mut subtitles, window);
             File::create(&temp_file)?.write_all(subtitles.as_vtt().as_bytes())?;
         }
         Some(SubtitleType::Mp4Ttml) => {
@@ -154,7 +166,8 @@ pub fn download_subtitle_stream(
             ))?;
 
             let ttml = Mp4TtmlParser::parse_init(&subtitles_data)?;
-            let subtitles = ttml.parse_media(&subtitles_data)?;
+            let mut subtitles = ttml.parse_media(&subtitles_data)?;
+            trim(&mut subtitles, window);
             File::create(&temp_file)?.write_all(subtitles.as_srt().as_bytes())?;
         }
         Some(SubtitleType::TtmlText) => {
@@ -172,7 +185,21 @@ pub fn download_subtitle_stream(
                     x,
                 )
             })?;
-            File::create(&temp_file)?.write_all(ttml.into_subtitles().as_srt().as_bytes())?;
+            let mut subtitles = ttml.into_subtitles();
+            trim(&mut subtitles, window);
+            File::create(&temp_file)?.write_all(subtitles.as_srt().as_bytes())?;
+        }
+        _ if window.is_some() => {
+            let mut subtitles = Subtitles::from_text(&String::from_utf8_lossy(&subtitles_data));
+            trim(&mut subtitles, window);
+            File::create(&temp_file)?.write_all(
+                if ext == "vtt" {
+                    subtitles.as_vtt()
+                } else {
+                    subtitles.as_srt()
+                }
+                .as_bytes(),
+            )?;
         }
         _ => File::create(&temp_file)?.write_all(&subtitles_data)?,
     };
@@ -181,22 +208,46 @@ pub fn download_subtitle_stream(
         " {} stream successfully",
         "Downloaded".colorize("bold green"),
     ))?;
-    eprintln!();
-    pb.reset(Some(0));
+    pb.finish();
+    pb.reset(0);
     Ok(())
 }
 
-pub fn download_subtitle_streams(
+#[allow(clippy::too_many_arguments)]
+pub async fn download_subtitle_streams(
     base_url: Option<Url>,
     client: &Client,
+    fetch_retry: &RetryPolicy,
+    retry: &RetryPolicy,
+    limiters: &[RateLimiter],
     directory: &Option<PathBuf>,
     subtitle_streams: &Vec<MediaPlaylist>,
-    pb: &mut RichProgress,
+    pb: &mut Progress,
     temp_files: &mut Vec<Stream>,
+    window: Option<&Window>,
 ) -> Result<()> {
     for stream in subtitle_streams {
-        download_subtitle_stream(base_url.clone(), client, directory, stream, pb, temp_files)?;
+        download_subtitle_stream(
+            base_url.clone(),
+            client,
+            fetch_retry,
+            retry,
+            limiters,
+            directory,
+            stream,
+            pb,
+            temp_files,
+            window,
+        )
+        .await?;
     }
 
     Ok(())
 }
+
+/// Clip subtitles to time range of downloaded video and audio streams.
+fn trim(subtitles: &mut Subtitles, window: Option<&Window>) {
+    if let Some(window) = window {
+        subtitles.trim(window.start, window.end);
+    }
+}
@@ -10,8 +10,11 @@ pub(crate) fn parse_as_master(
         streams.push(playlist::MediaPlaylist {
             bandwidth: Some(video_stream.bandwidth),
             channels: None,
+            chapters: vec![],
             codecs: video_stream.codecs.to_owned(),
+            default: false,
             extension: Some("ts".to_owned()), // Cannot be comment here
+            forced: false,
             frame_rate: video_stream.frame_rate.map(|x| x as f32),
             i_frame: video_stream.is_i_frame,
             language: None,
@@ -35,10 +38,13 @@ pub(crate) fn parse_as_master(
                 m3u8_rs::AlternativeMediaType::Video => streams.push(playlist::MediaPlaylist {
                     bandwidth: None, // Cannot be comment here
                     channels: None,
-                    codecs: None,                     // Cannot be comment here
+                    chapters: vec![],
+                    codecs: None, // Cannot be comment here
+                    default: alternative_stream.default,
                     extension: Some("ts".to_owned()), // Cannot be comment here
-                    frame_rate: None,                 // Cannot be comment here
-                    i_frame: false,                   // Cannot be comment here
+                    forced: alternative_stream.forced,
+                    frame_rate: None, // Cannot be comment here
+                    i_frame: false,   // Cannot be comment here
                     language: None,
                     live: false, // Cannot be comment here
                     media_type: playlist::MediaType::Video,
@@ -54,8 +60,11 @@ pub(crate) fn parse_as_master(
                         .channels
                         .as_ref()
                         .map(|x| x.parse::<f32>().unwrap()),
-                    codecs: None,                     // Cannot be comment here
+                    chapters: vec![],
+                    codecs: None, // Cannot be comment here
+                    default: alternative_stream.default,
                     extension: Some("ts".to_owned()), // Cannot be comment here
+                    forced: alternative_stream.forced,
                     frame_rate: None,
                     i_frame: false,
                     language: alternative_stream
@@ -75,8 +84,11 @@ pub(crate) fn parse_as_master(
                     streams.push(playlist::MediaPlaylist {
                         bandwidth: None,
                         channels: None,
-                        codecs: None,                      // Cannot be comment here
+                        chapters: vec![],
+                        codecs: None, // Cannot be comment here
+                        default: alternative_stream.default,
                         extension: Some("vtt".to_owned()), // Cannot be comment here
+                        forced: alternative_stream.forced,
                         frame_rate: None,
                         i_frame: false,
                         language: alternative_stream
@@ -98,8 +110,11 @@ pub(crate) fn parse_as_master(
                         .channels
                         .as_ref()
                         .map(|x| x.parse::<f32>().unwrap()),
-                    codecs: None,     // Cannot be comment here
-                    extension: None,  // Cannot be comment here
+                    chapters: vec![],
+                    codecs: None, // Cannot be comment here
+                    default: alternative_stream.default,
+                    extension: None, // Cannot be comment here
+                    forced: alternative_stream.forced,
                     frame_rate: None, // Cannot be comment here
                     i_frame: false,   // Cannot be comment here
                     language: alternative_stream
@@ -129,8 +144,42 @@ pub(crate) fn push_segments(m3u8: &m3u8_rs::MediaPlaylist, playlist: &mut playli
     playlist.live = !m3u8.end_list;
 
     let mut previous_byterange_end = 0;
+    // Remaining duration of current ad break, which is infinite when it is not known.
+    let mut ad_break: Option<f32> = None;
+
+    for (i, segment) in m3u8.segments.iter().enumerate() {
+        for tag in &segment.unknown_tags {
+            match tag.tag.as_str() {
+                "X-CUE-OUT" => ad_break = Some(cue_duration(tag.rest.as_deref())),
+                "X-CUE-OUT-CONT" if ad_break.is_none() => ad_break = Some(f32::INFINITY),
+                "X-CUE-IN" => ad_break = None,
+                _ => (),
+            }
+        }
+
+        if let Some(daterange) = &segment.daterange {
+            if let Some(attributes) = &daterange.other_attributes {
+                if attributes.contains_key("SCTE35-OUT") {
+                    ad_break = Some(
+                        daterange
+                            .duration
+                            .or(daterange.planned_duration)
+                            .map(|x| x as f32)
+                            .unwrap_or(f32::INFINITY),
+                    );
+                } else if attributes.contains_key("SCTE35-IN") {
+                    ad_break = None;
+                }
+            }
+        }
+
+        let ad = ad_break.is_some();
+
+        if let Some(remaining) = ad_break {
+            // Ad break ends once its whole duration is covered by segments.
+            ad_break = Some(remaining - segment.duration).filter(|x| *x > 0.001);
+        }
 
-    for segment in &m3u8.segments {
         let map = segment.map.as_ref().map(|x| playlist::Map {
             uri: x.uri.to_owned(),
             range: x.byte_range.as_ref().map(|x| {
@@ -167,7 +216,10 @@ pub(crate) fn push_segments(m3u8: &m3u8_rs::MediaPlaylist, playlist: &mut playli
         });
 
         playlist.segments.push(playlist::Segment {
+            ad,
+            discontinuity: segment.discontinuity,
             duration: segment.duration,
+            gap: segment.unknown_tags.iter().any(|x| x.tag == "X-GAP"),
             key: if let Some(m3u8_rs::Key {
                 iv,
                 keyformat,
@@ -209,7 +261,9 @@ pub(crate) fn push_segments(m3u8: &m3u8_rs::MediaPlaylist, playlist: &mut playli
                 None
             },
             map,
+            program_date_time: segment.program_date_time,
             range,
+            sequence: m3u8.media_sequence + i as u64,
             uri: segment.uri.to_owned(),
         });
     }
@@ -240,3 +294,13 @@ pub(crate) fn push_segments(m3u8: &m3u8_rs::MediaPlaylist, playlist: &mut playli
         }
     }
 }
+
+/// Duration of ad break from `#EXT-X-CUE-OUT` tag, either as `DURATION=30` or `30`.
+fn cue_duration(rest: Option<&str>) -> f32 {
+    rest.and_then(|x| {
+        x.split(',')
+            .map(|x| x.trim_start_matches("DURATION="))
+            .find_map(|x| x.parse::<f32>().ok())
+    })
+    .unwrap_or(f32::INFINITY)
+}
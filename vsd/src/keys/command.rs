@@ -0,0 +1,58 @@
+use super::{KeyProvider, KeyRequest, KeysFuture};
+use anyhow::{anyhow, bail};
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+/// Fetch keys by running an external command. Key request is written to its stdin as json
+/// and keys are read from its stdout, see [`KeyRequest`] for format of keys.
+pub struct CommandKeyProvider {
+    args: Vec<String>,
+    program: String,
+}
+
+impl CommandKeyProvider {
+    pub fn new(program: String, args: Vec<String>) -> Self {
+        Self { args, program }
+    }
+}
+
+impl KeyProvider for CommandKeyProvider {
+    fn name(&self) -> String {
+        self.program.clone()
+    }
+
+    fn keys<'a>(&'a self, request: &'a KeyRequest) -> KeysFuture<'a> {
+        Box::pin(async move {
+            let input = serde_json::to_vec(request)?;
+            let args = self.args.clone();
+            let program = self.program.clone();
+
+            let output = tokio::task::spawn_blocking(move || {
+                let mut child = Command::new(program)
+                    .args(args)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()?;
+                // stdin is closed when it is dropped, so that command knows request has ended.
+                child.stdin.take().unwrap().write_all(&input)?;
+                child.wait_with_output()
+            })
+            .await?
+            .map_err(|x| anyhow!("could not run {}. {}", self.program, x))?;
+
+            if !output.status.success() {
+                bail!(
+                    "{} exited with code {}.\n\n{}",
+                    self.program,
+                    output.status.code().unwrap_or(1),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+
+            super::parse_keys(&String::from_utf8_lossy(&output.stdout))
+        })
+    }
+}
@@ -0,0 +1,33 @@
+use super::{KeyProvider, KeyRequest, KeysFuture};
+use anyhow::anyhow;
+use std::path::PathBuf;
+
+/// Read keys from a file, see [`KeyRequest`] for format of keys.
+/// Only keys of requested key ids (and keys without key ids) are used.
+pub struct FileKeyProvider {
+    path: PathBuf,
+}
+
+impl FileKeyProvider {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl KeyProvider for FileKeyProvider {
+    fn name(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+
+    fn keys<'a>(&'a self, request: &'a KeyRequest) -> KeysFuture<'a> {
+        Box::pin(async move {
+            let text = std::fs::read_to_string(&self.path)
+                .map_err(|x| anyhow!("could not read {}. {}", self.path.to_string_lossy(), x))?;
+
+            Ok(super::parse_keys(&text)?
+                .into_iter()
+                .filter(|x| x.0.as_ref().is_none_or(|y| request.kids.contains(y)))
+                .collect())
+        })
+    }
+}
@@ -0,0 +1,38 @@
+use super::{KeyProvider, KeyRequest, KeysFuture};
+use reqwest::{Client, Url};
+
+/// Fetch keys from an http endpoint, e.g. a license proxy running locally.
+/// Key request is sent as json body of a POST request and keys are read from response,
+/// see [`KeyRequest`] for format of keys.
+pub struct HttpKeyProvider {
+    client: Client,
+    url: Url,
+}
+
+impl HttpKeyProvider {
+    pub fn new(client: Client, url: Url) -> Self {
+        Self { client, url }
+    }
+}
+
+impl KeyProvider for HttpKeyProvider {
+    fn name(&self) -> String {
+        self.url.to_string()
+    }
+
+    fn keys<'a>(&'a self, request: &'a KeyRequest) -> KeysFuture<'a> {
+        Box::pin(async move {
+            let text = self
+                .client
+                .post(self.url.clone())
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(serde_json::to_vec(request)?)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            super::parse_keys(&text)
+        })
+    }
+}
@@ -0,0 +1,113 @@
+mod command;
+mod file;
+mod http;
+mod store;
+
+pub use command::CommandKeyProvider;
+pub use file::FileKeyProvider;
+pub use http::HttpKeyProvider;
+pub use store::KeyStore;
+
+use crate::utils;
+use anyhow::{bail, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::{future::Future, pin::Pin};
+
+/// Keys returned by [`KeyProvider::keys`], key ids (in hex) are skipped for streams
+/// encrypted with a single key.
+pub type KeysFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Vec<(Option<String>, String)>>> + Send + 'a>>;
+
+/// Source of CENC content decryption keys. Providers are asked for keys when
+/// keys of default key ids weren't supplied using [`Downloader::key`](crate::Downloader::key).
+pub trait KeyProvider: Send + Sync {
+    /// Name of provider, used in status messages.
+    fn name(&self) -> String;
+
+    /// Fetch decryption keys for key ids of streams.
+    fn keys<'a>(&'a self, request: &'a KeyRequest) -> KeysFuture<'a>;
+}
+
+/// Key ids and `pssh` boxes of encrypted streams, serialized as json for external providers.
+#[derive(Clone, Debug, Serialize)]
+pub struct KeyRequest {
+    /// Key ids (in hex) which are used by default for decrypting streams.
+    pub default_kids: Vec<String>,
+    /// All key ids (in hex) found in initialization segments.
+    pub kids: Vec<String>,
+    /// Base64 encoded `pssh` boxes found in initialization segments.
+    pub pssh: Vec<String>,
+}
+
+/// Parse keys returned by providers. Keys can be listed as `KID:KEY` (or `KEY`) lines
+/// or in json format, either as `{"keys": [{"kid": "...", "key": "..."}]}` or as an array
+/// of those objects or strings.
+pub(crate) fn parse_keys(text: &str) -> Result<Vec<(Option<String>, String)>> {
+    let mut keys = vec![];
+
+    if let Ok(value) = serde_json::from_str::<Value>(text) {
+        let entries = match &value {
+            Value::Array(x) => x,
+            Value::Object(x) => match x.get("keys") {
+                Some(Value::Array(x)) => x,
+                _ => bail!("keys array is missing from json."),
+            },
+            _ => bail!("keys should be listed as a json array."),
+        };
+
+        for entry in entries {
+            match entry {
+                Value::Object(x) => match x.get("key").and_then(|x| x.as_str()) {
+                    Some(key) => keys.push(normalize(x.get("kid").and_then(|x| x.as_str()), key)?),
+                    None => bail!("key is missing from {}.", entry),
+                },
+                Value::String(x) => keys.push(parse_key(x)?),
+                _ => bail!("could not parse {} as key.", entry),
+            }
+        }
+    } else {
+        for line in text.lines().map(|x| x.trim()) {
+            if !line.is_empty() && !line.starts_with('#') {
+                keys.push(parse_key(line)?);
+            }
+        }
+    }
+
+    Ok(keys)
+}
+
+fn parse_key(key: &str) -> Result<(Option<String>, String)> {
+    match key.trim().split_once(':') {
+        Some((kid, key)) => normalize(Some(kid), key),
+        None => normalize(None, key),
+    }
+}
+
+/// Check whether text is a key id, either in hex or in uuid format.
+pub(crate) fn is_key_id(kid: &str) -> bool {
+    let kid = kid.replace('-', "");
+    kid.len() == 32 && hex::decode(kid).is_ok()
+}
+
+/// Key ids and keys are stored in lowercase hex, base64 keys are also accepted.
+fn normalize(kid: Option<&str>, key: &str) -> Result<(Option<String>, String)> {
+    let kid = kid.map(|x| x.trim().to_lowercase().replace('-', ""));
+    let key = key.trim();
+
+    if let Some(kid) = &kid {
+        if !is_key_id(kid) {
+            bail!("{} is not a valid key id.", kid);
+        }
+    }
+
+    let key = if key.len() == 32 && hex::decode(key).is_ok() {
+        key.to_lowercase()
+    } else if let Some(key) = utils::decode_base64(key).ok().filter(|x| x.len() == 16) {
+        hex::encode(key)
+    } else {
+        bail!("{} is not a valid key.", key);
+    };
+
+    Ok((kid, key))
+}
@@ -0,0 +1,115 @@
+use crate::utils;
+use anyhow::{anyhow, Result};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Local database of decryption keys, stored as json (`{"keys": [{"kid": "...", "key": "..."}]}`).
+/// Key ids and keys are stored in lowercase hex.
+#[derive(Clone, Debug)]
+pub struct KeyStore {
+    keys: BTreeMap<String, String>,
+    path: PathBuf,
+}
+
+impl KeyStore {
+    /// Default location of keystore i.e. `vsd/keys.json` inside configuration directory of current user.
+    pub fn default_path() -> Option<PathBuf> {
+        utils::config_dir().map(|x| x.join("vsd").join("keys.json"))
+    }
+
+    /// Open keystore from `path`, an empty keystore is created if it doesn't exists.
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let mut keys = BTreeMap::new();
+
+        if path.exists() {
+            let text = std::fs::read_to_string(&path)
+                .map_err(|x| anyhow!("could not read {}. {}", path.to_string_lossy(), x))?;
+
+            for (kid, key) in super::parse_keys(&text)
+                .map_err(|x| anyhow!("could not parse {}. {}", path.to_string_lossy(), x))?
+            {
+                if let Some(kid) = kid {
+                    keys.insert(kid, key);
+                }
+            }
+        }
+
+        Ok(Self { keys, path })
+    }
+
+    /// Path of keystore file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Key of a key id (in hex).
+    pub fn get(&self, kid: &str) -> Option<&String> {
+        self.keys.get(&kid.to_lowercase().replace('-', ""))
+    }
+
+    /// Add a key (in hex or base64), returns `true` if keystore is changed.
+    pub fn insert(&mut self, kid: &str, key: &str) -> Result<bool> {
+        let (kid, key) = super::normalize(Some(kid), key)?;
+        let kid = kid.unwrap();
+
+        if self.keys.get(&kid) == Some(&key) {
+            return Ok(false);
+        }
+
+        self.keys.insert(kid, key);
+        Ok(true)
+    }
+
+    /// Remove key of a key id (in hex).
+    pub fn remove(&mut self, kid: &str) -> Option<String> {
+        self.keys.remove(&kid.to_lowercase().replace('-', ""))
+    }
+
+    /// Stored key ids and keys, sorted by key ids.
+    pub fn keys(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.keys.iter()
+    }
+
+    /// Keystore in json format, same as the one used for storing it.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&json!({
+            "keys": self
+                .keys
+                .iter()
+                .map(|(kid, key)| json!({ "kid": kid, "key": key }))
+                .collect::<Vec<_>>()
+        }))?)
+    }
+
+    /// Write keystore to its path. Keystore is written to a temporary file first and then renamed,
+    /// so that it is never left half written.
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            if !parent.as_os_str().is_empty() && !parent.exists() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".tmp");
+
+        // Keystore shouldn't be readable by other users. Mode is only set when file is created,
+        // so any leftover temporary file is removed first.
+        let _ = std::fs::remove_file(&temp_path);
+        let mut options = std::fs::OpenOptions::new();
+        options.create(true).truncate(true).write(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        options
+            .open(&temp_path)?
+            .write_all(self.to_json()?.as_bytes())?;
+        std::fs::rename(&temp_path, &self.path)
+            .map_err(|x| anyhow!("could not write {}. {}", self.path.to_string_lossy(), x))?;
+        Ok(())
+    }
+}
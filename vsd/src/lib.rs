@@ -0,0 +1,70 @@
+//! **v**ideo **s**tream **d**ownloader library for downloading video streams served over HTTP
+//! from [HLS](https://howvideo.works/#hls) and [DASH](https://howvideo.works/#dash) playlists.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use vsd::{reqwest::Client, Downloader, PlaylistFetcher, ProgressEvent, Quality};
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let client = Client::new();
+//! let fetcher = PlaylistFetcher::new(client.clone())
+//!     .quality(Quality::Highest)
+//!     .progress(|event| {
+//!         if let ProgressEvent::Message(message) = event {
+//!             println!("{}", message);
+//!         }
+//!     });
+//!
+//! let meta = fetcher.fetch("https://example.com/master.m3u8").await?;
+//! let selected_playlists = fetcher.parse_selected(&meta).await?;
+//!
+//! Downloader::new(client)
+//!     .output(Some("video.mp4".to_owned()))
+//!     .progress(|event| {
+//!         if let ProgressEvent::Message(message) = event {
+//!             println!("{}", message);
+//!         }
+//!     })
+//!     .download(selected_playlists)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod cookie;
+mod dash;
+mod downloader;
+mod hls;
+mod keys;
+mod limiter;
+mod merger;
+mod mux;
+mod playlist;
+mod progress;
+mod retry;
+mod selector;
+mod utils;
+
+#[doc(hidden)]
+pub mod commands;
+
+pub use commands::Quality;
+pub use downloader::{
+    Clip, Discontinuity, Downloader, FailurePolicy, InputMetadata, PlaylistFetcher, SegmentError,
+    SegmentErrorKind, SelectedPlaylists,
+};
+pub use keys::{
+    CommandKeyProvider, FileKeyProvider, HttpKeyProvider, KeyProvider, KeyRequest, KeyStore,
+    KeysFuture,
+};
+pub use mux::{FfmpegPreset, Muxer};
+pub use playlist::{
+    Chapter, Key, KeyMethod, Map, MasterPlaylist, MediaPlaylist, MediaType, PlaylistType, Range,
+    Segment,
+};
+pub use progress::{ProgressCallback, ProgressEvent};
+pub use reqwest;
+pub use retry::RetryPolicy;
+pub use selector::{StreamSelector, StreamSelectors};
+pub use tokio_util::sync::CancellationToken;
@@ -0,0 +1,80 @@
+/*
+    REFERENCES
+    ----------
+
+    1. https://en.wikipedia.org/wiki/Token_bucket
+
+*/
+
+use crate::playlist::MediaType;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Token bucket which limits combined download rate of everyone sharing it.
+///
+/// Bucket holds at most one second worth of tokens. Downloads can take tokens in advance,
+/// which makes the bucket go in debt and everyone has to wait until it is paid off.
+#[derive(Clone)]
+pub(crate) struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+struct Bucket {
+    last: Instant,
+    rate: f64,
+    tokens: f64,
+}
+
+impl RateLimiter {
+    /// Create a new limiter which allows `rate` bytes per second.
+    pub(crate) fn new(rate: u64) -> Self {
+        let rate = rate.max(1) as f64;
+
+        Self {
+            bucket: Arc::new(Mutex::new(Bucket {
+                last: Instant::now(),
+                rate,
+                tokens: rate,
+            })),
+        }
+    }
+
+    /// Wait until `bytes` can be consumed.
+    pub(crate) async fn acquire(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.last).as_secs_f64() * bucket.rate;
+            bucket.tokens = (bucket.tokens + refill).min(bucket.rate);
+            bucket.last = now;
+            bucket.tokens -= bytes as f64;
+
+            if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / bucket.rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Limiters which apply to streams of `media_type`, i.e. the limiter shared by all streams
+    /// and the limiter of `media_type`. Limits set later takes precedence.
+    pub(crate) fn of(
+        limiters: &[(Option<MediaType>, RateLimiter)],
+        media_type: &MediaType,
+    ) -> Vec<Self> {
+        let all = limiters.iter().rev().find(|x| x.0.is_none());
+        let own = limiters
+            .iter()
+            .rev()
+            .find(|x| x.0.as_ref() == Some(media_type));
+
+        all.into_iter().chain(own).map(|x| x.1.clone()).collect()
+    }
+}
@@ -1,22 +1,13 @@
-mod commands;
-mod cookie;
-mod dash;
-mod downloader;
-mod hls;
-mod merger;
-mod playlist;
-mod utils;
-
 use clap::{ColorChoice, Parser};
-use commands::{Args, Commands};
 use kdam::{term, term::Colorizer};
 use requestty::symbols;
 use std::{
     io::{stderr, IsTerminal},
     process,
 };
+use vsd::commands::{Args, Commands};
 
-fn run() -> anyhow::Result<()> {
+async fn run() -> anyhow::Result<()> {
     let args = Args::parse();
 
     term::init(match args.color {
@@ -29,20 +20,22 @@ fn run() -> anyhow::Result<()> {
         #[cfg(feature = "browser")]
         Commands::Capture(args) => args.execute()?,
         Commands::Extract(args) => args.execute()?,
+        Commands::Keys(args) => args.execute()?,
         Commands::Merge(args) => args.execute()?,
-        Commands::Save(args) => args.execute()?,
+        Commands::Save(args) => args.execute().await?,
     }
 
     Ok(())
 }
 
-fn main() {
+#[tokio::main]
+async fn main() {
     let mut symbols = symbols::UNICODE;
     symbols.completed = '•';
     symbols.cross = 'x';
     symbols::set(symbols);
 
-    if let Err(e) = run() {
+    if let Err(e) = run().await {
         eprintln!("{}: {}", "error".colorize("bold red"), e);
         process::exit(1);
     }
@@ -51,8 +44,6 @@ fn main() {
 /*
     TODOs
 
-    1. Add resume support
-    2. Create a custom thread pool module
-    3. Reduce dependency on anyhow crate
-    4. Reduce dependency on ffmpeg
+    1. Reduce dependency on anyhow crate
+    2. Reduce dependency on ffmpeg
 */
@@ -1,6 +1,14 @@
 // use crate::progress::DownloadProgress;
+use crate::mux::Normalizer;
 use anyhow::Result;
-use std::{collections::HashMap, fs, fs::File, io::Write, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fs,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
 
 pub(super) struct Merger {
     size: usize,
@@ -12,44 +20,169 @@ pub(super) struct Merger {
     indexed: usize,
 
     directory: Option<PathBuf>,
+    fillers: HashSet<usize>,
+    journal: Journal,
+    normalizer: Option<Normalizer>,
+    resumed: HashSet<usize>,
+    /// State of normalizer after last resumed segment.
+    state: Option<String>,
 }
 
 impl Merger {
-    pub(super) fn new(size: usize, filename: &str) -> Result<Self> {
+    /// Merge segments into a file. Segments written by an interrupted download are resumed
+    /// if its journal belongs to same stream, see `MediaPlaylist::journal_id`.
+    pub(super) fn new(size: usize, filename: &str, id: &str) -> Result<Self> {
+        let journal_path = Journal::path(Path::new(filename));
+        let header = Journal::header(size, id);
+        let mut entries = Journal::read(&journal_path, &header, size);
+        entries.sort_by_key(|x| x.0);
+
+        // Only an unbroken run of segments from the start of file can be resumed.
+        let mut pos = 0;
+        let mut end = 0;
+
+        for (index, start, stop, _) in &entries {
+            if *index != pos || *start != end {
+                break;
+            }
+
+            pos += 1;
+            end = *stop;
+        }
+
+        let resumable = pos != 0
+            && fs::metadata(filename)
+                .map(|x| x.len() >= end)
+                .unwrap_or(false);
+
+        let file = if resumable {
+            let mut file = OpenOptions::new().write(true).open(filename)?;
+            file.set_len(end)?;
+            file.seek(SeekFrom::End(0))?;
+            file
+        } else {
+            pos = 0;
+            end = 0;
+            File::create(filename)?
+        };
+
+        entries.truncate(pos);
+        let state = entries.last().and_then(|x| x.3.clone());
+
         Ok(Self {
             size: size - 1,
-            file: File::create(filename)?,
-            pos: 0,
+            file,
+            pos,
             buffers: HashMap::new(),
-            stored_bytes: 0,
-            flushed_bytes: 0,
-            indexed: 0,
+            stored_bytes: end as usize,
+            flushed_bytes: end as usize,
+            indexed: pos,
             directory: None,
+            fillers: HashSet::new(),
+            journal: Journal::create(journal_path, header, &entries)?,
+            normalizer: None,
+            resumed: (0..pos).collect(),
+            state,
         })
     }
 
-    pub(super) fn with_directory(size: usize, directory: &str) -> Result<Self> {
+    pub(super) fn with_directory(size: usize, directory: &str, id: &str) -> Result<Self> {
+        let journal_path = Journal::path(Path::new(directory));
+        let header = Journal::header(size, id);
         let directory = PathBuf::from(directory);
 
         if !directory.exists() {
             fs::create_dir_all(&directory)?;
         }
 
+        let extension = directory.extension().unwrap().to_string_lossy().to_string();
+        let entries = Journal::read(&journal_path, &header, size)
+            .into_iter()
+            .filter(|(index, start, stop, _)| {
+                fs::metadata(directory.join(format!("{}.{}", index, extension)))
+                    .map(|x| x.len() == (stop - start))
+                    .unwrap_or(false)
+            })
+            .collect::<Vec<_>>();
+        let stored_bytes = entries
+            .iter()
+            .map(|(_, start, stop, _)| (stop - start) as usize)
+            .sum();
+
         Ok(Self {
             size: size - 1,
-            file: File::create(directory.join(format!(
-                "0.{}",
-                directory.extension().unwrap().to_string_lossy()
-            )))?,
-            pos: 0,
+            file: OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(directory.join(format!("0.{}", extension)))?,
+            pos: entries.len(),
             buffers: HashMap::new(),
-            stored_bytes: 0,
-            flushed_bytes: 0,
-            indexed: 0,
+            stored_bytes,
+            flushed_bytes: stored_bytes,
+            indexed: entries.len(),
             directory: Some(directory),
+            fillers: HashSet::new(),
+            journal: Journal::create(journal_path, header, &entries)?,
+            normalizer: None,
+            resumed: entries.iter().map(|x| x.0).collect(),
+            state: None,
         })
     }
 
+    /// Check whether an interrupted download of this file can be resumed, i.e. its journal
+    /// belongs to a stream of same identity.
+    pub(super) fn is_resumable(filename: &Path, size: usize, id: &str) -> bool {
+        File::open(Journal::path(filename))
+            .ok()
+            .and_then(|x| BufReader::new(x).lines().next())
+            .and_then(|x| x.ok())
+            .is_some_and(|x| x == Journal::header(size, id))
+    }
+
+    /// Rewrite timestamps of segments at these positions (and segments after them) to continue
+    /// from previous segments. State of normalizer is stored in journal, so that timestamps of
+    /// segments written by a previous run don't have to be read again. Segments of previous run
+    /// are downloaded again if its state isn't found in journal. Segments which are not merged
+    /// into a single file are left unchanged.
+    pub(super) fn normalize(&mut self, discontinuities: &[usize]) -> Result<()> {
+        if self.directory.is_some() {
+            return Ok(());
+        }
+
+        if self.normalizer.is_none() && !self.resumed.is_empty() && !discontinuities.is_empty() {
+            self.normalizer = self
+                .state
+                .take()
+                .and_then(|x| serde_json::from_str::<Normalizer>(&x).ok());
+
+            if self.normalizer.is_none() {
+                self.file.set_len(0)?;
+                self.file.seek(SeekFrom::Start(0))?;
+                self.pos = 0;
+                self.stored_bytes = 0;
+                self.flushed_bytes = 0;
+                self.indexed = 0;
+                self.resumed.clear();
+
+                if self.journal.file.is_some() {
+                    self.journal = Journal::create(
+                        self.journal.path.clone(),
+                        self.journal.header.clone(),
+                        &[],
+                    )?;
+                }
+            }
+        }
+
+        let normalizer = self.normalizer.get_or_insert_with(Normalizer::default);
+
+        for pos in discontinuities {
+            normalizer.insert(*pos);
+        }
+
+        Ok(())
+    }
+
     pub(super) fn write(&mut self, pos: usize, buf: &[u8]) -> Result<()> {
         if let Some(directory) = &self.directory {
             self.file = File::create(directory.join(format!(
@@ -60,11 +193,27 @@ impl Merger {
         }
 
         if self.directory.is_some() || (pos == 0 || (self.pos != 0 && self.pos == pos)) {
+            let mut normalized;
+            let buf = if let Some(normalizer) = &mut self.normalizer {
+                normalized = buf.to_vec();
+                normalizer.normalize(pos, &mut normalized);
+                &normalized
+            } else {
+                buf
+            };
+
             self.file.write_all(buf)?;
             self.file.flush()?;
             self.pos += 1;
             let size = buf.len();
             self.stored_bytes += size;
+
+            if self.directory.is_some() {
+                self.record(pos, 0, size)?;
+            } else {
+                self.record(pos, self.flushed_bytes, self.flushed_bytes + size)?;
+            }
+
             self.flushed_bytes += size;
         } else {
             self.buffers.insert(pos, buf.to_vec());
@@ -75,13 +224,37 @@ impl Merger {
         Ok(())
     }
 
+    /// Write a filler in place of a failed segment. Fillers aren't recorded in journal,
+    /// so that failed segments are downloaded again when download is resumed.
+    pub(super) fn write_filler(&mut self, pos: usize, buf: &[u8]) -> Result<()> {
+        self.fillers.insert(pos);
+        self.write(pos, buf)
+    }
+
+    fn record(&mut self, pos: usize, start: usize, end: usize) -> Result<()> {
+        if self.fillers.remove(&pos) {
+            return Ok(());
+        }
+
+        let state = match &self.normalizer {
+            Some(normalizer) => Some(serde_json::to_string(normalizer)?),
+            None => None,
+        };
+        self.journal.record(pos, start, end, state.as_deref())
+    }
+
     pub(super) fn flush(&mut self) -> Result<()> {
         while self.pos <= self.size {
             let op_buf = self.buffers.remove(&self.pos);
 
-            if let Some(buf) = op_buf {
+            if let Some(mut buf) = op_buf {
+                if let Some(normalizer) = &mut self.normalizer {
+                    normalizer.normalize(self.pos, &mut buf);
+                }
+
                 self.file.write_all(&buf)?;
                 self.file.flush()?;
+                self.record(self.pos, self.flushed_bytes, self.flushed_bytes + buf.len())?;
                 self.pos += 1;
                 self.flushed_bytes += buf.len();
                 // self.update()?;
@@ -93,14 +266,47 @@ impl Merger {
         Ok(())
     }
 
-    // pub(super) fn position(&self) -> usize {
-    //     self.pos
-    // }
+    /// Remove the resume journal, should be called once all segments are written.
+    pub(super) fn finish(&mut self) -> Result<()> {
+        self.journal.remove()
+    }
+
+    /// Stop tracking written segments for streams which cannot be resumed.
+    pub(super) fn untrack(mut self) -> Result<Self> {
+        self.journal.remove()?;
+        Ok(self)
+    }
+
+    /// Expect more segments to be written, used for live streams.
+    pub(super) fn grow(&mut self, size: usize) {
+        self.size += size;
+    }
+
+    /// Leave out segments which aren't written to file yet, used for live streams
+    /// whose recording is stopped before all of their segments are downloaded.
+    pub(super) fn truncate(&mut self) {
+        self.stored_bytes -= self.buffers.drain().map(|(_, x)| x.len()).sum::<usize>();
+        self.size = self.pos.saturating_sub(1);
+    }
+
+    /// Number of segments which are written to file.
+    pub(super) fn position(&self) -> usize {
+        self.pos
+    }
 
     pub(super) fn buffered(&self) -> bool {
         self.buffers.is_empty() && self.pos >= (self.size + 1)
     }
 
+    /// Number of segments which were already written by a previous run.
+    pub(super) fn resumed(&self) -> usize {
+        self.resumed.len()
+    }
+
+    pub(super) fn is_resumed(&self, pos: usize) -> bool {
+        self.resumed.contains(&pos)
+    }
+
     pub(super) fn stored(&self) -> usize {
         self.stored_bytes
     }
@@ -113,3 +319,113 @@ impl Merger {
         }
     }
 }
+
+/// Index, start and end byte offsets and normalizer state of a written segment.
+type Entry = (usize, u64, u64, Option<String>);
+
+/// On-disk journal of segments which are written to a temporary stream file.
+///
+/// First line is a header containing total number of segments in stream and identity of stream,
+/// journals with a different header are discarded. Every next line is an entry of a written
+/// segment as `<index> <start> <end> [state]`, where `start` and `end` are byte offsets of
+/// segment inside file and `state` is json of timestamp normalizer after writing segment.
+struct Journal {
+    file: Option<File>,
+    header: String,
+    path: PathBuf,
+}
+
+impl Journal {
+    fn path(filename: &Path) -> PathBuf {
+        let mut path = OsString::from(filename.as_os_str());
+        path.push(".journal");
+        PathBuf::from(path)
+    }
+
+    fn header(size: usize, id: &str) -> String {
+        format!("segments {} {}", size, id)
+    }
+
+    fn read(path: &Path, header: &str, size: usize) -> Vec<Entry> {
+        let mut entries = vec![];
+
+        if let Ok(file) = File::open(path) {
+            let mut lines = BufReader::new(file).lines().map_while(|x| x.ok());
+
+            if lines.next().as_deref() == Some(header) {
+                for line in lines {
+                    let mut parts = line.splitn(4, ' ');
+                    let values = parts
+                        .by_ref()
+                        .take(3)
+                        .filter_map(|x| x.parse::<u64>().ok())
+                        .collect::<Vec<_>>();
+
+                    if let [index, start, end] = values[..] {
+                        if (index as usize) < size && start <= end {
+                            let state = parts.next().map(|x| x.to_owned());
+                            entries.push((index as usize, start, end, state));
+                        }
+                    }
+                }
+            }
+        }
+
+        entries
+    }
+
+    fn create(path: PathBuf, header: String, entries: &[Entry]) -> Result<Self> {
+        let mut file = File::create(&path)?;
+        writeln!(file, "{}", header)?;
+
+        for (index, start, end, state) in entries {
+            Self::write_entry(&mut file, *index, *start, *end, state.as_deref())?;
+        }
+
+        file.flush()?;
+
+        Ok(Self {
+            file: Some(file),
+            header,
+            path,
+        })
+    }
+
+    fn record(
+        &mut self,
+        index: usize,
+        start: usize,
+        end: usize,
+        state: Option<&str>,
+    ) -> Result<()> {
+        if let Some(file) = &mut self.file {
+            Self::write_entry(file, index, start as u64, end as u64, state)?;
+            file.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn write_entry(
+        file: &mut File,
+        index: usize,
+        start: u64,
+        end: u64,
+        state: Option<&str>,
+    ) -> Result<()> {
+        match state {
+            Some(state) => writeln!(file, "{} {} {} {}", index, start, end, state)?,
+            None => writeln!(file, "{} {} {}", index, start, end)?,
+        }
+
+        Ok(())
+    }
+
+    fn remove(&mut self) -> Result<()> {
+        if self.file.take().is_some() && self.path.exists() {
+            fs::remove_file(&self.path)?;
+        }
+
+        Ok(())
+    }
+}
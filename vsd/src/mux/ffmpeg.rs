@@ -0,0 +1,191 @@
+/*
+    REFERENCES
+    ----------
+
+    1. https://ffmpeg.org/ffmpeg.html#Stream-specifiers-1
+    2. https://ffmpeg.org/ffmpeg.html#Advanced-options (-progress)
+
+*/
+
+use super::FfmpegPreset;
+use crate::{downloader::Stream, playlist::MediaType, progress::Progress};
+use anyhow::{bail, Result};
+use kdam::term::Colorizer;
+use std::{
+    io::{BufRead, BufReader, Read},
+    path::Path,
+    process::{Command, Stdio},
+};
+
+/// Mux downloaded streams to output using ffmpeg.
+/// Muxing progress is read from `-progress pipe:1` and reported relative to `duration` (in seconds).
+/// Errors written by ffmpeg are included in the returned error if it fails.
+pub(crate) fn mux(
+    binary: &Path,
+    duration: Option<f32>,
+    extra_args: &[String],
+    output: &str,
+    pb: &Progress,
+    preset: FfmpegPreset,
+    streams: &[&Stream],
+) -> Result<()> {
+    let mut args = vec![];
+
+    for stream in streams {
+        args.extend_from_slice(&["-i".to_owned(), stream.file_path.clone()]);
+    }
+
+    // Audio and subtitles muxed inside video streams are mapped after audio and subtitles streams,
+    // so that indices of output streams (per type) are same as indices of their input files.
+    let is_video = |x: &Stream| matches!(x.media_type, MediaType::Video | MediaType::Undefined);
+
+    for (specifier, media_type) in [
+        ("v", MediaType::Video),
+        ("a", MediaType::Audio),
+        ("s", MediaType::Subtitles),
+    ] {
+        for (i, stream) in streams.iter().enumerate() {
+            if media_type != MediaType::Video && stream.media_type == media_type {
+                args.extend_from_slice(&["-map".to_owned(), format!("{}:{}?", i, specifier)]);
+            }
+        }
+
+        for (i, stream) in streams.iter().enumerate() {
+            if is_video(stream) {
+                args.extend_from_slice(&["-map".to_owned(), format!("{}:{}?", i, specifier)]);
+            }
+        }
+    }
+
+    let mp4 = [".m4a", ".m4v", ".mov", ".mp4"]
+        .iter()
+        .any(|x| output.to_lowercase().ends_with(x));
+
+    args.extend_from_slice(&["-c".to_owned(), "copy".to_owned()]);
+
+    match preset {
+        FfmpegPreset::Copy => (),
+        FfmpegPreset::H264 => args.extend(
+            ["-c:v", "libx264", "-c:a", "aac"]
+                .iter()
+                .map(|x| x.to_string()),
+        ),
+        FfmpegPreset::H265 => {
+            args.extend(
+                ["-c:v", "libx265", "-c:a", "aac"]
+                    .iter()
+                    .map(|x| x.to_string()),
+            );
+
+            if mp4 {
+                // Apple devices only play h265 streams with hvc1 tag.
+                args.extend_from_slice(&["-tag:v".to_owned(), "hvc1".to_owned()]);
+            }
+        }
+    }
+
+    if mp4 && streams.iter().any(|x| x.media_type == MediaType::Subtitles) {
+        args.extend_from_slice(&["-c:s".to_owned(), "mov_text".to_owned()]);
+    }
+
+    for (specifier, media_type) in [("a", MediaType::Audio), ("s", MediaType::Subtitles)] {
+        let streams = streams
+            .iter()
+            .filter(|x| x.media_type == media_type)
+            .collect::<Vec<_>>();
+
+        // First stream is marked as default when streams don't say otherwise.
+        let has_default = streams.iter().any(|x| x.default);
+
+        for (i, stream) in streams.iter().enumerate() {
+            if let Some(language) = &stream.language {
+                args.extend_from_slice(&[
+                    format!("-metadata:s:{}:{}", specifier, i),
+                    format!("language={}", language),
+                ]);
+            }
+
+            let mut disposition = vec![];
+
+            if stream.default || (!has_default && i == 0) {
+                disposition.push("default");
+            }
+
+            if stream.forced {
+                disposition.push("forced");
+            }
+
+            args.extend_from_slice(&[
+                format!("-disposition:{}:{}", specifier, i),
+                if disposition.is_empty() {
+                    "0".to_owned()
+                } else {
+                    disposition.join("+")
+                },
+            ]);
+        }
+    }
+
+    args.extend_from_slice(extra_args);
+    args.push(output.to_owned());
+
+    pb.print(format!(
+        "  {} {} {}",
+        "Executing".colorize("bold cyan"),
+        binary.to_string_lossy(),
+        args.iter()
+            .map(|x| if x.contains(' ') {
+                format!("\"{}\"", x)
+            } else {
+                x.to_owned()
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    ));
+
+    let mut child = Command::new(binary)
+        .args(["-hide_banner", "-nostats", "-loglevel", "error"])
+        .args(["-progress", "pipe:1"])
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Read stderr on a separate thread, otherwise ffmpeg blocks once the pipe is full.
+    let mut stderr = child.stderr.take().unwrap();
+    let stderr = std::thread::spawn(move || {
+        let mut buf = String::new();
+        let _ = stderr.read_to_string(&mut buf);
+        buf
+    });
+
+    let mut mux_pb = pb.muxing(duration);
+
+    for line in BufReader::new(child.stdout.take().unwrap()).lines() {
+        // out_time_us is N/A until first packet is written.
+        if let Some(Ok(time)) = line?.strip_prefix("out_time_us=").map(|x| x.parse::<i64>()) {
+            mux_pb.set_muxed(time.max(0) as f32 / 1_000_000.0, duration)?;
+        }
+    }
+
+    let status = child.wait()?;
+    let stderr = stderr.join().unwrap_or_default();
+    mux_pb.finish();
+
+    if !status.success() {
+        let stderr = stderr.trim();
+
+        if stderr.is_empty() {
+            bail!("ffmpeg exited with code {}.", status.code().unwrap_or(1));
+        }
+
+        bail!(
+            "ffmpeg exited with code {}.\n\n{}",
+            status.code().unwrap_or(1),
+            stderr
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,783 @@
+/*
+    REFERENCES
+    ----------
+
+    1. https://www.rfc-editor.org/rfc/rfc8794 (EBML)
+    2. https://www.rfc-editor.org/rfc/rfc9559 (Matroska)
+    3. https://www.matroska.org/technical/codec_specs.html
+    4. https://www.matroska.org/technical/subtitles.html
+    5. https://opus-codec.org/docs/opus_in_isobmff.html
+
+*/
+
+use super::{
+    iso_639_2,
+    mp4::{get_u32, get_u64, parse_moof, parse_moov, read_box, scan},
+};
+use crate::{
+    downloader::Stream,
+    playlist::{Chapter, MediaType},
+};
+use anyhow::{anyhow, bail, Result};
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+};
+use vsd_mp4::text::Subtitles;
+
+const EBML: u32 = 0x1A45DFA3;
+const SEGMENT: u32 = 0x18538067;
+const SEEK_HEAD: u32 = 0x114D9B74;
+const INFO: u32 = 0x1549A966;
+const TRACKS: u32 = 0x1654AE6B;
+const CHAPTERS: u32 = 0x1043A770;
+const CLUSTER: u32 = 0x1F43B675;
+const CUES: u32 = 0x1C53BB6B;
+
+/// Duration of a cluster (in milliseconds) when there is no video track to split clusters at
+/// keyframes. Block timestamps are relative to cluster and can't exceed 16 bits either way.
+const CLUSTER_DURATION: i64 = 5000;
+
+struct Track {
+    codec_id: &'static str,
+    codec_private: Option<Vec<u8>>,
+    default: bool,
+    forced: bool,
+    kind: TrackKind,
+    language: Option<String>,
+}
+
+enum TrackKind {
+    Audio { channels: u16, sample_rate: u32 },
+    Subtitles,
+    Video { height: u16, width: u16 },
+}
+
+struct Block {
+    /// Decode time (in milliseconds), used for interleaving blocks of all tracks.
+    decode_time: i64,
+    key: bool,
+    payload: Payload,
+    /// Presentation time (in milliseconds).
+    time: i64,
+    /// Track number (index of track + 1).
+    track: u64,
+}
+
+enum Payload {
+    /// Input index, offset and size of sample data.
+    File {
+        input: usize,
+        offset: u64,
+        size: u64,
+    },
+    Text {
+        additional: Option<String>,
+        duration: u64,
+        text: String,
+    },
+}
+
+/// Check whether all tracks of a fragmented mp4 file have codecs which can be written
+/// to matroska files.
+pub(super) fn is_supported(path: &str) -> Result<bool> {
+    let mut file = File::open(path)?;
+
+    let Some(header) = scan(&mut file)?.into_iter().find(|x| &x.name == b"moov") else {
+        return Ok(false);
+    };
+
+    for trak in parse_moov(&read_box(&mut file, &header)?)?.traks {
+        if codec(&trak.sample_entry).is_err() {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Mux fragmented mp4 video and audio streams along with text subtitles to a single
+/// matroska file. Samples are copied as they are and interleaved by their decode time.
+pub(super) fn mux(streams: &[&Stream], chapters: &[Chapter], output: &str) -> Result<()> {
+    let mut tracks = vec![];
+    let mut blocks = vec![];
+
+    for (input, stream) in streams.iter().enumerate() {
+        if stream.media_type == MediaType::Subtitles {
+            let text = std::fs::read_to_string(&stream.file_path)?;
+            let subtitles = Subtitles::from_text(&text);
+            let webvtt = stream.file_path.ends_with(".vtt");
+            let track = tracks.len() as u64 + 1;
+
+            for cue in subtitles.cues() {
+                let start = (cue.start_time.max(0.0) * 1000.0).round() as i64;
+                let end = (cue.end_time.max(0.0) * 1000.0).round() as i64;
+
+                blocks.push(Block {
+                    decode_time: start,
+                    key: true,
+                    payload: Payload::Text {
+                        additional: (webvtt && !cue.settings.is_empty())
+                            .then(|| format!("{}\n", cue.settings)),
+                        duration: end.saturating_sub(start) as u64,
+                        text: cue.payload.clone(),
+                    },
+                    time: start,
+                    track,
+                });
+            }
+
+            tracks.push(Track {
+                codec_id: if webvtt {
+                    "S_TEXT/WEBVTT"
+                } else {
+                    "S_TEXT/UTF8"
+                },
+                codec_private: webvtt.then(|| b"WEBVTT".to_vec()),
+                default: stream.default,
+                forced: stream.forced,
+                kind: TrackKind::Subtitles,
+                language: stream.language.clone(),
+            });
+        } else {
+            index_fmp4(input, stream, &mut tracks, &mut blocks)?;
+        }
+    }
+
+    // Subtitle cues are timed from zero, so samples are shifted back by the earliest decode
+    // time of video and audio tracks (e.g. clipped or live streams).
+    let start = blocks
+        .iter()
+        .filter(|x| matches!(x.payload, Payload::File { .. }))
+        .map(|x| x.decode_time)
+        .min()
+        .unwrap_or(0);
+
+    if start > 0 {
+        for block in blocks
+            .iter_mut()
+            .filter(|x| matches!(x.payload, Payload::File { .. }))
+        {
+            block.decode_time -= start;
+            block.time -= start;
+        }
+    }
+
+    // First track of each type is marked as default when streams don't say otherwise.
+    for kind in 0..3 {
+        let of_kind = |x: &Track| {
+            kind == match x.kind {
+                TrackKind::Audio { .. } => 0,
+                TrackKind::Subtitles => 1,
+                TrackKind::Video { .. } => 2,
+            }
+        };
+
+        if !tracks.iter().any(|x| of_kind(x) && x.default) {
+            if let Some(track) = tracks.iter_mut().find(|x| of_kind(x)) {
+                track.default = true;
+            }
+        }
+    }
+
+    // Samples of a track are already in decode order and sort_by_key doesn't reorder equal keys.
+    blocks.sort_by_key(|x| x.decode_time);
+
+    let duration = blocks
+        .iter()
+        .map(|x| match &x.payload {
+            Payload::Text { duration, .. } => x.time + *duration as i64,
+            _ => x.time,
+        })
+        .max()
+        .unwrap_or(0)
+        .max(0);
+
+    let video_tracks = tracks
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| matches!(x.kind, TrackKind::Video { .. }))
+        .map(|(i, _)| i as u64 + 1)
+        .collect::<Vec<_>>();
+
+    let mut inputs = streams
+        .iter()
+        .map(|x| File::open(&x.file_path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut writer = BufWriter::new(File::create(output)?);
+
+    let mut ebml = uint(0x4286, 1); // EBMLVersion
+    ebml.extend(uint(0x42F7, 1)); // EBMLReadVersion
+    ebml.extend(uint(0x42F2, 4)); // EBMLMaxIDLength
+    ebml.extend(uint(0x42F3, 8)); // EBMLMaxSizeLength
+    ebml.extend(string(0x4282, "matroska")); // DocType
+    ebml.extend(uint(0x4287, 4)); // DocTypeVersion
+    ebml.extend(uint(0x4285, 2)); // DocTypeReadVersion
+    let ebml = element(EBML, &ebml);
+
+    // Segment size is unknown until everything is written.
+    let mut segment = id(SEGMENT);
+    segment.extend([0x01, 0, 0, 0, 0, 0, 0, 0]);
+
+    let segment_start = (ebml.len() + segment.len()) as u64;
+    writer.write_all(&ebml)?;
+    writer.write_all(&segment)?;
+
+    let mut position = 0; // relative to segment_start
+    let mut seek_entries = vec![];
+
+    // Seek head is rewritten once position of cues is known, positions are always 8 bytes.
+    let seek_head_size = seek_head(&[(INFO, 0), (TRACKS, 0), (CHAPTERS, 0), (CUES, 0)]).len();
+    writer.write_all(&void(seek_head_size))?;
+    position += seek_head_size as u64;
+
+    let mut info = uint(0x2AD7B1, 1_000_000); // TimestampScale, i.e. timestamps are in milliseconds
+    info.extend(float(0x4489, duration as f64)); // Duration
+    info.extend(string(0x4D80, "vsd")); // MuxingApp
+    info.extend(string(0x5741, concat!("vsd ", env!("CARGO_PKG_VERSION")))); // WritingApp
+    let info = element(INFO, &info);
+    seek_entries.push((INFO, position));
+    writer.write_all(&info)?;
+    position += info.len() as u64;
+
+    let tracks = element(
+        TRACKS,
+        &tracks
+            .iter()
+            .enumerate()
+            .flat_map(|(i, x)| track_entry(i as u64 + 1, x))
+            .collect::<Vec<_>>(),
+    );
+    seek_entries.push((TRACKS, position));
+    writer.write_all(&tracks)?;
+    position += tracks.len() as u64;
+
+    if !chapters.is_empty() {
+        let chapters = element(CHAPTERS, &element(0x45B9, &chapter_atoms(chapters))); // EditionEntry
+        seek_entries.push((CHAPTERS, position));
+        writer.write_all(&chapters)?;
+        position += chapters.len() as u64;
+    }
+
+    let mut cues = vec![];
+    let mut cluster: Option<(i64, Vec<u8>)> = None;
+
+    for block in blocks {
+        let video_key = block.key && video_tracks.contains(&block.track);
+        let new_cluster = match &cluster {
+            Some((timestamp, _)) => {
+                let relative = block.time - timestamp;
+                relative > i16::MAX as i64
+                    || relative < i16::MIN as i64
+                    || video_key
+                    || (video_tracks.is_empty() && relative >= CLUSTER_DURATION)
+            }
+            None => true,
+        };
+
+        if new_cluster {
+            if let Some((timestamp, data)) = cluster.take() {
+                position += write_cluster(&mut writer, timestamp, &data)?;
+            }
+
+            let timestamp = block.time.max(0);
+
+            if video_key || video_tracks.is_empty() {
+                cues.push((timestamp, block.track, position));
+            }
+
+            cluster = Some((timestamp, vec![]));
+        }
+
+        let (timestamp, data) = cluster.as_mut().unwrap();
+        let relative = (block.time - *timestamp) as i16;
+
+        match block.payload {
+            Payload::File {
+                input,
+                offset,
+                size,
+            } => {
+                let file = &mut inputs[input];
+                let mut sample = vec![0; size as usize];
+                file.seek(SeekFrom::Start(offset))?;
+                file.read_exact(&mut sample)
+                    .map_err(|_| anyhow!("{} ended unexpectedly.", streams[input].file_path))?;
+
+                let mut simple_block = vint(block.track);
+                simple_block.extend(relative.to_be_bytes());
+                simple_block.push(if block.key { 0x80 } else { 0 });
+                simple_block.extend(sample);
+                data.extend(element(0xA3, &simple_block)); // SimpleBlock
+            }
+            Payload::Text {
+                additional,
+                duration,
+                text,
+            } => {
+                let mut inner = vint(block.track);
+                inner.extend(relative.to_be_bytes());
+                inner.push(0);
+                inner.extend(text.as_bytes());
+
+                let mut block_group = element(0xA1, &inner); // Block
+                block_group.extend(uint(0x9B, duration)); // BlockDuration
+
+                if let Some(additional) = additional {
+                    // BlockAdditions > BlockMore > BlockAddID, BlockAdditional
+                    block_group.extend(element(
+                        0x75A1,
+                        &element(
+                            0xA6,
+                            &[uint(0xEE, 1), element(0xA5, additional.as_bytes())].concat(),
+                        ),
+                    ));
+                }
+
+                data.extend(element(0xA0, &block_group)); // BlockGroup
+            }
+        }
+    }
+
+    if let Some((timestamp, data)) = cluster.take() {
+        position += write_cluster(&mut writer, timestamp, &data)?;
+    }
+
+    let cues = element(
+        CUES,
+        &cues
+            .iter()
+            .flat_map(|(time, track, cluster_position)| {
+                // CuePoint > CueTime, CueTrackPositions > CueTrack, CueClusterPosition
+                element(
+                    0xBB,
+                    &[
+                        uint(0xB3, *time as u64),
+                        element(
+                            0xB7,
+                            &[uint(0xF7, *track), uint(0xF1, *cluster_position)].concat(),
+                        ),
+                    ]
+                    .concat(),
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+    seek_entries.push((CUES, position));
+    writer.write_all(&cues)?;
+    position += cues.len() as u64;
+
+    let mut file = writer.into_inner()?;
+    let mut seek_head = seek_head(&seek_entries);
+
+    if seek_head.len() < seek_head_size {
+        seek_head.extend(void(seek_head_size - seek_head.len()));
+    }
+
+    file.seek(SeekFrom::Start(segment_start))?;
+    file.write_all(&seek_head)?;
+    file.seek(SeekFrom::Start(segment_start - 7))?;
+    file.write_all(&position.to_be_bytes()[1..])?;
+    Ok(())
+}
+
+/// Add tracks of a fragmented mp4 file along with a block for each sample of its fragments.
+/// Only the first moov box is used when it is repeated before every segment.
+fn index_fmp4(
+    input: usize,
+    stream: &Stream,
+    tracks: &mut Vec<Track>,
+    blocks: &mut Vec<Block>,
+) -> Result<()> {
+    let mut file = File::open(&stream.file_path)?;
+    let boxes = scan(&mut file)?;
+
+    let Some(moov) = boxes.iter().find(|x| &x.name == b"moov") else {
+        bail!("{} doesn't have a moov box.", stream.file_path);
+    };
+
+    let moov = parse_moov(&read_box(&mut file, moov)?)?;
+    let first_track = tracks.len() as u64 + 1;
+
+    for trak in &moov.traks {
+        let (codec_id, codec_private) =
+            codec(&trak.sample_entry).map_err(|e| anyhow!("{} ({})", e, stream.file_path))?;
+        let entry = &trak.sample_entry;
+
+        let kind = match &trak.handler {
+            _ if entry.len() < 36 => TrackKind::Subtitles,
+            b"vide" => TrackKind::Video {
+                height: u16::from_be_bytes([entry[34], entry[35]]),
+                width: u16::from_be_bytes([entry[32], entry[33]]),
+            },
+            b"soun" => TrackKind::Audio {
+                channels: u16::from_be_bytes([entry[24], entry[25]]),
+                sample_rate: get_u32(entry, 32) >> 16,
+            },
+            _ => TrackKind::Subtitles,
+        };
+
+        tracks.push(Track {
+            codec_id,
+            codec_private,
+            default: stream.default,
+            forced: stream.forced,
+            kind,
+            language: stream.language.clone(),
+        });
+    }
+
+    for header in boxes.iter().filter(|x| &x.name == b"moof") {
+        let moof = read_box(&mut file, header)?;
+
+        for traf in parse_moof(&moof)?.trafs {
+            let Some(index) = moov.traks.iter().position(|x| x.id == traf.track_id) else {
+                continue;
+            };
+
+            let trak = &moov.traks[index];
+            // Default sample duration, size and flags.
+            let trex = moov
+                .trexs
+                .iter()
+                .find(|x| x.0 == traf.track_id)
+                .map(|x| (get_u32(&x.1, 8), get_u32(&x.1, 12), get_u32(&x.1, 16)))
+                .unwrap_or_default();
+            let timescale = trak.timescale.max(1) as i64;
+            let milliseconds = |x: i64| (x as i128 * 1000 / timescale as i128) as i64;
+
+            let base = traf
+                .base_data_offset
+                .map_or(header.offset, |x| get_u64(&moof, x));
+            let mut offset = base;
+            let mut decode_time = traf.tfdt.map_or(0, |x| x.2) as i64;
+
+            for run in &traf.runs {
+                if let Some(data_offset) = run.data_offset {
+                    offset = base.wrapping_add_signed(data_offset as i64);
+                }
+
+                for (i, sample) in run.samples.iter().enumerate() {
+                    let duration = sample.duration.or(traf.default_duration).unwrap_or(trex.0);
+                    let size = sample.size.or(traf.default_size).unwrap_or(trex.1) as u64;
+                    let flags = if i == 0 {
+                        run.first_sample_flags.or(sample.flags)
+                    } else {
+                        sample.flags
+                    }
+                    .or(traf.default_flags)
+                    .unwrap_or(trex.2);
+
+                    blocks.push(Block {
+                        decode_time: milliseconds(decode_time - trak.media_time),
+                        // sample_is_non_sync_sample
+                        key: flags & 0x00010000 == 0,
+                        payload: Payload::File {
+                            input,
+                            offset,
+                            size,
+                        },
+                        time: milliseconds(decode_time + sample.cts - trak.media_time),
+                        track: first_track + index as u64,
+                    });
+
+                    offset += size;
+                    decode_time += duration as i64;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Matroska codec id and codec private data of a mp4 sample entry.
+fn codec(entry: &[u8]) -> Result<(&'static str, Option<Vec<u8>>)> {
+    if entry.len() < 8 {
+        bail!("track doesn't have a sample entry.");
+    }
+
+    let name = &entry[4..8];
+    // fixed fields of visual and audio (version 0, 1 and 2) sample entries
+    let fields = match name {
+        b"avc1" | b"avc3" | b"hev1" | b"hvc1" | b"av01" | b"vp09" => 78,
+        _ if entry.len() >= 18 => match u16::from_be_bytes([entry[16], entry[17]]) {
+            1 => 44,
+            2 => 64,
+            _ => 28,
+        },
+        _ => 28,
+    };
+    let child = |name: &[u8; 4]| child_box(entry.get((8 + fields)..).unwrap_or_default(), name);
+
+    Ok(match name {
+        b"avc1" | b"avc3" => ("V_MPEG4/ISO/AVC", child(b"avcC")),
+        b"hev1" | b"hvc1" => ("V_MPEGH/ISO/HEVC", child(b"hvcC")),
+        b"av01" => ("V_AV1", child(b"av1C")),
+        b"vp09" => ("V_VP9", None),
+        b"ac-3" => ("A_AC3", None),
+        b"ec-3" => ("A_EAC3", None),
+        b"fLaC" => (
+            "A_FLAC",
+            child(b"dfLa").map(|x| [&b"fLaC"[..], x.get(4..).unwrap_or_default()].concat()),
+        ),
+        b"Opus" => ("A_OPUS", child(b"dOps").map(|x| opus_head(&x))),
+        b"mp4a" => {
+            let esds = child(b"esds").unwrap_or_default();
+
+            match decoder_config(esds.get(4..).unwrap_or_default()) {
+                Some((0x40 | 0x66 | 0x67 | 0x68, config)) => ("A_AAC", config),
+                Some((0x69 | 0x6B, _)) => ("A_MPEG/L3", None),
+                _ => bail!("unsupported mp4a object type for matroska output."),
+            }
+        }
+        x => bail!(
+            "{} codec isn't supported for matroska output.",
+            String::from_utf8_lossy(x)
+        ),
+    })
+}
+
+/// Payload of a child box.
+fn child_box(data: &[u8], name: &[u8; 4]) -> Option<Vec<u8>> {
+    let mut position = 0;
+
+    while position + 8 <= data.len() {
+        let size = get_u32(data, position) as usize;
+
+        if size < 8 || position + size > data.len() {
+            break;
+        }
+
+        if &data[(position + 4)..(position + 8)] == name {
+            return Some(data[(position + 8)..(position + size)].to_vec());
+        }
+
+        position += size;
+    }
+
+    None
+}
+
+/// Object type indication and decoder specific info from ES descriptor of esds box.
+fn decoder_config(data: &[u8]) -> Option<(u8, Option<Vec<u8>>)> {
+    let (tag, es) = descriptor(data)?;
+
+    if tag != 0x03 || es.len() < 3 {
+        return None;
+    }
+
+    let flags = es[2];
+    let mut position = 3;
+
+    if flags & 0x80 != 0 {
+        position += 2; // depends on es id
+    }
+
+    if flags & 0x40 != 0 {
+        position += 1 + *es.get(position)? as usize; // url
+    }
+
+    if flags & 0x20 != 0 {
+        position += 2; // ocr es id
+    }
+
+    let (tag, decoder_config) = descriptor(es.get(position..)?)?;
+
+    if tag != 0x04 || decoder_config.len() < 13 {
+        return None;
+    }
+
+    let specific_info = descriptor(&decoder_config[13..])
+        .filter(|x| x.0 == 0x05)
+        .map(|x| x.1.to_vec());
+    Some((decoder_config[0], specific_info))
+}
+
+/// Tag and payload of a mpeg-4 descriptor.
+fn descriptor(data: &[u8]) -> Option<(u8, &[u8])> {
+    let tag = *data.first()?;
+    let mut size = 0;
+    let mut position = 1;
+
+    for _ in 0..4 {
+        let x = *data.get(position)?;
+        size = (size << 7) | (x & 0x7F) as usize;
+        position += 1;
+
+        if x & 0x80 == 0 {
+            break;
+        }
+    }
+
+    Some((tag, data.get(position..(position + size))?))
+}
+
+/// Convert opus specific box (big endian) to opus identification header (little endian).
+fn opus_head(dops: &[u8]) -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+
+    if dops.len() >= 11 {
+        head.push(1); // version
+        head.push(dops[1]); // output channel count
+        head.extend(u16::from_be_bytes([dops[2], dops[3]]).to_le_bytes()); // pre skip
+        head.extend(get_u32(dops, 4).to_le_bytes()); // input sample rate
+        head.extend(i16::from_be_bytes([dops[8], dops[9]]).to_le_bytes()); // output gain
+        head.extend(&dops[10..]); // channel mapping family and table
+    }
+
+    head
+}
+
+fn track_entry(number: u64, track: &Track) -> Vec<u8> {
+    let mut entry = uint(0xD7, number); // TrackNumber
+    entry.extend(uint(0x73C5, number)); // TrackUID
+    entry.extend(uint(
+        0x83, // TrackType
+        match track.kind {
+            TrackKind::Audio { .. } => 2,
+            TrackKind::Subtitles => 0x11,
+            TrackKind::Video { .. } => 1,
+        },
+    ));
+    entry.extend(uint(0x88, track.default as u64)); // FlagDefault
+    entry.extend(uint(0x55AA, track.forced as u64)); // FlagForced
+    entry.extend(uint(0x9C, 0)); // FlagLacing
+    entry.extend(string(0x86, track.codec_id)); // CodecID
+
+    if let Some(codec_private) = &track.codec_private {
+        entry.extend(element(0x63A2, codec_private)); // CodecPrivate
+    }
+
+    // Language defaults to eng, so it is always written.
+    entry.extend(string(
+        0x22B59C, // Language
+        &track
+            .language
+            .as_deref()
+            .and_then(iso_639_2)
+            .unwrap_or("und".to_owned()),
+    ));
+
+    if let Some(language) = &track.language {
+        entry.extend(string(0x22B59D, language)); // LanguageBCP47
+    }
+
+    match track.kind {
+        TrackKind::Audio {
+            channels,
+            sample_rate,
+        } => {
+            // Audio > SamplingFrequency, Channels
+            entry.extend(element(
+                0xE1,
+                &[float(0xB5, sample_rate as f64), uint(0x9F, channels as u64)].concat(),
+            ));
+        }
+        TrackKind::Video { height, width } => {
+            // Video > PixelWidth, PixelHeight
+            entry.extend(element(
+                0xE0,
+                &[uint(0xB0, width as u64), uint(0xBA, height as u64)].concat(),
+            ));
+        }
+        TrackKind::Subtitles => (),
+    }
+
+    element(0xAE, &entry) // TrackEntry
+}
+
+fn chapter_atoms(chapters: &[Chapter]) -> Vec<u8> {
+    let mut atoms = uint(0x45DB, 0); // EditionFlagDefault
+    atoms.extend(uint(0x45BC, 1)); // EditionUID
+
+    for (i, chapter) in chapters.iter().enumerate() {
+        // ChapterAtom > ChapterUID, ChapterTimeStart, ChapterDisplay > ChapString, ChapLanguage
+        atoms.extend(element(
+            0xB6,
+            &[
+                uint(0x73C4, i as u64 + 1),
+                uint(0x91, (chapter.start.max(0.0) as f64 * 1e9).round() as u64),
+                element(
+                    0x80,
+                    &[string(0x85, &chapter.title), string(0x437C, "und")].concat(),
+                ),
+            ]
+            .concat(),
+        ));
+    }
+
+    atoms
+}
+
+fn seek_head(entries: &[(u32, u64)]) -> Vec<u8> {
+    let mut seek_head = vec![];
+
+    for (seek_id, position) in entries {
+        // Seek > SeekID, SeekPosition
+        seek_head.extend(element(
+            0x4DBB,
+            &[
+                element(0x53AB, &id(*seek_id)),
+                element(0x53AC, &position.to_be_bytes()),
+            ]
+            .concat(),
+        ));
+    }
+
+    element(SEEK_HEAD, &seek_head)
+}
+
+fn write_cluster(writer: &mut impl Write, timestamp: i64, blocks: &[u8]) -> Result<u64> {
+    let mut cluster = id(CLUSTER);
+    let payload = uint(0xE7, timestamp as u64); // Timestamp
+    cluster.extend(vint((payload.len() + blocks.len()) as u64));
+    cluster.extend(payload);
+    writer.write_all(&cluster)?;
+    writer.write_all(blocks)?;
+    Ok((cluster.len() + blocks.len()) as u64)
+}
+
+fn void(size: usize) -> Vec<u8> {
+    // id (1 byte) + size (8 bytes)
+    let mut void = vec![0xEC, 0x01];
+    void.extend(&((size - 9) as u64).to_be_bytes()[1..]);
+    void.resize(size, 0);
+    void
+}
+
+fn element(element_id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut data = id(element_id);
+    data.extend(vint(payload.len() as u64));
+    data.extend(payload);
+    data
+}
+
+fn uint(element_id: u32, value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let start = bytes.iter().position(|x| *x != 0).unwrap_or(7);
+    element(element_id, &bytes[start..])
+}
+
+fn float(element_id: u32, value: f64) -> Vec<u8> {
+    element(element_id, &value.to_be_bytes())
+}
+
+fn string(element_id: u32, value: &str) -> Vec<u8> {
+    element(element_id, value.as_bytes())
+}
+
+/// Element ids already contain their length marker.
+fn id(element_id: u32) -> Vec<u8> {
+    let bytes = element_id.to_be_bytes();
+    let start = bytes.iter().position(|x| *x != 0).unwrap_or(3);
+    bytes[start..].to_vec()
+}
+
+/// Variable length integer of element data sizes and track numbers.
+fn vint(value: u64) -> Vec<u8> {
+    let length = (1..8).find(|x| value < (1 << (7 * x)) - 1).unwrap_or(8);
+    let mut data = value.to_be_bytes()[(8 - length)..].to_vec();
+    data[0] |= 0x80 >> (length - 1);
+    data
+}
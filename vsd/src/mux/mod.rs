@@ -0,0 +1,167 @@
+pub(crate) mod ffmpeg;
+mod mkv;
+mod mp4;
+mod normalize;
+mod ts;
+
+pub(crate) use normalize::Normalizer;
+pub(crate) use ts::remux;
+
+use crate::{
+    downloader::Stream,
+    playlist::{Chapter, MediaType},
+};
+use anyhow::Result;
+use std::{fs::File, io::Read};
+
+/// Program used for muxing downloaded streams to output file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Muxer {
+    /// Use ffmpeg if it is installed, otherwise use native muxer if it supports the streams.
+    #[default]
+    Auto,
+    /// Always use ffmpeg.
+    Ffmpeg,
+    /// Always use native muxer, which writes fragmented mp4 or matroska files from
+    /// fragmented mp4 or mpeg-ts (h264, h265 and aac) video and audio streams along with
+    /// webvtt and subrip subtitles.
+    Native,
+}
+
+/// Codec arguments passed to ffmpeg for muxing streams.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FfmpegPreset {
+    /// Copy all streams without re-encoding them.
+    #[default]
+    Copy,
+    /// Re-encode video streams to h264 (libx264) and audio streams to aac.
+    H264,
+    /// Re-encode video streams to h265 (libx265) and audio streams to aac.
+    H265,
+}
+
+/// Check whether output can be muxed natively, judging from its extension only.
+pub(crate) fn supports_output(output: &str) -> bool {
+    [".m4a", ".m4v", ".mka", ".mkv", ".mp4"]
+        .iter()
+        .any(|x| output.to_lowercase().ends_with(x))
+}
+
+/// Check whether downloaded streams can be muxed natively to output.
+pub(crate) fn supports(streams: &[&Stream], output: &str) -> Result<bool> {
+    if !supports_output(output) {
+        return Ok(false);
+    }
+
+    for stream in streams {
+        if stream.media_type == MediaType::Subtitles {
+            if !(stream.file_path.ends_with(".srt") || stream.file_path.ends_with(".vtt")) {
+                return Ok(false);
+            }
+        } else if mp4::is_fragmented(&stream.file_path)? {
+            if is_matroska(output) && !mkv::is_supported(&stream.file_path)? {
+                return Ok(false);
+            }
+        } else if !ts::is_remuxable(&stream.file_path)? {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Mux downloaded streams natively to output.
+/// Mpeg-ts streams are remuxed to fragmented mp4 files before muxing them.
+/// Chapters are only written to matroska files.
+pub(crate) fn mux(streams: &[&Stream], chapters: &[Chapter], output: &str) -> Result<()> {
+    let mut remuxed = vec![];
+
+    for stream in streams {
+        if stream.media_type != MediaType::Subtitles && !mp4::is_fragmented(&stream.file_path)? {
+            let file_path = format!("{}.mp4", stream.file_path);
+            ts::remux(std::slice::from_ref(&stream.file_path), &file_path)?;
+            remuxed.push(Stream {
+                default: stream.default,
+                file_path,
+                forced: stream.forced,
+                language: stream.language.clone(),
+                media_type: stream.media_type.clone(),
+            });
+        }
+    }
+
+    let fmp4_streams = streams
+        .iter()
+        .map(|x| {
+            remuxed
+                .iter()
+                .find(|y| y.file_path == format!("{}.mp4", x.file_path))
+                .unwrap_or(x)
+        })
+        .collect::<Vec<_>>();
+    let result = if is_matroska(output) {
+        mkv::mux(&fmp4_streams, chapters, output)
+    } else {
+        mp4::mux(&fmp4_streams, output)
+    };
+
+    for stream in &remuxed {
+        let _ = std::fs::remove_file(&stream.file_path);
+    }
+
+    result
+}
+
+fn is_matroska(output: &str) -> bool {
+    [".mka", ".mkv"]
+        .iter()
+        .any(|x| output.to_lowercase().ends_with(x))
+}
+
+fn read_header(path: &str, buf: &mut [u8]) -> Result<usize> {
+    let mut file = File::open(path)?;
+    let mut read = 0;
+
+    while read < buf.len() {
+        match file.read(&mut buf[read..])? {
+            0 => break,
+            x => read += x,
+        }
+    }
+
+    Ok(read)
+}
+
+/// ISO 639-2/T code of a RFC 5646 language tag (eg. en, eng or en-US).
+fn iso_639_2(language: &str) -> Option<String> {
+    let language = language.split(['-', '_']).next()?.trim().to_lowercase();
+
+    if !language.chars().all(|x| x.is_ascii_lowercase()) {
+        return None;
+    }
+
+    match language.len() {
+        2 => ISO_639_1_TO_2
+            .split(',')
+            .find(|x| x.starts_with(&language))
+            .map(|x| x[3..].to_owned()),
+        3 => Some(language),
+        _ => None,
+    }
+}
+
+const ISO_639_1_TO_2: &str = "aa:aar,ab:abk,ae:ave,af:afr,ak:aka,am:amh,an:arg,ar:ara,as:asm,\
+av:ava,ay:aym,az:aze,ba:bak,be:bel,bg:bul,bh:bih,bi:bis,bm:bam,bn:ben,bo:bod,br:bre,bs:bos,\
+ca:cat,ce:che,ch:cha,co:cos,cr:cre,cs:ces,cu:chu,cv:chv,cy:cym,da:dan,de:deu,dv:div,dz:dzo,\
+ee:ewe,el:ell,en:eng,eo:epo,es:spa,et:est,eu:eus,fa:fas,ff:ful,fi:fin,fj:fij,fo:fao,fr:fra,\
+fy:fry,ga:gle,gd:gla,gl:glg,gn:grn,gu:guj,gv:glv,ha:hau,he:heb,hi:hin,ho:hmo,hr:hrv,ht:hat,\
+hu:hun,hy:hye,hz:her,ia:ina,id:ind,ie:ile,ig:ibo,ii:iii,ik:ipk,io:ido,is:isl,it:ita,iu:iku,\
+ja:jpn,jv:jav,ka:kat,kg:kon,ki:kik,kj:kua,kk:kaz,kl:kal,km:khm,kn:kan,ko:kor,kr:kau,ks:kas,\
+ku:kur,kv:kom,kw:cor,ky:kir,la:lat,lb:ltz,lg:lug,li:lim,ln:lin,lo:lao,lt:lit,lu:lub,lv:lav,\
+mg:mlg,mh:mah,mi:mri,mk:mkd,ml:mal,mn:mon,mr:mar,ms:msa,mt:mlt,my:mya,na:nau,nb:nob,nd:nde,\
+ne:nep,ng:ndo,nl:nld,nn:nno,no:nor,nr:nbl,nv:nav,ny:nya,oc:oci,oj:oji,om:orm,or:ori,os:oss,\
+pa:pan,pi:pli,pl:pol,ps:pus,pt:por,qu:que,rm:roh,rn:run,ro:ron,ru:rus,rw:kin,sa:san,sc:srd,\
+sd:snd,se:sme,sg:sag,si:sin,sk:slk,sl:slv,sm:smo,sn:sna,so:som,sq:sqi,sr:srp,ss:ssw,st:sot,\
+su:sun,sv:swe,sw:swa,ta:tam,te:tel,tg:tgk,th:tha,ti:tir,tk:tuk,tl:tgl,tn:tsn,to:ton,tr:tur,\
+ts:tso,tt:tat,tw:twi,ty:tah,ug:uig,uk:ukr,ur:urd,uz:uzb,ve:ven,vi:vie,vo:vol,wa:wln,wo:wol,\
+xh:xho,yi:yid,yo:yor,za:zha,zh:zho,zu:zul";
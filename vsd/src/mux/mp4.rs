@@ -0,0 +1,1140 @@
+/*
+    REFERENCES
+    ----------
+
+    1. https://www.iso.org/standard/83102.html (ISO/IEC 14496-12)
+    2. https://www.iso.org/standard/75394.html (ISO/IEC 14496-30)
+    3. https://github.com/shaka-project/shaka-player/blob/d6001097a9751bd9211eb52f940e282ead026a32/lib/util/mp4_generator.js
+
+*/
+
+use super::{iso_639_2, read_header};
+use crate::{downloader::Stream, playlist::MediaType};
+use anyhow::{anyhow, bail, Result};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    sync::{Arc, Mutex},
+};
+use vsd_mp4::{children, text::Subtitles, Mp4Parser, ParsedBox};
+
+/// Timescale of movie and subtitle tracks.
+pub(super) const TIMESCALE: u32 = 1000;
+/// Duration of each fragment of subtitle tracks (in milliseconds).
+const SUBTITLE_FRAGMENT_DURATION: u64 = 60_000;
+/// Decode time going backwards by more than this (in seconds) is treated as a discontinuity,
+/// e.g. timeline of next period starting from zero.
+const DISCONTINUITY_THRESHOLD: u64 = 1;
+
+pub(super) struct BoxHeader {
+    pub(super) name: [u8; 4],
+    pub(super) offset: u64,
+    pub(super) size: u64,
+}
+
+struct Track {
+    default_duration: u32,
+    /// Largest end time of fragments (in track timescale).
+    end_time: u64,
+    /// Decode time of next fragment, used for detecting discontinuities.
+    next_decode_time: u64,
+    /// Shift applied to decode times for keeping them monotonic.
+    shift: u64,
+    timescale: u32,
+    trak: Vec<u8>,
+    trex: Vec<u8>,
+}
+
+struct Fragment {
+    /// Positions of `base_data_offset` fields inside moof box.
+    base_data_offsets: Vec<usize>,
+    /// Moof box (along with mdat box for subtitle fragments).
+    data: Vec<u8>,
+    /// Input index, offset and size of media data which follows moof box in input.
+    media: Option<(usize, u64, u64)>,
+    /// Offset of moof box in input.
+    offset: u64,
+    /// Position of `sequence_number` field inside moof box.
+    sequence: usize,
+    /// Positions and versions of `tfdt` boxes inside moof box, along with timescale of their track.
+    tfdts: Vec<(usize, u32, u32)>,
+    /// Start time of fragment (in seconds), used for interleaving fragments of all tracks.
+    time: f64,
+}
+
+#[derive(Default)]
+pub(super) struct TrakInfo {
+    elst: Option<(usize, u32)>,
+    /// Handler type of `hdlr` box, e.g. `vide` or `soun`.
+    pub(super) handler: [u8; 4],
+    pub(super) id: u32,
+    mdhd: Option<(usize, u32)>,
+    /// Media time of first edit (in track timescale).
+    pub(super) media_time: i64,
+    /// First sample entry of `stsd` box.
+    pub(super) sample_entry: Vec<u8>,
+    size: usize,
+    start: usize,
+    pub(super) timescale: u32,
+    tkhd: Option<(usize, u32)>,
+}
+
+#[derive(Default)]
+pub(super) struct MoovInfo {
+    timescale: u32,
+    pub(super) traks: Vec<TrakInfo>,
+    /// Track id and payload of `trex` boxes.
+    pub(super) trexs: Vec<(u32, Vec<u8>)>,
+}
+
+#[derive(Default)]
+pub(super) struct TrafInfo {
+    /// Position of `base_data_offset` field inside moof box.
+    pub(super) base_data_offset: Option<usize>,
+    pub(super) default_duration: Option<u32>,
+    pub(super) default_flags: Option<u32>,
+    pub(super) default_size: Option<u32>,
+    pub(super) duration: u64,
+    pub(super) implicit_samples: u64,
+    pub(super) runs: Vec<TrunInfo>,
+    pub(super) tfdt: Option<(usize, u32, u64)>,
+    pub(super) track_id: u32,
+    track_id_position: usize,
+}
+
+#[derive(Default)]
+pub(super) struct TrunInfo {
+    pub(super) data_offset: Option<i32>,
+    pub(super) first_sample_flags: Option<u32>,
+    pub(super) samples: Vec<TrunSample>,
+}
+
+pub(super) struct TrunSample {
+    /// Composition time offset (in track timescale).
+    pub(super) cts: i64,
+    pub(super) duration: Option<u32>,
+    pub(super) flags: Option<u32>,
+    pub(super) size: Option<u32>,
+}
+
+#[derive(Default)]
+pub(super) struct MoofInfo {
+    sequence: Option<usize>,
+    pub(super) trafs: Vec<TrafInfo>,
+}
+
+/// Check whether file is a fragmented mp4 file.
+pub(super) fn is_fragmented(path: &str) -> Result<bool> {
+    let mut header = [0; 8];
+
+    if read_header(path, &mut header)? < 8
+        || ![b"ftyp", b"moof", b"moov", b"sidx", b"styp"]
+            .contains(&&[header[4], header[5], header[6], header[7]])
+    {
+        return Ok(false);
+    }
+
+    Ok(scan(&mut File::open(path)?)?
+        .iter()
+        .any(|x| &x.name == b"moof"))
+}
+
+/// Mux fragmented mp4 video and audio streams along with text subtitles to a single
+/// fragmented mp4 file. Track ids are renumbered and fragments of all tracks are interleaved
+/// by their decode time.
+pub(super) fn mux(streams: &[&Stream], output: &str) -> Result<()> {
+    let mut tracks = vec![];
+    let mut fragments = vec![];
+    let mut first_subtitles = true;
+    let mut media_tracks = vec![];
+
+    for (input, stream) in streams.iter().enumerate() {
+        if stream.media_type == MediaType::Subtitles {
+            let subtitles = Subtitles::from_text(&std::fs::read_to_string(&stream.file_path)?);
+            let id = tracks.len() as u32 + 1;
+            let (track, track_fragments) =
+                subtitle_track(id, &subtitles, stream.language.as_deref(), first_subtitles);
+            tracks.push(track);
+            fragments.extend(track_fragments);
+            first_subtitles = false;
+        } else {
+            let first_track = tracks.len();
+            index_fmp4(input, stream, &mut tracks, &mut fragments)?;
+            media_tracks.extend(first_track..tracks.len());
+        }
+    }
+
+    // Video and audio tracks are rebased to start from zero (same as ffmpeg), so that they
+    // line up with subtitles, which start from zero even when streams are clipped.
+    let start = fragments
+        .iter()
+        .filter(|x| x.media.is_some())
+        .map(|x| x.time)
+        .fold(f64::MAX, f64::min);
+
+    if start > 0.0 && start != f64::MAX {
+        for fragment in fragments.iter_mut().filter(|x| x.media.is_some()) {
+            for &(position, version, timescale) in &fragment.tfdts {
+                let shift = (start * timescale as f64).round() as u64;
+
+                if version == 1 {
+                    let decode_time = get_u64(&fragment.data, position);
+                    put_u64(
+                        &mut fragment.data,
+                        position,
+                        decode_time.saturating_sub(shift),
+                    );
+                } else {
+                    let decode_time = get_u32(&fragment.data, position) as u64;
+                    put_u32(
+                        &mut fragment.data,
+                        position,
+                        decode_time.saturating_sub(shift) as u32,
+                    );
+                }
+            }
+
+            fragment.time -= start;
+        }
+
+        for &index in &media_tracks {
+            let track = &mut tracks[index];
+            let shift = (start * track.timescale as f64).round() as u64;
+            track.end_time = track.end_time.saturating_sub(shift);
+        }
+    }
+
+    // Stable sort keeps fragments of a track in their original order.
+    fragments.sort_by(|x, y| x.time.total_cmp(&y.time));
+
+    let duration = tracks
+        .iter()
+        .map(|x| x.end_time * TIMESCALE as u64 / x.timescale.max(1) as u64)
+        .max()
+        .unwrap_or(0);
+
+    let mut inputs = streams
+        .iter()
+        .map(|x| File::open(&x.file_path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut writer = BufWriter::new(File::create(output)?);
+
+    let ftyp = ftyp();
+    let moov = moov(
+        &tracks.iter().map(|x| x.trak.clone()).collect::<Vec<_>>(),
+        &tracks.iter().map(|x| x.trex.clone()).collect::<Vec<_>>(),
+        duration,
+    );
+    writer.write_all(&ftyp)?;
+    writer.write_all(&moov)?;
+
+    let mut position = (ftyp.len() + moov.len()) as u64;
+
+    for (i, fragment) in fragments.iter_mut().enumerate() {
+        put_u32(&mut fragment.data, fragment.sequence, i as u32 + 1);
+
+        for &base_data_offset in &fragment.base_data_offsets {
+            let value = get_u64(&fragment.data, base_data_offset);
+            put_u64(
+                &mut fragment.data,
+                base_data_offset,
+                (value + position).wrapping_sub(fragment.offset),
+            );
+        }
+
+        writer.write_all(&fragment.data)?;
+        position += fragment.data.len() as u64;
+
+        if let Some((input, offset, size)) = fragment.media {
+            let file = &mut inputs[input];
+            file.seek(SeekFrom::Start(offset))?;
+            let copied = io::copy(&mut file.take(size), &mut writer)?;
+
+            if copied != size {
+                bail!("{} ended unexpectedly.", streams[input].file_path);
+            }
+
+            position += size;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read tracks and fragments of a fragmented mp4 file.
+/// Init segments which are repeated before every segment (decrypted streams) are skipped.
+fn index_fmp4(
+    input: usize,
+    stream: &Stream,
+    tracks: &mut Vec<Track>,
+    fragments: &mut Vec<Fragment>,
+) -> Result<()> {
+    let mut file = File::open(&stream.file_path)?;
+    let boxes = scan(&mut file)?;
+
+    let moov = boxes
+        .iter()
+        .find(|x| &x.name == b"moov")
+        .ok_or_else(|| anyhow!("couldn't find moov box in {}.", stream.file_path))?;
+    let moov = read_box(&mut file, moov)?;
+    let moov_info = parse_moov(&moov)?;
+
+    let language = stream.language.as_deref().and_then(iso_639_2);
+    let mut track_ids = HashMap::new();
+
+    for trak_info in &moov_info.traks {
+        let id = tracks.len() as u32 + 1;
+        let mut trak = moov[trak_info.start..(trak_info.start + trak_info.size)].to_vec();
+        let position = |x: usize| x - trak_info.start;
+
+        let (tkhd, version) = trak_info
+            .tkhd
+            .ok_or_else(|| anyhow!("couldn't find tkhd box in {}.", stream.file_path))?;
+        let (mdhd, mdhd_version) = trak_info
+            .mdhd
+            .ok_or_else(|| anyhow!("couldn't find mdhd box in {}.", stream.file_path))?;
+
+        // Durations are carried by fragments, tkhd duration is in movie timescale of input.
+        if version == 1 {
+            put_u32(&mut trak, position(tkhd) + 16, id);
+            put_u64(&mut trak, position(tkhd) + 24, 0);
+        } else {
+            put_u32(&mut trak, position(tkhd) + 8, id);
+            put_u32(&mut trak, position(tkhd) + 16, 0);
+        }
+
+        if let Some(language) = &language {
+            let language_position = position(mdhd) + if mdhd_version == 1 { 28 } else { 16 };
+            trak[language_position..(language_position + 2)]
+                .copy_from_slice(&pack_language(language));
+        }
+
+        if let Some((elst, version)) = trak_info.elst {
+            rescale_elst(&mut trak, position(elst), version, moov_info.timescale);
+        }
+
+        let mut trex = moov_info
+            .trexs
+            .iter()
+            .find(|x| x.0 == trak_info.id)
+            .map(|x| x.1.clone())
+            .unwrap_or_else(|| vec![0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        put_u32(&mut trex, 0, id);
+
+        track_ids.insert(trak_info.id, tracks.len());
+        tracks.push(Track {
+            default_duration: get_u32(&trex, 8),
+            end_time: 0,
+            next_decode_time: 0,
+            shift: 0,
+            timescale: trak_info.timescale,
+            trak,
+            trex: full_box(b"trex", 0, 0, &trex),
+        });
+    }
+
+    for (i, moof) in boxes.iter().enumerate() {
+        if &moof.name != b"moof" {
+            continue;
+        }
+
+        let media = boxes[(i + 1)..]
+            .iter()
+            .take_while(|x| [b"free", b"mdat", b"skip"].contains(&&x.name))
+            .collect::<Vec<_>>();
+
+        // Incomplete fragment at the end of file.
+        if !media.iter().any(|x| &x.name == b"mdat") {
+            continue;
+        }
+
+        let media_offset = moof.offset + moof.size;
+        let media_size = media.iter().map(|x| x.size).sum::<u64>();
+
+        let mut data = read_box(&mut file, moof)?;
+        let moof_info = parse_moof(&data)?;
+        let mut time = f64::MAX;
+        let mut base_data_offsets = vec![];
+        let mut tfdts = vec![];
+
+        for traf in &moof_info.trafs {
+            let track_index = *track_ids.get(&traf.track_id).ok_or_else(|| {
+                anyhow!(
+                    "fragment of unknown track {} in {}.",
+                    traf.track_id,
+                    stream.file_path
+                )
+            })?;
+            let track = &mut tracks[track_index];
+
+            put_u32(&mut data, traf.track_id_position, track_index as u32 + 1);
+            base_data_offsets.extend(traf.base_data_offset);
+
+            let duration = traf.duration
+                + (traf.implicit_samples
+                    * traf.default_duration.unwrap_or(track.default_duration) as u64);
+            let mut decode_time = match traf.tfdt {
+                Some((_, _, decode_time)) => decode_time + track.shift,
+                None => track.next_decode_time,
+            };
+
+            if decode_time + (DISCONTINUITY_THRESHOLD * track.timescale as u64)
+                < track.next_decode_time
+            {
+                track.shift += track.next_decode_time - decode_time;
+                decode_time = track.next_decode_time;
+            }
+
+            if let Some((position, version, value)) = traf.tfdt {
+                tfdts.push((position, version, track.timescale));
+
+                if decode_time != value {
+                    if version == 1 {
+                        put_u64(&mut data, position, decode_time);
+                    } else {
+                        put_u32(&mut data, position, decode_time as u32);
+                    }
+                }
+            }
+
+            track.next_decode_time = decode_time + duration;
+            track.end_time = track.end_time.max(track.next_decode_time);
+            time = time.min(decode_time as f64 / track.timescale.max(1) as f64);
+        }
+
+        fragments.push(Fragment {
+            base_data_offsets,
+            data,
+            media: Some((input, media_offset, media_size)),
+            offset: moof.offset,
+            sequence: moof_info
+                .sequence
+                .ok_or_else(|| anyhow!("couldn't find mfhd box in {}.", stream.file_path))?,
+            tfdts,
+            time,
+        });
+    }
+
+    Ok(())
+}
+
+pub(super) fn scan(file: &mut File) -> Result<Vec<BoxHeader>> {
+    let length = file.metadata()?.len();
+    let mut boxes = vec![];
+    let mut offset = 0;
+
+    while offset + 8 <= length {
+        let mut header = [0; 16];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header[..8])?;
+
+        let size = match get_u32(&header, 0) as u64 {
+            0 => length - offset,
+            1 => {
+                file.read_exact(&mut header[8..])?;
+                get_u64(&header, 8)
+            }
+            size => size,
+        };
+
+        // Incomplete box at the end of file.
+        if size < 8 || offset + size > length {
+            break;
+        }
+
+        boxes.push(BoxHeader {
+            name: [header[4], header[5], header[6], header[7]],
+            offset,
+            size,
+        });
+        offset += size;
+    }
+
+    Ok(boxes)
+}
+
+pub(super) fn read_box(file: &mut File, header: &BoxHeader) -> Result<Vec<u8>> {
+    let mut data = vec![0; header.size as usize];
+    file.seek(SeekFrom::Start(header.offset))?;
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+pub(super) fn parse_moov(data: &[u8]) -> Result<MoovInfo> {
+    let info = Arc::new(Mutex::new(MoovInfo::default()));
+    let info_mvhd = info.clone();
+    let info_trak = info.clone();
+    let info_tkhd = info.clone();
+    let info_elst = info.clone();
+    let info_mdhd = info.clone();
+    let info_hdlr = info.clone();
+    let info_stsd = info.clone();
+    let info_trex = info.clone();
+
+    Mp4Parser::default()
+        ._box("moov", Arc::new(children))
+        .full_box(
+            "mvhd",
+            Arc::new(move |mut _box| {
+                let version = _box.version.unwrap_or(0);
+                skip(&mut _box, if version == 1 { 16 } else { 8 })?;
+                info_mvhd.lock().unwrap().timescale = read_u32(&mut _box)?;
+                Ok(())
+            }),
+        )
+        ._box(
+            "trak",
+            Arc::new(move |_box| {
+                info_trak.lock().unwrap().traks.push(TrakInfo {
+                    size: _box.size,
+                    start: _box.start as usize,
+                    ..Default::default()
+                });
+                children(_box)
+            }),
+        )
+        .full_box(
+            "tkhd",
+            Arc::new(move |mut _box| {
+                let version = _box.version.unwrap_or(0);
+                let position = payload_position(&_box);
+                skip(&mut _box, if version == 1 { 16 } else { 8 })?;
+                let id = read_u32(&mut _box)?;
+
+                if let Some(trak) = info_tkhd.lock().unwrap().traks.last_mut() {
+                    trak.id = id;
+                    trak.tkhd = Some((position, version));
+                }
+
+                Ok(())
+            }),
+        )
+        ._box("edts", Arc::new(children))
+        .full_box(
+            "elst",
+            Arc::new(move |mut _box| {
+                let version = _box.version.unwrap_or(0);
+                let position = payload_position(&_box);
+                let mut media_time = 0;
+
+                if read_u32(&mut _box)? > 0 {
+                    media_time = if version == 1 {
+                        skip(&mut _box, 8)?;
+                        _box.reader.read_u64().map_err(|_| {
+                            vsd_mp4::Error::new_read_err("ELST box media time (u64)")
+                        })? as i64
+                    } else {
+                        skip(&mut _box, 4)?;
+                        read_u32(&mut _box)? as i32 as i64
+                    };
+                }
+
+                if let Some(trak) = info_elst.lock().unwrap().traks.last_mut() {
+                    trak.elst = Some((position, version));
+                    // empty edits (-1) are not supported
+                    trak.media_time = media_time.max(0);
+                }
+
+                Ok(())
+            }),
+        )
+        ._box("mdia", Arc::new(children))
+        .full_box(
+            "mdhd",
+            Arc::new(move |mut _box| {
+                let version = _box.version.unwrap_or(0);
+                let position = payload_position(&_box);
+                skip(&mut _box, if version == 1 { 16 } else { 8 })?;
+                let timescale = read_u32(&mut _box)?;
+
+                if let Some(trak) = info_mdhd.lock().unwrap().traks.last_mut() {
+                    trak.mdhd = Some((position, version));
+                    trak.timescale = timescale;
+                }
+
+                Ok(())
+            }),
+        )
+        .full_box(
+            "hdlr",
+            Arc::new(move |mut _box| {
+                skip(&mut _box, 4)?;
+                let handler = read_u32(&mut _box)?;
+
+                if let Some(trak) = info_hdlr.lock().unwrap().traks.last_mut() {
+                    trak.handler = handler.to_be_bytes();
+                }
+
+                Ok(())
+            }),
+        )
+        ._box("minf", Arc::new(children))
+        ._box("stbl", Arc::new(children))
+        .full_box(
+            "stsd",
+            Arc::new(move |mut _box| {
+                let position = payload_position(&_box);
+                let count = read_u32(&mut _box)?;
+                let entries = _box
+                    .reader
+                    .read_bytes_u8((_box.size + _box.start as usize).saturating_sub(position + 4))
+                    .map_err(|_| vsd_mp4::Error::new_read_err("STSD box entries"))?;
+
+                if count > 0 && entries.len() >= 8 {
+                    let size = (get_u32(&entries, 0) as usize).clamp(8, entries.len());
+
+                    if let Some(trak) = info_stsd.lock().unwrap().traks.last_mut() {
+                        trak.sample_entry = entries[..size].to_vec();
+                    }
+                }
+
+                Ok(())
+            }),
+        )
+        ._box("mvex", Arc::new(children))
+        .full_box(
+            "trex",
+            Arc::new(move |mut _box| {
+                let payload = _box
+                    .reader
+                    .read_bytes_u8(20)
+                    .map_err(|_| vsd_mp4::Error::new_read_err("TREX box payload (20 bytes)"))?;
+                info_trex
+                    .lock()
+                    .unwrap()
+                    .trexs
+                    .push((get_u32(&payload, 0), payload));
+                Ok(())
+            }),
+        )
+        .parse(data, None, None)?;
+
+    let info = std::mem::take(&mut *info.lock().unwrap());
+    Ok(info)
+}
+
+pub(super) fn parse_moof(data: &[u8]) -> Result<MoofInfo> {
+    let info = Arc::new(Mutex::new(MoofInfo::default()));
+    let info_mfhd = info.clone();
+    let info_traf = info.clone();
+    let info_tfhd = info.clone();
+    let info_tfdt = info.clone();
+    let info_trun = info.clone();
+
+    Mp4Parser::default()
+        ._box("moof", Arc::new(children))
+        .full_box(
+            "mfhd",
+            Arc::new(move |_box| {
+                info_mfhd.lock().unwrap().sequence = Some(payload_position(&_box));
+                Ok(())
+            }),
+        )
+        ._box(
+            "traf",
+            Arc::new(move |_box| {
+                info_traf.lock().unwrap().trafs.push(TrafInfo::default());
+                children(_box)
+            }),
+        )
+        .full_box(
+            "tfhd",
+            Arc::new(move |mut _box| {
+                let flags = _box.flags.unwrap_or(0);
+                let position = payload_position(&_box);
+                let track_id = read_u32(&mut _box)?;
+                let mut base_data_offset = None;
+                let mut default_duration = None;
+                let mut default_size = None;
+                let mut default_flags = None;
+
+                if flags & 0x000001 != 0 {
+                    base_data_offset = Some(position + 4);
+                    skip(&mut _box, 8)?;
+                }
+
+                if flags & 0x000002 != 0 {
+                    skip(&mut _box, 4)?;
+                }
+
+                if flags & 0x000008 != 0 {
+                    default_duration = Some(read_u32(&mut _box)?);
+                }
+
+                if flags & 0x000010 != 0 {
+                    default_size = Some(read_u32(&mut _box)?);
+                }
+
+                if flags & 0x000020 != 0 {
+                    default_flags = Some(read_u32(&mut _box)?);
+                }
+
+                if let Some(traf) = info_tfhd.lock().unwrap().trafs.last_mut() {
+                    traf.base_data_offset = base_data_offset;
+                    traf.default_duration = default_duration;
+                    traf.default_flags = default_flags;
+                    traf.default_size = default_size;
+                    traf.track_id = track_id;
+                    traf.track_id_position = position;
+                }
+
+                Ok(())
+            }),
+        )
+        .full_box(
+            "tfdt",
+            Arc::new(move |mut _box| {
+                let version = _box.version.unwrap_or(0);
+                let position = payload_position(&_box);
+                let decode_time = if version == 1 {
+                    _box.reader
+                        .read_u64()
+                        .map_err(|_| vsd_mp4::Error::new_read_err("TFDT box decode time (u64)"))?
+                } else {
+                    read_u32(&mut _box)? as u64
+                };
+
+                if let Some(traf) = info_tfdt.lock().unwrap().trafs.last_mut() {
+                    traf.tfdt = Some((position, version, decode_time));
+                }
+
+                Ok(())
+            }),
+        )
+        .full_box(
+            "trun",
+            Arc::new(move |mut _box| {
+                let version = _box.version.unwrap_or(0);
+                let flags = _box.flags.unwrap_or(0);
+                let sample_count = read_u32(&mut _box)?;
+                let mut run = TrunInfo::default();
+
+                if flags & 0x000001 != 0 {
+                    run.data_offset = Some(read_u32(&mut _box)? as i32);
+                }
+
+                if flags & 0x000004 != 0 {
+                    run.first_sample_flags = Some(read_u32(&mut _box)?);
+                }
+
+                let field = |mask: u32, _box: &mut ParsedBox| {
+                    if flags & mask != 0 {
+                        read_u32(_box).map(Some)
+                    } else {
+                        Ok(None)
+                    }
+                };
+
+                for _ in 0..sample_count {
+                    let duration = field(0x000100, &mut _box)?;
+                    let size = field(0x000200, &mut _box)?;
+                    let sample_flags = field(0x000400, &mut _box)?;
+                    let cts = match field(0x000800, &mut _box)? {
+                        Some(x) if version == 1 => x as i32 as i64,
+                        Some(x) => x as i64,
+                        None => 0,
+                    };
+                    run.samples.push(TrunSample {
+                        cts,
+                        duration,
+                        flags: sample_flags,
+                        size,
+                    });
+                }
+
+                if let Some(traf) = info_trun.lock().unwrap().trafs.last_mut() {
+                    if flags & 0x000100 != 0 {
+                        traf.duration += run
+                            .samples
+                            .iter()
+                            .map(|x| x.duration.unwrap_or(0) as u64)
+                            .sum::<u64>();
+                    } else {
+                        traf.implicit_samples += sample_count as u64;
+                    }
+
+                    traf.runs.push(run);
+                }
+
+                Ok(())
+            }),
+        )
+        .parse(data, None, None)?;
+
+    let info = std::mem::take(&mut *info.lock().unwrap());
+    Ok(info)
+}
+
+/// Build a webvtt track from subtitles, whose samples are split into fragments.
+fn subtitle_track(
+    id: u32,
+    subtitles: &Subtitles,
+    language: Option<&str>,
+    default: bool,
+) -> (Track, Vec<Fragment>) {
+    let cues = subtitles.cues();
+    let milliseconds = |x: f32| (x.max(0.0) * 1000.0).round() as u64;
+
+    let mut boundaries = cues
+        .iter()
+        .flat_map(|x| [milliseconds(x.start_time), milliseconds(x.end_time)])
+        .chain([0])
+        .collect::<Vec<_>>();
+    boundaries.sort();
+    boundaries.dedup();
+
+    // Samples cover the whole timeline, each holds cues which are active throughout it.
+    let mut samples = vec![];
+
+    for window in boundaries.windows(2) {
+        let (start, end) = (window[0], window[1]);
+        let mut sample = vec![];
+
+        for cue in cues {
+            if milliseconds(cue.start_time) <= start && milliseconds(cue.end_time) >= end {
+                let mut vttc = vec![];
+
+                if !cue.settings.is_empty() {
+                    vttc.extend(mp4_box(b"sttg", cue.settings.as_bytes()));
+                }
+
+                vttc.extend(mp4_box(b"payl", cue.payload.as_bytes()));
+                sample.extend(mp4_box(b"vttc", &vttc));
+            }
+        }
+
+        if sample.is_empty() {
+            sample = mp4_box(b"vtte", &[]);
+        }
+
+        samples.push((start, end - start, sample));
+    }
+
+    let mut fragments = vec![];
+    let mut samples = samples.into_iter().peekable();
+
+    while let Some(first) = samples.next() {
+        let start = first.0;
+        let mut fragment_samples = vec![first];
+
+        while let Some(sample) = samples.next_if(|x| x.0 < start + SUBTITLE_FRAGMENT_DURATION) {
+            fragment_samples.push(sample);
+        }
+
+        fragments.push(subtitle_fragment(id, start, &fragment_samples));
+    }
+
+    let wvtt = mp4_box(
+        b"wvtt",
+        &[&[0, 0, 0, 0, 0, 0, 0, 1][..], &mp4_box(b"vttC", b"WEBVTT")].concat(),
+    );
+    let trak = trak(
+        id,
+        default,
+        b"text",
+        TIMESCALE,
+        language.and_then(iso_639_2).as_deref(),
+        &wvtt,
+        (0, 0),
+    );
+
+    let end_time = cues
+        .iter()
+        .map(|x| milliseconds(x.end_time))
+        .max()
+        .unwrap_or(0);
+
+    (
+        Track {
+            default_duration: 0,
+            end_time,
+            next_decode_time: end_time,
+            shift: 0,
+            timescale: TIMESCALE,
+            trak,
+            trex: trex(id),
+        },
+        fragments,
+    )
+}
+
+fn subtitle_fragment(id: u32, start: u64, samples: &[(u64, u64, Vec<u8>)]) -> Fragment {
+    let mut trun = (samples.len() as u32).to_be_bytes().to_vec();
+    trun.extend(0_u32.to_be_bytes()); // data offset, set below
+
+    for (_, duration, sample) in samples {
+        trun.extend((*duration as u32).to_be_bytes());
+        trun.extend((sample.len() as u32).to_be_bytes());
+    }
+
+    // tfhd flags: default-base-is-moof, trun flags: data-offset, sample-duration and sample-size
+    let traf = [
+        full_box(b"tfhd", 0, 0x020000, &id.to_be_bytes()),
+        full_box(b"tfdt", 1, 0, &start.to_be_bytes()),
+        full_box(b"trun", 0, 0x000301, &trun),
+    ]
+    .concat();
+    let mut moof = mp4_box(
+        b"moof",
+        &[full_box(b"mfhd", 0, 0, &[0; 4]), mp4_box(b"traf", &traf)].concat(),
+    );
+
+    // moof (8) + mfhd (16) + traf (8) + tfhd (16) + tfdt (20) + trun header (12) + sample count
+    let data_offset_position = 8 + 16 + 8 + 16 + 20 + 12 + 4;
+    let data_offset = moof.len() as u32 + 8;
+    put_u32(&mut moof, data_offset_position, data_offset);
+
+    let mdat = mp4_box(
+        b"mdat",
+        &samples
+            .iter()
+            .flat_map(|x| x.2.iter().copied())
+            .collect::<Vec<_>>(),
+    );
+    moof.extend(mdat);
+
+    Fragment {
+        base_data_offsets: vec![],
+        data: moof,
+        media: None,
+        offset: 0,
+        sequence: 8 + 12,
+        tfdts: vec![],
+        time: start as f64 / TIMESCALE as f64,
+    }
+}
+
+pub(super) fn ftyp() -> Vec<u8> {
+    mp4_box(
+        b"ftyp",
+        &[
+            &b"isom"[..],
+            &0x200_u32.to_be_bytes(),
+            b"isom",
+            b"iso5",
+            b"iso6",
+            b"mp41",
+        ]
+        .concat(),
+    )
+}
+
+/// Movie box of fragmented tracks, `duration` of movie is in movie timescale.
+pub(super) fn moov(traks: &[Vec<u8>], trexs: &[Vec<u8>], duration: u64) -> Vec<u8> {
+    let mut mvhd = vec![0; 16]; // creation and modification time
+    mvhd.extend(TIMESCALE.to_be_bytes());
+    mvhd.extend(0_u64.to_be_bytes()); // duration is in mehd box
+    mvhd.extend(0x00010000_u32.to_be_bytes()); // rate
+    mvhd.extend(0x0100_u16.to_be_bytes()); // volume
+    mvhd.extend([0; 10]);
+    mvhd.extend(matrix());
+    mvhd.extend([0; 24]);
+    mvhd.extend((traks.len() as u32 + 1).to_be_bytes());
+
+    let mut moov = full_box(b"mvhd", 1, 0, &mvhd);
+    moov.extend(traks.concat());
+    moov.extend(mp4_box(
+        b"mvex",
+        &[
+            full_box(b"mehd", 1, 0, &duration.to_be_bytes()),
+            trexs.concat(),
+        ]
+        .concat(),
+    ));
+    mp4_box(b"moov", &moov)
+}
+
+/// Track box of a fragmented track with a single sample entry.
+/// Size of visual tracks is `(width, height)`.
+pub(super) fn trak(
+    id: u32,
+    enabled: bool,
+    handler: &[u8; 4],
+    timescale: u32,
+    language: Option<&str>,
+    sample_entry: &[u8],
+    size: (u16, u16),
+) -> Vec<u8> {
+    let (media_header, name) = match handler {
+        b"soun" => (full_box(b"smhd", 0, 0, &[0; 4]), &b"SoundHandler\0"[..]),
+        b"vide" => (full_box(b"vmhd", 0, 1, &[0; 8]), &b"VideoHandler\0"[..]),
+        _ => (full_box(b"nmhd", 0, 0, &[]), &b"SubtitleHandler\0"[..]),
+    };
+
+    mp4_box(
+        b"trak",
+        &[
+            tkhd(id, enabled, handler == b"soun", size),
+            mp4_box(
+                b"mdia",
+                &[
+                    mdhd(timescale, language),
+                    full_box(
+                        b"hdlr",
+                        0,
+                        0,
+                        &[&[0, 0, 0, 0][..], handler, &[0; 12], name].concat(),
+                    ),
+                    mp4_box(
+                        b"minf",
+                        &[
+                            media_header,
+                            mp4_box(
+                                b"dinf",
+                                &full_box(
+                                    b"dref",
+                                    0,
+                                    0,
+                                    &[&1_u32.to_be_bytes()[..], &full_box(b"url ", 0, 1, &[])]
+                                        .concat(),
+                                ),
+                            ),
+                            mp4_box(
+                                b"stbl",
+                                &[
+                                    full_box(
+                                        b"stsd",
+                                        0,
+                                        0,
+                                        &[&1_u32.to_be_bytes()[..], sample_entry].concat(),
+                                    ),
+                                    full_box(b"stts", 0, 0, &[0; 4]),
+                                    full_box(b"stsc", 0, 0, &[0; 4]),
+                                    full_box(b"stsz", 0, 0, &[0; 8]),
+                                    full_box(b"stco", 0, 0, &[0; 4]),
+                                ]
+                                .concat(),
+                            ),
+                        ]
+                        .concat(),
+                    ),
+                ]
+                .concat(),
+            ),
+        ]
+        .concat(),
+    )
+}
+
+/// Track extends box without any sample defaults other than sample description index.
+pub(super) fn trex(id: u32) -> Vec<u8> {
+    full_box(
+        b"trex",
+        0,
+        0,
+        &[id, 1, 0, 0, 0]
+            .iter()
+            .flat_map(|x| x.to_be_bytes())
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn tkhd(id: u32, enabled: bool, audio: bool, size: (u16, u16)) -> Vec<u8> {
+    let mut tkhd = vec![0; 8]; // creation and modification time
+    tkhd.extend(id.to_be_bytes());
+    tkhd.extend([0; 4]);
+    tkhd.extend(0_u32.to_be_bytes()); // duration
+    tkhd.extend([0; 8]);
+    tkhd.extend([0; 4]); // layer and alternate group
+    tkhd.extend(if audio { 0x0100_u16 } else { 0 }.to_be_bytes()); // volume
+    tkhd.extend([0; 2]);
+    tkhd.extend(matrix());
+    tkhd.extend(((size.0 as u32) << 16).to_be_bytes());
+    tkhd.extend(((size.1 as u32) << 16).to_be_bytes());
+
+    // flags: track_enabled (only for default track) and track_in_movie
+    full_box(b"tkhd", 0, if enabled { 0x000003 } else { 0x000002 }, &tkhd)
+}
+
+fn mdhd(timescale: u32, language: Option<&str>) -> Vec<u8> {
+    let mut mdhd = vec![0; 8]; // creation and modification time
+    mdhd.extend(timescale.to_be_bytes());
+    mdhd.extend(0_u32.to_be_bytes()); // duration
+    mdhd.extend(pack_language(language.unwrap_or("und")));
+    mdhd.extend([0; 2]);
+    full_box(b"mdhd", 0, 0, &mdhd)
+}
+
+fn matrix() -> Vec<u8> {
+    [0x00010000_u32, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000]
+        .iter()
+        .flat_map(|x| x.to_be_bytes())
+        .collect()
+}
+
+/// Pack ISO 639-2/T language code as three 5 bit characters.
+fn pack_language(language: &str) -> [u8; 2] {
+    let code = language.bytes().take(3).fold(0_u16, |code, x| {
+        (code << 5) | ((x.wrapping_sub(0x60) & 0x1F) as u16)
+    });
+    code.to_be_bytes()
+}
+
+/// Rescale segment durations of edit list entries from movie timescale of input to output.
+fn rescale_elst(trak: &mut [u8], position: usize, version: u32, timescale: u32) {
+    let entry_count = get_u32(trak, position) as usize;
+    let entry_size = if version == 1 { 20 } else { 12 };
+
+    for i in 0..entry_count {
+        let entry = position + 4 + (i * entry_size);
+
+        if entry + entry_size > trak.len() {
+            break;
+        }
+
+        if version == 1 {
+            let duration = get_u64(trak, entry) as u128 * TIMESCALE as u128;
+            put_u64(trak, entry, (duration / timescale.max(1) as u128) as u64);
+        } else {
+            let duration = get_u32(trak, entry) as u64 * TIMESCALE as u64;
+            put_u32(trak, entry, (duration / timescale.max(1) as u64) as u32);
+        }
+    }
+}
+
+pub(super) fn mp4_box(name: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+    data.extend(name);
+    data.extend(payload);
+    data
+}
+
+pub(super) fn full_box(name: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let mut data = ((version as u32) << 24 | flags).to_be_bytes().to_vec();
+    data.extend(payload);
+    mp4_box(name, &data)
+}
+
+pub(super) fn payload_position(_box: &ParsedBox) -> usize {
+    (_box.start + _box.header_size()) as usize
+}
+
+pub(super) fn skip(_box: &mut ParsedBox, bytes: u64) -> vsd_mp4::HandlerResult {
+    _box.reader.skip(bytes).map_err(|_| {
+        vsd_mp4::Error::new_read_err(format!("{} box data ({} bytes)", _box.name, bytes))
+    })
+}
+
+pub(super) fn read_u32(_box: &mut ParsedBox) -> Result<u32, vsd_mp4::Error> {
+    _box.reader
+        .read_u32()
+        .map_err(|_| vsd_mp4::Error::new_read_err(format!("{} box data (u32)", _box.name)))
+}
+
+pub(super) fn get_u32(data: &[u8], position: usize) -> u32 {
+    u32::from_be_bytes(data[position..(position + 4)].try_into().unwrap())
+}
+
+pub(super) fn get_u64(data: &[u8], position: usize) -> u64 {
+    u64::from_be_bytes(data[position..(position + 8)].try_into().unwrap())
+}
+
+pub(super) fn put_u32(data: &mut [u8], position: usize, value: u32) {
+    data[position..(position + 4)].copy_from_slice(&value.to_be_bytes());
+}
+
+pub(super) fn put_u64(data: &mut [u8], position: usize, value: u64) {
+    data[position..(position + 8)].copy_from_slice(&value.to_be_bytes());
+}
@@ -0,0 +1,275 @@
+/*
+    REFERENCES
+    ----------
+
+    1. https://datatracker.ietf.org/doc/html/rfc8216#section-4.3.2.3 (EXT-X-DISCONTINUITY)
+    2. https://en.wikipedia.org/wiki/Packetized_elementary_stream
+    3. https://www.iso.org/standard/83102.html (ISO/IEC 14496-12)
+
+*/
+
+use super::{
+    mp4::{self, get_u32, put_u32, put_u64},
+    ts::{DISCONTINUITY_THRESHOLD, PACKET_SIZE, PES_TIMESCALE, SYNC_BYTE},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// PES timestamps (and PCR base) are 33 bit wide.
+const TIMESTAMP_MASK: u64 = (1 << 33) - 1;
+
+#[derive(Default, Deserialize, Serialize)]
+struct Track {
+    /// End time of last fragment (fmp4), after shifting.
+    end: u64,
+    /// Last timestamp (after shifting) and its difference from previous timestamp (mpeg-ts).
+    last: Option<(u64, u64)>,
+    /// Shift applied to decode times of fragments (fmp4).
+    offset: u64,
+}
+
+/// Rewrite timestamps of segments after discontinuities, so that they continue from the end
+/// of previous segments when segments are concatenated. Segments should be normalized in order.
+/// Normalizer can be serialized, so that an interrupted download can be resumed from its state.
+#[derive(Default, Deserialize, Serialize)]
+pub(crate) struct Normalizer {
+    /// Positions of segments which start a new discontinuity range.
+    #[serde(skip)]
+    discontinuities: HashSet<usize>,
+    /// Tracks of mpeg-ts segments by pid.
+    pids: HashMap<u16, Track>,
+    /// Default sample durations of fmp4 tracks (from `trex` boxes) by track id.
+    sample_durations: HashMap<u32, u32>,
+    /// Tracks of fmp4 segments by track id.
+    tracks: HashMap<u32, Track>,
+    /// Shift applied to timestamps of mpeg-ts segments (modulo 2^33).
+    ts_offset: u64,
+}
+
+impl Normalizer {
+    /// Mark segment at this position as start of a new discontinuity range.
+    pub(crate) fn insert(&mut self, pos: usize) {
+        self.discontinuities.insert(pos);
+    }
+
+    /// Normalize timestamps of segment at this position in place. Segments which can't be
+    /// parsed are left unchanged.
+    pub(crate) fn normalize(&mut self, pos: usize, data: &mut [u8]) {
+        let discontinuity = self.discontinuities.remove(&pos);
+
+        if data.len() >= PACKET_SIZE && data[0] == SYNC_BYTE {
+            self.normalize_ts(data, discontinuity);
+        } else {
+            self.normalize_mp4(data, discontinuity);
+        }
+    }
+
+    fn normalize_ts(&mut self, data: &mut [u8], discontinuity: bool) {
+        if discontinuity {
+            let end = self
+                .pids
+                .values()
+                .filter_map(|x| x.last.map(|(last, duration)| last + duration))
+                .max();
+
+            // Earliest timestamp of segment is placed right after the end of previous segments.
+            let start = data
+                .chunks_exact(PACKET_SIZE)
+                .take_while(|x| x[0] == SYNC_BYTE)
+                .filter_map(|x| {
+                    let (_, position, pts, dts) = pes_timestamps(x)?;
+                    let (start, end) = dts.or(pts)?;
+                    Some(timestamp(&x[(position + start)..(position + end)]))
+                })
+                .min();
+
+            if let (Some(end), Some(start)) = (end, start) {
+                self.ts_offset = end.wrapping_sub(start) & TIMESTAMP_MASK;
+            }
+        }
+
+        let offset = self.ts_offset;
+
+        for packet in data.chunks_exact_mut(PACKET_SIZE) {
+            if packet[0] != SYNC_BYTE {
+                break;
+            }
+
+            if offset != 0 {
+                if let Some(position) = pcr_position(packet) {
+                    let pcr = &mut packet[position..(position + 6)];
+                    let base = (pcr[0] as u64) << 25
+                        | (pcr[1] as u64) << 17
+                        | (pcr[2] as u64) << 9
+                        | (pcr[3] as u64) << 1
+                        | (pcr[4] >> 7) as u64;
+                    let base = (base + offset) & TIMESTAMP_MASK;
+                    pcr[0] = (base >> 25) as u8;
+                    pcr[1] = (base >> 17) as u8;
+                    pcr[2] = (base >> 9) as u8;
+                    pcr[3] = (base >> 1) as u8;
+                    pcr[4] = ((base & 0x01) as u8) << 7 | (pcr[4] & 0x7F);
+                }
+            }
+
+            if let Some((pid, position, pts, dts)) = pes_timestamps(packet) {
+                let mut time = None;
+
+                for range in [pts, dts].into_iter().flatten() {
+                    let range = (position + range.0)..(position + range.1);
+                    let value = (timestamp(&packet[range.clone()]) + offset) & TIMESTAMP_MASK;
+                    set_timestamp(&mut packet[range], value);
+                    time = Some(value);
+                }
+
+                if let Some(time) = time {
+                    let track = self.pids.entry(pid).or_default();
+                    let duration = match track.last {
+                        Some((last, duration)) => {
+                            let difference = time.wrapping_sub(last) & TIMESTAMP_MASK;
+
+                            if difference <= DISCONTINUITY_THRESHOLD * PES_TIMESCALE {
+                                difference
+                            } else {
+                                duration
+                            }
+                        }
+                        None => 0,
+                    };
+                    track.last = Some((time, duration));
+                }
+            }
+        }
+    }
+
+    fn normalize_mp4(&mut self, data: &mut [u8], discontinuity: bool) {
+        let mut position = 0;
+        let mut shifted = HashSet::new();
+
+        while position + 8 <= data.len() {
+            let mut size = get_u32(data, position) as u64;
+            let mut header_size = 8;
+
+            if size == 1 && position + 16 <= data.len() {
+                size = mp4::get_u64(data, position + 8);
+                header_size = 16;
+            } else if size == 0 {
+                size = (data.len() - position) as u64;
+            }
+
+            if size < header_size || position as u64 + size > data.len() as u64 {
+                break;
+            }
+
+            let end = position + size as usize;
+
+            match &data[(position + 4)..(position + 8)] {
+                b"moov" => {
+                    if let Ok(info) = mp4::parse_moov(&data[position..end]) {
+                        for (track_id, trex) in info.trexs {
+                            self.sample_durations.insert(track_id, get_u32(&trex, 8));
+                        }
+                    }
+                }
+                b"moof" => {
+                    if let Ok(info) = mp4::parse_moof(&data[position..end]) {
+                        for traf in info.trafs {
+                            let Some((tfdt, version, decode_time)) = traf.tfdt else {
+                                continue;
+                            };
+
+                            let track = self.tracks.entry(traf.track_id).or_default();
+
+                            if discontinuity && shifted.insert(traf.track_id) && track.end != 0 {
+                                track.offset = track.end.wrapping_sub(decode_time);
+                            }
+
+                            let decode_time = decode_time.wrapping_add(track.offset);
+                            let tfdt = position + tfdt;
+
+                            if version == 1 {
+                                put_u64(data, tfdt, decode_time);
+                            } else if let Ok(decode_time) = u32::try_from(decode_time) {
+                                put_u32(data, tfdt, decode_time);
+                            }
+
+                            let sample_duration = traf
+                                .default_duration
+                                .or(self.sample_durations.get(&traf.track_id).copied())
+                                .unwrap_or(0);
+                            track.end = decode_time
+                                + traf.duration
+                                + traf.implicit_samples * sample_duration as u64;
+                        }
+                    }
+                }
+                _ => (),
+            }
+
+            position = end;
+        }
+    }
+}
+
+/// Position of PCR field inside mpeg-ts packet, if present.
+fn pcr_position(packet: &[u8]) -> Option<usize> {
+    let adaptation_field = packet[3] & 0x20 != 0;
+
+    (adaptation_field && packet[4] >= 7 && packet[5] & 0x10 != 0).then_some(6)
+}
+
+/// Pid, position of PES header and ranges of PTS and DTS fields (relative to PES header)
+/// inside mpeg-ts packet which starts a PES packet.
+#[allow(clippy::type_complexity)]
+fn pes_timestamps(
+    packet: &[u8],
+) -> Option<(u16, usize, Option<(usize, usize)>, Option<(usize, usize)>)> {
+    let pid = ((packet[1] & 0x1F) as u16) << 8 | packet[2] as u16;
+    let payload_unit_start = packet[1] & 0x40 != 0;
+    let adaptation_field = packet[3] & 0x20 != 0;
+    let payload = packet[3] & 0x10 != 0;
+
+    if !payload_unit_start || !payload {
+        return None;
+    }
+
+    let position = if adaptation_field {
+        5 + packet[4] as usize
+    } else {
+        4
+    };
+
+    let pes = packet.get(position..)?;
+
+    // Stream ids without optional PES header, e.g. padding and private stream 2.
+    if pes.len() < 9
+        || pes[0..3] != [0, 0, 1]
+        || matches!(
+            pes[3],
+            0xBC | 0xBE | 0xBF | 0xF0 | 0xF1 | 0xF2 | 0xF8 | 0xFF
+        )
+    {
+        return None;
+    }
+
+    let flags = pes[7] >> 6;
+    let pts = (flags & 0x02 != 0 && pes.len() >= 14).then_some((9, 14));
+    let dts = (flags == 0x03 && pes.len() >= 19).then_some((14, 19));
+    Some((pid, position, pts, dts))
+}
+
+fn timestamp(data: &[u8]) -> u64 {
+    (((data[0] >> 1) & 0x07) as u64) << 30
+        | (data[1] as u64) << 22
+        | ((data[2] >> 1) as u64) << 15
+        | (data[3] as u64) << 7
+        | (data[4] >> 1) as u64
+}
+
+fn set_timestamp(data: &mut [u8], value: u64) {
+    data[0] = (data[0] & 0xF1) | (((value >> 30) & 0x07) as u8) << 1;
+    data[1] = (value >> 22) as u8;
+    data[2] = ((value >> 15) as u8) << 1 | 0x01;
+    data[3] = (value >> 7) as u8;
+    data[4] = (value as u8) << 1 | 0x01;
+}
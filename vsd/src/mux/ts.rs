@@ -0,0 +1,1180 @@
+/*
+    REFERENCES
+    ----------
+
+    1. https://en.wikipedia.org/wiki/MPEG_transport_stream
+    2. https://en.wikipedia.org/wiki/Packetized_elementary_stream
+    3. https://wiki.multimedia.cx/index.php/ADTS
+    4. https://www.itu.int/rec/T-REC-H.264 (7.3.2.1.1 Sequence parameter set data syntax)
+    5. https://www.itu.int/rec/T-REC-H.265 (7.3.2.2 Sequence parameter set RBSP syntax)
+    6. https://www.iso.org/standard/83118.html (ISO/IEC 14496-15)
+    7. https://github.com/FFmpeg/FFmpeg/blob/master/libavformat/hevc.c
+
+*/
+
+use super::mp4::{ftyp, full_box, moov, mp4_box, trak, trex, TIMESCALE};
+use anyhow::{bail, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+};
+
+pub(super) const PACKET_SIZE: usize = 188;
+pub(super) const SYNC_BYTE: u8 = 0x47;
+/// Clock rate of PES timestamps, also used as timescale of video tracks.
+pub(super) const PES_TIMESCALE: u64 = 90000;
+/// Minimum duration of each fragment (in seconds), fragments of video tracks
+/// always start with a keyframe.
+const FRAGMENT_DURATION: u64 = 2;
+/// Timestamps jumping backwards or forwards by more than this (in seconds) are treated as
+/// a discontinuity, e.g. timeline of next period starting from zero.
+pub(super) const DISCONTINUITY_THRESHOLD: u64 = 10;
+/// Number of samples in each aac frame.
+const AAC_FRAME_SAMPLES: u64 = 1024;
+const AAC_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+/// Stream types of audio and video codecs which can't be remuxed, i.e. mpeg-1/2 video,
+/// mpeg-1/2 audio, mpeg-4 visual, latm aac, ac-3 and e-ac-3.
+const UNSUPPORTED_STREAM_TYPES: [u8; 10] =
+    [0x01, 0x02, 0x03, 0x04, 0x10, 0x11, 0x81, 0x87, 0xc1, 0xc2];
+
+/// Sample entry box of a video track along with its resolution (width and height).
+type VideoSampleEntry = (Vec<u8>, (u16, u16));
+
+#[derive(Clone, Copy, PartialEq)]
+enum Codec {
+    Aac,
+    H264,
+    H265,
+}
+
+impl Codec {
+    /// Codec of a program map table stream type. Stream types set by sample-aes decryption
+    /// are also accepted.
+    fn from_stream_type(stream_type: u8) -> Option<Self> {
+        match stream_type {
+            0x0f | 0xcf => Some(Self::Aac),
+            0x1b | 0xdb => Some(Self::H264),
+            0x24 => Some(Self::H265),
+            _ => None,
+        }
+    }
+}
+
+struct Pes {
+    data: Vec<u8>,
+    dts: Option<u64>,
+    pts: Option<u64>,
+}
+
+struct Sample {
+    /// Composition time offset (in track timescale).
+    cts: u32,
+    data: Vec<u8>,
+    duration: u32,
+    key: bool,
+    /// Decode time (in track timescale).
+    time: u64,
+}
+
+struct Track {
+    /// Partial aac frames left over from previous pes packet.
+    audio_buffer: Vec<u8>,
+    codec: Codec,
+    /// Largest end time of fragments (in track timescale).
+    end_time: u64,
+    /// Decode time of first sample (in 90 kHz clock).
+    first_dts: Option<u64>,
+    id: u32,
+    /// Last unwrapped decode time (in 90 kHz clock).
+    last_dts: Option<u64>,
+    /// Decode time of next aac frame (in track timescale).
+    next_time: Option<u64>,
+    pending: Vec<Sample>,
+    pes: Vec<u8>,
+    pid: u16,
+    sample_entry: Option<Vec<u8>>,
+    /// Shift applied to decode times (in 90 kHz clock) for keeping them continuous.
+    shift: i64,
+    size: (u16, u16),
+    timescale: u32,
+}
+
+impl Track {
+    fn new(pid: u16, codec: Codec, id: u32) -> Self {
+        Self {
+            audio_buffer: vec![],
+            codec,
+            end_time: 0,
+            first_dts: None,
+            id,
+            last_dts: None,
+            next_time: None,
+            pending: vec![],
+            pes: vec![],
+            pid,
+            sample_entry: None,
+            shift: 0,
+            size: (0, 0),
+            timescale: PES_TIMESCALE as u32,
+        }
+    }
+
+    /// Unwrap a 33 bit timestamp into a monotonic 64 bit timestamp closest to previous one.
+    fn unwrap(&mut self, timestamp: u64) -> u64 {
+        let timestamp = match self.last_dts {
+            Some(last) => {
+                let wraps = (last >> 33) as i64;
+                [wraps - 1, wraps, wraps + 1]
+                    .iter()
+                    .filter(|x| **x >= 0)
+                    .map(|x| ((*x as u64) << 33) | timestamp)
+                    .min_by_key(|x| x.abs_diff(last))
+                    .unwrap()
+            }
+            None => timestamp,
+        };
+
+        self.last_dts = Some(timestamp);
+        timestamp
+    }
+
+    fn span(&self) -> u64 {
+        match (self.pending.first(), self.pending.last()) {
+            (Some(first), Some(last)) => last.time + last.duration as u64 - first.time,
+            _ => 0,
+        }
+    }
+}
+
+/// Output of second pass over input.
+struct Output {
+    /// Smallest decode time of all tracks (in 90 kHz clock).
+    base: u64,
+    /// Track ids of elementary stream pids, other streams are ignored.
+    ids: HashMap<u16, u32>,
+    sequence: u32,
+    writer: BufWriter<File>,
+}
+
+/// Remuxer state shared by both passes over input. In first pass (without output) codec
+/// configuration and first timestamp of each track is collected and no samples are kept.
+struct Remuxer {
+    output: Option<Output>,
+    pmt_pids: HashSet<u16>,
+    program_map: bool,
+    tracks: Vec<Track>,
+    unsupported: bool,
+}
+
+impl Remuxer {
+    fn new(output: Option<Output>) -> Self {
+        Self {
+            output,
+            pmt_pids: HashSet::new(),
+            program_map: false,
+            tracks: vec![],
+            unsupported: false,
+        }
+    }
+
+    fn base(&self) -> Option<u64> {
+        self.output.as_ref().map(|x| x.base)
+    }
+
+    /// First pass is complete when every track has its codec configuration and first timestamp.
+    fn is_configured(&self) -> bool {
+        self.program_map
+            && self
+                .tracks
+                .iter()
+                .all(|x| x.sample_entry.is_some() && x.first_dts.is_some())
+    }
+
+    fn packet(&mut self, packet: &[u8]) -> Result<()> {
+        if packet[0] != SYNC_BYTE {
+            bail!("invalid mpeg-ts packet (sync byte not found).");
+        }
+
+        let payload_unit_start = packet[1] & 0x40 != 0;
+        let pid = (((packet[1] & 0x1F) as u16) << 8) | packet[2] as u16;
+        let adaptation_field_control = (packet[3] >> 4) & 0x03;
+
+        let mut payload_start = 4;
+
+        if adaptation_field_control & 0x02 != 0 {
+            payload_start += 1 + packet[4] as usize;
+        }
+
+        if adaptation_field_control & 0x01 == 0 || payload_start >= PACKET_SIZE {
+            return Ok(());
+        }
+
+        let payload = &packet[payload_start..];
+
+        if pid == 0 {
+            if payload_unit_start {
+                self.pmt_pids.extend(parse_pat(payload));
+            }
+        } else if self.pmt_pids.contains(&pid) {
+            if payload_unit_start && !self.program_map {
+                self.parse_pmt(payload);
+            }
+        } else if let Some(index) = self.tracks.iter().position(|x| x.pid == pid) {
+            if payload_unit_start {
+                let pes = std::mem::take(&mut self.tracks[index].pes);
+                self.pes(index, &pes)?;
+            }
+
+            self.tracks[index].pes.extend_from_slice(payload);
+        }
+
+        Ok(())
+    }
+
+    /// Program map table section is expected to fit in a single packet.
+    /// Only the first program map table is used.
+    fn parse_pmt(&mut self, payload: &[u8]) {
+        let pointer = payload[0] as usize;
+
+        if 1 + pointer + 12 > payload.len() {
+            return;
+        }
+
+        let section = &payload[(1 + pointer)..];
+        let section_length = (((section[1] & 0x0F) as usize) << 8) | section[2] as usize;
+        let program_info_length = (((section[10] & 0x0F) as usize) << 8) | section[11] as usize;
+        let end = (3 + section_length).saturating_sub(4).min(section.len()); // without crc
+        let mut position = 12 + program_info_length;
+
+        while position + 5 <= end {
+            let stream_type = section[position];
+            let pid = (((section[position + 1] & 0x1F) as u16) << 8) | section[position + 2] as u16;
+            let es_info_length =
+                (((section[position + 3] & 0x0F) as usize) << 8) | section[position + 4] as usize;
+
+            if let Some(codec) = Codec::from_stream_type(stream_type) {
+                match &self.output {
+                    Some(output) => {
+                        if let Some(id) = output.ids.get(&pid) {
+                            self.tracks.push(Track::new(pid, codec, *id));
+                        }
+                    }
+                    None => self.tracks.push(Track::new(pid, codec, 0)),
+                }
+            } else if UNSUPPORTED_STREAM_TYPES.contains(&stream_type) {
+                self.unsupported = true;
+            }
+
+            position += 5 + es_info_length;
+        }
+
+        self.program_map = true;
+    }
+
+    fn pes(&mut self, index: usize, data: &[u8]) -> Result<()> {
+        if let Some(pes) = parse_pes(data) {
+            match self.tracks[index].codec {
+                Codec::Aac => self.audio(index, pes)?,
+                Codec::H264 | Codec::H265 => self.video(index, pes)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Video pes packets are expected to carry a single access unit.
+    fn video(&mut self, index: usize, pes: Pes) -> Result<()> {
+        let base = self.base();
+        let track = &mut self.tracks[index];
+        let hevc = track.codec == Codec::H265;
+        let nal_units = nal_units(&pes.data);
+
+        if track.sample_entry.is_none() {
+            if let Some((sample_entry, size)) = video_sample_entry(track.codec, &nal_units)? {
+                track.sample_entry = Some(sample_entry);
+                track.size = size;
+            } else {
+                return Ok(());
+            }
+        }
+
+        let mut data = vec![];
+        let mut key = false;
+
+        for nal_unit in &nal_units {
+            let nal_type = if hevc {
+                (nal_unit[0] >> 1) & 0x3F
+            } else {
+                nal_unit[0] & 0x1F
+            };
+
+            // access unit delimiter and parameter sets are kept in sample entry
+            if (hevc && (32..=35).contains(&nal_type)) || (!hevc && [7, 8, 9].contains(&nal_type)) {
+                continue;
+            }
+
+            key |= if hevc {
+                (16..=21).contains(&nal_type)
+            } else {
+                nal_type == 5
+            };
+            data.extend((nal_unit.len() as u32).to_be_bytes());
+            data.extend_from_slice(nal_unit);
+        }
+
+        let Some(pts) = pes.pts else {
+            // continuation of previous access unit
+            if let Some(last) = track.pending.last_mut() {
+                last.data.extend(data);
+            }
+
+            return Ok(());
+        };
+
+        let dts = pes.dts.unwrap_or(pts);
+        let cts = pts.wrapping_sub(dts) & 0x1_FFFF_FFFF;
+        let cts = if cts > u32::MAX as u64 { 0 } else { cts as u32 };
+        let dts = track.unwrap(dts);
+        track.first_dts.get_or_insert(dts);
+
+        let Some(base) = base else {
+            return Ok(());
+        };
+
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let mut time = (dts as i64 + track.shift - base as i64).max(0) as u64;
+
+        if let Some(last) = track.pending.last() {
+            if time <= last.time || time > last.time + (DISCONTINUITY_THRESHOLD * PES_TIMESCALE) {
+                let duration = match track.pending.len() {
+                    1 => 3000,
+                    x => track.pending[x - 2].duration as u64,
+                };
+                track.shift += (last.time + duration) as i64 - time as i64;
+                time = last.time + duration;
+            }
+        }
+
+        if let Some(last) = track.pending.last_mut() {
+            last.duration = (time - last.time) as u32;
+        }
+
+        if key && track.span() >= FRAGMENT_DURATION * PES_TIMESCALE {
+            self.flush(index)?;
+        }
+
+        self.tracks[index].pending.push(Sample {
+            cts,
+            data,
+            duration: 0,
+            key,
+            time,
+        });
+        Ok(())
+    }
+
+    fn audio(&mut self, index: usize, pes: Pes) -> Result<()> {
+        let base = self.base();
+        let track = &mut self.tracks[index];
+        let mut data = std::mem::take(&mut track.audio_buffer);
+        data.extend(pes.data);
+
+        let mut frames = vec![];
+        let mut position = 0;
+
+        while position + 7 <= data.len() {
+            if data[position] != 0xFF || data[position + 1] & 0xF6 != 0xF0 {
+                position += 1;
+                continue;
+            }
+
+            let header_size = if data[position + 1] & 0x01 != 0 { 7 } else { 9 };
+            let frame_length = (((data[position + 3] & 0x03) as usize) << 11)
+                | ((data[position + 4] as usize) << 3)
+                | ((data[position + 5] as usize) >> 5);
+
+            if frame_length <= header_size {
+                position += 1;
+                continue;
+            }
+
+            if position + frame_length > data.len() {
+                break;
+            }
+
+            if track.sample_entry.is_none() {
+                let (sample_entry, sample_rate) = audio_sample_entry(&data[position..])?;
+                track.sample_entry = Some(sample_entry);
+                track.timescale = sample_rate;
+            }
+
+            frames.push(data[(position + header_size)..(position + frame_length)].to_vec());
+            position += frame_length;
+        }
+
+        track.audio_buffer = data[position..].to_vec();
+
+        if track.sample_entry.is_none() {
+            return Ok(());
+        }
+
+        if let Some(pts) = pes.pts {
+            let pts = track.unwrap(pts);
+            track.first_dts.get_or_insert(pts);
+        }
+
+        let Some(base) = base else {
+            return Ok(());
+        };
+
+        let timescale = track.timescale as u64;
+
+        if let Some(pts) = track.last_dts.filter(|_| pes.pts.is_some()) {
+            let time =
+                (pts as i64 + track.shift - base as i64).max(0) as u64 * timescale / PES_TIMESCALE;
+
+            match track.next_time {
+                None => track.next_time = Some(time),
+                // discontinuity, keep continuing from previous frames
+                Some(next)
+                    if time + (timescale / 2) < next
+                        || time > next + (DISCONTINUITY_THRESHOLD * timescale) =>
+                {
+                    track.shift +=
+                        (next as i64 - time as i64) * PES_TIMESCALE as i64 / timescale as i64;
+                }
+                // gap in audio, start a new fragment after it
+                Some(next) if time > next + (timescale / 2) => {
+                    self.flush(index)?;
+                    self.tracks[index].next_time = Some(time);
+                }
+                _ => (),
+            }
+        }
+
+        for frame in frames {
+            let track = &mut self.tracks[index];
+            let Some(time) = track.next_time else {
+                break;
+            };
+
+            track.next_time = Some(time + AAC_FRAME_SAMPLES);
+            track.pending.push(Sample {
+                cts: 0,
+                data: frame,
+                duration: AAC_FRAME_SAMPLES as u32,
+                key: true,
+                time,
+            });
+
+            if track.span() >= FRAGMENT_DURATION * timescale {
+                self.flush(index)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Process remaining pes packets and write remaining samples.
+    fn finish(&mut self) -> Result<()> {
+        for index in 0..self.tracks.len() {
+            let pes = std::mem::take(&mut self.tracks[index].pes);
+            self.pes(index, &pes)?;
+
+            let pending = &mut self.tracks[index].pending;
+
+            if let [.., previous, last] = &mut pending[..] {
+                last.duration = previous.duration;
+            } else if let [last] = &mut pending[..] {
+                if last.duration == 0 {
+                    last.duration = 3000;
+                }
+            }
+
+            self.flush(index)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write pending samples of a track as a fragment.
+    fn flush(&mut self, index: usize) -> Result<()> {
+        let track = &mut self.tracks[index];
+        let samples = std::mem::take(&mut track.pending);
+
+        let (Some(output), Some(first), Some(last)) =
+            (&mut self.output, samples.first(), samples.last())
+        else {
+            return Ok(());
+        };
+
+        track.end_time = track.end_time.max(last.time + last.duration as u64);
+        output.sequence += 1;
+
+        let video = track.codec != Codec::Aac;
+        let mut trun = (samples.len() as u32).to_be_bytes().to_vec();
+        trun.extend(0_u32.to_be_bytes()); // data offset, set below
+
+        for sample in &samples {
+            trun.extend(sample.duration.to_be_bytes());
+            trun.extend((sample.data.len() as u32).to_be_bytes());
+            trun.extend(
+                if sample.key {
+                    0x02000000_u32
+                } else {
+                    0x01010000
+                }
+                .to_be_bytes(),
+            );
+
+            if video {
+                trun.extend(sample.cts.to_be_bytes());
+            }
+        }
+
+        // tfhd flags: default-base-is-moof, trun flags: data-offset, sample-duration,
+        // sample-size, sample-flags and sample-composition-time-offset (only for video)
+        let traf = [
+            full_box(b"tfhd", 0, 0x020000, &track.id.to_be_bytes()),
+            full_box(b"tfdt", 1, 0, &first.time.to_be_bytes()),
+            full_box(b"trun", 0, if video { 0x000f01 } else { 0x000701 }, &trun),
+        ]
+        .concat();
+        let mut moof = mp4_box(
+            b"moof",
+            &[
+                full_box(b"mfhd", 0, 0, &output.sequence.to_be_bytes()),
+                mp4_box(b"traf", &traf),
+            ]
+            .concat(),
+        );
+
+        // moof (8) + mfhd (16) + traf (8) + tfhd (16) + tfdt (20) + trun header (12) + sample count
+        let data_offset_position = 8 + 16 + 8 + 16 + 20 + 12 + 4;
+        let data_offset = moof.len() as u32 + 8;
+        moof[data_offset_position..(data_offset_position + 4)]
+            .copy_from_slice(&data_offset.to_be_bytes());
+
+        let size = samples.iter().map(|x| x.data.len()).sum::<usize>() + 8;
+        output.writer.write_all(&moof)?;
+        output.writer.write_all(&(size as u32).to_be_bytes())?;
+        output.writer.write_all(b"mdat")?;
+
+        for sample in &samples {
+            output.writer.write_all(&sample.data)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Sequential reader of packets from multiple files. Trailing partial packets are ignored.
+struct Packets<'a> {
+    file: Option<BufReader<File>>,
+    inputs: std::slice::Iter<'a, String>,
+}
+
+impl<'a> Packets<'a> {
+    fn new(inputs: &'a [String]) -> Self {
+        Self {
+            file: None,
+            inputs: inputs.iter(),
+        }
+    }
+
+    fn next(&mut self, packet: &mut [u8; PACKET_SIZE]) -> Result<bool> {
+        loop {
+            if let Some(file) = &mut self.file {
+                match file.read_exact(packet) {
+                    Ok(_) => return Ok(true),
+                    Err(e) if e.kind() == ErrorKind::UnexpectedEof => self.file = None,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+
+            match self.inputs.next() {
+                Some(input) => self.file = Some(BufReader::new(File::open(input)?)),
+                None => return Ok(false),
+            }
+        }
+    }
+}
+
+/// Check whether file is a mpeg-ts file whose audio and video streams can be remuxed.
+pub(super) fn is_remuxable(path: &str) -> Result<bool> {
+    let inputs = [path.to_owned()];
+    let mut packets = Packets::new(&inputs);
+    let mut packet = [0; PACKET_SIZE];
+    let mut remuxer = Remuxer::new(None);
+
+    while !remuxer.program_map && packets.next(&mut packet)? {
+        if packet[0] != SYNC_BYTE {
+            return Ok(false);
+        }
+
+        remuxer.packet(&packet)?;
+    }
+
+    Ok(remuxer.program_map && !remuxer.unsupported && !remuxer.tracks.is_empty())
+}
+
+/// Remux concatenated mpeg-ts files to a single fragmented mp4 file.
+pub(crate) fn remux(inputs: &[String], output: &str) -> Result<()> {
+    let mut packet = [0; PACKET_SIZE];
+
+    // First pass collects codec configurations and first timestamps.
+    let mut packets = Packets::new(inputs);
+    let mut remuxer = Remuxer::new(None);
+
+    while !remuxer.is_configured() && packets.next(&mut packet)? {
+        remuxer.packet(&packet)?;
+    }
+
+    remuxer.finish()?;
+
+    if remuxer.unsupported {
+        bail!("mpeg-ts streams contain audio or video codecs which can't be remuxed.");
+    }
+
+    let tracks = remuxer
+        .tracks
+        .into_iter()
+        .filter(|x| x.sample_entry.is_some() && x.first_dts.is_some())
+        .collect::<Vec<_>>();
+
+    if tracks.is_empty() {
+        bail!("no h264, h265 or aac streams found in mpeg-ts streams.");
+    }
+
+    let traks = tracks
+        .iter()
+        .enumerate()
+        .map(|(i, x)| {
+            let handler = if x.codec == Codec::Aac {
+                b"soun"
+            } else {
+                b"vide"
+            };
+
+            trak(
+                i as u32 + 1,
+                true,
+                handler,
+                x.timescale,
+                None,
+                x.sample_entry.as_ref().unwrap(),
+                x.size,
+            )
+        })
+        .collect::<Vec<_>>();
+    let trexs = (1..=tracks.len() as u32).map(trex).collect::<Vec<_>>();
+
+    let ftyp = ftyp();
+    let moov = moov(&traks, &trexs, 0);
+    // mehd box is at the start of mvex box which is at the end of moov box
+    let mehd_position = ftyp.len() + moov.len() - trexs.concat().len() - 8;
+
+    let mut writer = BufWriter::new(File::create(output)?);
+    writer.write_all(&ftyp)?;
+    writer.write_all(&moov)?;
+
+    // Second pass writes samples of tracks found in first pass.
+    let mut packets = Packets::new(inputs);
+    let mut remuxer = Remuxer::new(Some(Output {
+        base: tracks.iter().filter_map(|x| x.first_dts).min().unwrap(),
+        ids: tracks
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (x.pid, i as u32 + 1))
+            .collect(),
+        sequence: 0,
+        writer,
+    }));
+
+    while packets.next(&mut packet)? {
+        remuxer.packet(&packet)?;
+    }
+
+    remuxer.finish()?;
+
+    let duration = remuxer
+        .tracks
+        .iter()
+        .map(|x| x.end_time * TIMESCALE as u64 / x.timescale.max(1) as u64)
+        .max()
+        .unwrap_or(0);
+
+    let mut file = remuxer.output.unwrap().writer.into_inner()?;
+    file.seek(SeekFrom::Start(mehd_position as u64))?;
+    file.write_all(&duration.to_be_bytes())?;
+    Ok(())
+}
+
+fn parse_pat(payload: &[u8]) -> Vec<u16> {
+    let pointer = payload[0] as usize;
+    let mut pids = vec![];
+
+    if 1 + pointer + 8 > payload.len() {
+        return pids;
+    }
+
+    let section = &payload[(1 + pointer)..];
+    let section_length = (((section[1] & 0x0F) as usize) << 8) | section[2] as usize;
+    let end = (3 + section_length).saturating_sub(4).min(section.len()); // without crc
+    let mut position = 8;
+
+    while position + 4 <= end {
+        let program_number = ((section[position] as u16) << 8) | section[position + 1] as u16;
+
+        if program_number != 0 {
+            pids.push(
+                (((section[position + 2] & 0x1F) as u16) << 8) | section[position + 3] as u16,
+            );
+        }
+
+        position += 4;
+    }
+
+    pids
+}
+
+fn parse_pes(data: &[u8]) -> Option<Pes> {
+    if data.len() < 9 || data[0..3] != [0, 0, 1] {
+        return None;
+    }
+
+    let flags = data[7];
+    let payload_start = 9 + data[8] as usize;
+
+    if payload_start > data.len() {
+        return None;
+    }
+
+    let timestamp = |x: &[u8]| {
+        (((x[0] >> 1) & 0x07) as u64) << 30
+            | (x[1] as u64) << 22
+            | ((x[2] >> 1) as u64) << 15
+            | (x[3] as u64) << 7
+            | (x[4] >> 1) as u64
+    };
+
+    Some(Pes {
+        data: data[payload_start..].to_vec(),
+        dts: (flags & 0xC0 == 0xC0 && data.len() >= 19).then(|| timestamp(&data[14..19])),
+        pts: (flags & 0x80 != 0 && data.len() >= 14).then(|| timestamp(&data[9..14])),
+    })
+}
+
+/// Split annex b byte stream into nal units (without start codes).
+fn nal_units(data: &[u8]) -> Vec<&[u8]> {
+    let mut starts = vec![];
+    let mut zeros = 0;
+
+    for (i, x) in data.iter().enumerate() {
+        if *x == 1 && zeros >= 2 {
+            starts.push((i + 1, i - zeros.min(3)));
+        }
+
+        zeros = if *x == 0 { zeros + 1 } else { 0 };
+    }
+
+    let mut nal_units = vec![];
+
+    for (i, (start, _)) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).map(|x| x.1).unwrap_or(data.len());
+        let mut nal_unit = &data[*start..end.max(*start)];
+
+        // trailing zero bytes
+        while let [rest @ .., 0] = nal_unit {
+            nal_unit = rest;
+        }
+
+        if !nal_unit.is_empty() {
+            nal_units.push(nal_unit);
+        }
+    }
+
+    nal_units
+}
+
+/// Build avc1 or hvc1 sample entry from parameter sets of an access unit, returns none
+/// if access unit doesn't have all of the parameter sets.
+fn video_sample_entry(codec: Codec, nal_units: &[&[u8]]) -> Result<Option<VideoSampleEntry>> {
+    let parameter_sets = |nal_type: u8| {
+        nal_units
+            .iter()
+            .filter(|x| {
+                if codec == Codec::H265 {
+                    x.len() > 2 && (x[0] >> 1) & 0x3F == nal_type
+                } else {
+                    x.len() > 1 && x[0] & 0x1F == nal_type
+                }
+            })
+            .copied()
+            .collect::<Vec<_>>()
+    };
+
+    let (name, config, size) = if codec == Codec::H265 {
+        let (vps, sps, pps) = (parameter_sets(32), parameter_sets(33), parameter_sets(34));
+
+        if vps.is_empty() || sps.is_empty() || pps.is_empty() {
+            return Ok(None);
+        }
+
+        let (hvcc, size) = hvcc(&vps, &sps, &pps)?;
+        (b"hvc1", mp4_box(b"hvcC", &hvcc), size)
+    } else {
+        let (sps, pps) = (parameter_sets(7), parameter_sets(8));
+
+        if sps.is_empty() || pps.is_empty() {
+            return Ok(None);
+        }
+
+        let (avcc, size) = avcc(&sps, &pps)?;
+        (b"avc1", mp4_box(b"avcC", &avcc), size)
+    };
+
+    let mut entry = vec![0, 0, 0, 0, 0, 0, 0, 1]; // reserved and data reference index
+    entry.extend([0; 16]);
+    entry.extend(size.0.to_be_bytes());
+    entry.extend(size.1.to_be_bytes());
+    entry.extend(0x00480000_u32.to_be_bytes()); // horizontal resolution
+    entry.extend(0x00480000_u32.to_be_bytes()); // vertical resolution
+    entry.extend([0; 4]);
+    entry.extend(1_u16.to_be_bytes()); // frame count
+    entry.extend([0; 32]); // compressor name
+    entry.extend(0x0018_u16.to_be_bytes()); // depth
+    entry.extend(0xFFFF_u16.to_be_bytes()); // pre defined
+    entry.extend(config);
+    Ok(Some((mp4_box(name, &entry), size)))
+}
+
+fn avcc(sps: &[&[u8]], pps: &[&[u8]]) -> Result<(Vec<u8>, (u16, u16))> {
+    let rbsp = remove_emulation_prevention(&sps[0][1..]);
+    let mut reader = BitReader::new(&rbsp);
+
+    let profile_idc = reader.bits(8)?;
+    reader.skip(16)?; // constraint flags and level
+    reader.ue()?; // seq_parameter_set_id
+
+    let mut chroma_format_idc = 1;
+    let mut bit_depth_luma_minus8 = 0;
+    let mut bit_depth_chroma_minus8 = 0;
+    let high_profile =
+        [100, 110, 122, 244, 44, 83, 86, 118, 128, 138, 139, 134, 135].contains(&profile_idc);
+
+    if high_profile {
+        chroma_format_idc = reader.ue()?;
+
+        if chroma_format_idc == 3 {
+            reader.skip(1)?; // separate_colour_plane_flag
+        }
+
+        bit_depth_luma_minus8 = reader.ue()?;
+        bit_depth_chroma_minus8 = reader.ue()?;
+        reader.skip(1)?; // qpprime_y_zero_transform_bypass_flag
+
+        if reader.bit()? == 1 {
+            // seq_scaling_matrix_present_flag
+            for i in 0..(if chroma_format_idc == 3 { 12 } else { 8 }) {
+                if reader.bit()? == 1 {
+                    let size = if i < 6 { 16 } else { 64 };
+                    let mut last_scale = 8;
+                    let mut next_scale = 8;
+
+                    for _ in 0..size {
+                        if next_scale != 0 {
+                            next_scale = (last_scale + reader.se()? + 256) % 256;
+                        }
+
+                        if next_scale != 0 {
+                            last_scale = next_scale;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    reader.ue()?; // log2_max_frame_num_minus4
+    let pic_order_cnt_type = reader.ue()?;
+
+    if pic_order_cnt_type == 0 {
+        reader.ue()?; // log2_max_pic_order_cnt_lsb_minus4
+    } else if pic_order_cnt_type == 1 {
+        reader.skip(1)?; // delta_pic_order_always_zero_flag
+        reader.se()?; // offset_for_non_ref_pic
+        reader.se()?; // offset_for_top_to_bottom_field
+
+        for _ in 0..reader.ue()? {
+            reader.se()?; // offset_for_ref_frame
+        }
+    }
+
+    reader.ue()?; // max_num_ref_frames
+    reader.skip(1)?; // gaps_in_frame_num_value_allowed_flag
+    let pic_width_in_mbs_minus1 = reader.ue()?;
+    let pic_height_in_map_units_minus1 = reader.ue()?;
+    let frame_mbs_only_flag = reader.bit()?;
+
+    if frame_mbs_only_flag == 0 {
+        reader.skip(1)?; // mb_adaptive_frame_field_flag
+    }
+
+    reader.skip(1)?; // direct_8x8_inference_flag
+
+    let mut width = (pic_width_in_mbs_minus1 + 1) * 16;
+    let mut height = (2 - frame_mbs_only_flag) * (pic_height_in_map_units_minus1 + 1) * 16;
+
+    if reader.bit()? == 1 {
+        // frame_cropping_flag
+        let (crop_unit_x, crop_unit_y) = match chroma_format_idc {
+            0 | 3 => (1, 2 - frame_mbs_only_flag),
+            2 => (2, 2 - frame_mbs_only_flag),
+            _ => (2, 2 * (2 - frame_mbs_only_flag)),
+        };
+        let (left, right) = (reader.ue()?, reader.ue()?);
+        let (top, bottom) = (reader.ue()?, reader.ue()?);
+        width = width.saturating_sub((left + right) * crop_unit_x);
+        height = height.saturating_sub((top + bottom) * crop_unit_y);
+    }
+
+    let mut avcc = vec![1, sps[0][1], sps[0][2], sps[0][3], 0xFF];
+    avcc.push(0xE0 | sps.len() as u8);
+
+    for x in sps {
+        avcc.extend((x.len() as u16).to_be_bytes());
+        avcc.extend_from_slice(x);
+    }
+
+    avcc.push(pps.len() as u8);
+
+    for x in pps {
+        avcc.extend((x.len() as u16).to_be_bytes());
+        avcc.extend_from_slice(x);
+    }
+
+    if [100, 110, 122, 144].contains(&profile_idc) {
+        avcc.push(0xFC | chroma_format_idc as u8);
+        avcc.push(0xF8 | bit_depth_luma_minus8 as u8);
+        avcc.push(0xF8 | bit_depth_chroma_minus8 as u8);
+        avcc.push(0); // number of sps extensions
+    }
+
+    Ok((avcc, (width as u16, height as u16)))
+}
+
+fn hvcc(vps: &[&[u8]], sps: &[&[u8]], pps: &[&[u8]]) -> Result<(Vec<u8>, (u16, u16))> {
+    let rbsp = remove_emulation_prevention(&sps[0][2..]);
+
+    if rbsp.len() < 13 {
+        bail!("couldn't parse h265 sequence parameter set.");
+    }
+
+    let mut reader = BitReader::new(&rbsp);
+    reader.skip(4)?; // sps_video_parameter_set_id
+    let max_sub_layers_minus1 = reader.bits(3)?;
+    let temporal_id_nesting_flag = reader.bit()?;
+    reader.skip(96)?; // general profile, tier and level
+
+    let mut sub_layer_flags = vec![];
+
+    for _ in 0..max_sub_layers_minus1 {
+        sub_layer_flags.push((reader.bit()?, reader.bit()?));
+    }
+
+    if max_sub_layers_minus1 > 0 {
+        reader.skip(2 * (8 - max_sub_layers_minus1))?;
+    }
+
+    for (profile_present, level_present) in sub_layer_flags {
+        reader.skip(88 * profile_present + 8 * level_present)?;
+    }
+
+    reader.ue()?; // sps_seq_parameter_set_id
+    let chroma_format_idc = reader.ue()?;
+
+    if chroma_format_idc == 3 {
+        reader.skip(1)?; // separate_colour_plane_flag
+    }
+
+    let mut width = reader.ue()?;
+    let mut height = reader.ue()?;
+
+    if reader.bit()? == 1 {
+        // conformance_window_flag
+        let (sub_width, sub_height) = match chroma_format_idc {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        };
+        let (left, right) = (reader.ue()?, reader.ue()?);
+        let (top, bottom) = (reader.ue()?, reader.ue()?);
+        width = width.saturating_sub((left + right) * sub_width);
+        height = height.saturating_sub((top + bottom) * sub_height);
+    }
+
+    let bit_depth_luma_minus8 = reader.ue()?;
+    let bit_depth_chroma_minus8 = reader.ue()?;
+
+    let mut hvcc = vec![1];
+    hvcc.extend_from_slice(&rbsp[1..13]); // general profile, tier and level
+    hvcc.extend(0xF000_u16.to_be_bytes()); // min_spatial_segmentation_idc
+    hvcc.push(0xFC); // parallelism type
+    hvcc.push(0xFC | chroma_format_idc as u8);
+    hvcc.push(0xF8 | bit_depth_luma_minus8 as u8);
+    hvcc.push(0xF8 | bit_depth_chroma_minus8 as u8);
+    hvcc.extend(0_u16.to_be_bytes()); // average frame rate
+                                      // constant frame rate, number of temporal layers, temporal id nesting and length size
+    hvcc.push((((max_sub_layers_minus1 + 1) << 3) | (temporal_id_nesting_flag << 2) | 3) as u8);
+    hvcc.push(3); // number of arrays
+
+    for (nal_type, nal_units) in [(32, vps), (33, sps), (34, pps)] {
+        hvcc.push(0x80 | nal_type); // array completeness
+        hvcc.extend((nal_units.len() as u16).to_be_bytes());
+
+        for x in nal_units {
+            hvcc.extend((x.len() as u16).to_be_bytes());
+            hvcc.extend_from_slice(x);
+        }
+    }
+
+    Ok((hvcc, (width as u16, height as u16)))
+}
+
+/// Build mp4a sample entry from an adts header, also returns sample rate.
+fn audio_sample_entry(header: &[u8]) -> Result<(Vec<u8>, u32)> {
+    let object_type = ((header[2] >> 6) & 0x03) + 1;
+    let frequency_index = (header[2] >> 2) & 0x0F;
+    let channel_configuration = ((header[2] & 0x01) << 2) | ((header[3] >> 6) & 0x03);
+
+    let Some(sample_rate) = AAC_SAMPLE_RATES.get(frequency_index as usize).copied() else {
+        bail!(
+            "invalid adts sampling frequency index ({}).",
+            frequency_index
+        );
+    };
+
+    // audio specific config
+    let config = [
+        (object_type << 3) | (frequency_index >> 1),
+        ((frequency_index & 0x01) << 7) | (channel_configuration << 3),
+    ];
+
+    let mut decoder_config = vec![0x40, 0x15, 0, 0, 0]; // aac, audio stream and buffer size
+    decoder_config.extend([0; 8]); // maximum and average bitrate
+    decoder_config.extend([0x05, config.len() as u8]);
+    decoder_config.extend(config);
+
+    let mut descriptor = vec![0, 0, 0]; // es id and flags
+    descriptor.extend([0x04, decoder_config.len() as u8]);
+    descriptor.extend(decoder_config);
+    descriptor.extend([0x06, 1, 0x02]); // sl config
+
+    let mut esds = vec![0x03, descriptor.len() as u8];
+    esds.extend(descriptor);
+
+    let channels = if channel_configuration == 0 {
+        2
+    } else {
+        channel_configuration as u16
+    };
+
+    let mut entry = vec![0, 0, 0, 0, 0, 0, 0, 1]; // reserved and data reference index
+    entry.extend([0; 8]);
+    entry.extend(channels.to_be_bytes());
+    entry.extend(16_u16.to_be_bytes()); // sample size
+    entry.extend([0; 4]);
+    entry.extend((sample_rate << 16).to_be_bytes());
+    entry.extend(full_box(b"esds", 0, 0, &esds));
+    Ok((mp4_box(b"mp4a", &entry), sample_rate))
+}
+
+fn remove_emulation_prevention(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+
+    for x in data {
+        if zeros >= 2 && *x == 3 {
+            zeros = 0;
+            continue;
+        }
+
+        zeros = if *x == 0 { zeros + 1 } else { 0 };
+        output.push(*x);
+    }
+
+    output
+}
+
+/// Reader of exp-golomb coded parameter sets.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn bit(&mut self) -> Result<u32> {
+        let Some(byte) = self.data.get(self.position / 8) else {
+            bail!("couldn't parse sequence parameter set (unexpected end).");
+        };
+
+        let bit = (byte >> (7 - (self.position % 8))) & 0x01;
+        self.position += 1;
+        Ok(bit as u32)
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32> {
+        let mut value = 0;
+
+        for _ in 0..count {
+            value = (value << 1) | self.bit()?;
+        }
+
+        Ok(value)
+    }
+
+    fn skip(&mut self, count: u32) -> Result<()> {
+        self.position += count as usize;
+
+        if self.position > self.data.len() * 8 {
+            bail!("couldn't parse sequence parameter set (unexpected end).");
+        }
+
+        Ok(())
+    }
+
+    fn ue(&mut self) -> Result<u32> {
+        let mut leading_zeros = 0;
+
+        while self.bit()? == 0 {
+            leading_zeros += 1;
+
+            if leading_zeros > 31 {
+                bail!("couldn't parse sequence parameter set (invalid exp-golomb code).");
+            }
+        }
+
+        Ok(((1_u64 << leading_zeros) - 1 + self.bits(leading_zeros)? as u64) as u32)
+    }
+
+    fn se(&mut self) -> Result<i32> {
+        let value = self.ue()? as i64;
+
+        Ok(if value % 2 == 0 {
+            -(value / 2) as i32
+        } else {
+            ((value + 1) / 2) as i32
+        })
+    }
+}
@@ -7,23 +7,31 @@
 
 */
 
-use crate::commands::Quality;
+use crate::{
+    commands::Quality,
+    merger::Merger,
+    progress::Progress,
+    selector::{StreamSelector, StreamSelectors},
+};
 use anyhow::{bail, Result};
+use chrono::{DateTime, FixedOffset};
 use kdam::term::Colorizer;
 use requestty::prompt::style::Stylize;
 use reqwest::{header::HeaderValue, Url};
 use serde::Serialize;
 use std::{fmt::Display, io::Write, path::PathBuf};
 
+/// Streams available in a DASH or HLS playlist.
 #[derive(Serialize)]
-pub(crate) struct MasterPlaylist {
-    pub(crate) playlist_type: PlaylistType,
-    pub(crate) uri: String,
-    pub(crate) streams: Vec<MediaPlaylist>,
+pub struct MasterPlaylist {
+    pub playlist_type: PlaylistType,
+    pub uri: String,
+    pub streams: Vec<MediaPlaylist>,
 }
 
 impl MasterPlaylist {
-    pub(crate) fn sort_streams(
+    /// Sort streams by media type and bandwidth, streams with preferred languages are kept first.
+    pub fn sort_streams(
         mut self,
         prefer_audio_lang: Option<String>,
         prefer_subs_lang: Option<String>,
@@ -151,15 +159,66 @@ impl MasterPlaylist {
         has_resolution.or(has_height)
     }
 
+    /// Select video, audio and subtitles streams to download. User is prompted for selection
+    /// unless `skip_prompts` is true, any selection expression is given or progress is reported
+    /// to a callback. Streams of a media type are pre-selected by its selection expressions,
+    /// otherwise by defaults, i.e. video stream of `quality` and first audio and subtitles streams.
     pub(crate) fn select_streams(
         self,
         quality: Quality,
+        selectors: &StreamSelectors,
         skip_prompts: bool,
         raw_prompts: bool,
+        pb: &Progress,
     ) -> Result<(Vec<MediaPlaylist>, Vec<MediaPlaylist>)> {
-        let default_video_stream_index = self.select_video_stream(&quality);
+        let skip_prompts =
+            skip_prompts || !selectors.is_empty() || matches!(pb, Progress::Callback(_));
+        let selection = |media_type: MediaType, selectors: &[StreamSelector], all: bool| {
+            let streams = self
+                .streams
+                .iter()
+                .filter(|x| x.media_type == media_type)
+                .collect::<Vec<_>>();
+            let selection = StreamSelector::select(selectors, &streams, all);
+            // Subtitles streams are optional, unless they are filtered by an expression.
+            let required = if media_type == MediaType::Subtitles {
+                selectors.iter().any(|x| x.has_filters())
+            } else {
+                !streams.is_empty()
+            };
+
+            if selection.is_empty() && required {
+                bail!(
+                    "playlist doesn't contain any {} stream matching {}.",
+                    media_type,
+                    selectors
+                        .iter()
+                        .map(|x| x.to_string())
+                        .collect::<Vec<_>>()
+                        .join(" or ")
+                );
+            }
+
+            Ok(selection)
+        };
+
+        let video_selection = if selectors.video.is_empty() {
+            self.select_video_stream(&quality).map(|x| vec![x])
+        } else {
+            Some(selection(MediaType::Video, &selectors.video, false)?)
+        };
+        let audio_selection = if selectors.audio.is_empty() {
+            vec![0]
+        } else {
+            selection(MediaType::Audio, &selectors.audio, false)?
+        };
+        let subtitle_selection = if selectors.subtitles.is_empty() {
+            vec![0]
+        } else {
+            selection(MediaType::Subtitles, &selectors.subtitles, true)?
+        };
 
-        if let Some(default_video_stream_index) = default_video_stream_index {
+        if let Some(video_selection) = video_selection {
             let mut video_streams = vec![];
             let mut audio_streams = vec![];
             let mut subtitle_streams = vec![];
@@ -183,18 +242,15 @@ impl MasterPlaylist {
                 "─────── Video Streams ────────".to_owned(),
             ));
             choices_with_default.extend(video_streams.iter().enumerate().map(|(i, x)| {
-                requestty::Choice((x.display_video_stream(), i == default_video_stream_index))
+                requestty::Choice((x.display_video_stream(), video_selection.contains(&i)))
             }));
             choices_with_default_ranges[0] = 1..choices_with_default.len();
             choices_with_default.push(requestty::Separator(
                 "─────── Audio Streams ────────".to_owned(),
             ));
-            choices_with_default.extend(
-                audio_streams
-                    .iter()
-                    .enumerate()
-                    .map(|(i, x)| requestty::Choice((x.display_audio_stream(), i == 0))),
-            );
+            choices_with_default.extend(audio_streams.iter().enumerate().map(|(i, x)| {
+                requestty::Choice((x.display_audio_stream(), audio_selection.contains(&i)))
+            }));
 
             if skip_prompts || raw_prompts {
                 choices_with_default_ranges[1] =
@@ -207,12 +263,9 @@ impl MasterPlaylist {
             choices_with_default.push(requestty::Separator(
                 "────── Subtitle Streams ──────".to_owned(),
             ));
-            choices_with_default.extend(
-                subtitle_streams
-                    .iter()
-                    .enumerate()
-                    .map(|(i, x)| requestty::Choice((x.display_subtitle_stream(), i == 0))),
-            );
+            choices_with_default.extend(subtitle_streams.iter().enumerate().map(|(i, x)| {
+                requestty::Choice((x.display_subtitle_stream(), subtitle_selection.contains(&i)))
+            }));
 
             if skip_prompts || raw_prompts {
                 choices_with_default_ranges[2] =
@@ -225,13 +278,13 @@ impl MasterPlaylist {
             // println!("{:?}", choices_with_default_ranges);
 
             if skip_prompts || raw_prompts {
-                println!("Select streams to download:");
+                pb.print("Select streams to download:");
                 let mut selected_choices_index = vec![];
                 let mut index = 1;
 
                 for choice in choices_with_default {
                     if let requestty::Separator(seperator) = choice {
-                        println!("{}", seperator.replace('─', "-"));
+                        pb.print(seperator.replace('─', "-"));
                     } else {
                         let (message, selected) = choice.unwrap_choice();
 
@@ -239,17 +292,17 @@ impl MasterPlaylist {
                             selected_choices_index.push(index);
                         }
 
-                        println!(
+                        pb.print(format!(
                             "{:2}) [{}] {}",
                             index,
                             if selected { 'x' } else { ' ' },
                             message
-                        );
+                        ));
                         index += 1;
                     }
                 }
 
-                println!("------------------------------");
+                pb.print("------------------------------");
 
                 if raw_prompts && !skip_prompts {
                     print!(
@@ -281,29 +334,29 @@ impl MasterPlaylist {
                 for i in selected_choices_index {
                     if choices_with_default_ranges[0].contains(&i) {
                         let stream = video_streams.remove(i - video_streams_offset);
-                        println!(
+                        pb.print(format!(
                             "   {} {}",
                             "Selected".colorize("bold green"),
                             stream.display_stream()
-                        );
+                        ));
                         selected_streams.push(stream);
                         video_streams_offset += 1;
                     } else if choices_with_default_ranges[1].contains(&i) {
                         let stream = audio_streams.remove(i - audio_streams_offset);
-                        println!(
+                        pb.print(format!(
                             "   {} {}",
                             "Selected".colorize("bold green"),
                             stream.display_stream()
-                        );
+                        ));
                         selected_streams.push(stream);
                         audio_streams_offset += 1;
                     } else if choices_with_default_ranges[2].contains(&i) {
                         let stream = subtitle_streams.remove(i - subtitle_streams_offset);
-                        println!(
+                        pb.print(format!(
                             "   {} {}",
                             "Selected".colorize("bold green"),
                             stream.display_stream()
-                        );
+                        ));
                         selected_subtitle_streams.push(stream);
                         subtitle_streams_offset += 1;
                     }
@@ -360,25 +413,39 @@ impl MasterPlaylist {
     }
 }
 
-#[derive(Default, Serialize)]
-pub(crate) struct MediaPlaylist {
-    pub(crate) bandwidth: Option<u64>,
-    pub(crate) channels: Option<f32>,
-    pub(crate) codecs: Option<String>,
-    pub(crate) extension: Option<String>,
-    pub(crate) frame_rate: Option<f32>,
-    pub(crate) i_frame: bool,
-    pub(crate) language: Option<String>,
-    pub(crate) live: bool,
-    pub(crate) media_type: MediaType,
-    pub(crate) playlist_type: PlaylistType,
-    pub(crate) resolution: Option<(u64, u64)>,
-    pub(crate) segments: Vec<Segment>,
-    pub(crate) uri: String,
+/// Chapter of a stream, e.g. a period of multi-period DASH presentations.
+#[derive(Clone, Serialize)]
+pub struct Chapter {
+    /// Start time of chapter (in seconds).
+    pub start: f32,
+    pub title: String,
+}
+
+/// Video, audio or subtitles stream along with its segments.
+#[derive(Clone, Default, Serialize)]
+pub struct MediaPlaylist {
+    pub bandwidth: Option<u64>,
+    pub channels: Option<f32>,
+    pub chapters: Vec<Chapter>,
+    pub codecs: Option<String>,
+    /// Stream is marked to be selected by default (`DEFAULT=YES` or `main` role).
+    pub default: bool,
+    pub extension: Option<String>,
+    /// Stream is marked as forced (`FORCED=YES` or `forced-subtitle` role).
+    pub forced: bool,
+    pub frame_rate: Option<f32>,
+    pub i_frame: bool,
+    pub language: Option<String>,
+    pub live: bool,
+    pub media_type: MediaType,
+    pub playlist_type: PlaylistType,
+    pub resolution: Option<(u64, u64)>,
+    pub segments: Vec<Segment>,
+    pub uri: String,
 }
 
 impl MediaPlaylist {
-    pub(crate) fn is_hls(&self) -> bool {
+    pub fn is_hls(&self) -> bool {
         matches!(&self.playlist_type, PlaylistType::Hls)
     }
 
@@ -396,7 +463,31 @@ impl MediaPlaylist {
         None
     }
 
-    pub(crate) fn extension(&self) -> String {
+    /// Identity of stream, i.e. its uri and a hash of its segments (FNV-1a of uris and byte ranges).
+    /// Used for checking whether an interrupted download belongs to this stream. Query strings
+    /// are left out as they often contain expiring tokens.
+    pub(crate) fn journal_id(&self) -> String {
+        let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+        let mut update = |data: &[u8]| {
+            for byte in data {
+                hash = (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+
+        for segment in &self.segments {
+            update(segment.uri.split('?').next().unwrap().as_bytes());
+
+            if let Some(range) = &segment.range {
+                update(format!(" {}-{}", range.start, range.end).as_bytes());
+            }
+
+            update(b"\n");
+        }
+
+        format!("{:016x} {}", hash, self.uri.split('?').next().unwrap())
+    }
+
+    pub fn extension(&self) -> String {
         if let Some(ext) = &self.extension {
             return ext.to_owned();
         }
@@ -457,7 +548,11 @@ impl MediaPlaylist {
             path = directory.join(path);
         }
 
-        if path.exists() {
+        // Reuse path of an interrupted download so that it can be resumed.
+        // Live streams are never resumed as their older segments are gone by then.
+        let id = self.journal_id();
+
+        if path.exists() && (self.live || !Merger::is_resumable(&path, self.segments.len(), &id)) {
             for i in 1.. {
                 path.set_file_name(format!(
                     "{}_{}_({}).{}",
@@ -467,7 +562,9 @@ impl MediaPlaylist {
                     ext
                 ));
 
-                if !path.exists() {
+                if !path.exists()
+                    || (!self.live && Merger::is_resumable(&path, self.segments.len(), &id))
+                {
                     return path;
                 }
             }
@@ -476,7 +573,7 @@ impl MediaPlaylist {
         path
     }
 
-    pub(crate) fn display_stream(&self) -> String {
+    pub fn display_stream(&self) -> String {
         match self.media_type {
             MediaType::Audio => self.display_audio_stream(),
             MediaType::Subtitles => self.display_subtitle_stream(),
@@ -608,15 +705,17 @@ impl MediaPlaylist {
     }
 }
 
-#[derive(Default, Serialize)]
-pub(crate) enum PlaylistType {
+/// Type of playlist from which a stream is parsed.
+#[derive(Clone, Default, Serialize)]
+pub enum PlaylistType {
     Dash,
     #[default]
     Hls,
 }
 
-#[derive(Clone, Default, PartialEq, Serialize)]
-pub(crate) enum MediaType {
+/// Type of media in a stream.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub enum MediaType {
     Audio,
     Subtitles,
     #[default]
@@ -639,8 +738,9 @@ impl Display for MediaType {
     }
 }
 
+/// Encryption method of segments.
 #[derive(Clone, PartialEq, Serialize)]
-pub(crate) enum KeyMethod {
+pub enum KeyMethod {
     Aes128,
     Cenc,
     None,
@@ -648,10 +748,27 @@ pub(crate) enum KeyMethod {
     SampleAes,
 }
 
+impl Display for KeyMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Aes128 => "AES-128",
+                Self::Cenc => "CENC",
+                Self::None => "NONE",
+                Self::Other(x) => x,
+                Self::SampleAes => "SAMPLE-AES",
+            }
+        )
+    }
+}
+
+/// Byte range of a resource, both ends are inclusive.
 #[derive(Clone, Serialize)]
-pub(crate) struct Range {
-    pub(crate) start: u64,
-    pub(crate) end: u64,
+pub struct Range {
+    pub start: u64,
+    pub end: u64,
 }
 
 impl Range {
@@ -660,10 +777,11 @@ impl Range {
     }
 }
 
+/// Initialization segment of a stream.
 #[derive(Clone, Serialize)]
-pub(crate) struct Map {
-    pub(crate) uri: String,
-    pub(crate) range: Option<Range>,
+pub struct Map {
+    pub uri: String,
+    pub range: Option<Range>,
 }
 
 /*
@@ -675,20 +793,33 @@ pub(crate) struct Map {
 #EXT-X-KEY:METHOD=SAMPLE-AES-CTR,KEYFORMAT="com.microsoft.playready",KEYFORMATVERSIONS="1",URI="data:text/plain;charset=UTF-16;base64,xAEAAAEAAQC6ATwAVwBSAE0ASABFAEEARABFAFIAIAB4AG0AbABuAHMAPQAiAGgAdAB0AHAAOgAvAC8AcwBjAGgAZQBtAGEAcwAuAG0AaQBjAHIAbwBzAG8AZgB0AC4AYwBvAG0ALwBEAFIATQAvADIAMAAwADcALwAwADMALwBQAGwAYQB5AFIAZQBhAGQAeQBIAGUAYQBkAGUAcgAiACAAdgBlAHIAcwBpAG8AbgA9ACIANAAuADAALgAwAC4AMAAiAD4APABEAEEAVABBAD4APABQAFIATwBUAEUAQwBUAEkATgBGAE8APgA8AEsARQBZAEwARQBOAD4AMQA2ADwALwBLAEUAWQBMAEUATgA+ADwAQQBMAEcASQBEAD4AQQBFAFMAQwBUAFIAPAAvAEEATABHAEkARAA+ADwALwBQAFIATwBUAEUAQwBUAEkATgBGAE8APgA8AEsASQBEAD4AOQBmAEIAMQAxAEsAMQB0AC8ARQBtAFEANABYAEMATQBjAEoANgBnAEkAZwA9AD0APAAvAEsASQBEAD4APAAvAEQAQQBUAEEAPgA8AC8AVwBSAE0ASABFAEEARABFAFIAPgA="
 
 */
+/// Decryption information of segments.
 #[derive(Clone, Serialize)]
-pub(crate) struct Key {
-    pub(crate) default_kid: Option<String>,
-    pub(crate) iv: Option<String>,
-    pub(crate) key_format: Option<String>,
-    pub(crate) method: KeyMethod,
-    pub(crate) uri: Option<String>,
+pub struct Key {
+    pub default_kid: Option<String>,
+    pub iv: Option<String>,
+    pub key_format: Option<String>,
+    pub method: KeyMethod,
+    pub uri: Option<String>,
 }
 
+/// Media segment of a stream.
 #[derive(Clone, Default, Serialize)]
-pub(crate) struct Segment {
-    pub(crate) range: Option<Range>,
-    pub(crate) duration: f32, // consider changing it to f64
-    pub(crate) key: Option<Key>,
-    pub(crate) map: Option<Map>,
-    pub(crate) uri: String,
+pub struct Segment {
+    pub range: Option<Range>,
+    /// Segment is a part of an ad break (`#EXT-X-CUE-OUT` to `#EXT-X-CUE-IN` or `SCTE35-OUT` date range).
+    pub ad: bool,
+    /// Segment starts a new discontinuity range (`#EXT-X-DISCONTINUITY`), i.e. its timestamps,
+    /// encoding or format are not continuous with previous segment.
+    pub discontinuity: bool,
+    pub duration: f32, // consider changing it to f64
+    /// Segment is not available (`#EXT-X-GAP`).
+    pub gap: bool,
+    pub key: Option<Key>,
+    pub map: Option<Map>,
+    /// Date and time of first sample of segment (`#EXT-X-PROGRAM-DATE-TIME`).
+    pub program_date_time: Option<DateTime<FixedOffset>>,
+    /// Media sequence number of segment (HLS).
+    pub sequence: u64,
+    pub uri: String,
 }
@@ -0,0 +1,192 @@
+use crate::utils;
+use anyhow::Result;
+use kdam::{tqdm, BarExt, Column, RichProgress};
+use std::sync::Arc;
+
+/// Progress of a download, reported to the callback set using
+/// [`Downloader::progress`](crate::Downloader::progress).
+#[derive(Clone, Debug)]
+pub enum ProgressEvent {
+    /// Status message, e.g. stream which is being downloaded or a warning.
+    Message(String),
+    /// Number of segments to download, downloaded segments count is reset to zero.
+    Reset(usize),
+    /// More segments are expected to be downloaded, used for live streams.
+    Extend(usize),
+    /// Number of segments which were downloaded (or resumed) since last event.
+    Advance(usize),
+    /// Downloaded bytes and estimated size (in bytes) of the download, if known.
+    Bytes {
+        downloaded: usize,
+        estimated: Option<usize>,
+    },
+    /// Download speed in bytes per second.
+    Speed(usize),
+    /// Duration of output (in seconds) which is muxed by ffmpeg and total duration of
+    /// downloaded streams, if known.
+    Muxed { duration: Option<f32>, muxed: f32 },
+}
+
+/// Callback which receives download progress events.
+pub type ProgressCallback = Arc<dyn Fn(ProgressEvent) + Send + Sync>;
+
+/// Either the terminal progress bar or a user supplied callback.
+pub(crate) enum Progress {
+    Bar(Box<RichProgress>),
+    Callback(ProgressCallback),
+}
+
+impl Progress {
+    /// Callback, if any, otherwise the terminal progress bar.
+    pub(crate) fn new(callback: Option<ProgressCallback>) -> Self {
+        match callback {
+            Some(callback) => Self::Callback(callback),
+            None => Self::bar(),
+        }
+    }
+
+    fn bar() -> Self {
+        Self::Bar(Box::new(RichProgress::new(
+            tqdm!(unit = " SEG".to_owned(), dynamic_ncols = true),
+            vec![
+                Column::Text("[bold blue]?".to_owned()),
+                Column::Animation,
+                Column::Percentage(0),
+                Column::Text("•".to_owned()),
+                Column::CountTotal,
+                Column::Text("•".to_owned()),
+                Column::ElapsedTime,
+                Column::Text("[cyan]>".to_owned()),
+                Column::RemainingTime,
+                Column::Text("•".to_owned()),
+                Column::Rate,
+            ],
+        )))
+    }
+
+    /// Progress bar (or same callback) for muxing streams using ffmpeg, total is the duration
+    /// (in seconds) of streams.
+    pub(crate) fn muxing(&self, duration: Option<f32>) -> Self {
+        match self {
+            Self::Bar(_) => Self::Bar(Box::new(RichProgress::new(
+                tqdm!(
+                    total = duration.map(|x| x.ceil() as usize).unwrap_or(0),
+                    unit = " s".to_owned(),
+                    dynamic_ncols = true
+                ),
+                vec![
+                    Column::Text("[bold cyan]Muxing".to_owned()),
+                    Column::Animation,
+                    Column::Percentage(0),
+                    Column::Text("•".to_owned()),
+                    Column::ElapsedTime,
+                    Column::Text("[cyan]>".to_owned()),
+                    Column::RemainingTime,
+                ],
+            ))),
+            Self::Callback(callback) => Self::Callback(callback.clone()),
+        }
+    }
+
+    /// Write a message above the progress bar.
+    pub(crate) fn write<T: Into<String>>(&mut self, message: T) -> Result<()> {
+        match self {
+            Self::Bar(pb) => pb.write(message.into())?,
+            Self::Callback(callback) => callback(ProgressEvent::Message(message.into())),
+        }
+
+        Ok(())
+    }
+
+    /// Print a message when progress bar is not being drawn.
+    pub(crate) fn print<T: Into<String>>(&self, message: T) {
+        match self {
+            Self::Bar(_) => println!("{}", message.into()),
+            Self::Callback(callback) => callback(ProgressEvent::Message(message.into())),
+        }
+    }
+
+    pub(crate) fn reset(&mut self, total: usize) {
+        match self {
+            Self::Bar(pb) => pb.pb.reset(Some(total)),
+            Self::Callback(callback) => callback(ProgressEvent::Reset(total)),
+        }
+    }
+
+    pub(crate) fn extend(&mut self, count: usize) {
+        match self {
+            Self::Bar(pb) => pb.pb.total += count,
+            Self::Callback(callback) => callback(ProgressEvent::Extend(count)),
+        }
+    }
+
+    pub(crate) fn update(&mut self, n: usize) -> Result<()> {
+        match self {
+            Self::Bar(pb) => {
+                pb.update(n)?;
+            }
+            Self::Callback(callback) => callback(ProgressEvent::Advance(n)),
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn set_bytes(&mut self, downloaded: usize, estimated: Option<usize>) {
+        match self {
+            Self::Bar(pb) => pb.replace(
+                0,
+                Column::Text(format!(
+                    "[bold blue]{}",
+                    if let Some(estimated) = estimated {
+                        utils::format_download_bytes(downloaded, estimated)
+                    } else {
+                        utils::format_bytes(downloaded, 2).2
+                    }
+                )),
+            ),
+            Self::Callback(callback) => callback(ProgressEvent::Bytes {
+                downloaded,
+                estimated,
+            }),
+        }
+    }
+
+    pub(crate) fn set_speed(&mut self, speed: usize) {
+        match self {
+            Self::Bar(pb) => pb.replace(
+                12,
+                Column::Text(format!("[yellow]{}/s", utils::format_bytes(speed, 2).2)),
+            ),
+            Self::Callback(callback) => callback(ProgressEvent::Speed(speed)),
+        }
+    }
+
+    pub(crate) fn set_muxed(&mut self, muxed: f32, duration: Option<f32>) -> Result<()> {
+        match self {
+            Self::Bar(pb) => {
+                pb.update_to(muxed as usize)?;
+            }
+            Self::Callback(callback) => callback(ProgressEvent::Muxed { duration, muxed }),
+        }
+
+        Ok(())
+    }
+
+    /// Add download speed column to progress bar.
+    pub(crate) fn show_speed(&mut self) {
+        if let Self::Bar(pb) = self {
+            pb.replace(2, Column::Percentage(2));
+            pb.columns.extend_from_slice(&[
+                Column::Text("•".to_owned()),
+                Column::Text("[yellow]?".to_owned()),
+            ]);
+        }
+    }
+
+    /// Move to next line after progress bar is completed.
+    pub(crate) fn finish(&self) {
+        if let Self::Bar(_) = self {
+            eprintln!();
+        }
+    }
+}
@@ -0,0 +1,219 @@
+/*
+    REFERENCES
+    ----------
+
+    1. https://aws.amazon.com/blogs/architecture/exponential-backoff-and-jitter
+    2. https://www.rfc-editor.org/rfc/rfc9110#section-10.2.3
+
+*/
+
+use crate::limiter::RateLimiter;
+use anyhow::{anyhow, Result};
+use kdam::term::Colorizer;
+use reqwest::{header, RequestBuilder, Response, StatusCode};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+/// Retry behaviour of http requests.
+///
+/// Failed requests are retried after an exponentially increasing delay with jitter,
+/// or after the delay asked by server through `Retry-After` header (up to maximum delay).
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Maximum number of retries of a request.
+    pub retries: u8,
+    /// Delay before first retry, doubled on every next retry.
+    pub delay: Duration,
+    /// Upper limit of delay between retries, also applied to delay asked by server.
+    pub max_delay: Duration,
+    /// Response statuses which are never retried.
+    pub no_retry: Vec<StatusCode>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 15,
+            delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            no_retry: vec![StatusCode::FORBIDDEN, StatusCode::NOT_FOUND],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Same policy with a different number of retries.
+    pub fn with_retries(mut self, retries: u8) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Send request until it succeeds with a success status.
+    /// `on_retry` receives a message before every retry.
+    pub(crate) async fn send<F: FnMut(String)>(
+        &self,
+        request: RequestBuilder,
+        mut on_retry: F,
+    ) -> Result<Response> {
+        self.send_counted(&request, &mut 0, &mut on_retry).await
+    }
+
+    /// Same as [`RetryPolicy::send`] but reading the response body is also retried.
+    pub(crate) async fn bytes<F: FnMut(String)>(
+        &self,
+        request: RequestBuilder,
+        on_retry: F,
+    ) -> Result<Vec<u8>> {
+        self.bytes_limited(request, &[], on_retry).await
+    }
+
+    /// Same as [`RetryPolicy::bytes`] but response body is read no faster than `limiters` allow.
+    pub(crate) async fn bytes_limited<F: FnMut(String)>(
+        &self,
+        request: RequestBuilder,
+        limiters: &[RateLimiter],
+        mut on_retry: F,
+    ) -> Result<Vec<u8>> {
+        let mut attempt = 0;
+
+        loop {
+            let mut response = self
+                .send_counted(&request, &mut attempt, &mut on_retry)
+                .await?;
+            let url = response.url().to_owned();
+
+            match read_body(&mut response, limiters).await {
+                Ok(bytes) => return Ok(bytes),
+                Err(e) if attempt < self.retries as u32 => {
+                    attempt += 1;
+                    let delay = self.delay(attempt, None);
+                    on_retry(message(url.as_str(), &reason(&e), delay));
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => return Err(anyhow!("{} ({})", url, reason(&e))),
+            }
+        }
+    }
+
+    async fn send_counted<F: FnMut(String)>(
+        &self,
+        request: &RequestBuilder,
+        attempt: &mut u32,
+        on_retry: &mut F,
+    ) -> Result<Response> {
+        loop {
+            let request = request
+                .try_clone()
+                .ok_or_else(|| anyhow!("request with a streaming body cannot be retried."))?;
+
+            let (url, reason, retry_after) = match request.send().await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) => {
+                    let status = response.status();
+                    let url = response.url().to_string();
+
+                    if self.no_retry.contains(&status) {
+                        return Err(anyhow!("{} (status {})", url, status));
+                    }
+
+                    (url, format!("status {}", status), retry_after(&response))
+                }
+                Err(e) if e.is_builder() => return Err(e.into()),
+                Err(e) => (
+                    e.url().map(|x| x.to_string()).unwrap_or_default(),
+                    reason(&e),
+                    None,
+                ),
+            };
+
+            if *attempt >= self.retries as u32 {
+                return Err(anyhow!(
+                    "{} ({}, reached maximum number of retries)",
+                    url,
+                    reason
+                ));
+            }
+
+            *attempt += 1;
+            let delay = self.delay(*attempt, retry_after);
+            on_retry(message(&url, &reason, delay));
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Exponential backoff with equal jitter, i.e. a random delay between half and full
+    /// of backoff. Delay asked by server is respected up to maximum delay.
+    fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let backoff = self
+            .delay
+            .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        let jitter = (RandomState::new().build_hasher().finish() % 1000) as f64 / 1000.0;
+        let delay = backoff.mul_f64(0.5 + (jitter / 2.0));
+
+        match retry_after {
+            Some(retry_after) => retry_after.min(self.max_delay).max(delay),
+            None => delay,
+        }
+    }
+}
+
+/// Parse `Retry-After` header of 429 and 503 responses, which is either delay in seconds
+/// or a http date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    if response.status() != StatusCode::TOO_MANY_REQUESTS
+        && response.status() != StatusCode::SERVICE_UNAVAILABLE
+    {
+        return None;
+    }
+
+    let value = response.headers().get(header::RETRY_AFTER)?.to_str().ok()?;
+
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        Some(Duration::from_secs(seconds))
+    } else {
+        let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+        (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .ok()
+    }
+}
+
+async fn read_body(response: &mut Response, limiters: &[RateLimiter]) -> reqwest::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(response.content_length().unwrap_or(0) as usize);
+
+    while let Some(chunk) = response.chunk().await? {
+        for limiter in limiters {
+            limiter.acquire(chunk.len()).await;
+        }
+
+        data.extend_from_slice(&chunk);
+    }
+
+    Ok(data)
+}
+
+fn reason(error: &reqwest::Error) -> String {
+    if error.is_timeout() {
+        "timeout".to_owned()
+    } else if error.is_connect() {
+        "connection error".to_owned()
+    } else if error.is_body() || error.is_decode() {
+        "body read error".to_owned()
+    } else {
+        error.to_string()
+    }
+}
+
+fn message(url: &str, reason: &str, delay: Duration) -> String {
+    format!(
+        "    {} {} ({}), retrying in {:.1}s",
+        "Request".colorize("bold yellow"),
+        url,
+        reason,
+        delay.as_secs_f32()
+    )
+}
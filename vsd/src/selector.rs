@@ -0,0 +1,532 @@
+use crate::playlist::MediaPlaylist;
+use anyhow::{anyhow, bail, Result};
+use std::{collections::BTreeSet, fmt::Display, str::FromStr};
+
+/// Selection expressions for video, audio and subtitles streams, see [`StreamSelector`].
+/// Streams of a media type without any expression are selected by defaults.
+#[derive(Clone, Debug, Default)]
+pub struct StreamSelectors {
+    pub audio: Vec<StreamSelector>,
+    pub subtitles: Vec<StreamSelector>,
+    pub video: Vec<StreamSelector>,
+}
+
+impl StreamSelectors {
+    pub fn is_empty(&self) -> bool {
+        self.audio.is_empty() && self.subtitles.is_empty() && self.video.is_empty()
+    }
+}
+
+/// Expression for selecting streams without prompts, e.g. `res>=720,codec=avc1,best`
+/// or `lang=en|fr,all`. Expression is a comma separated list of filters, all of which should
+/// match a stream, along with an optional count of matching streams to select.
+///
+/// Filters are written as `KEY OP VALUE`, where `OP` can be `=`, `!=`, `>=`, `<=`, `>` or `<`.
+/// Multiple values can be separated by `|` and `*` matches any value (glob). A bare `*` value
+/// matches every stream, including streams which don't specify that key (e.g. `lang=*`).
+///
+/// | Key | Matches |
+/// | --- | ------- |
+/// | `bw` | bandwidth, with an optional `K`, `M` or `G` suffix (e.g. `bw<=5M`) |
+/// | `channels` | number of audio channels |
+/// | `codec` | codecs, by prefix (e.g. `codec=avc1` or `codec=hvc1\|hev1`) |
+/// | `default` | default flag (`yes` or `no`), `default` is same as `default=yes` |
+/// | `forced` | forced flag (`yes` or `no`), `forced` is same as `forced=yes` |
+/// | `lang` | language, also by its primary subtag (e.g. `lang=en` matches `en-US`) |
+/// | `res` | height (e.g. `res>=720` or `res=1080p`) or resolution (e.g. `res=1920x1080`) |
+///
+/// Count can be `best` (first matching stream), `worst` (last matching stream), `all` or
+/// a number of best matching streams. Streams are sorted from best to worst by resolution,
+/// channels and bandwidth. By default `best` stream is selected, except for subtitles
+/// streams of which `all` matching streams are selected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamSelector {
+    count: Option<Count>,
+    expression: String,
+    filters: Vec<Filter>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Count {
+    All,
+    Best(usize),
+    Worst,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Filter {
+    key: Key,
+    op: Op,
+    values: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Key {
+    Bandwidth,
+    Channels,
+    Codec,
+    Default,
+    Forced,
+    Language,
+    Resolution,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ge,
+    Gt,
+    Le,
+    Lt,
+    Ne,
+}
+
+impl StreamSelector {
+    /// Whether the expression has any filters, i.e. it isn't just a count of streams.
+    pub(crate) fn has_filters(&self) -> bool {
+        !self.filters.is_empty()
+    }
+
+    /// Indexes of streams (sorted from best to worst) selected by any of the selectors.
+    /// All matching streams are selected by default when `all` is true.
+    pub(crate) fn select(selectors: &[Self], streams: &[&MediaPlaylist], all: bool) -> Vec<usize> {
+        let default = if all { Count::All } else { Count::Best(1) };
+        let mut selected = BTreeSet::new();
+
+        for selector in selectors {
+            let matched = streams
+                .iter()
+                .enumerate()
+                .filter(|(_, x)| selector.filters.iter().all(|y| y.matches(x)))
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>();
+
+            match selector.count.unwrap_or(default) {
+                Count::All => selected.extend(matched),
+                Count::Best(count) => selected.extend(matched.into_iter().take(count)),
+                Count::Worst => selected.extend(matched.last()),
+            }
+        }
+
+        selected.into_iter().collect()
+    }
+}
+
+impl FromStr for StreamSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut count = None;
+        let mut filters = vec![];
+
+        for term in s.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+            let term_count = match term.to_lowercase().as_str() {
+                "all" => Some(Count::All),
+                "best" => Some(Count::Best(1)),
+                "worst" => Some(Count::Worst),
+                x => x.parse::<usize>().ok().map(Count::Best),
+            };
+
+            if let Some(term_count) = term_count {
+                if count.replace(term_count).is_some() {
+                    bail!("count of streams is specified twice in {}.", s);
+                }
+
+                continue;
+            }
+
+            filters.push(
+                term.parse::<Filter>()
+                    .map_err(|x| anyhow!("{} in selection expression {}", x, s))?,
+            );
+        }
+
+        Ok(Self {
+            count,
+            expression: s.to_owned(),
+            filters,
+        })
+    }
+}
+
+impl Display for StreamSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let position = s.find(['=', '!', '>', '<']).unwrap_or(s.len());
+        let (key, rest) = s.split_at(position);
+        let key = match key.trim().to_lowercase().as_str() {
+            "bw" | "bandwidth" => Key::Bandwidth,
+            "channels" => Key::Channels,
+            "codec" | "codecs" => Key::Codec,
+            "default" => Key::Default,
+            "forced" => Key::Forced,
+            "lang" | "language" => Key::Language,
+            "res" | "resolution" => Key::Resolution,
+            x => bail!("unknown key {}", x),
+        };
+
+        // Flags can be used without any value.
+        if rest.is_empty() && matches!(key, Key::Default | Key::Forced) {
+            return Ok(Self {
+                key,
+                op: Op::Eq,
+                values: vec!["yes".to_owned()],
+            });
+        }
+
+        let (op, value) = [
+            ("!=", Op::Ne),
+            (">=", Op::Ge),
+            ("<=", Op::Le),
+            ("=", Op::Eq),
+            (">", Op::Gt),
+            ("<", Op::Lt),
+        ]
+        .into_iter()
+        .find_map(|(x, op)| rest.strip_prefix(x).map(|y| (op, y)))
+        .ok_or_else(|| anyhow!("missing operator after {}", key.name()))?;
+
+        let values = value
+            .split('|')
+            .map(|x| x.trim().to_lowercase())
+            .collect::<Vec<_>>();
+
+        if values.iter().any(|x| x.is_empty()) {
+            bail!("missing value of {}", key.name());
+        }
+
+        let numeric = matches!(key, Key::Bandwidth | Key::Channels | Key::Resolution);
+
+        if !matches!(op, Op::Eq | Op::Ne) {
+            if !numeric {
+                bail!("{} can only be compared using = or !=", key.name());
+            }
+
+            if values.len() > 1 || values[0].contains('*') {
+                bail!("{} can only be compared with a single value", key.name());
+            }
+        }
+
+        for value in values.iter().filter(|x| !x.contains('*')) {
+            let valid = match key {
+                Key::Bandwidth => parse_bandwidth(value).is_some(),
+                Key::Channels => value.parse::<f32>().is_ok(),
+                Key::Default | Key::Forced => parse_flag(value).is_some(),
+                Key::Resolution => parse_resolution(value).is_some(),
+                Key::Codec | Key::Language => true,
+            };
+
+            if !valid {
+                bail!("invalid value {} of {}", value, key.name());
+            }
+        }
+
+        Ok(Self { key, op, values })
+    }
+}
+
+impl Filter {
+    fn matches(&self, stream: &MediaPlaylist) -> bool {
+        let matched = match self.op {
+            Op::Eq | Op::Ne => self.values.iter().any(|x| self.equals(stream, x)),
+            _ => self.compare(stream).is_some_and(|x| match self.op {
+                Op::Ge => x.is_ge(),
+                Op::Gt => x.is_gt(),
+                Op::Le => x.is_le(),
+                Op::Lt => x.is_lt(),
+                Op::Eq | Op::Ne => unreachable!(),
+            }),
+        };
+
+        matched != (self.op == Op::Ne)
+    }
+
+    fn equals(&self, stream: &MediaPlaylist, value: &str) -> bool {
+        if value == "*" {
+            return true;
+        }
+
+        let pattern = value
+            .contains('*')
+            .then(|| glob::Pattern::new(value).ok())
+            .flatten();
+        let text_matches = |x: &str| {
+            let x = x.to_lowercase();
+            pattern.as_ref().map_or(x == value, |y| y.matches(&x))
+        };
+
+        match self.key {
+            Key::Bandwidth => stream
+                .bandwidth
+                .is_some_and(|x| text_matches(&x.to_string()) || Some(x) == parse_bandwidth(value)),
+            Key::Channels => stream.channels.is_some_and(|x| {
+                text_matches(&x.to_string()) || Some(x) == value.parse::<f32>().ok()
+            }),
+            Key::Codec => stream.codecs.as_ref().is_some_and(|x| {
+                x.split(',').any(|y| {
+                    let y = y.trim().to_lowercase();
+                    text_matches(&y) || (pattern.is_none() && y.starts_with(value))
+                })
+            }),
+            Key::Default => parse_flag(value).is_some_and(|x| x == stream.default),
+            Key::Forced => parse_flag(value).is_some_and(|x| x == stream.forced),
+            Key::Language => stream.language.as_ref().is_some_and(|x| {
+                text_matches(x) || x.split(['-', '_']).next().is_some_and(text_matches)
+            }),
+            Key::Resolution => stream.resolution.is_some_and(|(w, h)| {
+                text_matches(&format!("{}x{}", w, h))
+                    || match parse_resolution(value) {
+                        Some((Some(x), y)) => (w, h) == (x, y),
+                        Some((None, y)) => h == y,
+                        None => false,
+                    }
+            }),
+        }
+    }
+
+    fn compare(&self, stream: &MediaPlaylist) -> Option<std::cmp::Ordering> {
+        let value = &self.values[0];
+
+        match self.key {
+            Key::Bandwidth => Some(stream.bandwidth?.cmp(&parse_bandwidth(value)?)),
+            Key::Channels => stream.channels?.partial_cmp(&value.parse::<f32>().ok()?),
+            Key::Resolution => {
+                let (w, h) = stream.resolution?;
+
+                match parse_resolution(value)? {
+                    (Some(x), y) => Some((w * h).cmp(&(x * y))),
+                    (None, y) => Some(h.cmp(&y)),
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Key {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Bandwidth => "bw",
+            Self::Channels => "channels",
+            Self::Codec => "codec",
+            Self::Default => "default",
+            Self::Forced => "forced",
+            Self::Language => "lang",
+            Self::Resolution => "res",
+        }
+    }
+}
+
+fn parse_bandwidth(value: &str) -> Option<u64> {
+    let (value, multiplier) = match value.chars().last()? {
+        'k' => (&value[..(value.len() - 1)], 1e3),
+        'm' => (&value[..(value.len() - 1)], 1e6),
+        'g' => (&value[..(value.len() - 1)], 1e9),
+        _ => (value, 1.0),
+    };
+
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|x| *x >= 0.0)
+        .map(|x| (x * multiplier) as u64)
+}
+
+fn parse_flag(value: &str) -> Option<bool> {
+    match value {
+        "yes" | "true" | "1" => Some(true),
+        "no" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Height (`720` or `720p`) or resolution (`1280x720`).
+fn parse_resolution(value: &str) -> Option<(Option<u64>, u64)> {
+    if let Some((w, h)) = value.split_once('x') {
+        Some((Some(w.parse().ok()?), h.parse().ok()?))
+    } else {
+        Some((None, value.trim_end_matches('p').parse().ok()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::Quality,
+        playlist::{MasterPlaylist, MediaType, PlaylistType},
+        progress::Progress,
+    };
+    use std::sync::Arc;
+
+    fn stream(resolution: (u64, u64), bandwidth: u64, codecs: &str) -> MediaPlaylist {
+        MediaPlaylist {
+            bandwidth: Some(bandwidth),
+            codecs: Some(codecs.to_owned()),
+            resolution: Some(resolution),
+            ..Default::default()
+        }
+    }
+
+    fn audio(language: &str, channels: f32) -> MediaPlaylist {
+        MediaPlaylist {
+            channels: Some(channels),
+            language: Some(language.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn select(expressions: &[&str], streams: &[MediaPlaylist], all: bool) -> Vec<usize> {
+        let selectors = expressions
+            .iter()
+            .map(|x| x.parse::<StreamSelector>().unwrap())
+            .collect::<Vec<_>>();
+        StreamSelector::select(&selectors, &streams.iter().collect::<Vec<_>>(), all)
+    }
+
+    fn video_streams() -> Vec<MediaPlaylist> {
+        vec![
+            stream((1920, 1080), 8_000_000, "avc1.640028,mp4a.40.2"),
+            stream((1920, 1080), 6_000_000, "hvc1.1.6.L120.90"),
+            stream((1280, 720), 4_000_000, "avc1.64001f"),
+            stream((854, 480), 1_500_000, "avc1.64001e"),
+        ]
+    }
+
+    #[test]
+    fn parse_expressions() {
+        let selector = "res>=720".parse::<StreamSelector>().unwrap();
+        assert_eq!(selector.count, None);
+        assert_eq!(
+            selector.filters,
+            vec![Filter {
+                key: Key::Resolution,
+                op: Op::Ge,
+                values: vec!["720".to_owned()],
+            }]
+        );
+
+        let selector = "lang=en|FR, all".parse::<StreamSelector>().unwrap();
+        assert_eq!(selector.count, Some(Count::All));
+        assert_eq!(selector.filters[0].values, vec!["en", "fr"]);
+        assert_eq!(selector.to_string(), "lang=en|FR, all");
+
+        let selector = "forced,2".parse::<StreamSelector>().unwrap();
+        assert_eq!(selector.count, Some(Count::Best(2)));
+        assert_eq!(selector.filters[0].values, vec!["yes"]);
+
+        assert!("best".parse::<StreamSelector>().unwrap().filters.is_empty());
+    }
+
+    #[test]
+    fn parse_invalid_expressions() {
+        for expression in [
+            "size=720",
+            "res",
+            "res>=",
+            "lang>=en",
+            "bw<=5M|6M",
+            "res>=72*",
+            "bw=fast",
+            "best,worst",
+        ] {
+            assert!(
+                expression.parse::<StreamSelector>().is_err(),
+                "{} should be invalid",
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn select_resolution() {
+        let streams = video_streams();
+        assert_eq!(select(&["res>=720"], &streams, false), vec![0]);
+        assert_eq!(select(&["res>=720,all"], &streams, false), vec![0, 1, 2]);
+        assert_eq!(select(&["res>=720,worst"], &streams, false), vec![2]);
+        assert_eq!(select(&["res=1280x720"], &streams, false), vec![2]);
+        assert_eq!(select(&["res=480p"], &streams, false), vec![3]);
+        assert_eq!(select(&["res>1080"], &streams, false), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn select_bandwidth() {
+        let streams = video_streams();
+        assert_eq!(select(&["bw<=5M"], &streams, false), vec![2]);
+        assert_eq!(select(&["bw<=5M,all"], &streams, false), vec![2, 3]);
+        assert_eq!(select(&["bw>4000k,2"], &streams, false), vec![0, 1]);
+        assert_eq!(select(&["bw=1500000"], &streams, false), vec![3]);
+    }
+
+    #[test]
+    fn select_languages() {
+        let streams = vec![
+            audio("en-US", 6.0),
+            audio("fr", 2.0),
+            audio("de", 2.0),
+            audio("en", 2.0),
+        ];
+        assert_eq!(select(&["lang=en|fr,all"], &streams, false), vec![0, 1, 3]);
+        assert_eq!(select(&["lang=en|fr"], &streams, false), vec![0]);
+        assert_eq!(select(&["lang=en|fr"], &streams, true), vec![0, 1, 3]);
+        assert_eq!(select(&["lang!=en,all"], &streams, false), vec![1, 2]);
+        assert_eq!(
+            select(&["lang=de", "channels>2"], &streams, false),
+            vec![0, 2]
+        );
+    }
+
+    #[test]
+    fn select_glob() {
+        let streams = video_streams();
+        assert_eq!(select(&["codec=hvc*|hev*"], &streams, false), vec![1]);
+        assert_eq!(
+            select(&["codec=avc1.6400*,all"], &streams, false),
+            vec![0, 2, 3]
+        );
+        assert_eq!(select(&["res=*x1080,all"], &streams, false), vec![0, 1]);
+        assert_eq!(select(&["codec=avc1,all"], &streams, false), vec![0, 2, 3]);
+        assert_eq!(select(&["lang=*"], &streams, true), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn select_streams_without_match() {
+        let playlist = || MasterPlaylist {
+            playlist_type: PlaylistType::Hls,
+            streams: vec![
+                MediaPlaylist {
+                    media_type: MediaType::Video,
+                    ..stream((1280, 720), 4_000_000, "avc1.64001f")
+                },
+                MediaPlaylist {
+                    media_type: MediaType::Audio,
+                    ..audio("en", 2.0)
+                },
+            ],
+            uri: String::new(),
+        };
+        let select_streams = |audio: &str, subtitles: &str| {
+            let pb = Progress::Callback(Arc::new(|_| ()));
+            let selectors = StreamSelectors {
+                audio: vec![audio.parse().unwrap()],
+                subtitles: vec![subtitles.parse().unwrap()],
+                video: vec![],
+            };
+            playlist().select_streams(Quality::Highest, &selectors, true, false, &pb)
+        };
+
+        let (streams, subtitles) = select_streams("lang=en", "all").unwrap();
+        assert_eq!(streams.len(), 2);
+        assert!(subtitles.is_empty());
+
+        assert!(select_streams("lang=fr", "all").is_err());
+        assert!(select_streams("lang=en", "lang=en").is_err());
+    }
+}
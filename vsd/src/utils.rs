@@ -1,7 +1,10 @@
 use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
 use anyhow::{anyhow, bail, Result};
 use base64::Engine;
-use std::{env, path::Path};
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
 
 type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
 
@@ -48,9 +51,9 @@ pub(super) fn decode_base64<T: AsRef<[u8]>>(input: T) -> Result<Vec<u8>> {
         .map_err(|x| x.into())
 }
 
-// pub(super) fn encode_base64<T: AsRef<[u8]>>(input: T) -> String {
-//     base64::engine::general_purpose::STANDARD.encode(input)
-// }
+pub(super) fn encode_base64<T: AsRef<[u8]>>(input: T) -> String {
+    base64::engine::general_purpose::STANDARD.encode(input)
+}
 
 pub(super) fn decrypt_aes_128_cbc(
     input: &mut [u8],
@@ -69,7 +72,7 @@ pub(super) fn decrypt_aes_128_cbc(
     let mut iv_c = [0_u8; 16];
 
     if let Some(iv) = iv {
-        let iv_length = key.len();
+        let iv_length = iv.len();
 
         if iv_length != 16 {
             bail!("invalid iv size i.e. {} but expected size 16.", iv_length);
@@ -84,24 +87,48 @@ pub(super) fn decrypt_aes_128_cbc(
         .map_err(|x| anyhow!("{}", x))
 }
 
-pub(super) fn find_ffmpeg() -> Option<String> {
-    Some(
-        env::var("PATH")
-            .ok()?
-            .split(if cfg!(target_os = "windows") {
-                ';'
-            } else {
-                ':'
-            })
-            .find(|s| {
-                Path::new(s)
-                    .join(if cfg!(target_os = "windows") {
-                        "ffmpeg.exe"
-                    } else {
-                        "ffmpeg"
-                    })
-                    .exists()
-            })?
-            .to_owned(),
-    )
+/// Path of ffmpeg binary, which is searched in PATH when not supplied.
+pub(super) fn find_ffmpeg(path: Option<&Path>) -> Option<PathBuf> {
+    if let Some(path) = path {
+        return path.exists().then(|| path.to_owned());
+    }
+
+    let binary = if cfg!(target_os = "windows") {
+        "ffmpeg.exe"
+    } else {
+        "ffmpeg"
+    };
+
+    env::var("PATH")
+        .ok()?
+        .split(if cfg!(target_os = "windows") {
+            ';'
+        } else {
+            ':'
+        })
+        .map(|s| Path::new(s).join(binary))
+        .find(|x| x.exists())
+}
+
+/// Configuration directory of current user i.e. `%APPDATA%` on windows,
+/// `~/Library/Application Support` on macos and `$XDG_CONFIG_HOME` or `~/.config` otherwise.
+pub(super) fn config_dir() -> Option<PathBuf> {
+    let home = || {
+        env::var_os("HOME")
+            .filter(|x| !x.is_empty())
+            .map(PathBuf::from)
+    };
+
+    if cfg!(target_os = "windows") {
+        env::var_os("APPDATA")
+            .filter(|x| !x.is_empty())
+            .map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home().map(|x| x.join("Library").join("Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|x| !x.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| home().map(|x| x.join(".config")))
+    }
 }